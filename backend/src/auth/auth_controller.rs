@@ -0,0 +1,104 @@
+use axum::{
+    extract::{Query, State},
+    http::HeaderMap,
+    middleware,
+    response::IntoResponse,
+    routing::{delete, get, post, put},
+    Extension, Json, Router,
+};
+
+use std::sync::Arc;
+
+use super::{
+    auth_service::{require_read, require_write, AuthService},
+    dto::{CreateTokenDto, CurrentUser, LoginDto, UpdateUserDto},
+};
+use crate::links::dto::IdDto;
+
+pub struct AuthController {}
+
+impl AuthController {
+    pub async fn login(
+        State(service): State<Arc<AuthService>>,
+        Json(dto): Json<LoginDto>,
+    ) -> impl IntoResponse {
+        service.login(dto).await
+    }
+
+    pub async fn logout(
+        State(service): State<Arc<AuthService>>,
+        headers: HeaderMap,
+    ) -> impl IntoResponse {
+        service.logout(&headers).await
+    }
+
+    pub async fn me(Extension(current): Extension<CurrentUser>) -> impl IntoResponse {
+        Json(current.user)
+    }
+
+    pub async fn get_tokens(
+        State(service): State<Arc<AuthService>>,
+        Extension(current): Extension<CurrentUser>,
+    ) -> impl IntoResponse {
+        service.get_tokens(&current).await
+    }
+
+    pub async fn create_token(
+        State(service): State<Arc<AuthService>>,
+        Extension(current): Extension<CurrentUser>,
+        Json(dto): Json<CreateTokenDto>,
+    ) -> impl IntoResponse {
+        service.create_token(&current, dto).await
+    }
+
+    pub async fn remove_token(
+        State(service): State<Arc<AuthService>>,
+        Extension(current): Extension<CurrentUser>,
+        Query(query): Query<IdDto>,
+    ) -> impl IntoResponse {
+        service.remove_token(&current, query.id).await
+    }
+
+    pub async fn get_users(
+        State(service): State<Arc<AuthService>>,
+        Extension(current): Extension<CurrentUser>,
+    ) -> impl IntoResponse {
+        service.get_users(&current).await
+    }
+
+    pub async fn update_user(
+        State(service): State<Arc<AuthService>>,
+        Extension(current): Extension<CurrentUser>,
+        Query(query): Query<IdDto>,
+        Json(dto): Json<UpdateUserDto>,
+    ) -> impl IntoResponse {
+        service.update_user(&current, query.id, dto).await
+    }
+}
+
+/// Routes of sessions, API tokens and users, only served when authentication is enabled
+pub fn auth_routes(auth: Arc<AuthService>) -> Router {
+    let read = Router::new()
+        .route("/auth/me", get(AuthController::me))
+        .route("/auth/tokens", get(AuthController::get_tokens))
+        .route("/auth/users", get(AuthController::get_users))
+        .route_layer(middleware::from_fn_with_state(
+            Arc::clone(&auth),
+            require_read,
+        ));
+    let write = Router::new()
+        .route("/auth/tokens", post(AuthController::create_token))
+        .route("/auth/tokens", delete(AuthController::remove_token))
+        .route("/auth/users", put(AuthController::update_user))
+        .route_layer(middleware::from_fn_with_state(
+            Arc::clone(&auth),
+            require_write,
+        ));
+
+    Router::new()
+        .route("/auth/login", post(AuthController::login))
+        .route("/auth/logout", post(AuthController::logout))
+        .merge(read)
+        .merge(write)
+        .with_state(auth)
+}
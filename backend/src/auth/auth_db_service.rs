@@ -0,0 +1,265 @@
+use super::dto::{ApiToken, TokenScope, User, UserUsage};
+use crate::{config::Config, metrics, utils::get_now_time};
+use log::error;
+use rusqlite::{params, Connection, OptionalExtension, Result, Row};
+
+pub struct AuthDbService {
+    db_name: String,
+}
+
+impl AuthDbService {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            db_name: config.db_name.clone(),
+        }
+    }
+
+    fn open_connection(&self) -> Result<Connection> {
+        let mut conn = Connection::open(&self.db_name)?;
+        conn.profile(Some(metrics::observe_query));
+        Ok(conn)
+    }
+
+    pub fn create_user(
+        &self,
+        username: &str,
+        password_hash: &str,
+        is_admin: bool,
+        quota_bytes: Option<u64>,
+    ) -> Result<&str> {
+        let conn = self.open_connection()?;
+
+        match conn.execute(
+            "INSERT INTO users (username, password_hash, is_admin, quota_bytes, date_create)
+                VALUES (?, ?, ?, ?, ?)",
+            params![
+                username,
+                password_hash,
+                is_admin,
+                quota_bytes,
+                get_now_time()
+            ],
+        ) {
+            Ok(changes) => {
+                if changes == 1 {
+                    Ok("One user created")
+                } else {
+                    Ok("No user created")
+                }
+            }
+            Err(e) => {
+                error!("Error creating user: {}", e);
+                Err(e)
+            }
+        }
+    }
+
+    pub fn count_users(&self) -> Result<usize> {
+        let conn = self.open_connection()?;
+        conn.query_row("SELECT COUNT(*) FROM users", [], |row| row.get(0))
+    }
+
+    pub fn get_user(&self, id: usize) -> Result<Option<User>> {
+        let conn = self.open_connection()?;
+        conn.query_row("SELECT * FROM users WHERE id = ?", [id], map_user)
+            .optional()
+    }
+
+    /// Every user with the storage used by its links
+    pub fn get_users(&self) -> Result<Vec<UserUsage>> {
+        let conn = self.open_connection()?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT u.*, ({}) AS used_bytes FROM users u ORDER BY u.id",
+            used_bytes_query("u.id")
+        ))?;
+
+        let rows = stmt.query_map([], |row| {
+            Ok(UserUsage {
+                user: map_user(row)?,
+                used_bytes: row.get("used_bytes")?,
+            })
+        })?;
+
+        let result: Result<Vec<_>, _> = rows.collect();
+        result
+    }
+
+    pub fn update_user(
+        &self,
+        id: usize,
+        is_admin: Option<bool>,
+        quota_bytes: Option<Option<u64>>,
+    ) -> Result<&str> {
+        let conn = self.open_connection()?;
+
+        let changes = conn.execute(
+            "UPDATE users
+                SET is_admin = COALESCE(?, is_admin),
+                    quota_bytes = CASE WHEN ? THEN ? ELSE quota_bytes END
+                WHERE id = ?",
+            params![is_admin, quota_bytes.is_some(), quota_bytes.flatten(), id],
+        )?;
+
+        Ok(if changes == 1 {
+            "One user updated"
+        } else {
+            "No user updated"
+        })
+    }
+
+    /// Bytes of the distinct mediafiles of the user's links, a file shared by two links counts once
+    pub fn get_used_bytes(&self, user_id: usize) -> Result<u64> {
+        let conn = self.open_connection()?;
+        conn.query_row(&used_bytes_query("?"), [user_id], |row| row.get(0))
+    }
+
+    /// The user with its password hash
+    pub fn get_user_by_name(&self, username: &str) -> Result<Option<(User, String)>> {
+        let conn = self.open_connection()?;
+
+        conn.query_row(
+            "SELECT * FROM users WHERE username = ?",
+            [username],
+            |row| Ok((map_user(row)?, row.get("password_hash")?)),
+        )
+        .optional()
+    }
+
+    pub fn create_session(&self, token_hash: &str, user_id: usize, expires_at: &str) -> Result<()> {
+        let conn = self.open_connection()?;
+        let now = get_now_time();
+
+        conn.execute("DELETE FROM sessions WHERE expires_at <= ?", [&now])?;
+        conn.execute(
+            "INSERT INTO sessions (token_hash, user_id, date_create, expires_at) VALUES (?, ?, ?, ?)",
+            params![token_hash, user_id, now, expires_at],
+        )?;
+
+        Ok(())
+    }
+
+    /// User of a session that has not expired
+    pub fn get_session_user(&self, token_hash: &str) -> Result<Option<User>> {
+        let conn = self.open_connection()?;
+
+        conn.query_row(
+            "SELECT u.* FROM sessions s JOIN users u ON u.id = s.user_id
+                WHERE s.token_hash = ? AND s.expires_at > ?",
+            params![token_hash, get_now_time()],
+            map_user,
+        )
+        .optional()
+    }
+
+    pub fn remove_session(&self, token_hash: &str) -> Result<()> {
+        let conn = self.open_connection()?;
+        conn.execute("DELETE FROM sessions WHERE token_hash = ?", [token_hash])?;
+        Ok(())
+    }
+
+    /// Returns the id of the new token
+    pub fn create_token(
+        &self,
+        user_id: usize,
+        name: &str,
+        token_hash: &str,
+        scope: TokenScope,
+    ) -> Result<usize> {
+        let conn = self.open_connection()?;
+
+        conn.execute(
+            "INSERT INTO api_tokens (user_id, name, token_hash, scope, date_create) VALUES (?, ?, ?, ?, ?)",
+            params![user_id, name, token_hash, scope.as_str(), get_now_time()],
+        )?;
+
+        Ok(conn.last_insert_rowid() as usize)
+    }
+
+    pub fn get_tokens(&self, user_id: usize) -> Result<Vec<ApiToken>> {
+        let conn = self.open_connection()?;
+        let mut stmt = conn.prepare("SELECT * FROM api_tokens WHERE user_id = ? ORDER BY id")?;
+
+        let rows = stmt.query_map([user_id], map_token)?;
+
+        let result: Result<Vec<_>, _> = rows.collect();
+        result
+    }
+
+    /// User and scope of an API token, marks the token as used
+    pub fn get_token_user(&self, token_hash: &str) -> Result<Option<(User, TokenScope)>> {
+        let conn = self.open_connection()?;
+
+        let found = conn
+            .query_row(
+                "SELECT u.*, t.id AS token_id, t.scope FROM api_tokens t JOIN users u ON u.id = t.user_id
+                    WHERE t.token_hash = ?",
+                [token_hash],
+                |row| {
+                    Ok((
+                        map_user(row)?,
+                        TokenScope::parse(&row.get::<_, String>("scope")?),
+                        row.get::<_, usize>("token_id")?,
+                    ))
+                },
+            )
+            .optional()?;
+
+        Ok(match found {
+            Some((user, scope, token_id)) => {
+                conn.execute(
+                    "UPDATE api_tokens SET last_used_at = ? WHERE id = ?",
+                    params![get_now_time(), token_id],
+                )?;
+                Some((user, scope))
+            }
+            None => None,
+        })
+    }
+
+    pub fn remove_token(&self, user_id: usize, id: usize) -> Result<&str> {
+        let conn = self.open_connection()?;
+
+        let changes = conn.execute(
+            "DELETE FROM api_tokens WHERE id = ? AND user_id = ?",
+            params![id, user_id],
+        )?;
+
+        Ok(if changes == 1 {
+            "One token removed"
+        } else {
+            "No token removed"
+        })
+    }
+}
+
+fn map_user(row: &Row) -> Result<User> {
+    Ok(User {
+        id: row.get("id")?,
+        username: row.get("username")?,
+        is_admin: row.get("is_admin")?,
+        quota_bytes: row.get("quota_bytes")?,
+        date_create: row.get("date_create")?,
+    })
+}
+
+fn used_bytes_query(user_id: &str) -> String {
+    format!(
+        "SELECT COALESCE(SUM(m.size), 0) FROM mediafiles m
+            WHERE m.id IN (
+                SELECT ml.mediafile_id FROM mediafiles_links ml
+                JOIN links l ON l.id = ml.link_id
+                WHERE l.user_id = {}
+            )",
+        user_id
+    )
+}
+
+fn map_token(row: &Row) -> Result<ApiToken> {
+    Ok(ApiToken {
+        id: row.get("id")?,
+        name: row.get("name")?,
+        scope: TokenScope::parse(&row.get::<_, String>("scope")?),
+        date_create: row.get("date_create")?,
+        last_used_at: row.get("last_used_at")?,
+    })
+}
@@ -0,0 +1,481 @@
+use super::{
+    auth_db_service::AuthDbService,
+    dto::{
+        Access, CreateTokenDto, CreatedToken, CurrentUser, LoginDto, TokenScope, UpdateUserDto,
+        User,
+    },
+};
+use crate::{
+    config::Config,
+    links::dto::IResult,
+    utils::{error_response, get_time_after, parse_size, server_error_response, success_response},
+};
+use argon2::{
+    password_hash::{
+        rand_core::{OsRng, RngCore},
+        PasswordHash, PasswordHasher, PasswordVerifier, SaltString,
+    },
+    Argon2,
+};
+use axum::{
+    extract::State,
+    http::{
+        header::{AUTHORIZATION, COOKIE, SET_COOKIE},
+        HeaderMap, Request, StatusCode,
+    },
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::Duration;
+use log::{error, info, warn};
+use sha2::{Digest, Sha256};
+use std::{collections::HashMap, sync::Arc};
+
+/// Cookie holding the session token of the web UI
+const SESSION_COOKIE: &str = "parsephoto_session";
+
+const MIN_PASSWORD_LENGTH: usize = 8;
+
+pub struct AuthService {
+    auth_db_service: Arc<AuthDbService>,
+    enabled: bool,
+    session_ttl: Duration,
+    secure_cookie: bool,
+    /// Quota of users without their own
+    user_quota: Option<u64>,
+}
+
+impl AuthService {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            auth_db_service: Arc::new(AuthDbService::new(config)),
+            enabled: config.auth_enabled,
+            session_ttl: config.session_ttl,
+            secure_cookie: config.session_cookie_secure,
+            user_quota: config.user_quota,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Names of every user by id
+    pub fn get_user_names(&self) -> Result<HashMap<usize, String>, String> {
+        self.auth_db_service
+            .get_users()
+            .map(|users| {
+                users
+                    .into_iter()
+                    .map(|usage| (usage.user.id, usage.user.username))
+                    .collect()
+            })
+            .map_err(|e| e.to_string())
+    }
+
+    pub fn find_user(&self, username: &str) -> Result<Option<User>, String> {
+        self.auth_db_service
+            .get_user_by_name(username)
+            .map(|found| found.map(|(user, _)| user))
+            .map_err(|e| e.to_string())
+    }
+
+    pub fn count_users(&self) -> Result<usize, String> {
+        self.auth_db_service
+            .count_users()
+            .map_err(|e| e.to_string())
+    }
+
+    /// Creates a user, the first user of an instance is always an admin
+    pub async fn create_user(
+        &self,
+        username: &str,
+        password: &str,
+        is_admin: bool,
+        quota_bytes: Option<u64>,
+    ) -> Result<String, String> {
+        let username = username.trim();
+        if username.is_empty() {
+            return Err("Username is required".to_string());
+        }
+        if password.chars().count() < MIN_PASSWORD_LENGTH {
+            return Err(format!(
+                "Password must be at least {} characters long",
+                MIN_PASSWORD_LENGTH
+            ));
+        }
+
+        if self
+            .auth_db_service
+            .get_user_by_name(username)
+            .map_err(|e| e.to_string())?
+            .is_some()
+        {
+            return Err(format!("User {} already exists", username));
+        }
+
+        let salt = SaltString::generate(&mut OsRng);
+        let password_hash = Argon2::default()
+            .hash_password(password.as_bytes(), &salt)
+            .map_err(|e| format!("Failed to hash password: {}", e))?
+            .to_string();
+
+        let is_admin = is_admin || self.count_users()? == 0;
+        info!(
+            "Creating {} {}",
+            if is_admin { "admin" } else { "user" },
+            username
+        );
+        self.auth_db_service
+            .create_user(username, &password_hash, is_admin, quota_bytes)
+            .map(|m| m.to_string())
+            .map_err(|e| e.to_string())
+    }
+
+    /// Starts a session of the web UI, its token is sent back as an `HttpOnly` cookie
+    pub async fn login(&self, dto: LoginDto) -> impl IntoResponse {
+        let invalid = || {
+            error_response(
+                "Invalid username or password".to_string(),
+                StatusCode::UNAUTHORIZED,
+            )
+        };
+
+        let (user, password_hash) = match self.auth_db_service.get_user_by_name(dto.username.trim())
+        {
+            Ok(Some(found)) => found,
+            Ok(None) => return Err(invalid()),
+            Err(e) => return Err(server_error_response(e.to_string())),
+        };
+
+        let verified = PasswordHash::new(&password_hash).is_ok_and(|hash| {
+            Argon2::default()
+                .verify_password(dto.password.as_bytes(), &hash)
+                .is_ok()
+        });
+        if !verified {
+            warn!("Failed login of user {}", user.username);
+            return Err(invalid());
+        }
+
+        let token = generate_token();
+        let Some(expires_at) = get_time_after(self.session_ttl) else {
+            return Err(server_error_response(
+                "SESSION_TTL is out of range".to_string(),
+            ));
+        };
+        if let Err(e) =
+            self.auth_db_service
+                .create_session(&hash_token(&token), user.id, &expires_at)
+        {
+            return Err(server_error_response(e.to_string()));
+        }
+
+        info!("User {} logged in", user.username);
+        let cookie = self.session_cookie(&token, self.session_ttl.num_seconds());
+        Ok((StatusCode::OK, [(SET_COOKIE, cookie)], Json(user)))
+    }
+
+    /// Ends the session of the request, if any, and clears the cookie
+    pub async fn logout(&self, headers: &HeaderMap) -> impl IntoResponse {
+        if let Some(token) = session_token(headers) {
+            if let Err(e) = self.auth_db_service.remove_session(&hash_token(&token)) {
+                return Err(server_error_response(e.to_string()));
+            }
+        }
+
+        Ok((
+            [(SET_COOKIE, self.session_cookie("", 0))],
+            success_response("Logged out".to_string()),
+        ))
+    }
+
+    pub async fn get_tokens(&self, current: &CurrentUser) -> impl IntoResponse {
+        match self.auth_db_service.get_tokens(current.user.id) {
+            Ok(tokens) => Ok((StatusCode::OK, Json(tokens))),
+            Err(e) => {
+                error!("Error getting API tokens: {}", e);
+                Err(server_error_response(
+                    "Error getting API tokens".to_string(),
+                ))
+            }
+        }
+    }
+
+    pub async fn create_token(
+        &self,
+        current: &CurrentUser,
+        dto: CreateTokenDto,
+    ) -> impl IntoResponse {
+        let name = dto.name.trim();
+        if name.is_empty() {
+            return Err(error_response(
+                "Token name is required".to_string(),
+                StatusCode::BAD_REQUEST,
+            ));
+        }
+
+        let scope = dto.scope.unwrap_or_default();
+        if scope > current.scope {
+            return Err(error_response(
+                format!(
+                    "A {} token cannot create {} tokens",
+                    current.scope.as_str(),
+                    scope.as_str()
+                ),
+                StatusCode::FORBIDDEN,
+            ));
+        }
+
+        let token = format!("pp_{}", generate_token());
+        info!(
+            "Creating {} API token {} of user {}",
+            scope.as_str(),
+            name,
+            current.user.username
+        );
+
+        match self
+            .auth_db_service
+            .create_token(current.user.id, name, &hash_token(&token), scope)
+        {
+            Ok(id) => Ok((
+                StatusCode::OK,
+                Json(CreatedToken {
+                    id,
+                    name: name.to_string(),
+                    scope,
+                    token,
+                }),
+            )),
+            Err(e) => Err(server_error_response(e.to_string())),
+        }
+    }
+
+    pub async fn remove_token(&self, current: &CurrentUser, id: usize) -> impl IntoResponse {
+        info!("Removing API token with id: {}", &id);
+
+        match self.auth_db_service.remove_token(current.user.id, id) {
+            Ok(m) => Ok(success_response(m.to_string())),
+            Err(e) => Err(server_error_response(e.to_string())),
+        }
+    }
+
+    /// Users with their storage usage, admins only
+    pub async fn get_users(&self, current: &CurrentUser) -> impl IntoResponse {
+        if !current.user.is_admin {
+            return Err(admin_required());
+        }
+
+        match self.auth_db_service.get_users() {
+            Ok(users) => Ok((StatusCode::OK, Json(users))),
+            Err(e) => {
+                error!("Error getting users: {}", e);
+                Err(server_error_response("Error getting users".to_string()))
+            }
+        }
+    }
+
+    /// Changes the role or the quota of a user, admins only
+    pub async fn update_user(
+        &self,
+        current: &CurrentUser,
+        id: usize,
+        dto: UpdateUserDto,
+    ) -> impl IntoResponse {
+        if !current.user.is_admin {
+            return Err(admin_required());
+        }
+        if id == current.user.id && dto.is_admin == Some(false) {
+            return Err(error_response(
+                "Admins cannot remove their own admin role".to_string(),
+                StatusCode::BAD_REQUEST,
+            ));
+        }
+
+        let quota_bytes = match dto.quota.as_deref().map(str::trim) {
+            None => None,
+            Some("") => Some(None),
+            Some(quota) => match parse_size(quota) {
+                Some(bytes) => Some(Some(bytes)),
+                None => {
+                    return Err(error_response(
+                        format!("{} is not a valid quota", quota),
+                        StatusCode::BAD_REQUEST,
+                    ))
+                }
+            },
+        };
+
+        info!("Updating user with id: {}", &id);
+        match self
+            .auth_db_service
+            .update_user(id, dto.is_admin, quota_bytes)
+        {
+            Ok(m) => Ok(success_response(m.to_string())),
+            Err(e) => Err(server_error_response(e.to_string())),
+        }
+    }
+
+    /// Fails when the links of the user already fill its quota, links without owner have none
+    pub fn check_quota(&self, user_id: usize) -> Result<(), String> {
+        if user_id == 0 {
+            return Ok(());
+        }
+
+        let user = self
+            .auth_db_service
+            .get_user(user_id)
+            .map_err(|e| e.to_string())?;
+        let quota = match user.and_then(|user| user.quota_bytes).or(self.user_quota) {
+            Some(quota) => quota,
+            None => return Ok(()),
+        };
+
+        let used = self
+            .auth_db_service
+            .get_used_bytes(user_id)
+            .map_err(|e| e.to_string())?;
+        if used >= quota {
+            return Err(format!(
+                "Storage quota of {} bytes exceeded, {} bytes used",
+                quota, used
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// User of a bearer API token or of a session cookie, the token wins when both are sent
+    fn authenticate(&self, headers: &HeaderMap) -> Result<Option<CurrentUser>, String> {
+        let bearer = headers
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+
+        if let Some(token) = bearer {
+            return self
+                .auth_db_service
+                .get_token_user(&hash_token(token.trim()))
+                .map(|found| found.map(|(user, scope)| CurrentUser { user, scope }))
+                .map_err(|e| e.to_string());
+        }
+
+        match session_token(headers) {
+            Some(token) => self
+                .auth_db_service
+                .get_session_user(&hash_token(&token))
+                .map(|user| {
+                    user.map(|user| CurrentUser {
+                        user,
+                        scope: TokenScope::Write,
+                    })
+                })
+                .map_err(|e| e.to_string()),
+            None => Ok(None),
+        }
+    }
+
+    /// Passes requests of users with `scope` on with the `CurrentUser` and its `Access`
+    /// in their extensions, every request with `Access::ALL` when authentication is disabled
+    async fn authorize<B>(
+        &self,
+        scope: TokenScope,
+        mut request: Request<B>,
+        next: Next<B>,
+    ) -> Response {
+        if !self.enabled {
+            request.extensions_mut().insert(Access::ALL);
+            return next.run(request).await;
+        }
+
+        let current = match self.authenticate(request.headers()) {
+            Ok(Some(current)) => current,
+            Ok(None) => {
+                return error_response(
+                    "Authentication required".to_string(),
+                    StatusCode::UNAUTHORIZED,
+                )
+                .into_response()
+            }
+            Err(e) => {
+                error!("Error authenticating request: {}", e);
+                return server_error_response("Error authenticating request".to_string())
+                    .into_response();
+            }
+        };
+
+        if current.scope < scope {
+            return error_response(
+                format!("This request needs a {} token", scope.as_str()),
+                StatusCode::FORBIDDEN,
+            )
+            .into_response();
+        }
+
+        request.extensions_mut().insert(Access::new(&current.user));
+        request.extensions_mut().insert(current);
+        next.run(request).await
+    }
+
+    fn session_cookie(&self, token: &str, max_age: i64) -> String {
+        let mut cookie = format!(
+            "{}={}; Path=/; Max-Age={}; HttpOnly; SameSite=Strict",
+            SESSION_COOKIE, token, max_age
+        );
+        if self.secure_cookie {
+            cookie.push_str("; Secure");
+        }
+        cookie
+    }
+}
+
+/// Middleware of routes that change nothing
+pub async fn require_read<B>(
+    State(auth): State<Arc<AuthService>>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    auth.authorize(TokenScope::Read, request, next).await
+}
+
+/// Middleware of routes that download, check, change or remove anything
+pub async fn require_write<B>(
+    State(auth): State<Arc<AuthService>>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    auth.authorize(TokenScope::Write, request, next).await
+}
+
+fn admin_required() -> (StatusCode, Json<IResult>) {
+    error_response(
+        "Only admins can manage users".to_string(),
+        StatusCode::FORBIDDEN,
+    )
+}
+
+fn session_token(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .find_map(|pair| {
+            let (name, value) = pair.trim().split_once('=')?;
+            (name == SESSION_COOKIE && !value.is_empty()).then(|| value.to_string())
+        })
+}
+
+/// Random hex token for sessions and API tokens
+fn generate_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Tokens are stored hashed, so a leaked database holds no usable tokens
+fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
@@ -0,0 +1,121 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct User {
+    pub id: usize,
+    pub username: String,
+    #[serde(rename = "isAdmin")]
+    pub is_admin: bool,
+    /// Storage quota in bytes, none falls back to the global `USER_QUOTA`
+    #[serde(rename = "quotaBytes")]
+    pub quota_bytes: Option<u64>,
+    #[serde(rename = "dateCreate")]
+    pub date_create: String,
+}
+
+/// What an API token may do, sessions of the web UI are always `write`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TokenScope {
+    /// Listings and other requests that change nothing
+    #[default]
+    Read,
+    /// Every request, including downloads, checks and removals
+    Write,
+}
+
+impl TokenScope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TokenScope::Read => "read",
+            TokenScope::Write => "write",
+        }
+    }
+
+    pub fn parse(scope: &str) -> Self {
+        serde_json::from_value(serde_json::Value::String(scope.to_string())).unwrap_or_default()
+    }
+}
+
+/// User of a request authenticated by a session cookie or an API token
+#[derive(Debug, Clone)]
+pub struct CurrentUser {
+    pub user: User,
+    pub scope: TokenScope,
+}
+
+/// Links a request may see and change, inserted into the extensions of every authorized request
+#[derive(Debug, Clone, Copy)]
+pub struct Access {
+    pub user_id: usize,
+    pub is_admin: bool,
+}
+
+impl Access {
+    /// Access of requests when authentication is disabled and of the command line
+    pub const ALL: Access = Access {
+        user_id: 0,
+        is_admin: true,
+    };
+
+    pub fn new(user: &User) -> Self {
+        Self {
+            user_id: user.id,
+            is_admin: user.is_admin,
+        }
+    }
+
+    /// Admins see links of every user, users see their own links
+    pub fn owns(&self, owner_id: usize) -> bool {
+        self.is_admin || self.user_id == owner_id
+    }
+}
+
+/// User listed for admins with the storage used by its links
+#[derive(Debug, Serialize)]
+pub struct UserUsage {
+    #[serde(flatten)]
+    pub user: User,
+    #[serde(rename = "usedBytes")]
+    pub used_bytes: u64,
+}
+
+#[derive(Deserialize)]
+pub struct UpdateUserDto {
+    #[serde(rename = "isAdmin")]
+    pub is_admin: Option<bool>,
+    /// Quota like `10G` or `500M`, empty removes the quota of the user
+    pub quota: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ApiToken {
+    pub id: usize,
+    pub name: String,
+    pub scope: TokenScope,
+    #[serde(rename = "dateCreate")]
+    pub date_create: String,
+    #[serde(rename = "lastUsedAt")]
+    pub last_used_at: Option<String>,
+}
+
+/// Token returned once when it is created, only its hash is stored
+#[derive(Debug, Serialize)]
+pub struct CreatedToken {
+    pub id: usize,
+    pub name: String,
+    pub scope: TokenScope,
+    pub token: String,
+}
+
+#[derive(Deserialize)]
+pub struct LoginDto {
+    pub username: String,
+    pub password: String,
+}
+
+#[derive(Deserialize)]
+pub struct CreateTokenDto {
+    pub name: String,
+    pub scope: Option<TokenScope>,
+}
@@ -0,0 +1,4 @@
+pub mod auth_controller;
+pub mod auth_db_service;
+pub mod auth_service;
+pub mod dto;
@@ -0,0 +1,832 @@
+use super::dto::{Command, Export, ExportLink, ExportMediafile, LinksArgs};
+use crate::{
+    auth::{auth_service::AuthService, dto::Access},
+    config::Config,
+    http_client::HttpClient,
+    links::{
+        download_permits::DownloadPermits,
+        dto::{CreateLinkDto, IResult, Link, RemoveLinkQuery},
+        links_service::LinksService,
+    },
+    mediafiles::{dto::CreateDto, mediafiles_service::MediafilesService},
+    storage::{dto::FileDisposal, storage_service::Storage},
+    tags::{
+        dto::{LinkTagsDto, MediafileTagsDto},
+        tags_service::TagsService,
+    },
+    trash::trash_service::TrashService,
+    utils::get_now_time,
+};
+use axum::response::IntoResponse;
+use indicatif::{ProgressBar, ProgressStyle};
+use log::info;
+use std::{
+    cmp::Reverse,
+    collections::{HashMap, HashSet},
+    fs,
+    future::Future,
+    io::{self, IsTerminal, Write},
+    path::Path,
+    sync::Arc,
+};
+
+/// Version of the `export` file, `import` refuses other versions
+const EXPORT_VERSION: u32 = 1;
+
+/// Runs the command line commands with the services the web server uses
+pub struct CliService {
+    config: Arc<Config>,
+    auth_service: Arc<AuthService>,
+    links_service: Arc<LinksService>,
+    mediafiles_service: Arc<MediafilesService>,
+    tags_service: Arc<TagsService>,
+    trash_service: Arc<TrashService>,
+}
+
+impl CliService {
+    pub fn new(
+        config: Arc<Config>,
+        http: Arc<HttpClient>,
+        storage: Arc<dyn Storage>,
+        download_permits: Arc<DownloadPermits>,
+    ) -> Self {
+        Self {
+            auth_service: Arc::new(AuthService::new(&config)),
+            mediafiles_service: Arc::new(MediafilesService::new(
+                &config,
+                Arc::clone(&http),
+                Arc::clone(&storage),
+            )),
+            tags_service: Arc::new(TagsService::new(&config)),
+            trash_service: Arc::new(TrashService::new(
+                Arc::clone(&config),
+                Arc::clone(&http),
+                Arc::clone(&storage),
+                Arc::clone(&download_permits),
+            )),
+            links_service: Arc::new(LinksService::new(
+                Arc::clone(&config),
+                http,
+                storage,
+                download_permits,
+            )),
+            config,
+        }
+    }
+
+    /// Runs every command except `serve`, fails when any of its links failed
+    pub async fn run(&self, command: Command) -> Result<(), String> {
+        match command {
+            Command::Serve => Err("serve is not a batch command".to_string()),
+            Command::Add { urls } => self.add(urls).await,
+            Command::Download(args) => self.download(args).await,
+            Command::Check(args) => self.check(args).await,
+            Command::Scan { ids } => self.scan(&ids).await,
+            Command::Verify {
+                ids,
+                redownload_missing,
+                redownload_modified,
+            } => {
+                self.verify(&ids, redownload_missing, redownload_modified)
+                    .await
+            }
+            Command::Dedup { dry_run } => self.dedup(dry_run).await,
+            Command::MigrateStore { ids, dry_run } => self.migrate_store(&ids, dry_run).await,
+            Command::Views { dir, ids, symlink } => self.views(&dir, &ids, symlink).await,
+            Command::Remove {
+                ids,
+                permanent,
+                files,
+            } => self.remove(&ids, permanent, files).await,
+            Command::Restore { ids } => self.restore(&ids).await,
+            Command::PurgeTrash { all } => self.purge_trash(all).await,
+            Command::Gc { dry_run, files } => self.gc(dry_run, files).await,
+            Command::Export { output } => self.export(output.as_deref()).await,
+            Command::Import { file } => self.import(&file).await,
+            Command::CreateUser {
+                username,
+                admin,
+                quota,
+            } => self.create_user(&username, admin, quota).await,
+        }
+    }
+
+    async fn add(&self, urls: Vec<String>) -> Result<(), String> {
+        let mut failed = 0;
+
+        for path in urls {
+            match message(
+                self.links_service
+                    .create_one(Access::ALL, CreateLinkDto { path: path.clone() })
+                    .await,
+            )
+            .await
+            {
+                Ok(m) => println!("{}: {}", path, m),
+                Err(e) => {
+                    println!("{}: {}", path, e);
+                    failed += 1;
+                }
+            }
+        }
+
+        failures(failed, "urls")
+    }
+
+    async fn download(&self, args: LinksArgs) -> Result<(), String> {
+        let links = if args.all {
+            self.links_service
+                .get_links(&[])?
+                .into_iter()
+                // New links start as downloaded with no progress
+                .filter(|link| link.is_reachable && link.progress < 100)
+                .collect()
+        } else if args.ids.is_empty() {
+            return Err("Give link ids or --all".to_string());
+        } else {
+            self.links_service.get_links(&args.ids)?
+        };
+
+        self.for_each_link(&links, "download", |link| async move {
+            message(self.links_service.download(Access::ALL, link.id).await).await
+        })
+        .await
+    }
+
+    async fn check(&self, args: LinksArgs) -> Result<(), String> {
+        let links = if args.all {
+            self.links_service
+                .get_links(&[])?
+                .into_iter()
+                .filter(|link| link.is_reachable)
+                .collect()
+        } else if args.ids.is_empty() {
+            self.links_service.get_due_links()?
+        } else {
+            self.links_service.get_links(&args.ids)?
+        };
+
+        self.for_each_link(&links, "check", |link| async move {
+            self.links_service.check_link(link).await
+        })
+        .await
+    }
+
+    async fn scan(&self, ids: &[usize]) -> Result<(), String> {
+        let links: Vec<Link> = self
+            .links_service
+            .get_links(ids)?
+            .into_iter()
+            .filter(|link| !ids.is_empty() || link.is_reachable)
+            .collect();
+
+        self.for_each_link(&links, "scan", |link| async move {
+            message(
+                self.links_service
+                    .scan_files_for_link(Access::ALL, link.id)
+                    .await,
+            )
+            .await
+        })
+        .await
+    }
+
+    async fn verify(
+        &self,
+        ids: &[usize],
+        redownload_missing: bool,
+        redownload_modified: bool,
+    ) -> Result<(), String> {
+        let links = self.links_service.get_links(ids)?;
+
+        self.for_each_link(&links, "verify", |link| async move {
+            let report = self
+                .links_service
+                .verify_link(link, redownload_missing, redownload_modified)
+                .await?;
+            let summary = format!(
+                "checked {}, missing {}, modified {}, orphaned {}, redownloaded {}",
+                report.checked,
+                report.missing.len(),
+                report.modified.len(),
+                report.orphaned.len(),
+                report.redownloaded.len()
+            );
+
+            if report.missing.len() + report.modified.len() > report.redownloaded.len() {
+                Err(summary)
+            } else {
+                Ok(summary)
+            }
+        })
+        .await?;
+
+        // Directories without a link can only be found when the whole storage is checked
+        if ids.is_empty() {
+            if let Some(report) = self.links_service.find_unknown_dirs(&links).await? {
+                println!("{} files belong to no link:", report.orphaned.len());
+                for path in &report.orphaned {
+                    println!("  {}", path);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn dedup(&self, dry_run: bool) -> Result<(), String> {
+        let links = self.links_service.get_links(&[])?;
+
+        let bar = progress_bar(links.len(), "hash");
+        let mut hashes: Vec<(&Link, HashSet<String>)> = Vec::new();
+        for link in &links {
+            bar.set_message(link.name.clone());
+            let records = self.mediafiles_service.get_all_by_link_id(link.id).await?;
+            if !records.is_empty() {
+                hashes.push((link, records.into_iter().map(|r| r.hash).collect()));
+            }
+            bar.inc(1);
+        }
+        bar.finish_and_clear();
+
+        // A link duplicates the largest link of the same owner holding all its files,
+        // of two links with the same files the later one is the duplicate
+        let mut duplicates: Vec<(&Link, &Link)> = Vec::new();
+        let mut marked: HashSet<usize> = HashSet::new();
+        for (link, files) in &hashes {
+            if link.duplicate_id.is_some() {
+                continue;
+            }
+
+            let original = hashes
+                .iter()
+                .filter(|(other, other_files)| {
+                    other.id != link.id
+                        && other.user_id == link.user_id
+                        && other.duplicate_id.is_none()
+                        && !marked.contains(&other.id)
+                        && files.is_subset(other_files)
+                        && (files.len() < other_files.len() || other.id < link.id)
+                })
+                .max_by_key(|(other, other_files)| (other_files.len(), Reverse(other.id)));
+
+            if let Some((original, _)) = original {
+                marked.insert(link.id);
+                duplicates.push((link, original));
+            }
+        }
+
+        let mut failed = 0;
+        for (link, original) in &duplicates {
+            if dry_run {
+                println!(
+                    "id {}: duplicate of id {} {}",
+                    link.id, original.id, original.path
+                );
+                continue;
+            }
+
+            match message(
+                self.links_service
+                    .add_duplicate(Access::ALL, link.id, original.id)
+                    .await,
+            )
+            .await
+            {
+                Ok(m) => println!("id {}: {}", link.id, m),
+                Err(e) => {
+                    println!("id {}: {}", link.id, e);
+                    failed += 1;
+                }
+            }
+        }
+
+        info!("Found {} duplicate links", duplicates.len());
+        println!("{} duplicate links found", duplicates.len());
+
+        failures(failed, "links")
+    }
+
+    async fn migrate_store(&self, ids: &[usize], dry_run: bool) -> Result<(), String> {
+        if self.config.storage_layout != "content" {
+            return Err("Set STORAGE_LAYOUT=content before moving files to the store".to_string());
+        }
+        let links = self.links_service.get_links(ids)?;
+
+        self.for_each_link(&links, "migrate", |link| async move {
+            self.links_service.move_to_store(link, dry_run).await
+        })
+        .await
+    }
+
+    async fn views(&self, dir: &Path, ids: &[usize], symlink: bool) -> Result<(), String> {
+        if self.config.storage_backend != "local" {
+            return Err("Views are built for the local storage only".to_string());
+        }
+        let root = fs::canonicalize(&self.config.storage_root).map_err(|e| {
+            format!(
+                "Failed to read {}: {}",
+                self.config.storage_root.display(),
+                e
+            )
+        })?;
+        let links = self.links_service.get_links(ids)?;
+
+        self.for_each_link(&links, "views", |link| {
+            let root = &root;
+            async move {
+                let view = dir.join(&link.name);
+                fs::create_dir_all(&view)
+                    .map_err(|e| format!("Failed to create {}: {}", view.display(), e))?;
+
+                // Files of the link with the same name get their id in front
+                let mut names = HashSet::new();
+                let mut linked = 0;
+                for record in self.mediafiles_service.get_all_by_link_id(link.id).await? {
+                    let name = if names.insert(record.name.clone()) {
+                        record.name
+                    } else {
+                        format!("{}-{}", record.id, record.name)
+                    };
+                    let target = view.join(&name);
+                    let source = root.join(&record.path);
+                    if !source.is_file() {
+                        continue;
+                    }
+
+                    // An older view is replaced
+                    let _ = fs::remove_file(&target);
+                    let created = if symlink {
+                        create_symlink(&source, &target)
+                    } else {
+                        fs::hard_link(&source, &target)
+                    };
+                    created.map_err(|e| format!("Failed to link {}: {}", target.display(), e))?;
+                    linked += 1;
+                }
+
+                Ok(format!("{} files in {}", linked, view.display()))
+            }
+        })
+        .await
+    }
+
+    async fn remove(
+        &self,
+        ids: &[usize],
+        permanent: bool,
+        files: FileDisposal,
+    ) -> Result<(), String> {
+        let mut failed = 0;
+
+        for &id in ids {
+            let query = RemoveLinkQuery {
+                id,
+                permanent: Some(permanent),
+                files: Some(files),
+            };
+            match message(self.links_service.remove(Access::ALL, query).await).await {
+                Ok(m) => println!("id {}: {}", id, m),
+                Err(e) => {
+                    println!("id {}: {}", id, e);
+                    failed += 1;
+                }
+            }
+        }
+
+        failures(failed, "links")
+    }
+
+    async fn restore(&self, ids: &[usize]) -> Result<(), String> {
+        let mut failed = 0;
+
+        for &id in ids {
+            match message(self.links_service.restore(Access::ALL, id).await).await {
+                Ok(m) => println!("id {}: {}", id, m),
+                Err(e) => {
+                    println!("id {}: {}", id, e);
+                    failed += 1;
+                }
+            }
+        }
+
+        failures(failed, "links")
+    }
+
+    async fn purge_trash(&self, all: bool) -> Result<(), String> {
+        let report = if all {
+            self.trash_service.purge_all().await?
+        } else {
+            self.trash_service.purge_expired().await?
+        };
+
+        println!(
+            "{} links and {} mediafiles removed from the trash",
+            report.links, report.mediafiles
+        );
+        Ok(())
+    }
+
+    async fn gc(&self, dry_run: bool, files: FileDisposal) -> Result<(), String> {
+        let report = self
+            .links_service
+            .run_garbage_collection(dry_run, files)
+            .await?;
+
+        if dry_run {
+            for file in &report.files {
+                println!("{}", file);
+            }
+        }
+        println!(
+            "{} rows and {} mediafiles {}, {} files",
+            report.rows,
+            report.mediafiles,
+            if dry_run { "to remove" } else { "removed" },
+            report.files.len()
+        );
+        Ok(())
+    }
+
+    async fn export(&self, output: Option<&Path>) -> Result<(), String> {
+        let links = self.links_service.get_links(&[])?;
+        let owners = self.auth_service.get_user_names()?;
+        let paths: HashMap<usize, &str> = links
+            .iter()
+            .map(|link| (link.id, link.path.as_str()))
+            .collect();
+
+        let bar = progress_bar(links.len(), "export");
+        let mut exported = Vec::new();
+        for link in &links {
+            bar.set_message(link.name.clone());
+            let mediafiles = self
+                .mediafiles_service
+                .get_all_by_link_id(link.id)
+                .await?
+                .into_iter()
+                .map(|record| ExportMediafile {
+                    name: record.name,
+                    path: record.path,
+                    hash: record.hash,
+                    size: record.size,
+                    source_url: record.source_url,
+                    position_on_page: record.position_on_page,
+                    etag: record.etag,
+                    last_modified: record.last_modified,
+                    content_type: record.content_type,
+                    downloaded_at: record.downloaded_at,
+                    tags: record.tags,
+                })
+                .collect();
+
+            exported.push(ExportLink {
+                path: link.path.clone(),
+                owner: owners.get(&link.user_id).cloned(),
+                name: link.name.clone(),
+                is_reachable: link.is_reachable,
+                duplicate_path: link
+                    .duplicate_id
+                    .and_then(|id| paths.get(&id))
+                    .map(|path| path.to_string()),
+                check_interval: link.check_interval.clone(),
+                tags: link.tags.clone(),
+                mediafiles,
+            });
+            bar.inc(1);
+        }
+        bar.finish_and_clear();
+
+        let export = Export {
+            version: EXPORT_VERSION,
+            date_create: get_now_time(),
+            links: exported,
+        };
+        let json = serde_json::to_string_pretty(&export).map_err(|e| e.to_string())?;
+
+        match output {
+            Some(path) => {
+                fs::write(path, json)
+                    .map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+                println!("{} links exported to {}", links.len(), path.display());
+            }
+            None => io::stdout()
+                .write_all(json.as_bytes())
+                .map_err(|e| e.to_string())?,
+        }
+
+        Ok(())
+    }
+
+    async fn import(&self, file: &Path) -> Result<(), String> {
+        let text = fs::read_to_string(file)
+            .map_err(|e| format!("Failed to read {}: {}", file.display(), e))?;
+        let export: Export = serde_json::from_str(&text)
+            .map_err(|e| format!("{} is not a valid export: {}", file.display(), e))?;
+
+        if export.version != EXPORT_VERSION {
+            return Err(format!(
+                "Export version {} is not supported, expected {}",
+                export.version, EXPORT_VERSION
+            ));
+        }
+
+        let bar = progress_bar(export.links.len(), "import");
+        let mut failed = 0;
+        let mut ids: HashMap<&str, usize> = HashMap::new();
+        for link in &export.links {
+            bar.set_message(link.name.clone());
+            match self.import_link(link).await {
+                Ok((id, m)) => {
+                    ids.insert(&link.path, id);
+                    bar.suspend(|| println!("{}: {}", link.path, m));
+                }
+                Err(e) => {
+                    bar.suspend(|| println!("{}: {}", link.path, e));
+                    failed += 1;
+                }
+            }
+            bar.inc(1);
+        }
+        bar.finish_and_clear();
+
+        // Duplicates are linked once every link exists
+        for link in &export.links {
+            let ids = (
+                ids.get(link.path.as_str()),
+                link.duplicate_path
+                    .as_deref()
+                    .and_then(|path| ids.get(path)),
+            );
+            if let (Some(&id), Some(&duplicate_id)) = ids {
+                if let Err(e) = message(
+                    self.links_service
+                        .add_duplicate(Access::ALL, id, duplicate_id)
+                        .await,
+                )
+                .await
+                {
+                    println!("{}: {}", link.path, e);
+                    failed += 1;
+                }
+            }
+        }
+
+        failures(failed, "links")
+    }
+
+    /// Creates the link unless its owner has it, then adds its missing mediafiles and tags.
+    /// Links of owners missing here are imported without owner
+    async fn import_link(&self, link: &ExportLink) -> Result<(usize, String), String> {
+        let user_id = match &link.owner {
+            Some(owner) => self
+                .auth_service
+                .find_user(owner)?
+                .map_or(0, |user| user.id),
+            None => 0,
+        };
+        let access = Access {
+            user_id,
+            is_admin: true,
+        };
+
+        let stored = match self.links_service.get_by_path(user_id, &link.path)? {
+            Some(stored) => stored,
+            None => {
+                message(
+                    self.links_service
+                        .create_one(
+                            access,
+                            CreateLinkDto {
+                                path: link.path.clone(),
+                            },
+                        )
+                        .await,
+                )
+                .await?;
+                self.links_service
+                    .get_by_path(user_id, &link.path)?
+                    .ok_or_else(|| "Link was not created".to_string())?
+            }
+        };
+
+        if stored.check_interval.is_none() && link.check_interval.is_some() {
+            message(
+                self.links_service
+                    .set_check_interval(Access::ALL, stored.id, link.check_interval.clone())
+                    .await,
+            )
+            .await?;
+        }
+        if !link.is_reachable && stored.is_reachable {
+            message(
+                self.links_service
+                    .tag_unreachable(Access::ALL, stored.id, false)
+                    .await,
+            )
+            .await?;
+        }
+        if !link.tags.is_empty() {
+            message(
+                self.tags_service
+                    .tag_links(
+                        Access::ALL,
+                        LinkTagsDto {
+                            link_ids: vec![stored.id],
+                            tags: link.tags.clone(),
+                        },
+                        true,
+                    )
+                    .await,
+            )
+            .await?;
+        }
+
+        let records = self
+            .mediafiles_service
+            .get_all_by_link_id(stored.id)
+            .await?;
+        let known: HashSet<&str> = records.iter().map(|r| r.path.as_str()).collect();
+        let mut added = 0;
+        for mediafile in &link.mediafiles {
+            if known.contains(mediafile.path.as_str()) {
+                continue;
+            }
+
+            self.mediafiles_service
+                .create_one(CreateDto {
+                    name: mediafile.name.clone(),
+                    path: mediafile.path.clone(),
+                    hash: mediafile.hash.clone(),
+                    size: mediafile.size,
+                    link_id: stored.id,
+                    source_url: mediafile.source_url.clone(),
+                    position_on_page: mediafile.position_on_page,
+                    etag: mediafile.etag.clone(),
+                    last_modified: mediafile.last_modified.clone(),
+                    content_type: mediafile.content_type.clone(),
+                    downloaded_at: mediafile.downloaded_at.clone(),
+                })
+                .await?;
+            added += 1;
+        }
+
+        // Mediafile tags need the ids of the records created above
+        if link
+            .mediafiles
+            .iter()
+            .any(|mediafile| !mediafile.tags.is_empty())
+        {
+            let ids: HashMap<String, usize> = self
+                .mediafiles_service
+                .get_all_by_link_id(stored.id)
+                .await?
+                .into_iter()
+                .map(|record| (record.path, record.id))
+                .collect();
+
+            for mediafile in link.mediafiles.iter().filter(|m| !m.tags.is_empty()) {
+                if let Some(&id) = ids.get(&mediafile.path) {
+                    message(
+                        self.tags_service
+                            .tag_mediafiles(
+                                Access::ALL,
+                                MediafileTagsDto {
+                                    mediafile_ids: vec![id],
+                                    tags: mediafile.tags.clone(),
+                                },
+                                true,
+                            )
+                            .await,
+                    )
+                    .await?;
+                }
+            }
+        }
+
+        Ok((
+            stored.id,
+            format!("id {}, {} mediafiles added", stored.id, added),
+        ))
+    }
+
+    async fn create_user(
+        &self,
+        username: &str,
+        is_admin: bool,
+        quota: Option<u64>,
+    ) -> Result<(), String> {
+        if io::stdin().is_terminal() {
+            eprint!("Password for {}: ", username);
+        }
+        let mut password = String::new();
+        io::stdin()
+            .read_line(&mut password)
+            .map_err(|e| format!("Failed to read password: {}", e))?;
+
+        let m = self
+            .auth_service
+            .create_user(
+                username,
+                password.trim_end_matches(['\r', '\n']),
+                is_admin,
+                quota,
+            )
+            .await?;
+        println!("{}", m);
+
+        if !self.auth_service.is_enabled() {
+            println!("Authentication is disabled, set AUTH_ENABLED=true to require it");
+        }
+
+        Ok(())
+    }
+
+    /// Runs `action` on every link with a progress bar and prints its outcome,
+    /// fails when any link failed
+    async fn for_each_link<'a, F, Fut>(
+        &self,
+        links: &'a [Link],
+        action: &str,
+        run: F,
+    ) -> Result<(), String>
+    where
+        F: Fn(&'a Link) -> Fut,
+        Fut: Future<Output = Result<String, String>>,
+    {
+        let bar = progress_bar(links.len(), action);
+        let mut failed = 0;
+
+        for link in links {
+            bar.set_message(link.name.clone());
+            let result = run(link).await;
+            bar.suspend(|| match &result {
+                Ok(m) => println!("id {}: {}", link.id, m),
+                Err(e) => println!("id {}: failed, {}", link.id, e),
+            });
+            if result.is_err() {
+                failed += 1;
+            }
+            bar.inc(1);
+        }
+        bar.finish_and_clear();
+
+        println!(
+            "{}: {} of {} links succeeded",
+            action,
+            links.len() - failed,
+            links.len()
+        );
+        failures(failed, "links")
+    }
+}
+
+/// Bar drawn on stderr, hidden when stderr is not a terminal like under cron
+fn progress_bar(len: usize, action: &str) -> ProgressBar {
+    let bar = ProgressBar::new(len as u64);
+    bar.set_style(
+        ProgressStyle::with_template("{prefix:>8} [{bar:30}] {pos}/{len} {elapsed} {wide_msg}")
+            .unwrap()
+            .progress_chars("=> "),
+    );
+    bar.set_prefix(action.to_string());
+    bar
+}
+
+/// Message of a service response, an error when its status is not a success
+async fn message(response: impl IntoResponse) -> Result<String, String> {
+    let response = response.into_response();
+    let status = response.status();
+    let body = hyper::body::to_bytes(response.into_body())
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let text = match serde_json::from_slice::<IResult>(&body) {
+        Ok(result) => result.message,
+        Err(_) => String::from_utf8_lossy(&body).to_string(),
+    };
+
+    if status.is_success() {
+        Ok(text)
+    } else {
+        Err(text)
+    }
+}
+
+#[cfg(unix)]
+fn create_symlink(source: &Path, target: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(source, target)
+}
+
+#[cfg(windows)]
+fn create_symlink(source: &Path, target: &Path) -> io::Result<()> {
+    std::os::windows::fs::symlink_file(source, target)
+}
+
+fn failures(failed: usize, items: &str) -> Result<(), String> {
+    if failed == 0 {
+        Ok(())
+    } else {
+        Err(format!("{} {} failed", failed, items))
+    }
+}
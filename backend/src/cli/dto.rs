@@ -0,0 +1,165 @@
+use crate::{storage::dto::FileDisposal, utils::parse_size};
+use clap::{Args, Subcommand};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+/// Commands of the `parsePhoto` binary, `serve` when none is given
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Runs the web server
+    Serve,
+    /// Adds links by their page urls
+    Add {
+        #[arg(required = true)]
+        urls: Vec<String>,
+    },
+    /// Downloads media of links
+    Download(LinksArgs),
+    /// Compares link pages with their directories, links due for a check by default
+    Check(LinksArgs),
+    /// Adds files found in link directories to the database, moving them to the store with the
+    /// content addressed layout, every reachable link by default
+    Scan { ids: Vec<usize> },
+    /// Compares stored mediafiles with their records, every link by default
+    Verify {
+        ids: Vec<usize>,
+        #[arg(long)]
+        redownload_missing: bool,
+        #[arg(long)]
+        redownload_modified: bool,
+    },
+    /// Marks links whose mediafiles are all stored by another link as its duplicates
+    Dedup {
+        /// Only prints the duplicates found
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Moves files of link folders to the content addressed store, needs `STORAGE_LAYOUT=content`
+    MigrateStore {
+        ids: Vec<usize>,
+        /// Only prints the files that would move
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Builds a folder per link with links to its stored files, needs the local storage
+    Views {
+        /// Folder the link folders are created in
+        dir: PathBuf,
+        ids: Vec<usize>,
+        /// Creates symbolic links instead of hard links
+        #[arg(long)]
+        symlink: bool,
+    },
+    /// Moves links to the trash, or removes them with their records with `--permanent`
+    Remove {
+        #[arg(required = true)]
+        ids: Vec<usize>,
+        #[arg(long)]
+        permanent: bool,
+        /// What happens to the files of removed mediafiles: keep, delete or trash
+        #[arg(long, default_value = "keep", requires = "permanent")]
+        files: FileDisposal,
+    },
+    /// Takes links back out of the trash
+    Restore {
+        #[arg(required = true)]
+        ids: Vec<usize>,
+    },
+    /// Removes links and mediafiles trashed longer than `TRASH_RETENTION` for good
+    PurgeTrash {
+        /// Empties the whole trash
+        #[arg(long)]
+        all: bool,
+    },
+    /// Removes records left by removed links and mediafiles no link holds
+    Gc {
+        /// Only prints what would be removed
+        #[arg(long)]
+        dry_run: bool,
+        /// What happens to the files of removed mediafiles: keep, delete or trash
+        #[arg(long, default_value = "trash")]
+        files: FileDisposal,
+    },
+    /// Writes links with their mediafiles and tags as JSON
+    Export {
+        /// File to write, stdout by default
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+    /// Reads links written by `export`, links already stored get missing mediafiles and tags
+    Import { file: PathBuf },
+    /// Creates a user of the web UI and API, the password is read from stdin.
+    /// The first user is always an admin
+    CreateUser {
+        username: String,
+        /// Lets the user see and change the links of every user
+        #[arg(long)]
+        admin: bool,
+        /// Storage quota like 500M or 10G, `USER_QUOTA` applies without it
+        #[arg(long, value_parser = parse_quota)]
+        quota: Option<u64>,
+    },
+}
+
+#[derive(Args, Debug)]
+pub struct LinksArgs {
+    #[arg(conflicts_with = "all")]
+    pub ids: Vec<usize>,
+    /// Every reachable link not fully downloaded for `download`, every reachable link for `check`
+    #[arg(long)]
+    pub all: bool,
+}
+
+/// File written by `export`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Export {
+    pub version: u32,
+    #[serde(rename = "dateCreate")]
+    pub date_create: String,
+    pub links: Vec<ExportLink>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportLink {
+    pub path: String,
+    /// Username of the owner, links without owner have none
+    #[serde(default)]
+    pub owner: Option<String>,
+    pub name: String,
+    #[serde(rename = "isReachable")]
+    pub is_reachable: bool,
+    /// Path of the link this one duplicates
+    #[serde(rename = "duplicatePath")]
+    pub duplicate_path: Option<String>,
+    #[serde(rename = "checkInterval")]
+    pub check_interval: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub mediafiles: Vec<ExportMediafile>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportMediafile {
+    pub name: String,
+    pub path: String,
+    pub hash: String,
+    pub size: usize,
+    #[serde(rename = "sourceUrl")]
+    pub source_url: Option<String>,
+    #[serde(rename = "positionOnPage")]
+    pub position_on_page: Option<usize>,
+    pub etag: Option<String>,
+    #[serde(rename = "lastModified")]
+    pub last_modified: Option<String>,
+    #[serde(rename = "contentType")]
+    pub content_type: Option<String>,
+    #[serde(rename = "downloadedAt")]
+    pub downloaded_at: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+fn parse_quota(value: &str) -> Result<u64, String> {
+    parse_size(value).ok_or_else(|| format!("{} is not a size like 500M or 10G", value))
+}
@@ -0,0 +1,2 @@
+pub mod cli_service;
+pub mod dto;
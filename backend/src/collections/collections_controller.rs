@@ -0,0 +1,95 @@
+use axum::{
+    extract::{Query, State},
+    middleware,
+    response::IntoResponse,
+    routing::{delete, get, post, put},
+    Extension, Json, Router,
+};
+
+use std::sync::Arc;
+
+use super::{
+    collections_service::CollectionsService,
+    dto::{CollectionDto, CollectionLinksDto},
+};
+use crate::{
+    auth::{
+        auth_service::{require_read, require_write, AuthService},
+        dto::Access,
+    },
+    config::Config,
+    links::dto::IdDto,
+};
+
+pub struct CollectionsController {}
+
+impl CollectionsController {
+    pub async fn create(
+        State(service): State<Arc<CollectionsService>>,
+        Json(dto): Json<CollectionDto>,
+    ) -> impl IntoResponse {
+        service.create_one(dto).await
+    }
+
+    pub async fn get_all(
+        State(service): State<Arc<CollectionsService>>,
+        Extension(access): Extension<Access>,
+    ) -> impl IntoResponse {
+        service.get_all(access).await
+    }
+
+    pub async fn update(
+        State(service): State<Arc<CollectionsService>>,
+        Query(query): Query<IdDto>,
+        Json(dto): Json<CollectionDto>,
+    ) -> impl IntoResponse {
+        service.update_one(query.id, dto).await
+    }
+
+    pub async fn remove(
+        State(service): State<Arc<CollectionsService>>,
+        Query(query): Query<IdDto>,
+    ) -> impl IntoResponse {
+        service.remove(query.id).await
+    }
+
+    pub async fn add_links(
+        State(service): State<Arc<CollectionsService>>,
+        Extension(access): Extension<Access>,
+        Json(dto): Json<CollectionLinksDto>,
+    ) -> impl IntoResponse {
+        service.add_links(access, dto).await
+    }
+
+    pub async fn remove_links(
+        State(service): State<Arc<CollectionsService>>,
+        Extension(access): Extension<Access>,
+        Json(dto): Json<CollectionLinksDto>,
+    ) -> impl IntoResponse {
+        service.remove_links(access, dto).await
+    }
+}
+
+pub fn collections_routes(config: Arc<Config>, auth: Arc<AuthService>) -> Router {
+    let read = Router::new()
+        .route("/collections", get(CollectionsController::get_all))
+        .route_layer(middleware::from_fn_with_state(
+            Arc::clone(&auth),
+            require_read,
+        ));
+    let write = Router::new()
+        .route("/collections", post(CollectionsController::create))
+        .route("/collections", put(CollectionsController::update))
+        .route("/collections", delete(CollectionsController::remove))
+        .route("/collections/links", post(CollectionsController::add_links))
+        .route(
+            "/collections/links",
+            delete(CollectionsController::remove_links),
+        )
+        .route_layer(middleware::from_fn_with_state(auth, require_write));
+
+    Router::new()
+        .merge(read)
+        .merge(write)
+        .with_state(Arc::new(CollectionsService::new(&config)))
+}
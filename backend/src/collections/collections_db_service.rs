@@ -0,0 +1,140 @@
+use super::dto::{Collection, CollectionDto};
+use crate::{config::Config, metrics, utils::get_now_time};
+use log::error;
+use rusqlite::{params, Connection, Result, Row};
+
+pub struct CollectionsDbService {
+    db_name: String,
+}
+
+impl CollectionsDbService {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            db_name: config.db_name.clone(),
+        }
+    }
+
+    fn open_connection(&self) -> Result<Connection> {
+        let mut conn = Connection::open(&self.db_name)?;
+        conn.profile(Some(metrics::observe_query));
+        Ok(conn)
+    }
+
+    pub fn create_one(&self, dto: &CollectionDto) -> Result<&str> {
+        let conn = self.open_connection()?;
+        let now = get_now_time();
+
+        match conn.execute(
+            "INSERT INTO collections (name, description, date_create, date_update) VALUES (?, ?, ?, ?)",
+            params![dto.name.trim(), dto.description, now, now],
+        ) {
+            Ok(changes) => {
+                if changes == 1 {
+                    Ok("One collection created")
+                } else {
+                    Ok("No collection created")
+                }
+            }
+            Err(e) => {
+                error!("Error creating collection: {}", e);
+                Err(e)
+            }
+        }
+    }
+
+    pub fn update_one(&self, id: usize, dto: &CollectionDto) -> Result<&str> {
+        let conn = self.open_connection()?;
+
+        let changes = conn.execute(
+            "UPDATE collections SET name = ?, description = ?, date_update = ? WHERE id = ?",
+            params![dto.name.trim(), dto.description, get_now_time(), id],
+        )?;
+
+        Ok(if changes == 1 {
+            "One collection updated"
+        } else {
+            "No collection updated"
+        })
+    }
+
+    /// Collections with their number of links, counting only those of one owner when
+    /// `user_id` is set
+    pub fn get_all(&self, user_id: Option<usize>) -> Result<Vec<Collection>> {
+        let conn = self.open_connection()?;
+        let mut stmt = conn.prepare(
+            "SELECT c.*,
+                (SELECT COUNT(*) FROM collection_links cl JOIN links l ON l.id = cl.link_id
+                    WHERE cl.collection_id = c.id AND (?1 IS NULL OR l.user_id = ?1)) AS links
+            FROM collections c
+            ORDER BY c.name",
+        )?;
+
+        let rows = stmt.query_map([user_id], map_collection)?;
+
+        let result: Result<Vec<_>, _> = rows.collect();
+        result
+    }
+
+    /// Removes the collection, its links stay
+    pub fn remove(&self, id: usize) -> Result<&str> {
+        let mut conn = self.open_connection()?;
+        let tx = conn.transaction()?;
+
+        tx.execute("DELETE FROM collection_links WHERE collection_id = ?", [id])?;
+        let changes = tx.execute("DELETE FROM collections WHERE id = ?", [id])?;
+        tx.commit()?;
+
+        Ok(if changes == 1 {
+            "One collection removed"
+        } else {
+            "No collection removed"
+        })
+    }
+
+    /// Returns the number of links added to the collection
+    pub fn add_links(&self, collection_id: usize, link_ids: &[usize]) -> Result<usize> {
+        let mut conn = self.open_connection()?;
+        let tx = conn.transaction()?;
+        let now = get_now_time();
+        let mut added = 0;
+
+        for link_id in link_ids {
+            added += tx.execute(
+                "INSERT OR IGNORE INTO collection_links (collection_id, link_id, date_create)
+                    SELECT id, ?, ? FROM collections WHERE id = ?",
+                params![link_id, now, collection_id],
+            )?;
+        }
+
+        tx.commit()?;
+        Ok(added)
+    }
+
+    /// Returns the number of links removed from the collection
+    pub fn remove_links(&self, collection_id: usize, link_ids: &[usize]) -> Result<usize> {
+        let mut conn = self.open_connection()?;
+        let tx = conn.transaction()?;
+        let mut removed = 0;
+
+        for link_id in link_ids {
+            removed += tx.execute(
+                "DELETE FROM collection_links WHERE collection_id = ? AND link_id = ?",
+                params![collection_id, link_id],
+            )?;
+        }
+
+        tx.commit()?;
+        Ok(removed)
+    }
+}
+
+fn map_collection(row: &Row) -> Result<Collection> {
+    Ok(Collection {
+        id: row.get("id")?,
+        name: row.get("name")?,
+        description: row.get("description")?,
+        links: row.get("links")?,
+        date_create: row.get("date_create")?,
+        date_update: row.get("date_update")?,
+    })
+}
@@ -0,0 +1,141 @@
+use super::{
+    collections_db_service::CollectionsDbService,
+    dto::{CollectionDto, CollectionLinksDto},
+};
+use crate::{
+    auth::dto::Access,
+    config::Config,
+    links::{dto::IResult, links_db_service::LinksDbService},
+    utils::{error_response, server_error_response, success_response},
+};
+use axum::{http::StatusCode, response::IntoResponse, Json};
+use log::{error, info, warn};
+use std::sync::Arc;
+
+pub struct CollectionsService {
+    collections_db_service: Arc<CollectionsDbService>,
+    links_db_service: Arc<LinksDbService>,
+}
+
+impl CollectionsService {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            collections_db_service: Arc::new(CollectionsDbService::new(config)),
+            links_db_service: Arc::new(LinksDbService::new(config)),
+        }
+    }
+
+    pub async fn create_one(&self, dto: CollectionDto) -> impl IntoResponse {
+        info!("Creating collection {}", &dto.name);
+
+        if dto.name.trim().is_empty() {
+            return Err(error_response(
+                "Collection name is required".to_string(),
+                StatusCode::BAD_REQUEST,
+            ));
+        }
+
+        match self.collections_db_service.create_one(&dto) {
+            Ok(m) => Ok(success_response(m.to_string())),
+            Err(e) => Err(server_error_response(e.to_string())),
+        }
+    }
+
+    /// Every collection, counting only the links of the requesting user unless admin
+    pub async fn get_all(&self, access: Access) -> impl IntoResponse {
+        match self
+            .collections_db_service
+            .get_all((!access.is_admin).then_some(access.user_id))
+        {
+            Ok(collections) => Ok((StatusCode::OK, Json(collections))),
+            Err(e) => {
+                error!("Error getting collections: {}", e);
+                Err(server_error_response(
+                    "Error getting collections".to_string(),
+                ))
+            }
+        }
+    }
+
+    pub async fn update_one(&self, id: usize, dto: CollectionDto) -> impl IntoResponse {
+        info!("Updating collection with id: {}", &id);
+
+        if dto.name.trim().is_empty() {
+            return Err(error_response(
+                "Collection name is required".to_string(),
+                StatusCode::BAD_REQUEST,
+            ));
+        }
+
+        match self.collections_db_service.update_one(id, &dto) {
+            Ok(m) => Ok(success_response(m.to_string())),
+            Err(e) => Err(server_error_response(e.to_string())),
+        }
+    }
+
+    pub async fn remove(&self, id: usize) -> impl IntoResponse {
+        info!("Removing collection with id: {}", &id);
+
+        match self.collections_db_service.remove(id) {
+            Ok(m) => Ok(success_response(m.to_string())),
+            Err(e) => Err(server_error_response(e.to_string())),
+        }
+    }
+
+    pub async fn add_links(&self, access: Access, dto: CollectionLinksDto) -> impl IntoResponse {
+        self.check_owned(access, &dto.link_ids)?;
+
+        match self
+            .collections_db_service
+            .add_links(dto.collection_id, &dto.link_ids)
+        {
+            Ok(count) => Ok(success_response(format!(
+                "{} links added to collection",
+                count
+            ))),
+            Err(e) => Err(server_error_response(e.to_string())),
+        }
+    }
+
+    pub async fn remove_links(&self, access: Access, dto: CollectionLinksDto) -> impl IntoResponse {
+        self.check_owned(access, &dto.link_ids)?;
+
+        match self
+            .collections_db_service
+            .remove_links(dto.collection_id, &dto.link_ids)
+        {
+            Ok(count) => Ok(success_response(format!(
+                "{} links removed from collection",
+                count
+            ))),
+            Err(e) => Err(server_error_response(e.to_string())),
+        }
+    }
+
+    /// Fails with 404 on the first link the requesting user does not own
+    fn check_owned(
+        &self,
+        access: Access,
+        link_ids: &[usize],
+    ) -> Result<(), (StatusCode, Json<IResult>)> {
+        if access.is_admin {
+            return Ok(());
+        }
+
+        for &id in link_ids {
+            match self.links_db_service.get_one(id) {
+                Ok(Some(link)) if access.owns(link.user_id) => {}
+                Ok(_) => {
+                    warn!("Link with id {} not found", id);
+                    return Err(error_response(
+                        "Link not found".to_string(),
+                        StatusCode::NOT_FOUND,
+                    ));
+                }
+                Err(e) => return Err(server_error_response(e.to_string())),
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,28 @@
+use serde::{Deserialize, Serialize};
+
+/// Named group of links, links of any site can share a collection
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Collection {
+    pub id: usize,
+    pub name: String,
+    pub description: Option<String>,
+    pub links: usize,
+    #[serde(rename = "dateCreate")]
+    pub date_create: String,
+    #[serde(rename = "dateUpdate")]
+    pub date_update: String,
+}
+
+#[derive(Deserialize)]
+pub struct CollectionDto {
+    pub name: String,
+    pub description: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct CollectionLinksDto {
+    #[serde(rename = "collectionId")]
+    pub collection_id: usize,
+    #[serde(rename = "linkIds")]
+    pub link_ids: Vec<usize>,
+}
@@ -0,0 +1,4 @@
+pub mod collections_controller;
+pub mod collections_db_service;
+pub mod collections_service;
+pub mod dto;
@@ -1,85 +1,676 @@
+use crate::{
+    cli::dto::Command,
+    links::dto::ErrorClass,
+    rewrite::{self, CompiledRule, RewriteRule},
+    utils::{parse_interval, parse_size},
+};
+use chrono::Duration;
+use clap::Parser;
 use dotenvy::dotenv;
-use env_logger::Builder;
-use log::LevelFilter;
-use once_cell::sync::Lazy;
-use std::io::Write;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
 use std::{
-    env,
-    fs::{self, OpenOptions},
-    path::Path,
+    collections::HashMap,
+    env, fs,
+    net::IpAddr,
+    path::{Path, PathBuf},
+    str::FromStr,
     sync::Once,
 };
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::{
+    fmt::MakeWriter, layer::SubscriberExt, util::SubscriberInitExt, EnvFilter, Layer, Registry,
+};
+
+/// Config file read when `--config` and `CONFIG_FILE` are not set and the file exists
+const DEFAULT_CONFIG_FILE: &str = "config.toml";
+
+/// Every setting, named like its environment variable. In the config file and in `--set`
+/// the names are case insensitive, so `port = 8080` and `--set max_pages=5` work
+const SETTINGS: &[&str] = &[
+    "BIND_ADDRESS",
+    "PORT",
+    "DB_NAME",
+    "STORAGE_ROOT",
+    "STORAGE_BACKEND",
+    "STORAGE_LAYOUT",
+    "S3_BUCKET",
+    "S3_REGION",
+    "S3_ENDPOINT",
+    "S3_PREFIX",
+    "S3_ACCESS_KEY_ID",
+    "S3_SECRET_ACCESS_KEY",
+    "S3_PATH_STYLE",
+    "EXTENSIONS",
+    "MAX_CONCURRENT_DOWNLOADS",
+    "MAX_FILE_SIZE",
+    "MAX_PAGES",
+    "REWRITE_RULES",
+    "SCHEDULER_ENABLED",
+    "CHECK_INTERVAL",
+    "SCHEDULER_TICK",
+    "UNREACHABLE_AFTER_FAILURES",
+    "PERMANENT_ERRORS",
+    "USER_AGENT",
+    "HTTP_HEADERS",
+    "DOMAIN_HEADERS",
+    "COOKIES_FILE",
+    "PROXY",
+    "CONNECT_TIMEOUT",
+    "READ_TIMEOUT",
+    "MAX_REDIRECTS",
+    "STATS_CACHE_TTL",
+    "AUTH_ENABLED",
+    "SESSION_TTL",
+    "SESSION_COOKIE_SECURE",
+    "USER_QUOTA",
+    "GC_INTERVAL",
+    "TRASH_RETENTION",
+    "WATCH_ENABLED",
+    "WATCH_DEBOUNCE",
+    "LOG_DIR",
+    "LOG_FILE",
+    "LOG_FORMAT",
+    "LOG_ROTATION",
+    "LOG_MAX_FILES",
+    "LOG_STDOUT",
+];
+
+const DEFAULT_USER_AGENT: &str = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/114.0.0.0 Safari/537.36";
+
+/// Command line options, they override the config file and the environment
+#[derive(Parser, Debug, Default)]
+#[command(version, about = "Downloads and keeps track of photo galleries")]
+pub struct Cli {
+    /// TOML config file, `config.toml` is used when present
+    #[arg(long, env = "CONFIG_FILE", global = true)]
+    pub config: Option<PathBuf>,
+    #[arg(long, global = true)]
+    pub bind_address: Option<String>,
+    #[arg(long, global = true)]
+    pub port: Option<String>,
+    /// SQLite database file
+    #[arg(long, global = true)]
+    pub db_name: Option<String>,
+    /// Directory downloaded files are stored in
+    #[arg(long, global = true)]
+    pub storage_root: Option<String>,
+    /// Any other setting as `NAME=value`, like `--set max_pages=5`
+    #[arg(long = "set", value_name = "NAME=VALUE", global = true)]
+    pub settings: Vec<String>,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
 
-// Lazy-initialized static variables for configuration
-pub static PORT: Lazy<u16> = Lazy::new(|| {
-    env::var("PORT")
-        .expect("PORT must be set")
-        .parse()
-        .expect("PORT must be a number")
-});
-
-pub static DB_NAME: Lazy<String> = Lazy::new(|| env::var("DB_NAME").expect("DB_NAME must be set"));
-
-pub static ROOT_URL: Lazy<String> =
-    Lazy::new(|| env::var("ROOT_URL").expect("ROOT_URL must be set"));
-
-pub static EXTENSIONS: Lazy<Vec<String>> = Lazy::new(|| {
-    env::var("EXTENSIONS")
-        .expect("EXTENSIONS must be set")
-        .split(',')
-        .map(|ext| ext.trim().to_string())
-        .collect()
-});
-
-/// Initializes the configuration by loading environment variables
-pub fn init() {
-    dotenv().ok();
-
-    Lazy::force(&PORT);
-    Lazy::force(&DB_NAME);
-    Lazy::force(&ROOT_URL);
-    Lazy::force(&EXTENSIONS);
+/// Settings of the server, read from the config file, then the environment
+/// (and `.env`), then the command line, later sources override earlier ones
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub bind_address: IpAddr,
+    pub port: u16,
+    pub db_name: String,
+    /// Directory holding a folder of downloaded files per link with the `local` storage
+    pub storage_root: PathBuf,
+    /// `local` or `s3`
+    pub storage_backend: String,
+    /// `links` keeps a folder per link, `content` keeps files once by their hash under `store/`
+    pub storage_layout: String,
+    pub s3_bucket: Option<String>,
+    pub s3_region: String,
+    /// Url of an S3 compatible server like MinIO, AWS when not set
+    pub s3_endpoint: Option<String>,
+    /// Prefix of every object name in the bucket
+    pub s3_prefix: Option<String>,
+    /// Keys of the bucket, the default AWS credential chain is used when not set
+    pub s3_access_key_id: Option<String>,
+    pub s3_secret_access_key: Option<String>,
+    /// Addresses the bucket in the path instead of the host name, most S3 compatible servers need it
+    pub s3_path_style: bool,
+    /// Media extensions downloaded unless a site sets its own
+    pub extensions: Vec<String>,
+    /// Downloads running at once over all links, each site may limit its own further
+    pub max_concurrent_downloads: Option<usize>,
+    /// Largest response body read in bytes, larger media and pages fail
+    pub max_file_size: Option<usize>,
+    /// Maximum number of pages followed for one gallery
+    pub max_pages: usize,
+    /// Rewrite rules applied to media urls of every site after the site's own rules
+    pub rewrite_rules: Vec<CompiledRule>,
+    pub scheduler_enabled: bool,
+    /// How often a link is re-checked unless it has its own interval
+    pub check_interval: Duration,
+    /// How often the scheduler looks for links that are due for a check
+    pub scheduler_tick: Duration,
+    pub unreachable_after_failures: usize,
+    /// Error classes of a page request that make a link unreachable at once,
+    /// other failures only count towards `unreachable_after_failures`
+    pub permanent_errors: Vec<String>,
+    pub user_agent: String,
+    /// Headers sent with every request
+    pub http_headers: HashMap<String, String>,
+    /// Headers per host, a host also matches its subdomains
+    pub domain_headers: HashMap<String, HashMap<String, String>>,
+    /// Path to cookies exported in the Netscape cookies.txt format
+    pub cookies_file: Option<String>,
+    /// HTTP or SOCKS proxy url like `socks5://127.0.0.1:1080`
+    pub proxy: Option<String>,
+    pub connect_timeout: Duration,
+    /// Longest wait for the next piece of a response body
+    pub read_timeout: Duration,
+    pub max_redirects: usize,
+    /// How long `GET /stats` serves the last computed stats
+    pub stats_cache_ttl: Duration,
+    /// Requires a session or an API token for the links and mediafiles routes
+    pub auth_enabled: bool,
+    /// How long a login of the web UI lasts
+    pub session_ttl: Duration,
+    /// Sends the session cookie over HTTPS only
+    pub session_cookie_secure: bool,
+    /// Storage quota in bytes of users without their own quota
+    pub user_quota: Option<u64>,
+    /// How often orphaned records and mediafiles are collected, never when unset
+    pub gc_interval: Option<Duration>,
+    /// How long trashed links and mediafiles are kept before they are removed for good
+    pub trash_retention: Duration,
+    /// Keeps the records in sync with changes made to the storage root by hand
+    pub watch_enabled: bool,
+    /// How long a changed path must stay quiet before the watcher handles it
+    pub watch_debounce: Duration,
+    pub log_dir: String,
+    /// Name of the log file, rotated files get the date appended
+    pub log_file: String,
+    /// `text`, `pretty` or `json`
+    pub log_format: String,
+    /// `daily`, `hourly` or `never`
+    pub log_rotation: String,
+    /// Number of rotated log files kept, older ones are removed
+    pub log_max_files: usize,
+    /// Writes logs to stdout as well as to the log file
+    pub log_stdout: bool,
+}
+
+impl Config {
+    /// Loads `.env`, the config file, the environment and the command line,
+    /// every invalid or missing setting is reported at once
+    pub fn load(cli: &Cli) -> Result<Self, Vec<String>> {
+        dotenv().ok();
+
+        let mut sources = Sources::default();
+        let mut errors = Vec::new();
+
+        let config_file = cli
+            .config
+            .clone()
+            .or_else(|| Some(PathBuf::from(DEFAULT_CONFIG_FILE)).filter(|path| path.exists()));
+        if let Some(path) = config_file {
+            if let Err(e) = sources.read_file(&path) {
+                errors.push(e);
+            }
+        }
+
+        for name in SETTINGS {
+            if let Ok(value) = env::var(name) {
+                sources.set(name, Value::String(value), "environment");
+            }
+        }
+
+        Self::read(sources, errors, cli)
+    }
+
+    /// Settings of the command line only, `.env`, the config file and the environment are
+    /// not read
+    #[cfg(test)]
+    pub fn from_cli(cli: &Cli) -> Result<Self, Vec<String>> {
+        Self::read(Sources::default(), Vec::new(), cli)
+    }
+
+    /// Adds the command line to `sources` and reads every setting from them
+    fn read(mut sources: Sources, mut errors: Vec<String>, cli: &Cli) -> Result<Self, Vec<String>> {
+        let flags = [
+            ("BIND_ADDRESS", &cli.bind_address),
+            ("PORT", &cli.port),
+            ("DB_NAME", &cli.db_name),
+            ("STORAGE_ROOT", &cli.storage_root),
+        ];
+        for (name, value) in flags {
+            if let Some(value) = value {
+                sources.set(name, Value::String(value.clone()), "command line");
+            }
+        }
+        for setting in &cli.settings {
+            match setting.split_once('=') {
+                Some((name, value)) if is_setting(name) => sources.set(
+                    &name.trim().to_uppercase(),
+                    Value::String(value.to_string()),
+                    "command line",
+                ),
+                Some((name, _)) => errors.push(format!("Unknown setting {} in --set", name)),
+                None => errors.push(format!("--set {} must look like NAME=value", setting)),
+            }
+        }
+
+        let mut reader = Reader {
+            sources,
+            errors: &mut errors,
+        };
+        let config = Config {
+            bind_address: reader.parsed("BIND_ADDRESS", IpAddr::from([127, 0, 0, 1])),
+            port: reader.required("PORT"),
+            db_name: reader.text("DB_NAME", None),
+            storage_root: PathBuf::from(reader.text("STORAGE_ROOT", Some("result"))),
+            storage_backend: reader.text("STORAGE_BACKEND", Some("local")),
+            storage_layout: reader.text("STORAGE_LAYOUT", Some("links")),
+            s3_bucket: reader.optional("S3_BUCKET"),
+            s3_region: reader.text("S3_REGION", Some("us-east-1")),
+            s3_endpoint: reader.optional("S3_ENDPOINT"),
+            s3_prefix: reader.optional("S3_PREFIX"),
+            s3_access_key_id: reader.optional("S3_ACCESS_KEY_ID"),
+            s3_secret_access_key: reader.optional("S3_SECRET_ACCESS_KEY"),
+            s3_path_style: reader.flag("S3_PATH_STYLE"),
+            extensions: reader.list("EXTENSIONS", None),
+            max_concurrent_downloads: reader.optional("MAX_CONCURRENT_DOWNLOADS"),
+            max_file_size: reader.optional("MAX_FILE_SIZE"),
+            max_pages: reader.parsed("MAX_PAGES", 20),
+            rewrite_rules: reader.rewrite_rules("REWRITE_RULES"),
+            scheduler_enabled: reader.flag("SCHEDULER_ENABLED"),
+            check_interval: reader.interval("CHECK_INTERVAL", "1d"),
+            scheduler_tick: reader.interval("SCHEDULER_TICK", "1m"),
+            unreachable_after_failures: reader.parsed("UNREACHABLE_AFTER_FAILURES", 3),
+            permanent_errors: reader.list("PERMANENT_ERRORS", Some("not_found,gone")),
+            user_agent: reader.text("USER_AGENT", Some(DEFAULT_USER_AGENT)),
+            http_headers: reader.json("HTTP_HEADERS"),
+            domain_headers: reader
+                .json::<HashMap<String, HashMap<String, String>>>("DOMAIN_HEADERS")
+                .into_iter()
+                .map(|(host, headers)| (host.to_lowercase(), headers))
+                .collect(),
+            cookies_file: reader.optional("COOKIES_FILE"),
+            proxy: reader.optional("PROXY"),
+            connect_timeout: reader.interval("CONNECT_TIMEOUT", "10s"),
+            read_timeout: reader.interval("READ_TIMEOUT", "30s"),
+            max_redirects: reader.parsed("MAX_REDIRECTS", 10),
+            stats_cache_ttl: reader.interval("STATS_CACHE_TTL", "1m"),
+            auth_enabled: reader.flag("AUTH_ENABLED"),
+            session_ttl: reader.interval("SESSION_TTL", "30d"),
+            session_cookie_secure: reader.flag("SESSION_COOKIE_SECURE"),
+            user_quota: reader.size("USER_QUOTA"),
+            gc_interval: reader.optional_interval("GC_INTERVAL"),
+            trash_retention: reader.interval("TRASH_RETENTION", "30d"),
+            watch_enabled: reader.flag("WATCH_ENABLED"),
+            watch_debounce: reader.interval("WATCH_DEBOUNCE", "2s"),
+            log_dir: reader.text("LOG_DIR", Some("logs")),
+            log_file: reader.text("LOG_FILE", Some("server.log")),
+            log_format: reader.text("LOG_FORMAT", Some("text")),
+            log_rotation: reader.text("LOG_ROTATION", Some("daily")),
+            log_max_files: reader.parsed("LOG_MAX_FILES", 14),
+            log_stdout: reader.flag("LOG_STDOUT"),
+        };
+
+        errors.extend(config.validate());
+
+        if errors.is_empty() {
+            Ok(config)
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Checks settings that parsed but do not make sense
+    fn validate(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+
+        if self.storage_root.as_os_str().is_empty() {
+            errors.push("STORAGE_ROOT must not be empty".to_string());
+        }
+        match self.storage_backend.as_str() {
+            "local" => {}
+            "s3" => {
+                if self.s3_bucket.is_none() {
+                    errors.push("S3_BUCKET must be set with STORAGE_BACKEND=s3".to_string());
+                }
+                if self.s3_access_key_id.is_some() != self.s3_secret_access_key.is_some() {
+                    errors.push(
+                        "S3_ACCESS_KEY_ID and S3_SECRET_ACCESS_KEY must be set together"
+                            .to_string(),
+                    );
+                }
+            }
+            _ => errors.push("STORAGE_BACKEND must be local or s3".to_string()),
+        }
+        if self.watch_enabled && self.storage_backend != "local" {
+            errors.push("WATCH_ENABLED needs STORAGE_BACKEND=local".to_string());
+        }
+        if !["links", "content"].contains(&self.storage_layout.as_str()) {
+            errors.push("STORAGE_LAYOUT must be links or content".to_string());
+        }
+        if self.max_concurrent_downloads == Some(0) {
+            errors.push("MAX_CONCURRENT_DOWNLOADS must be greater than 0".to_string());
+        }
+        if self.max_file_size == Some(0) {
+            errors.push("MAX_FILE_SIZE must be greater than 0".to_string());
+        }
+        if self.max_pages == 0 {
+            errors.push("MAX_PAGES must be greater than 0".to_string());
+        }
+        if self.unreachable_after_failures == 0 {
+            errors.push("UNREACHABLE_AFTER_FAILURES must be greater than 0".to_string());
+        }
+        for class in &self.permanent_errors {
+            if ErrorClass::parse(class).as_str() != class {
+                errors.push(format!("PERMANENT_ERRORS: unknown error class {}", class));
+            }
+        }
+        if !["text", "pretty", "json"].contains(&self.log_format.as_str()) {
+            errors.push("LOG_FORMAT must be text, pretty or json".to_string());
+        }
+        if !["daily", "hourly", "never"].contains(&self.log_rotation.as_str()) {
+            errors.push("LOG_ROTATION must be daily, hourly or never".to_string());
+        }
+
+        errors
+    }
+}
+
+fn is_setting(name: &str) -> bool {
+    SETTINGS.contains(&name.trim().to_uppercase().as_str())
+}
+
+/// Raw setting values with the name of the source they came from
+#[derive(Default)]
+struct Sources {
+    values: HashMap<String, (Value, String)>,
+}
+
+impl Sources {
+    fn set(&mut self, name: &str, value: Value, source: &str) {
+        self.values
+            .insert(name.to_string(), (value, source.to_string()));
+    }
+
+    fn read_file(&mut self, path: &Path) -> Result<(), String> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read config file {}: {}", path.display(), e))?;
+        let table: toml::Table = toml::from_str(&text)
+            .map_err(|e| format!("Config file {} is not valid: {}", path.display(), e))?;
+
+        let source = path.display().to_string();
+        let mut unknown = Vec::new();
+        for (name, value) in table {
+            if !is_setting(&name) {
+                unknown.push(name);
+                continue;
+            }
+            let value = serde_json::to_value(value).map_err(|e| format!("{}: {}", name, e))?;
+            self.set(&name.to_uppercase(), value, &source);
+        }
+
+        if unknown.is_empty() {
+            Ok(())
+        } else {
+            Err(format!(
+                "Unknown settings in {}: {}",
+                source,
+                unknown.join(", ")
+            ))
+        }
+    }
+}
+
+/// Reads typed settings from the sources, collecting errors instead of stopping at the first
+struct Reader<'a> {
+    sources: Sources,
+    errors: &'a mut Vec<String>,
+}
+
+impl Reader<'_> {
+    /// The value as text with its source, numbers, booleans and arrays of the config file included
+    fn raw(&self, name: &str) -> Option<(String, String)> {
+        let (value, source) = self.sources.values.get(name)?;
+        let text = match value {
+            Value::String(text) => text.clone(),
+            Value::Array(values) => values
+                .iter()
+                .map(|value| match value {
+                    Value::String(text) => text.clone(),
+                    value => value.to_string(),
+                })
+                .collect::<Vec<_>>()
+                .join(","),
+            value => value.to_string(),
+        };
+        Some((text, source.clone()))
+    }
+
+    fn text(&mut self, name: &str, default: Option<&str>) -> String {
+        match self
+            .raw(name)
+            .map(|(text, _)| text)
+            .or(default.map(str::to_string))
+        {
+            Some(text) => text,
+            None => {
+                self.errors.push(format!("{} must be set", name));
+                String::new()
+            }
+        }
+    }
+
+    fn optional<T: FromStr>(&mut self, name: &str) -> Option<T> {
+        let (text, source) = self.raw(name)?;
+        match text.trim().parse() {
+            Ok(value) => Some(value),
+            Err(_) => {
+                self.errors
+                    .push(format!("{} from {} is not valid: {}", name, source, text));
+                None
+            }
+        }
+    }
+
+    fn required<T: FromStr + Default>(&mut self, name: &str) -> T {
+        if self.raw(name).is_none() {
+            self.errors.push(format!("{} must be set", name));
+        }
+        self.optional(name).unwrap_or_default()
+    }
+
+    fn parsed<T: FromStr>(&mut self, name: &str, default: T) -> T {
+        self.optional(name).unwrap_or(default)
+    }
+
+    fn flag(&mut self, name: &str) -> bool {
+        let Some((text, source)) = self.raw(name) else {
+            return false;
+        };
+
+        match text.trim().to_lowercase().as_str() {
+            "true" | "1" => true,
+            "false" | "0" => false,
+            _ => {
+                self.errors.push(format!(
+                    "{} from {} must be true, false, 1 or 0: {}",
+                    name, source, text
+                ));
+                false
+            }
+        }
+    }
+
+    /// Comma separated list, or an array in the config file
+    fn list(&mut self, name: &str, default: Option<&str>) -> Vec<String> {
+        self.text(name, default)
+            .split(',')
+            .map(|item| item.trim().to_string())
+            .filter(|item| !item.is_empty())
+            .collect()
+    }
+
+    fn interval(&mut self, name: &str, default: &str) -> Duration {
+        let (text, source) = self
+            .raw(name)
+            .unwrap_or((default.to_string(), "defaults".to_string()));
+
+        parse_interval(&text).unwrap_or_else(|| {
+            self.errors.push(format!(
+                "{} from {} must be an interval like 30m, 6h or 1d of at most 100 years: {}",
+                name, source, text
+            ));
+            Duration::zero()
+        })
+    }
+
+    fn optional_interval(&mut self, name: &str) -> Option<Duration> {
+        self.raw(name)?;
+        Some(self.interval(name, ""))
+    }
+
+    fn size(&mut self, name: &str) -> Option<u64> {
+        let (text, source) = self.raw(name)?;
+
+        let size = parse_size(&text);
+        if size.is_none() {
+            self.errors.push(format!(
+                "{} from {} must be a size like 500M or 10G: {}",
+                name, source, text
+            ));
+        }
+        size
+    }
+
+    /// Rewrite rules compiled once, an invalid pattern is a configuration error
+    fn rewrite_rules(&mut self, name: &str) -> Vec<CompiledRule> {
+        let rules: Vec<RewriteRule> = self.json(name);
+        rewrite::compile(&rules).unwrap_or_else(|e| {
+            self.errors.push(format!("{}: {}", name, e));
+            Vec::new()
+        })
+    }
+
+    /// JSON text, or a table or array in the config file
+    fn json<T: DeserializeOwned + Default>(&mut self, name: &str) -> T {
+        let (value, source) = match self.sources.values.get(name) {
+            Some((value, source)) => (value.clone(), source.clone()),
+            None => return T::default(),
+        };
+
+        let parsed = match value {
+            Value::String(text) => serde_json::from_str(&text),
+            value => serde_json::from_value(value),
+        };
+        parsed.unwrap_or_else(|e| {
+            self.errors
+                .push(format!("{} from {} is not valid: {}", name, source, e));
+            T::default()
+        })
+    }
 }
 
 static INIT: Once = Once::new();
 
-pub fn init_log() {
+/// Sets up `tracing` with the `RUST_LOG` filter, a rotated log file and optionally stdout.
+/// Records of the `log` macros go through `tracing` as well and carry the current span
+pub fn init_log(config: &Config) {
     INIT.call_once(|| {
-        // Укажите путь к файлу логов
-        let log_file_path = "logs/server.log";
+        fs::create_dir_all(&config.log_dir).expect("Failed to create log directory");
 
-        // Если файла нет, создайте его и необходимые директории
-        if !Path::new(log_file_path).exists() {
-            if let Some(parent) = Path::new(log_file_path).parent() {
-                fs::create_dir_all(parent).expect("Failed to create log directory");
-            }
-            OpenOptions::new()
-                .write(true)
-                .create(true)
-                .open(log_file_path)
-                .expect("Failed to create log file");
-        }
-
-        // Настройка `env_logger` для записи логов в файл
-        let file = OpenOptions::new()
-            .append(true)
-            .open(log_file_path)
-            .expect("Failed to open log file");
-
-        Builder::new()
-            .format(move |buf, record| {
-                writeln!(
-                    buf,
-                    "{} [{}] - {}",
-                    chrono::Local::now().format("%Y-%m-%d %H:%M:%S"),
-                    record.level(),
-                    record.args()
-                )
+        let file = RollingFileAppender::builder()
+            .rotation(match config.log_rotation.as_str() {
+                "hourly" => Rotation::HOURLY,
+                "never" => Rotation::NEVER,
+                _ => Rotation::DAILY,
             })
-            .filter(None, LevelFilter::Info)
-            .target(env_logger::Target::Pipe(Box::new(file)))
+            .filename_prefix(&config.log_file)
+            .max_log_files(config.log_max_files)
+            .build(&config.log_dir)
+            .expect("Failed to create log file");
+
+        let mut layers = vec![log_layer(file, false, &config.log_format)];
+        if config.log_stdout {
+            layers.push(log_layer(std::io::stdout, true, &config.log_format));
+        }
+
+        let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+
+        tracing_subscriber::registry()
+            .with(layers)
+            .with(filter)
             .init();
 
-        log::info!("Logger initialized and writing to {}", log_file_path);
+        tracing::info!(
+            "Logger initialized and writing to {}/{}",
+            config.log_dir,
+            config.log_file
+        );
     });
 }
+
+fn log_layer<W>(writer: W, ansi: bool, format: &str) -> Box<dyn Layer<Registry> + Send + Sync>
+where
+    W: for<'a> MakeWriter<'a> + Send + Sync + 'static,
+{
+    let layer = tracing_subscriber::fmt::layer()
+        .with_writer(writer)
+        .with_ansi(ansi);
+
+    match format {
+        "json" => layer
+            .json()
+            .with_current_span(true)
+            .with_span_list(true)
+            .boxed(),
+        "pretty" => layer.pretty().boxed(),
+        _ => layer.boxed(),
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    /// Config of the command line only, with the required settings and `settings` as `--set`
+    pub fn config(settings: &[&str]) -> Config {
+        Config::from_cli(&Cli {
+            port: Some("0".to_string()),
+            db_name: Some("test.db".to_string()),
+            settings: ["extensions=jpg"]
+                .iter()
+                .chain(settings)
+                .map(|setting| setting.to_string())
+                .collect(),
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn flags_accept_true_false_1_and_0() {
+        let cli = |value: &str| Cli {
+            port: Some("0".to_string()),
+            db_name: Some("test.db".to_string()),
+            settings: vec![
+                "extensions=jpg".to_string(),
+                format!("s3_path_style={}", value),
+            ],
+            ..Default::default()
+        };
+
+        for (value, expected) in [("true", true), ("1", true), ("false", false), ("0", false)] {
+            assert_eq!(
+                Config::from_cli(&cli(value)).unwrap().s3_path_style,
+                expected,
+                "{}",
+                value
+            );
+        }
+        for value in ["yes", "ture"] {
+            let errors = Config::from_cli(&cli(value)).unwrap_err();
+            assert_eq!(
+                errors,
+                [format!(
+                    "S3_PATH_STYLE from command line must be true, false, 1 or 0: {}",
+                    value
+                )]
+            );
+        }
+    }
+}
@@ -0,0 +1,149 @@
+use super::dto::{Crawl, CrawlItem, CrawlItemStatus};
+use crate::{config::Config, metrics, utils::get_now_time};
+use log::error;
+use rusqlite::{params, Connection, OptionalExtension, Result};
+
+pub struct CrawlsDbService {
+    db_name: String,
+}
+
+impl CrawlsDbService {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            db_name: config.db_name.clone(),
+        }
+    }
+
+    fn open_connection(&self) -> Result<Connection> {
+        let mut conn = Connection::open(&self.db_name)?;
+        conn.profile(Some(metrics::observe_query));
+        Ok(conn)
+    }
+
+    /// Stores a crawl snapshot together with all of its items
+    pub fn create_one(
+        &self,
+        link_id: usize,
+        page_hash: &str,
+        items: &[CrawlItem],
+    ) -> Result<usize> {
+        let mut conn = self.open_connection()?;
+        let tx = conn.transaction()?;
+
+        let count = |status: CrawlItemStatus| items.iter().filter(|i| i.status == status).count();
+        let media_count = items
+            .iter()
+            .filter(|i| i.status != CrawlItemStatus::Removed)
+            .count();
+
+        if let Err(e) = tx.execute(
+            "INSERT INTO crawls (link_id, page_hash, media_count, added, moved, removed, date_create)
+                VALUES (?, ?, ?, ?, ?, ?, ?)",
+            params![
+                link_id,
+                page_hash,
+                media_count,
+                count(CrawlItemStatus::New),
+                count(CrawlItemStatus::Moved),
+                count(CrawlItemStatus::Removed),
+                get_now_time()
+            ],
+        ) {
+            error!("Error creating crawl: {}", e);
+            return Err(e);
+        }
+
+        let crawl_id = tx.last_insert_rowid() as usize;
+        {
+            let mut stmt = tx.prepare(
+                "INSERT INTO crawl_items (crawl_id, url, position, status) VALUES (?, ?, ?, ?)",
+            )?;
+            for item in items {
+                stmt.execute(params![
+                    crawl_id,
+                    item.url,
+                    item.position,
+                    item.status.as_str()
+                ])?;
+            }
+        }
+
+        tx.commit()?;
+        Ok(crawl_id)
+    }
+
+    /// Returns the id, page hash and present items of the latest crawl of a link
+    pub fn get_latest(&self, link_id: usize) -> Result<Option<(usize, String, Vec<CrawlItem>)>> {
+        let conn = self.open_connection()?;
+
+        let crawl: Option<(usize, String)> = conn
+            .query_row(
+                "SELECT id, page_hash FROM crawls WHERE link_id = ? ORDER BY id DESC LIMIT 1",
+                [link_id],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?;
+
+        match crawl {
+            Some((crawl_id, page_hash)) => {
+                let items = get_items(&conn, crawl_id, false)?;
+                Ok(Some((crawl_id, page_hash, items)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    pub fn get_all_by_link_id(&self, link_id: usize) -> Result<Vec<Crawl>> {
+        let conn = self.open_connection()?;
+        let mut stmt = conn.prepare(
+            "SELECT id, link_id, page_hash, media_count, added, moved, removed, date_create
+                FROM crawls
+                WHERE link_id = ?
+                ORDER BY id DESC",
+        )?;
+
+        let rows = stmt.query_map([link_id], |row| {
+            Ok(Crawl {
+                id: row.get(0)?,
+                link_id: row.get(1)?,
+                page_hash: row.get(2)?,
+                media_count: row.get(3)?,
+                added: row.get(4)?,
+                moved: row.get(5)?,
+                removed: row.get(6)?,
+                date_create: row.get(7)?,
+                changes: Vec::new(),
+            })
+        })?;
+
+        let mut crawls: Vec<Crawl> = rows.collect::<Result<Vec<_>, _>>()?;
+        for crawl in crawls.iter_mut() {
+            crawl.changes = get_items(&conn, crawl.id, true)?
+                .into_iter()
+                .filter(|item| item.status != CrawlItemStatus::Unchanged)
+                .collect();
+        }
+
+        Ok(crawls)
+    }
+}
+
+fn get_items(conn: &Connection, crawl_id: usize, with_removed: bool) -> Result<Vec<CrawlItem>> {
+    let mut stmt = conn.prepare(
+        "SELECT url, position, status FROM crawl_items WHERE crawl_id = ? ORDER BY position",
+    )?;
+
+    let rows = stmt.query_map([crawl_id], |row| {
+        Ok(CrawlItem {
+            url: row.get(0)?,
+            position: row.get(1)?,
+            status: CrawlItemStatus::parse(&row.get::<_, String>(2)?),
+        })
+    })?;
+
+    let items: Vec<CrawlItem> = rows.collect::<Result<Vec<_>, _>>()?;
+    Ok(items
+        .into_iter()
+        .filter(|item| with_removed || item.status != CrawlItemStatus::Removed)
+        .collect())
+}
@@ -0,0 +1,184 @@
+use super::{
+    crawls_db_service::CrawlsDbService,
+    dto::{Crawl, CrawlDiff, CrawlItem, CrawlItemStatus},
+};
+use crate::config::Config;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+
+pub struct CrawlsService {
+    crawls_db_service: Arc<CrawlsDbService>,
+}
+
+impl CrawlsService {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            crawls_db_service: Arc::new(CrawlsDbService::new(config)),
+        }
+    }
+
+    /// Compares `media_urls` with the previous crawl of the link and stores the new snapshot
+    pub async fn record(
+        &self,
+        link_id: usize,
+        page: &str,
+        media_urls: &[String],
+    ) -> Result<CrawlDiff, String> {
+        let previous = self
+            .crawls_db_service
+            .get_latest(link_id)
+            .map_err(|e| e.to_string())?;
+        let page_hash = hash_page(page);
+
+        // An unchanged page with the same media is not stored again
+        if let Some((crawl_id, previous_hash, previous_items)) = &previous {
+            if *previous_hash == page_hash
+                && unique(media_urls).eq(previous_items.iter().map(|item| item.url.as_str()))
+            {
+                return Ok(CrawlDiff {
+                    crawl_id: *crawl_id,
+                    has_previous: true,
+                    added: Vec::new(),
+                    removed: Vec::new(),
+                    moved: 0,
+                });
+            }
+        }
+
+        let has_previous = previous.is_some();
+        let previous_items = previous.map(|(_, _, items)| items).unwrap_or_default();
+
+        let items = diff_media(&previous_items, media_urls);
+        let crawl_id = self
+            .crawls_db_service
+            .create_one(link_id, &page_hash, &items)
+            .map_err(|e| e.to_string())?;
+
+        let urls_with = |status: CrawlItemStatus| -> Vec<String> {
+            items
+                .iter()
+                .filter(|item| item.status == status)
+                .map(|item| item.url.clone())
+                .collect()
+        };
+
+        Ok(CrawlDiff {
+            crawl_id,
+            has_previous,
+            added: urls_with(CrawlItemStatus::New),
+            removed: urls_with(CrawlItemStatus::Removed),
+            moved: urls_with(CrawlItemStatus::Moved).len(),
+        })
+    }
+
+    pub async fn get_all_by_link_id(&self, link_id: usize) -> Result<Vec<Crawl>, String> {
+        self.crawls_db_service
+            .get_all_by_link_id(link_id)
+            .map_err(|e| e.to_string())
+    }
+}
+
+/// Builds the crawl items for `media_urls`, marking each one against `previous`.
+/// Only media whose order relative to the other kept media changed is `Moved`, so media
+/// inserted or removed around it do not move it. Urls missing from the page are appended
+/// with their previous position and `Removed` status.
+pub fn diff_media(previous: &[CrawlItem], media_urls: &[String]) -> Vec<CrawlItem> {
+    let previous_positions: HashMap<&str, usize> = previous
+        .iter()
+        .map(|item| (item.url.as_str(), item.position))
+        .collect();
+    let urls: Vec<&str> = unique(media_urls).collect();
+
+    // Kept media in their current order with their previous positions, the longest run
+    // still in the previous order stays in place
+    let kept: Vec<(usize, usize)> = urls
+        .iter()
+        .enumerate()
+        .filter_map(|(position, url)| {
+            previous_positions
+                .get(url)
+                .map(|&previous_position| (position, previous_position))
+        })
+        .collect();
+    let previous_order: Vec<usize> = kept.iter().map(|&(_, previous)| previous).collect();
+    let in_place: HashSet<usize> = longest_increasing(&previous_order)
+        .into_iter()
+        .map(|index| kept[index].0)
+        .collect();
+
+    let mut items: Vec<CrawlItem> = urls
+        .iter()
+        .enumerate()
+        .map(|(position, url)| {
+            let status = if !previous_positions.contains_key(url) {
+                CrawlItemStatus::New
+            } else if in_place.contains(&position) {
+                CrawlItemStatus::Unchanged
+            } else {
+                CrawlItemStatus::Moved
+            };
+            CrawlItem {
+                url: url.to_string(),
+                position,
+                status,
+            }
+        })
+        .collect();
+
+    let current: HashSet<&str> = urls.into_iter().collect();
+    items.extend(
+        previous
+            .iter()
+            .filter(|item| !current.contains(item.url.as_str()))
+            .map(|item| CrawlItem {
+                url: item.url.clone(),
+                position: item.position,
+                status: CrawlItemStatus::Removed,
+            }),
+    );
+
+    items
+}
+
+/// Urls in page order without repeats
+fn unique(media_urls: &[String]) -> impl Iterator<Item = &str> {
+    let mut seen = HashSet::new();
+    media_urls
+        .iter()
+        .map(String::as_str)
+        .filter(move |url| seen.insert(*url))
+}
+
+/// Indexes of a longest strictly increasing subsequence of `values`
+fn longest_increasing(values: &[usize]) -> HashSet<usize> {
+    // `tails[k]` is the index of the smallest last value of an increasing run of length k + 1
+    let mut tails: Vec<usize> = Vec::new();
+    let mut parents: Vec<Option<usize>> = vec![None; values.len()];
+
+    for (index, &value) in values.iter().enumerate() {
+        let length = tails.partition_point(|&tail| values[tail] < value);
+        if length > 0 {
+            parents[index] = Some(tails[length - 1]);
+        }
+        if length == tails.len() {
+            tails.push(index);
+        } else {
+            tails[length] = index;
+        }
+    }
+
+    let mut indexes = HashSet::new();
+    let mut next = tails.last().copied();
+    while let Some(index) = next {
+        indexes.insert(index);
+        next = parents[index];
+    }
+    indexes
+}
+
+fn hash_page(page: &str) -> String {
+    format!("{:x}", Sha256::digest(page.as_bytes()))
+}
@@ -0,0 +1,65 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Crawl {
+    pub id: usize,
+    #[serde(rename = "linkId")]
+    pub link_id: usize,
+    #[serde(rename = "pageHash")]
+    pub page_hash: String,
+    #[serde(rename = "mediaCount")]
+    pub media_count: usize,
+    pub added: usize,
+    pub moved: usize,
+    pub removed: usize,
+    #[serde(rename = "dateCreate")]
+    pub date_create: String,
+    /// Items that are new, moved or removed compared to the previous crawl
+    pub changes: Vec<CrawlItem>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CrawlItem {
+    pub url: String,
+    pub position: usize,
+    pub status: CrawlItemStatus,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CrawlItemStatus {
+    New,
+    Unchanged,
+    Moved,
+    Removed,
+}
+
+impl CrawlItemStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CrawlItemStatus::New => "new",
+            CrawlItemStatus::Unchanged => "unchanged",
+            CrawlItemStatus::Moved => "moved",
+            CrawlItemStatus::Removed => "removed",
+        }
+    }
+
+    pub fn parse(status: &str) -> Self {
+        match status {
+            "new" => CrawlItemStatus::New,
+            "moved" => CrawlItemStatus::Moved,
+            "removed" => CrawlItemStatus::Removed,
+            _ => CrawlItemStatus::Unchanged,
+        }
+    }
+}
+
+/// Result of comparing the media list of a page with the previous crawl
+pub struct CrawlDiff {
+    pub crawl_id: usize,
+    /// True when the link has been crawled before
+    pub has_previous: bool,
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub moved: usize,
+}
@@ -0,0 +1,3 @@
+pub mod crawls_db_service;
+pub mod crawls_service;
+pub mod dto;
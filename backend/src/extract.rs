@@ -0,0 +1,490 @@
+use crate::{
+    links::dto::PageMetadata,
+    sites::dto::{Extractor, Site},
+};
+use log::debug;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use select::{
+    document::Document,
+    predicate::{Name, Or},
+};
+use serde_json::Value;
+use std::collections::HashSet;
+
+static STATE_ASSIGNMENT: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"window\.(__[A-Za-z0-9_]+__)\s*=\s*").unwrap());
+
+/// Media urls of the page in page order as written on it without repeats, relative ones included
+/// `default_extensions` apply to embedded JSON when the site has no extensions of its own
+pub fn media_urls(page: &str, site: &Site, default_extensions: &[String]) -> Vec<String> {
+    let document = Document::from(page);
+
+    let media_urls = match site.extractor {
+        Extractor::Html => {
+            let media_urls = html_media_urls(&document);
+            if media_urls.is_empty() {
+                // Script-rendered galleries have no media tags until the scripts run
+                embedded_media_urls(&document, site, default_extensions)
+            } else {
+                media_urls
+            }
+        }
+        Extractor::Json => embedded_media_urls(&document, site, default_extensions),
+    };
+
+    let mut seen = HashSet::new();
+    media_urls
+        .into_iter()
+        .filter(|url| seen.insert(url.clone()))
+        .collect()
+}
+
+/// Title, OpenGraph and article metadata of the page
+pub fn page_metadata(page: &str) -> PageMetadata {
+    let document = Document::from(page);
+
+    // `og:` tags use `property`, many pages put them in `name` as well
+    let meta = |keys: &[&str]| {
+        keys.iter().find_map(|key| {
+            document
+                .find(Name("meta"))
+                .find(|node| {
+                    node.attr("property")
+                        .or(node.attr("name"))
+                        .or(node.attr("itemprop"))
+                        .is_some_and(|name| name.eq_ignore_ascii_case(key))
+                })
+                .and_then(|node| node.attr("content"))
+                .and_then(non_empty)
+        })
+    };
+
+    let ld_json = embedded_json(&document)
+        .into_iter()
+        .filter(|blob| blob.get("@context").is_some() || blob.get("@type").is_some())
+        .collect::<Vec<_>>();
+    let ld_string = |key: &str| {
+        ld_json.iter().find_map(|blob| match blob.get(key)? {
+            Value::String(value) => non_empty(value),
+            // `"author": {"@type": "Person", "name": "..."}`
+            value => value
+                .get("name")
+                .or(value.get(0).and_then(|first| first.get("name")))
+                .and_then(Value::as_str)
+                .and_then(non_empty),
+        })
+    };
+
+    PageMetadata {
+        title: document
+            .find(Name("title"))
+            .next()
+            .and_then(|node| non_empty(&node.text())),
+        og_title: meta(&["og:title"]),
+        description: meta(&["og:description", "description"]),
+        og_image: meta(&["og:image", "og:image:url", "twitter:image"]),
+        author: meta(&["author", "article:author"]).or_else(|| ld_string("author")),
+        published_at: meta(&["article:published_time", "datePublished", "date"])
+            .or_else(|| ld_string("datePublished")),
+    }
+}
+
+fn non_empty(text: &str) -> Option<String> {
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    (!text.is_empty()).then_some(text)
+}
+
+/// `src` of `img` and `video` tags
+fn html_media_urls(document: &Document) -> Vec<String> {
+    document
+        .find(Or(Name("img"), Name("video")))
+        .filter_map(|n| n.attr("src"))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Media urls found in the JSON embedded in page scripts, by the site media selectors
+/// or, without selectors, by every string ending with a media extension
+fn embedded_media_urls(
+    document: &Document,
+    site: &Site,
+    default_extensions: &[String],
+) -> Vec<String> {
+    let extensions = site.extensions.as_deref().unwrap_or(default_extensions);
+    let selectors: Vec<Vec<Step>> = site
+        .media_selectors
+        .iter()
+        .filter_map(|selector| match parse_selector(selector) {
+            Ok(steps) => Some(steps),
+            Err(e) => {
+                debug!("{}", e);
+                None
+            }
+        })
+        .collect();
+
+    let mut media_urls = Vec::new();
+    for blob in embedded_json(document) {
+        if selectors.is_empty() {
+            collect_media_strings(&blob, extensions, &mut media_urls);
+            continue;
+        }
+
+        for steps in &selectors {
+            for value in select(&blob, steps) {
+                match value {
+                    Value::String(url) => media_urls.push(url.clone()),
+                    value => collect_media_strings(value, extensions, &mut media_urls),
+                }
+            }
+        }
+    }
+
+    media_urls
+}
+
+/// JSON of `__NEXT_DATA__`, `application/ld+json` and `window.__X__ = {...}` scripts
+pub fn embedded_json(document: &Document) -> Vec<Value> {
+    let mut blobs = Vec::new();
+
+    for script in document.find(Name("script")) {
+        let text = script.text();
+        let is_json = script.attr("id") == Some("__NEXT_DATA__")
+            || script
+                .attr("type")
+                .is_some_and(|kind| kind.contains("json"));
+
+        if is_json {
+            match serde_json::from_str(text.trim()) {
+                Ok(blob) => blobs.push(blob),
+                Err(e) => debug!("Skipping invalid JSON script: {}", e),
+            }
+            continue;
+        }
+
+        for assignment in STATE_ASSIGNMENT.captures_iter(&text) {
+            let start = assignment.get(0).unwrap().end();
+            let literal = match balanced_literal(&text[start..]) {
+                Some(literal) => literal,
+                None => continue,
+            };
+
+            match serde_json::from_str(literal) {
+                Ok(blob) => blobs.push(blob),
+                Err(e) => debug!("Skipping window.{}: {}", &assignment[1], e),
+            }
+        }
+    }
+
+    blobs
+}
+
+/// The `{...}` or `[...]` literal at the start of `text`, strings taken into account
+fn balanced_literal(text: &str) -> Option<&str> {
+    if !text.starts_with(['{', '[']) {
+        return None;
+    }
+
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+
+    for (i, c) in text.char_indices() {
+        if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+
+        match c {
+            '"' => in_string = true,
+            '{' | '[' => depth += 1,
+            '}' | ']' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(&text[..=i]);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    None
+}
+
+fn collect_media_strings(value: &Value, extensions: &[String], media_urls: &mut Vec<String>) {
+    match value {
+        Value::String(url) if is_media_url(url, extensions) => media_urls.push(url.clone()),
+        Value::Array(values) => values
+            .iter()
+            .for_each(|value| collect_media_strings(value, extensions, media_urls)),
+        Value::Object(values) => values
+            .values()
+            .for_each(|value| collect_media_strings(value, extensions, media_urls)),
+        _ => {}
+    }
+}
+
+fn is_media_url(url: &str, extensions: &[String]) -> bool {
+    let path = url
+        .split(['?', '#'])
+        .next()
+        .unwrap_or_default()
+        .to_lowercase();
+    !url.contains(char::is_whitespace)
+        && extensions
+            .iter()
+            .any(|ext| path.ends_with(&ext.to_lowercase()))
+}
+
+/// One step of a media selector
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Step {
+    /// `.name` or `['name']`
+    Key(String),
+    /// `[0]`
+    Index(usize),
+    /// `.*` or `[*]`, every child
+    Wildcard,
+    /// `..name`, `name` at any depth
+    Descendant(String),
+}
+
+/// Parses a JSONPath-like selector such as `$.props.pageProps.images[*].src` or `$..contentUrl`
+pub fn parse_selector(selector: &str) -> Result<Vec<Step>, String> {
+    let invalid = |reason: &str| format!("Invalid media selector {}: {}", selector, reason);
+    let rest = selector.trim();
+    let mut rest = rest.strip_prefix('$').unwrap_or(rest);
+    let mut steps = Vec::new();
+
+    let name_end = |text: &str| text.find(['.', '[']).unwrap_or(text.len());
+
+    while !rest.is_empty() {
+        if let Some(after) = rest.strip_prefix("..") {
+            let end = name_end(after);
+            if end == 0 {
+                return Err(invalid("name expected after .."));
+            }
+            steps.push(Step::Descendant(after[..end].to_string()));
+            rest = &after[end..];
+        } else if let Some(after) = rest.strip_prefix('.') {
+            let end = name_end(after);
+            match &after[..end] {
+                "" => return Err(invalid("name expected after .")),
+                "*" => steps.push(Step::Wildcard),
+                name => steps.push(Step::Key(name.to_string())),
+            }
+            rest = &after[end..];
+        } else if let Some(after) = rest.strip_prefix('[') {
+            let end = after.find(']').ok_or_else(|| invalid("unclosed ["))?;
+            let inner = after[..end].trim();
+            if inner == "*" {
+                steps.push(Step::Wildcard);
+            } else if let Ok(index) = inner.parse() {
+                steps.push(Step::Index(index));
+            } else if inner.len() >= 2
+                && (inner.starts_with('\'') && inner.ends_with('\'')
+                    || inner.starts_with('"') && inner.ends_with('"'))
+            {
+                steps.push(Step::Key(inner[1..inner.len() - 1].to_string()));
+            } else {
+                return Err(invalid("index, * or quoted name expected in []"));
+            }
+            rest = &after[end + 1..];
+        } else if steps.is_empty() {
+            // A leading name without `$.` like `props.pageProps`
+            let end = name_end(rest);
+            steps.push(Step::Key(rest[..end].to_string()));
+            rest = &rest[end..];
+        } else {
+            return Err(invalid("., .. or [ expected"));
+        }
+    }
+
+    Ok(steps)
+}
+
+/// Values of `root` matched by the selector steps, in document order
+pub fn select<'a>(root: &'a Value, steps: &[Step]) -> Vec<&'a Value> {
+    let mut current = vec![root];
+
+    for step in steps {
+        let mut next = Vec::new();
+        for value in current {
+            match step {
+                Step::Key(name) => next.extend(value.get(name.as_str())),
+                Step::Index(index) => next.extend(value.get(*index)),
+                Step::Wildcard => match value {
+                    Value::Array(values) => next.extend(values.iter()),
+                    Value::Object(values) => next.extend(values.values()),
+                    _ => {}
+                },
+                Step::Descendant(name) => collect_descendants(value, name, &mut next),
+            }
+        }
+        current = next;
+    }
+
+    current
+}
+
+fn collect_descendants<'a>(value: &'a Value, name: &str, found: &mut Vec<&'a Value>) {
+    match value {
+        Value::Object(values) => {
+            for (key, child) in values {
+                if key == name {
+                    found.push(child);
+                }
+                collect_descendants(child, name, found);
+            }
+        }
+        Value::Array(values) => values
+            .iter()
+            .for_each(|child| collect_descendants(child, name, found)),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GALLERY: &str = include_str!("../fixtures/pages/gallery.html");
+    const NEXT_DATA: &str = include_str!("../fixtures/pages/next_data.html");
+    const INITIAL_STATE: &str = include_str!("../fixtures/pages/initial_state.html");
+    const ARTICLE: &str = include_str!("../fixtures/pages/article.html");
+
+    fn extensions() -> Vec<String> {
+        vec![".jpg".to_string(), ".png".to_string(), ".mp4".to_string()]
+    }
+
+    fn site(extractor: Extractor, media_selectors: &[&str]) -> Site {
+        Site {
+            extractor,
+            media_selectors: media_selectors.iter().map(|s| s.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn html_tags_in_page_order_without_repeats() {
+        assert_eq!(
+            media_urls(GALLERY, &site(Extractor::Html, &[]), &extensions()),
+            [
+                "/g/1.jpg",
+                "https://cdn.example.com/g/2_320.jpg",
+                "/g/clip.mp4",
+                "/g/4.png",
+            ]
+        );
+    }
+
+    #[test]
+    fn next_data_by_selectors() {
+        assert_eq!(
+            media_urls(
+                NEXT_DATA,
+                &site(Extractor::Json, &["$.props.pageProps.images[*].src"]),
+                &extensions(),
+            ),
+            [
+                "https://cdn.example.com/1.jpg",
+                "https://cdn.example.com/2.jpg",
+                "https://cdn.example.com/3.jpg",
+            ]
+        );
+        assert_eq!(
+            media_urls(
+                NEXT_DATA,
+                &site(Extractor::Json, &["$..cover"]),
+                &extensions()
+            ),
+            ["https://cdn.example.com/cover.jpg"]
+        );
+    }
+
+    #[test]
+    fn html_extractor_falls_back_to_embedded_json() {
+        // The page has no media tags, every string with a media extension is taken once
+        assert_eq!(
+            media_urls(NEXT_DATA, &site(Extractor::Html, &[]), &extensions()),
+            [
+                "https://cdn.example.com/1.jpg",
+                "https://cdn.example.com/1_thumb.jpg",
+                "https://cdn.example.com/2.jpg",
+                "https://cdn.example.com/3.jpg",
+                "https://cdn.example.com/cover.jpg",
+            ]
+        );
+    }
+
+    #[test]
+    fn window_state_with_braces_in_strings() {
+        assert_eq!(
+            media_urls(INITIAL_STATE, &site(Extractor::Json, &[]), &extensions()),
+            ["/media/a.jpg", "/media/b.png?w=1", "/media/c.mp4"]
+        );
+        assert_eq!(
+            media_urls(
+                INITIAL_STATE,
+                &site(Extractor::Json, &["$.gallery.items[1].url"]),
+                &extensions()
+            ),
+            ["/media/b.png?w=1"]
+        );
+    }
+
+    #[test]
+    fn metadata_from_meta_tags_and_ld_json() {
+        let metadata = page_metadata(ARTICLE);
+
+        assert_eq!(metadata.title.as_deref(), Some("Summer trip | Example"));
+        assert_eq!(metadata.og_title.as_deref(), Some("Summer trip"));
+        assert_eq!(
+            metadata.og_image.as_deref(),
+            Some("https://cdn.example.com/og/summer.jpg")
+        );
+        assert_eq!(
+            metadata.description.as_deref(),
+            Some("Photos of the summer trip")
+        );
+        assert_eq!(metadata.author.as_deref(), Some("Jane Doe"));
+        assert_eq!(metadata.published_at.as_deref(), Some("2024-07-01"));
+    }
+
+    #[test]
+    fn ld_json_media_and_selectors() {
+        assert_eq!(
+            media_urls(
+                ARTICLE,
+                &site(Extractor::Json, &["$..contentUrl"]),
+                &extensions()
+            ),
+            [
+                "https://cdn.example.com/photos/1.jpg",
+                "https://cdn.example.com/photos/2.jpg",
+            ]
+        );
+    }
+
+    #[test]
+    fn selector_syntax() {
+        assert_eq!(
+            parse_selector("$.props['page'][0].*..src").unwrap(),
+            [
+                Step::Key("props".to_string()),
+                Step::Key("page".to_string()),
+                Step::Index(0),
+                Step::Wildcard,
+                Step::Descendant("src".to_string()),
+            ]
+        );
+        assert!(parse_selector("$.images[").is_err());
+        assert!(parse_selector("$..").is_err());
+    }
+}
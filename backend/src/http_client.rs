@@ -0,0 +1,358 @@
+use crate::config::Config;
+use bytes::Bytes;
+use encoding_rs::{Encoding, UTF_8};
+use futures::{stream, Stream};
+use log::{info, warn};
+use reqwest::{
+    cookie::Jar,
+    header::{HeaderMap, HeaderName, HeaderValue, CONTENT_TYPE, REFERER},
+    redirect, Client, Proxy, RequestBuilder, Response, Url,
+};
+use std::{collections::HashMap, fmt, fs, sync::Arc, time::Duration};
+use tokio::time::timeout;
+
+/// Client shared by page and media requests with the transfer limits, built once in `main`
+/// and passed to the services that fetch pages and media
+pub struct HttpClient {
+    client: Client,
+    read_timeout: Duration,
+    max_body_size: Option<usize>,
+    domain_headers: HashMap<String, HashMap<String, String>>,
+}
+
+pub enum ReadError {
+    Timeout(Duration),
+    TooLarge(usize),
+    Failed(reqwest::Error),
+}
+
+impl fmt::Display for ReadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReadError::Timeout(read_timeout) => {
+                write!(f, "no data received for {} seconds", read_timeout.as_secs())
+            }
+            ReadError::TooLarge(max_body_size) => {
+                write!(f, "body is larger than {} bytes", max_body_size)
+            }
+            ReadError::Failed(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl HttpClient {
+    /// Builds the shared client from the configuration
+    pub fn new(config: &Config) -> Result<Self, String> {
+        let mut builder = Client::builder()
+            .user_agent(config.user_agent.as_str())
+            .default_headers(to_header_map(&config.http_headers)?)
+            .connect_timeout(
+                config
+                    .connect_timeout
+                    .to_std()
+                    .map_err(|e| format!("CONNECT_TIMEOUT: {}", e))?,
+            )
+            .redirect(redirect::Policy::limited(config.max_redirects));
+
+        if let Some(path) = config.cookies_file.as_ref() {
+            builder = builder.cookie_provider(Arc::new(load_cookies(path)?));
+        }
+
+        if let Some(proxy) = config.proxy.as_ref() {
+            info!("Using proxy {}", proxy);
+            builder = builder.proxy(Proxy::all(proxy).map_err(|e| format!("PROXY: {}", e))?);
+        }
+
+        Ok(Self {
+            client: builder.build().map_err(|e| e.to_string())?,
+            read_timeout: config
+                .read_timeout
+                .to_std()
+                .map_err(|e| format!("READ_TIMEOUT: {}", e))?,
+            max_body_size: config.max_file_size,
+            domain_headers: config.domain_headers.clone(),
+        })
+    }
+
+    /// Builds a GET request with the headers configured for the url host, the `headers`
+    /// of the site profile and a `Referer` pointing to the page the url was found on
+    pub fn get(
+        &self,
+        url: &str,
+        referer: Option<&str>,
+        headers: &HashMap<String, String>,
+    ) -> RequestBuilder {
+        self.with_headers(self.client.get(url), url, referer, headers)
+    }
+
+    /// Same as `get` for a HEAD request
+    pub fn head(
+        &self,
+        url: &str,
+        referer: Option<&str>,
+        headers: &HashMap<String, String>,
+    ) -> RequestBuilder {
+        self.with_headers(self.client.head(url), url, referer, headers)
+    }
+
+    fn with_headers(
+        &self,
+        request: RequestBuilder,
+        url: &str,
+        referer: Option<&str>,
+        headers: &HashMap<String, String>,
+    ) -> RequestBuilder {
+        let domain = get_host(url).and_then(|host| domain_headers(&self.domain_headers, &host));
+        request.headers(request_headers(referer, domain, headers))
+    }
+
+    /// Reads the whole response body, failing when no data arrives within `READ_TIMEOUT`
+    /// or the body grows over `MAX_FILE_SIZE`
+    pub async fn read_body(&self, mut response: Response) -> Result<Vec<u8>, ReadError> {
+        let max_body_size = self.max_body_size;
+        let too_large = |size: u64| max_body_size.is_some_and(|max| size > max as u64);
+        let too_large_error = || ReadError::TooLarge(max_body_size.unwrap_or_default());
+
+        if response.content_length().is_some_and(too_large) {
+            return Err(too_large_error());
+        }
+
+        let mut body = Vec::new();
+        loop {
+            match timeout(self.read_timeout, response.chunk()).await {
+                Ok(Ok(Some(chunk))) => {
+                    body.extend_from_slice(&chunk);
+                    if too_large(body.len() as u64) {
+                        return Err(too_large_error());
+                    }
+                }
+                Ok(Ok(None)) => return Ok(body),
+                Ok(Err(e)) => return Err(ReadError::Failed(e)),
+                Err(_) => return Err(ReadError::Timeout(self.read_timeout)),
+            }
+        }
+    }
+
+    /// Response body as a stream with the limits of `read_body`, the stream ends after an error
+    pub fn body_stream(
+        &self,
+        response: Response,
+    ) -> impl Stream<Item = Result<Bytes, ReadError>> + Send {
+        let read_timeout = self.read_timeout;
+        let max_body_size = self.max_body_size;
+        let too_large = move |size: u64| max_body_size.is_some_and(|max| size > max as u64);
+        let too_large_error = move || ReadError::TooLarge(max_body_size.unwrap_or_default());
+        let first = if response.content_length().is_some_and(too_large) {
+            Err(too_large_error())
+        } else {
+            Ok(response)
+        };
+
+        stream::unfold(Some((first, 0)), move |state| async move {
+            let (mut response, read) = match state? {
+                (Ok(response), read) => (response, read),
+                (Err(e), _) => return Some((Err(e), None)),
+            };
+
+            match timeout(read_timeout, response.chunk()).await {
+                Ok(Ok(Some(chunk))) => {
+                    let read = read + chunk.len() as u64;
+                    if too_large(read) {
+                        Some((Err(too_large_error()), None))
+                    } else {
+                        Some((Ok(chunk), Some((Ok(response), read))))
+                    }
+                }
+                Ok(Ok(None)) => None,
+                Ok(Err(e)) => Some((Err(ReadError::Failed(e)), None)),
+                Err(_) => Some((Err(ReadError::Timeout(read_timeout)), None)),
+            }
+        })
+    }
+
+    /// Reads the response body as text using the charset from `Content-Type`
+    pub async fn read_text(&self, response: Response) -> Result<String, ReadError> {
+        let encoding = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|content_type| content_type.split("charset=").nth(1))
+            .and_then(|charset| Encoding::for_label(charset.trim_matches('"').as_bytes()))
+            .unwrap_or(UTF_8);
+
+        let body = self.read_body(response).await?;
+        let (text, _, _) = encoding.decode(&body);
+        Ok(text.into_owned())
+    }
+}
+
+/// Headers of one request, each one set once: the domain headers replace the `Referer`,
+/// the site headers replace both, and all of them replace the client defaults
+fn request_headers(
+    referer: Option<&str>,
+    domain: Option<&HashMap<String, String>>,
+    site: &HashMap<String, String>,
+) -> HeaderMap {
+    let mut map = HeaderMap::new();
+    if let Some(referer) = referer.and_then(|referer| HeaderValue::from_str(referer).ok()) {
+        map.insert(REFERER, referer);
+    }
+
+    for (name, value) in domain.into_iter().flatten().chain(site) {
+        match (
+            HeaderName::from_bytes(name.as_bytes()),
+            HeaderValue::from_str(value),
+        ) {
+            (Ok(name), Ok(value)) => {
+                map.insert(name, value);
+            }
+            _ => warn!("Skipping invalid header {}", name),
+        }
+    }
+
+    map
+}
+
+pub fn get_host(url: &str) -> Option<String> {
+    Url::parse(url)
+        .ok()
+        .and_then(|url| url.host_str().map(|host| host.to_lowercase()))
+}
+
+/// Headers for `host` itself or for the closest parent domain listed in `DOMAIN_HEADERS`
+fn domain_headers<'a>(
+    domains: &'a HashMap<String, HashMap<String, String>>,
+    host: &str,
+) -> Option<&'a HashMap<String, String>> {
+    let mut domain = host;
+    loop {
+        if let Some(headers) = domains.get(domain) {
+            return Some(headers);
+        }
+        domain = domain.split_once('.')?.1;
+    }
+}
+
+fn to_header_map(headers: &HashMap<String, String>) -> Result<HeaderMap, String> {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            let name = HeaderName::from_bytes(name.as_bytes())
+                .map_err(|e| format!("Invalid header name {}: {}", name, e))?;
+            let value = HeaderValue::from_str(value)
+                .map_err(|e| format!("Invalid value of header {}: {}", name, e))?;
+            Ok((name, value))
+        })
+        .collect()
+}
+
+/// Loads cookies exported in the Netscape cookies.txt format
+fn load_cookies(path: &str) -> Result<Jar, String> {
+    let content =
+        fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    let now = chrono::Utc::now().timestamp();
+    let jar = Jar::default();
+    let mut count = 0;
+
+    for line in content.lines() {
+        // HttpOnly cookies are written as comments with a prefix
+        let line = line.trim();
+        let line = line.strip_prefix("#HttpOnly_").unwrap_or(line);
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() < 7 {
+            warn!("Skipping malformed cookie line in {}", path);
+            continue;
+        }
+        let (domain, cookie_path, secure, expires, name, value) = (
+            fields[0], fields[2], fields[3], fields[4], fields[5], fields[6],
+        );
+
+        if expires
+            .parse::<i64>()
+            .is_ok_and(|expires| expires != 0 && expires < now)
+        {
+            continue;
+        }
+
+        let host = domain.trim_start_matches('.');
+        let secure = secure.eq_ignore_ascii_case("TRUE");
+        let scheme = if secure { "https" } else { "http" };
+        let url = match Url::parse(&format!("{}://{}{}", scheme, host, cookie_path)) {
+            Ok(url) => url,
+            Err(e) => {
+                warn!("Skipping cookie for {}: {}", domain, e);
+                continue;
+            }
+        };
+
+        let mut cookie = format!("{}={}; Path={}", name, value, cookie_path);
+        if domain.starts_with('.') {
+            cookie.push_str(&format!("; Domain={}", host));
+        }
+        if secure {
+            cookie.push_str("; Secure");
+        }
+
+        jar.add_cookie_str(&cookie, &url);
+        count += 1;
+    }
+
+    info!("Loaded {} cookies from {}", count, path);
+    Ok(jar)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::USER_AGENT;
+
+    fn headers(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn request_headers_are_set_once() {
+        let domain = headers(&[
+            ("User-Agent", "domain-agent"),
+            ("Referer", "https://example.com/"),
+            ("Authorization", "token"),
+        ]);
+        let site = headers(&[("user-agent", "site-agent"), ("Accept", "image/*")]);
+
+        let map = request_headers(Some("https://example.com/gallery"), Some(&domain), &site);
+
+        assert_eq!(map.len(), 4);
+        assert_eq!(map.get_all(USER_AGENT).iter().count(), 1);
+        assert_eq!(map.get_all(REFERER).iter().count(), 1);
+        assert_eq!(map[USER_AGENT], "site-agent");
+        assert_eq!(map[REFERER], "https://example.com/");
+        assert_eq!(map["authorization"], "token");
+        assert_eq!(map["accept"], "image/*");
+    }
+
+    #[test]
+    fn request_headers_keep_referer_without_overrides() {
+        let map = request_headers(Some("https://example.com/gallery"), None, &HashMap::new());
+
+        assert_eq!(map.len(), 1);
+        assert_eq!(map[REFERER], "https://example.com/gallery");
+    }
+
+    #[test]
+    fn domain_headers_match_parent_domains() {
+        let mut domains = HashMap::new();
+        domains.insert("example.com".to_string(), headers(&[("Cookie", "a=1")]));
+
+        assert!(domain_headers(&domains, "example.com").is_some());
+        assert!(domain_headers(&domains, "cdn.example.com").is_some());
+        assert!(domain_headers(&domains, "example.org").is_none());
+        assert!(domain_headers(&domains, "badexample.com").is_none());
+    }
+}
@@ -2,10 +2,42 @@ use log::{error, info};
 use rusqlite::{Connection, Result};
 use std::path::Path;
 
-use crate::config;
+use crate::config::Config;
 
-pub fn init_db_tables() -> Result<()> {
-    let db_name = config::DB_NAME.clone();
+/// Columns of `links`, a path is unique per owner, `user_id` 0 is a link without owner
+const LINKS_COLUMNS: &str = "
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                user_id INTEGER NOT NULL DEFAULT 0,
+                path TEXT NOT NULL,
+                name TEXT,
+                is_downloaded BOOLEAN NOT NULL DEFAULT 1,
+                progress INTEGER DEFAULT 0,
+                downloaded_mediafiles INTEGER DEFAULT 0,
+                mediafiles INTEGER DEFAULT 0,
+                date_update DATETIME DEFAULT CURRENT_TIMESTAMP,
+                date_create DATETIME DEFAULT CURRENT_TIMESTAMP,
+                is_reachable BOOLEAN NOT NULL DEFAULT 0,
+                duplicate_id INTEGER DEFAULT NULL,
+                check_interval TEXT,
+                last_checked_at DATETIME,
+                next_check_at DATETIME,
+                check_failures INTEGER NOT NULL DEFAULT 0,
+                title TEXT,
+                og_title TEXT,
+                description TEXT,
+                og_image TEXT,
+                author TEXT,
+                published_at TEXT,
+                cover_path TEXT,
+                deleted_at DATETIME,
+                UNIQUE (user_id, path)
+            ";
+
+/// `user_version` of a database whose file paths were turned into storage keys
+const STORAGE_KEYS_VERSION: i64 = 1;
+
+pub fn init_db_tables(config: &Config) -> Result<()> {
+    let db_name = &config.db_name;
     info!("Checking database at {}", db_name);
 
     if !Path::new(&db_name).exists() {
@@ -18,22 +50,35 @@ pub fn init_db_tables() -> Result<()> {
 
     let conn = Connection::open(db_name)?;
     conn.execute(
-        "CREATE TABLE IF NOT EXISTS links (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                path TEXT NOT NULL UNIQUE,
-                name TEXT,
-                is_downloaded BOOLEAN NOT NULL DEFAULT 1,
-                progress INTEGER DEFAULT 0,
-                downloaded_mediafiles INTEGER DEFAULT 0,
-                mediafiles INTEGER DEFAULT 0,
-                date_update DATETIME DEFAULT CURRENT_TIMESTAMP,
-                date_create DATETIME DEFAULT CURRENT_TIMESTAMP,
-                is_reachable BOOLEAN NOT NULL DEFAULT 0,
-                duplicate_id INTEGER DEFAULT NULL
-            )",
+        &format!("CREATE TABLE IF NOT EXISTS links ({})", LINKS_COLUMNS),
         [],
     )?;
 
+    add_column_if_missing(&conn, "links", "check_interval", "TEXT")?;
+    add_column_if_missing(&conn, "links", "last_checked_at", "DATETIME")?;
+    add_column_if_missing(&conn, "links", "next_check_at", "DATETIME")?;
+    add_column_if_missing(
+        &conn,
+        "links",
+        "check_failures",
+        "INTEGER NOT NULL DEFAULT 0",
+    )?;
+    for column in [
+        "title",
+        "og_title",
+        "description",
+        "og_image",
+        "author",
+        "published_at",
+        "cover_path",
+    ] {
+        add_column_if_missing(&conn, "links", column, "TEXT")?;
+    }
+    migrate_links_owner(&conn)?;
+    add_column_if_missing(&conn, "links", "deleted_at", "DATETIME")?;
+
+    init_links_search(&conn)?;
+
     conn.execute(
         "CREATE TABLE IF NOT EXISTS mediafiles (
                 id INTEGER PRIMARY KEY AUTOINCREMENT,
@@ -41,11 +86,28 @@ pub fn init_db_tables() -> Result<()> {
                 name TEXT NOT NULL,
                 hash TEXT NOT NULL,
                 size INTEGER NOT NULL,
-                date_added DATETIME DEFAULT CURRENT_TIMESTAMP
+                date_added DATETIME DEFAULT CURRENT_TIMESTAMP,
+                source_url TEXT,
+                position_on_page INTEGER,
+                etag TEXT,
+                last_modified TEXT,
+                content_type TEXT,
+                downloaded_at DATETIME,
+                deleted_at DATETIME,
+                missing_at DATETIME
             )",
         [],
     )?;
 
+    add_column_if_missing(&conn, "mediafiles", "source_url", "TEXT")?;
+    add_column_if_missing(&conn, "mediafiles", "position_on_page", "INTEGER")?;
+    add_column_if_missing(&conn, "mediafiles", "etag", "TEXT")?;
+    add_column_if_missing(&conn, "mediafiles", "last_modified", "TEXT")?;
+    add_column_if_missing(&conn, "mediafiles", "content_type", "TEXT")?;
+    add_column_if_missing(&conn, "mediafiles", "downloaded_at", "DATETIME")?;
+    add_column_if_missing(&conn, "mediafiles", "deleted_at", "DATETIME")?;
+    add_column_if_missing(&conn, "mediafiles", "missing_at", "DATETIME")?;
+
     conn.execute(
         "CREATE TABLE IF NOT EXISTS mediafiles_links (
                 link_id INTEGER NOT NULL,
@@ -57,7 +119,333 @@ pub fn init_db_tables() -> Result<()> {
         [],
     )?;
 
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS crawls (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                link_id INTEGER NOT NULL,
+                page_hash TEXT NOT NULL,
+                media_count INTEGER NOT NULL DEFAULT 0,
+                added INTEGER NOT NULL DEFAULT 0,
+                moved INTEGER NOT NULL DEFAULT 0,
+                removed INTEGER NOT NULL DEFAULT 0,
+                date_create DATETIME DEFAULT CURRENT_TIMESTAMP,
+                FOREIGN KEY (link_id) REFERENCES links(id) ON DELETE CASCADE
+            )",
+        [],
+    )?;
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS crawl_items (
+                crawl_id INTEGER NOT NULL,
+                url TEXT NOT NULL,
+                position INTEGER NOT NULL,
+                status TEXT NOT NULL,
+                FOREIGN KEY (crawl_id) REFERENCES crawls(id) ON DELETE CASCADE
+            )",
+        [],
+    )?;
+
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS crawl_items_crawl_id ON crawl_items (crawl_id)",
+        [],
+    )?;
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS link_checks (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                link_id INTEGER NOT NULL,
+                status_code INTEGER,
+                error_class TEXT,
+                error TEXT,
+                latency_ms INTEGER NOT NULL DEFAULT 0,
+                final_url TEXT,
+                date_create DATETIME DEFAULT CURRENT_TIMESTAMP,
+                FOREIGN KEY (link_id) REFERENCES links(id) ON DELETE CASCADE
+            )",
+        [],
+    )?;
+
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS link_checks_link_id ON link_checks (link_id)",
+        [],
+    )?;
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS sites (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                host TEXT NOT NULL UNIQUE,
+                root_url TEXT,
+                extractor TEXT NOT NULL DEFAULT 'html',
+                media_selectors TEXT,
+                rewrite_rules TEXT,
+                extensions TEXT,
+                concurrency INTEGER,
+                headers TEXT,
+                pagination TEXT,
+                date_create DATETIME DEFAULT CURRENT_TIMESTAMP,
+                date_update DATETIME DEFAULT CURRENT_TIMESTAMP
+            )",
+        [],
+    )?;
+    add_column_if_missing(&conn, "sites", "pagination", "TEXT")?;
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS tags (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                name TEXT NOT NULL UNIQUE COLLATE NOCASE,
+                color TEXT,
+                date_create DATETIME DEFAULT CURRENT_TIMESTAMP
+            )",
+        [],
+    )?;
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS link_tags (
+                link_id INTEGER NOT NULL,
+                tag_id INTEGER NOT NULL,
+                PRIMARY KEY (link_id, tag_id)
+            )",
+        [],
+    )?;
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS mediafile_tags (
+                mediafile_id INTEGER NOT NULL,
+                tag_id INTEGER NOT NULL,
+                PRIMARY KEY (mediafile_id, tag_id)
+            )",
+        [],
+    )?;
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS collections (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                name TEXT NOT NULL UNIQUE,
+                description TEXT,
+                date_create DATETIME DEFAULT CURRENT_TIMESTAMP,
+                date_update DATETIME DEFAULT CURRENT_TIMESTAMP
+            )",
+        [],
+    )?;
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS collection_links (
+                collection_id INTEGER NOT NULL,
+                link_id INTEGER NOT NULL,
+                date_create DATETIME DEFAULT CURRENT_TIMESTAMP,
+                PRIMARY KEY (collection_id, link_id)
+            )",
+        [],
+    )?;
+    add_column_if_missing(&conn, "sites", "media_selectors", "TEXT")?;
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS users (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                username TEXT NOT NULL UNIQUE COLLATE NOCASE,
+                password_hash TEXT NOT NULL,
+                is_admin BOOLEAN NOT NULL DEFAULT 0,
+                quota_bytes INTEGER,
+                date_create DATETIME DEFAULT CURRENT_TIMESTAMP
+            )",
+        [],
+    )?;
+    add_column_if_missing(&conn, "users", "is_admin", "BOOLEAN NOT NULL DEFAULT 0")?;
+    add_column_if_missing(&conn, "users", "quota_bytes", "INTEGER")?;
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS sessions (
+                token_hash TEXT PRIMARY KEY,
+                user_id INTEGER NOT NULL,
+                date_create DATETIME DEFAULT CURRENT_TIMESTAMP,
+                expires_at DATETIME NOT NULL
+            )",
+        [],
+    )?;
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS api_tokens (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                user_id INTEGER NOT NULL,
+                name TEXT NOT NULL,
+                token_hash TEXT NOT NULL UNIQUE,
+                scope TEXT NOT NULL,
+                date_create DATETIME DEFAULT CURRENT_TIMESTAMP,
+                last_used_at DATETIME
+            )",
+        [],
+    )?;
+
+    migrate_paths_to_keys(&conn, config)?;
+
     info!("Database tables checked");
 
     Ok(())
 }
+
+/// Full-text index of links kept in sync by triggers, filled from existing links when created
+fn init_links_search(conn: &Connection) -> Result<()> {
+    let exists: bool = conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'links_fts')",
+        [],
+        |row| row.get(0),
+    )?;
+
+    conn.execute_batch(
+        "CREATE VIRTUAL TABLE IF NOT EXISTS links_fts USING fts5(
+                name, path, title, og_title, description,
+                content = 'links', content_rowid = 'id'
+            );
+            CREATE TRIGGER IF NOT EXISTS links_fts_insert AFTER INSERT ON links BEGIN
+                INSERT INTO links_fts (rowid, name, path, title, og_title, description)
+                    VALUES (new.id, new.name, new.path, new.title, new.og_title, new.description);
+            END;
+            CREATE TRIGGER IF NOT EXISTS links_fts_delete AFTER DELETE ON links BEGIN
+                INSERT INTO links_fts (links_fts, rowid, name, path, title, og_title, description)
+                    VALUES ('delete', old.id, old.name, old.path, old.title, old.og_title, old.description);
+            END;
+            CREATE TRIGGER IF NOT EXISTS links_fts_update
+                AFTER UPDATE OF name, path, title, og_title, description ON links BEGIN
+                INSERT INTO links_fts (links_fts, rowid, name, path, title, og_title, description)
+                    VALUES ('delete', old.id, old.name, old.path, old.title, old.og_title, old.description);
+                INSERT INTO links_fts (rowid, name, path, title, og_title, description)
+                    VALUES (new.id, new.name, new.path, new.title, new.og_title, new.description);
+            END;",
+    )?;
+
+    if !exists {
+        info!("Building links search index");
+        conn.execute("INSERT INTO links_fts (links_fts) VALUES ('rebuild')", [])?;
+    }
+
+    Ok(())
+}
+
+/// Rebuilds `links` of an older schema, where a path was unique over all links, with an owner
+/// column, SQLite cannot drop the old constraint in place. The search triggers go with the old
+/// table and are created again by `init_links_search`
+fn migrate_links_owner(conn: &Connection) -> Result<()> {
+    if has_column(conn, "links", "user_id")? {
+        return Ok(());
+    }
+
+    info!("Adding owners to links");
+    let columns = "id, path, name, is_downloaded, progress, downloaded_mediafiles, mediafiles,
+        date_update, date_create, is_reachable, duplicate_id, check_interval, last_checked_at,
+        next_check_at, check_failures, title, og_title, description, og_image, author,
+        published_at, cover_path";
+    conn.execute_batch(&format!(
+        "BEGIN;
+        CREATE TABLE links_owned ({columns_definition});
+        INSERT INTO links_owned ({columns}) SELECT {columns} FROM links;
+        DROP TABLE links;
+        ALTER TABLE links_owned RENAME TO links;
+        COMMIT;",
+        columns_definition = LINKS_COLUMNS,
+        columns = columns
+    ))
+}
+
+/// Turns filesystem paths under the storage root, stored before the storage was abstracted,
+/// into storage keys. Runs once, the database `user_version` records that it ran
+fn migrate_paths_to_keys(conn: &Connection, config: &Config) -> Result<()> {
+    let version: i64 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    if version >= STORAGE_KEYS_VERSION {
+        return Ok(());
+    }
+
+    let root = format!(
+        "{}/",
+        config.storage_root.to_string_lossy().trim_end_matches('/')
+    );
+
+    let tx = conn.unchecked_transaction()?;
+    let changes = tx.execute(
+        "UPDATE mediafiles SET path = substr(path, length(?1) + 1)
+            WHERE substr(path, 1, length(?1)) = ?1",
+        [&root],
+    )? + tx.execute(
+        "UPDATE links SET cover_path = substr(cover_path, length(?1) + 1)
+            WHERE substr(cover_path, 1, length(?1)) = ?1",
+        [&root],
+    )?;
+    tx.execute_batch(&format!("PRAGMA user_version = {}", STORAGE_KEYS_VERSION))?;
+    tx.commit()?;
+
+    if changes > 0 {
+        info!("{} file paths turned into storage keys", changes);
+    }
+
+    Ok(())
+}
+
+fn has_column(conn: &Connection, table: &str, column: &str) -> Result<bool> {
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table))?;
+    let exists = stmt
+        .query_map([], |row| row.get::<_, String>(1))?
+        .filter_map(|name| name.ok())
+        .any(|name| name == column);
+    Ok(exists)
+}
+
+/// Adds a column to a table created by an older version of the schema
+fn add_column_if_missing(
+    conn: &Connection,
+    table: &str,
+    column: &str,
+    definition: &str,
+) -> Result<()> {
+    if !has_column(conn, table, column)? {
+        info!("Adding column {} to table {}", column, table);
+        conn.execute(
+            &format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition),
+            [],
+        )?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::tests::config;
+    use std::fs;
+
+    #[test]
+    fn paths_are_turned_into_keys_once() {
+        let db_name = std::env::temp_dir().join(format!("paths-to-keys-{}.db", std::process::id()));
+        fs::write(&db_name, "").unwrap();
+        let config = config(&[
+            &format!("db_name={}", db_name.display()),
+            "storage_root=/data/result",
+        ]);
+        let path = |conn: &Connection| -> String {
+            conn.query_row("SELECT path FROM mediafiles", [], |row| row.get(0))
+                .unwrap()
+        };
+
+        init_db_tables(&config).unwrap();
+        let conn = Connection::open(&db_name).unwrap();
+        conn.execute(
+            "INSERT INTO mediafiles (path, name, hash, size) VALUES (?, '1.jpg', 'h', 1)",
+            ["/data/result/example.com/1.jpg"],
+        )
+        .unwrap();
+        conn.execute("PRAGMA user_version = 0", []).unwrap();
+
+        init_db_tables(&config).unwrap();
+        assert_eq!(path(&conn), "example.com/1.jpg");
+
+        conn.execute(
+            "UPDATE mediafiles SET path = ?",
+            ["/data/result/example.com/1.jpg"],
+        )
+        .unwrap();
+        init_db_tables(&config).unwrap();
+        let kept = path(&conn);
+        let _ = fs::remove_file(&db_name);
+
+        assert_eq!(kept, "/data/result/example.com/1.jpg");
+    }
+}
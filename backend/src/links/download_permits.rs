@@ -0,0 +1,60 @@
+use crate::{config::Config, sites::dto::Site};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore, SemaphorePermit};
+
+/// Download slots shared by every `LinksService`, built once in `main` so that the limits
+/// hold for the routes, the scheduler, the watcher and the command line together
+pub struct DownloadPermits {
+    /// Limits downloads running at once over all links to `MAX_CONCURRENT_DOWNLOADS`
+    global: Semaphore,
+    /// Slots of each site host with the `concurrency` they were made for,
+    /// shared by all links of the host
+    sites: Mutex<HashMap<String, (usize, Arc<Semaphore>)>>,
+}
+
+impl DownloadPermits {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            global: Semaphore::new(
+                config
+                    .max_concurrent_downloads
+                    .unwrap_or(Semaphore::MAX_PERMITS),
+            ),
+            sites: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Waits for a free slot of `MAX_CONCURRENT_DOWNLOADS`
+    pub async fn acquire(&self) -> Result<SemaphorePermit<'_>, String> {
+        self.global
+            .acquire()
+            .await
+            .map_err(|e| format!("Download queue closed: {}", e))
+    }
+
+    /// Waits for a free slot of the site `concurrency`, `None` when the site has no limit
+    pub async fn acquire_site(&self, site: &Site) -> Result<Option<OwnedSemaphorePermit>, String> {
+        let Some(concurrency) = site.concurrency else {
+            return Ok(None);
+        };
+        let semaphore = {
+            let mut sites = self.sites.lock().unwrap();
+            let entry = sites
+                .entry(site.host.clone())
+                .or_insert_with(|| (concurrency, Arc::new(Semaphore::new(concurrency))));
+            // An edited profile gets new slots, downloads already running keep the old ones
+            if entry.0 != concurrency {
+                *entry = (concurrency, Arc::new(Semaphore::new(concurrency)));
+            }
+            Arc::clone(&entry.1)
+        };
+        semaphore
+            .acquire_owned()
+            .await
+            .map(Some)
+            .map_err(|e| format!("Download queue closed: {}", e))
+    }
+}
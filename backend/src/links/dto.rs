@@ -1,8 +1,12 @@
+use crate::storage::dto::FileDisposal;
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Link {
     pub id: usize,
+    /// Owner of the link, 0 for links added without authentication
+    #[serde(rename = "userId")]
+    pub user_id: usize,
     pub path: String,
     pub name: String,
     #[serde(rename = "isDownloaded")]
@@ -21,6 +25,44 @@ pub struct Link {
     pub duplicate_id: Option<usize>,
     #[serde(rename = "duplicatePath")]
     pub duplicate_path: Option<String>,
+    #[serde(rename = "checkInterval")]
+    pub check_interval: Option<String>,
+    #[serde(rename = "lastCheckedAt")]
+    pub last_checked_at: Option<String>,
+    #[serde(rename = "nextCheckAt")]
+    pub next_check_at: Option<String>,
+    #[serde(rename = "checkFailures")]
+    pub check_failures: usize,
+    /// `og:title` or `<title>` of the page, the link name when the page has none
+    #[serde(rename = "displayName")]
+    pub display_name: String,
+    pub title: Option<String>,
+    #[serde(rename = "ogTitle")]
+    pub og_title: Option<String>,
+    pub description: Option<String>,
+    #[serde(rename = "ogImage")]
+    pub og_image: Option<String>,
+    pub author: Option<String>,
+    #[serde(rename = "publishedAt")]
+    pub published_at: Option<String>,
+    /// Storage key of the `og:image`, stored under `.covers/`
+    #[serde(rename = "coverPath")]
+    pub cover_path: Option<String>,
+    /// Time the link was moved to the trash, links in the trash are left out of every list
+    #[serde(rename = "deletedAt")]
+    pub deleted_at: Option<String>,
+    pub tags: Vec<String>,
+}
+
+/// Metadata read from the `head` of a link page
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct PageMetadata {
+    pub title: Option<String>,
+    pub og_title: Option<String>,
+    pub description: Option<String>,
+    pub og_image: Option<String>,
+    pub author: Option<String>,
+    pub published_at: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -34,12 +76,17 @@ pub struct CreateLinkDto {
     pub path: String,
 }
 
-#[derive(Deserialize)]
-pub struct BooleanQuery {
-    #[serde(rename = "isReachable")]
-    pub is_reachable: Option<bool>,
-    #[serde(rename = "showDuplicate")]
-    pub show_duplicate: Option<bool>,
+/// Page of `GET /links`
+#[derive(Debug, Serialize)]
+pub struct LinksPage {
+    pub items: Vec<Link>,
+    /// Number of links matching the filters on all pages
+    pub total: usize,
+    pub limit: usize,
+    pub offset: Option<usize>,
+    /// Cursor of the next page, none on the last page
+    #[serde(rename = "nextCursor")]
+    pub next_cursor: Option<String>,
 }
 
 #[derive(Deserialize)]
@@ -49,11 +96,50 @@ pub struct TagUnreachableParams {
     pub is_reachable: Option<bool>,
 }
 
+#[derive(Deserialize)]
+pub struct CheckIntervalParams {
+    pub id: usize,
+    /// Interval like `30m`, `6h` or `1d`, empty resets to the global `CHECK_INTERVAL`
+    pub interval: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct LimitQuery {
+    pub limit: Option<usize>,
+}
+
 #[derive(Deserialize)]
 pub struct IdDto {
     pub id: usize,
 }
 
+#[derive(Deserialize)]
+pub struct RemoveLinkQuery {
+    pub id: usize,
+    /// Removes the link with its records instead of moving it to the trash
+    pub permanent: Option<bool>,
+    /// What happens to the files no other link holds when the link is removed permanently
+    pub files: Option<FileDisposal>,
+}
+
+#[derive(Deserialize)]
+pub struct GarbageQuery {
+    #[serde(rename = "dryRun")]
+    pub dry_run: Option<bool>,
+    pub files: Option<FileDisposal>,
+}
+
+/// Outcome of a garbage collection
+#[derive(Debug, Default, Serialize)]
+pub struct GarbageReport {
+    /// Rows of tags, collections, checks, crawls and mediafiles of links that no longer exist
+    pub rows: usize,
+    /// Mediafiles held by no link
+    pub mediafiles: usize,
+    /// Files of the removed mediafiles
+    pub files: Vec<String>,
+}
+
 #[derive(Deserialize)]
 pub struct IdDublicateDto {
     #[serde(rename = "linkId")]
@@ -61,3 +147,145 @@ pub struct IdDublicateDto {
     #[serde(rename = "duplicateId")]
     pub duplicate_id: usize,
 }
+
+#[derive(Deserialize)]
+pub struct VerifyQuery {
+    pub id: Option<usize>,
+    #[serde(rename = "redownloadMissing")]
+    pub redownload_missing: Option<bool>,
+    #[serde(rename = "redownloadModified")]
+    pub redownload_modified: Option<bool>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct VerifyReport {
+    #[serde(rename = "linkId")]
+    pub link_id: Option<usize>,
+    pub checked: usize,
+    pub missing: Vec<String>,
+    pub modified: Vec<String>,
+    pub orphaned: Vec<String>,
+    pub redownloaded: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LinkCheck {
+    pub id: usize,
+    #[serde(rename = "linkId")]
+    pub link_id: usize,
+    #[serde(rename = "statusCode")]
+    pub status_code: Option<u16>,
+    #[serde(rename = "errorClass")]
+    pub error_class: Option<ErrorClass>,
+    pub error: Option<String>,
+    #[serde(rename = "latencyMs")]
+    pub latency_ms: usize,
+    #[serde(rename = "finalUrl")]
+    pub final_url: Option<String>,
+    #[serde(rename = "dateCreate")]
+    pub date_create: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorClass {
+    NotFound,
+    Gone,
+    ClientError,
+    ServerError,
+    Timeout,
+    Dns,
+    Connect,
+    Redirect,
+    Body,
+    Other,
+}
+
+impl ErrorClass {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorClass::NotFound => "not_found",
+            ErrorClass::Gone => "gone",
+            ErrorClass::ClientError => "client_error",
+            ErrorClass::ServerError => "server_error",
+            ErrorClass::Timeout => "timeout",
+            ErrorClass::Dns => "dns",
+            ErrorClass::Connect => "connect",
+            ErrorClass::Redirect => "redirect",
+            ErrorClass::Body => "body",
+            ErrorClass::Other => "other",
+        }
+    }
+
+    pub fn parse(class: &str) -> Self {
+        match class {
+            "not_found" => ErrorClass::NotFound,
+            "gone" => ErrorClass::Gone,
+            "client_error" => ErrorClass::ClientError,
+            "server_error" => ErrorClass::ServerError,
+            "timeout" => ErrorClass::Timeout,
+            "dns" => ErrorClass::Dns,
+            "connect" => ErrorClass::Connect,
+            "redirect" => ErrorClass::Redirect,
+            "body" => ErrorClass::Body,
+            _ => ErrorClass::Other,
+        }
+    }
+
+    pub fn from_status(status: u16) -> Self {
+        match status {
+            404 => ErrorClass::NotFound,
+            410 => ErrorClass::Gone,
+            400..=499 => ErrorClass::ClientError,
+            500..=599 => ErrorClass::ServerError,
+            _ => ErrorClass::Other,
+        }
+    }
+
+    /// Class of a request that got no response
+    pub fn from_request_error(e: &reqwest::Error) -> Self {
+        if e.is_timeout() {
+            ErrorClass::Timeout
+        } else if e.is_redirect() {
+            ErrorClass::Redirect
+        } else if e.is_connect() {
+            // hyper reports resolver failures as connect errors, the cause tells them apart
+            let mut source = std::error::Error::source(e);
+            while let Some(cause) = source {
+                let cause_text = cause.to_string();
+                if cause_text.contains("dns error")
+                    || cause_text.contains("failed to lookup address")
+                {
+                    return ErrorClass::Dns;
+                }
+                source = cause.source();
+            }
+            ErrorClass::Connect
+        } else if e.is_body() || e.is_decode() {
+            ErrorClass::Body
+        } else {
+            ErrorClass::Other
+        }
+    }
+}
+
+/// Outcome of a single page request
+pub struct PageFetch {
+    pub page: Option<String>,
+    pub status_code: Option<u16>,
+    pub error_class: Option<ErrorClass>,
+    pub error: Option<String>,
+    pub latency_ms: usize,
+    pub final_url: Option<String>,
+}
+
+impl PageFetch {
+    pub fn into_result(self) -> Result<String, String> {
+        match self.page {
+            Some(page) => Ok(page),
+            None => Err(self
+                .error
+                .unwrap_or_else(|| "Failed to fetch page".to_string())),
+        }
+    }
+}
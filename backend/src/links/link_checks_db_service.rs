@@ -0,0 +1,75 @@
+use super::dto::{ErrorClass, LinkCheck, PageFetch};
+use crate::{config::Config, metrics, utils::get_now_time};
+use log::error;
+use rusqlite::{params, Connection, Result};
+
+pub struct LinkChecksDbService {
+    db_name: String,
+}
+
+impl LinkChecksDbService {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            db_name: config.db_name.clone(),
+        }
+    }
+
+    fn open_connection(&self) -> Result<Connection> {
+        let mut conn = Connection::open(&self.db_name)?;
+        conn.profile(Some(metrics::observe_query));
+        Ok(conn)
+    }
+
+    pub fn create_one(&self, link_id: usize, fetch: &PageFetch) -> Result<&str> {
+        let conn = self.open_connection()?;
+
+        match conn.execute(
+            "INSERT INTO link_checks (link_id, status_code, error_class, error, latency_ms, final_url, date_create)
+                VALUES (?, ?, ?, ?, ?, ?, ?)",
+            params![
+                link_id,
+                fetch.status_code,
+                fetch.error_class.map(|class| class.as_str()),
+                fetch.error,
+                fetch.latency_ms,
+                fetch.final_url,
+                get_now_time()
+            ],
+        ) {
+            Ok(_) => Ok("One check created"),
+            Err(e) => {
+                error!("Error creating link check: {}", e);
+                Err(e)
+            }
+        }
+    }
+
+    pub fn get_all_by_link_id(&self, link_id: usize, limit: usize) -> Result<Vec<LinkCheck>> {
+        let conn = self.open_connection()?;
+        let mut stmt = conn.prepare(
+            "SELECT id, link_id, status_code, error_class, error, latency_ms, final_url, date_create
+                FROM link_checks
+                WHERE link_id = ?
+                ORDER BY id DESC
+                LIMIT ?",
+        )?;
+
+        let rows = stmt.query_map(params![link_id, limit], |row| {
+            Ok(LinkCheck {
+                id: row.get(0)?,
+                link_id: row.get(1)?,
+                status_code: row.get(2)?,
+                error_class: row
+                    .get::<_, Option<String>>(3)?
+                    .map(|class| ErrorClass::parse(&class)),
+                error: row.get(4)?,
+                latency_ms: row.get(5)?,
+                final_url: row.get(6)?,
+                date_create: row.get(7)?,
+            })
+        })?;
+
+        let result: Result<Vec<_>, _> = rows.collect();
+        result
+    }
+}
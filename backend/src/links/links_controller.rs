@@ -1,13 +1,27 @@
 use axum::{
-    extract::{Query, State},
+    extract::{Path, Query, State},
+    http::{header, HeaderMap},
+    middleware,
     response::IntoResponse,
     routing::{delete, get, post},
-    Json, Router,
+    Extension, Json, Router,
 };
 
 use std::sync::Arc;
 
-use super::{dto::*, links_service::LinksService};
+use crate::{
+    auth::{
+        auth_service::{require_read, require_write, AuthService},
+        dto::Access,
+    },
+    config::Config,
+    http_client::HttpClient,
+    storage::storage_service::Storage,
+};
+
+use super::{
+    download_permits::DownloadPermits, dto::*, links_query::LinksQuery, links_service::LinksService,
+};
 
 #[derive(Clone)]
 pub struct LinksController {}
@@ -15,79 +29,176 @@ pub struct LinksController {}
 impl LinksController {
     pub async fn create(
         State(service): State<Arc<LinksService>>,
+        Extension(access): Extension<Access>,
         Json(create_dto): Json<CreateLinkDto>,
     ) -> impl IntoResponse {
-        service.create_one(create_dto).await
+        service.create_one(access, create_dto).await
     }
 
     pub async fn get_all(
         State(service): State<Arc<LinksService>>,
-        Query(query): Query<BooleanQuery>,
+        Extension(access): Extension<Access>,
+        Query(query): Query<LinksQuery>,
     ) -> impl IntoResponse {
-        service
-            .get_all(
-                query.is_reachable.unwrap_or(false),
-                query.show_duplicate.unwrap_or(true),
-            )
-            .await
+        service.get_all(access, query).await
     }
 
     pub async fn remove(
         State(service): State<Arc<LinksService>>,
+        Extension(access): Extension<Access>,
+        Query(query): Query<RemoveLinkQuery>,
+    ) -> impl IntoResponse {
+        service.remove(access, query).await
+    }
+
+    pub async fn restore(
+        State(service): State<Arc<LinksService>>,
+        Extension(access): Extension<Access>,
         Query(query): Query<IdDto>,
     ) -> impl IntoResponse {
-        service.remove(query.id).await
+        service.restore(access, query.id).await
+    }
+
+    pub async fn collect_garbage(
+        State(service): State<Arc<LinksService>>,
+        Extension(access): Extension<Access>,
+        Query(query): Query<GarbageQuery>,
+    ) -> impl IntoResponse {
+        service
+            .collect_garbage(access, query.dry_run.unwrap_or(false), query.files)
+            .await
     }
 
     pub async fn download_files(
         State(service): State<Arc<LinksService>>,
+        Extension(access): Extension<Access>,
         Query(query): Query<IdDto>,
     ) -> impl IntoResponse {
-        service.download(query.id).await
+        service.download(access, query.id).await
     }
 
     pub async fn check_downloaded(
         State(service): State<Arc<LinksService>>,
+        Extension(access): Extension<Access>,
         Query(query): Query<IdDto>,
     ) -> impl IntoResponse {
-        service.check_downloaded(query.id).await
+        service.check_downloaded(access, query.id).await
     }
 
     pub async fn tag_unreachable(
         State(service): State<Arc<LinksService>>,
+        Extension(access): Extension<Access>,
         Query(query): Query<TagUnreachableParams>,
     ) -> impl IntoResponse {
         service
-            .tag_unreachable(query.id, query.is_reachable.unwrap_or(false))
+            .tag_unreachable(access, query.id, query.is_reachable.unwrap_or(false))
             .await
     }
 
     pub async fn scan_files_for_link(
         State(service): State<Arc<LinksService>>,
+        Extension(access): Extension<Access>,
         Query(query): Query<IdDto>,
     ) -> impl IntoResponse {
-        service.scan_files_for_link(query.id).await
+        service.scan_files_for_link(access, query.id).await
     }
 
-    pub async fn scan_files(State(service): State<Arc<LinksService>>) -> impl IntoResponse {
-        service.scan_files().await
+    pub async fn scan_files(
+        State(service): State<Arc<LinksService>>,
+        Extension(access): Extension<Access>,
+    ) -> impl IntoResponse {
+        service.scan_files(access).await
     }
 
     pub async fn add_duplicate(
         State(service): State<Arc<LinksService>>,
+        Extension(access): Extension<Access>,
         Query(query): Query<IdDublicateDto>,
     ) -> impl IntoResponse {
         service
-            .add_duplicate(query.link_id, query.duplicate_id)
+            .add_duplicate(access, query.link_id, query.duplicate_id)
+            .await
+    }
+
+    pub async fn set_check_interval(
+        State(service): State<Arc<LinksService>>,
+        Extension(access): Extension<Access>,
+        Query(query): Query<CheckIntervalParams>,
+    ) -> impl IntoResponse {
+        service
+            .set_check_interval(access, query.id, query.interval)
+            .await
+    }
+
+    pub async fn get_crawls(
+        State(service): State<Arc<LinksService>>,
+        Extension(access): Extension<Access>,
+        Path(id): Path<usize>,
+    ) -> impl IntoResponse {
+        service.get_crawls(access, id).await
+    }
+
+    pub async fn get_checks(
+        State(service): State<Arc<LinksService>>,
+        Extension(access): Extension<Access>,
+        Path(id): Path<usize>,
+        Query(query): Query<LimitQuery>,
+    ) -> impl IntoResponse {
+        service
+            .get_checks(access, id, query.limit.unwrap_or(100))
+            .await
+    }
+
+    pub async fn get_file(
+        State(service): State<Arc<LinksService>>,
+        Extension(access): Extension<Access>,
+        Path((id, name)): Path<(usize, String)>,
+        headers: HeaderMap,
+    ) -> impl IntoResponse {
+        let range = headers
+            .get(header::RANGE)
+            .and_then(|value| value.to_str().ok());
+        service.serve_file(access, id, &name, range).await
+    }
+
+    pub async fn verify(
+        State(service): State<Arc<LinksService>>,
+        Extension(access): Extension<Access>,
+        Query(query): Query<VerifyQuery>,
+    ) -> impl IntoResponse {
+        service
+            .verify(
+                access,
+                query.id,
+                query.redownload_missing.unwrap_or(false),
+                query.redownload_modified.unwrap_or(false),
+            )
             .await
     }
 }
 
-pub fn links_routes() -> Router {
-    Router::new()
-        .route("/links", post(LinksController::create))
+/// Routes are split by the scope they need, several `GET` routes download or change links
+pub fn links_routes(
+    config: Arc<Config>,
+    http: Arc<HttpClient>,
+    storage: Arc<dyn Storage>,
+    download_permits: Arc<DownloadPermits>,
+    auth: Arc<AuthService>,
+) -> Router {
+    let read = Router::new()
         .route("/links", get(LinksController::get_all))
+        .route("/links/:id/crawls", get(LinksController::get_crawls))
+        .route("/links/:id/checks", get(LinksController::get_checks))
+        .route("/links/:id/files/:name", get(LinksController::get_file))
+        .route_layer(middleware::from_fn_with_state(
+            Arc::clone(&auth),
+            require_read,
+        ));
+    let write = Router::new()
+        .route("/links", post(LinksController::create))
         .route("/links", delete(LinksController::remove))
+        .route("/links/restore", get(LinksController::restore))
+        .route("/links/gc", get(LinksController::collect_garbage))
         .route("/links/download", get(LinksController::download_files))
         .route(
             "/links/check_downloaded",
@@ -103,5 +214,20 @@ pub fn links_routes() -> Router {
         )
         .route("/links/scan_files", get(LinksController::scan_files))
         .route("/links/add_duplicate", get(LinksController::add_duplicate))
-        .with_state(Arc::new(LinksService::new()))
+        .route("/links/verify", get(LinksController::verify))
+        .route(
+            "/links/check_interval",
+            get(LinksController::set_check_interval),
+        )
+        .route_layer(middleware::from_fn_with_state(auth, require_write));
+
+    Router::new()
+        .merge(read)
+        .merge(write)
+        .with_state(Arc::new(LinksService::new(
+            config,
+            http,
+            storage,
+            download_permits,
+        )))
 }
@@ -1,29 +1,48 @@
-use super::dto::Link;
-use crate::utils::get_now_time;
+use super::{
+    dto::{GarbageReport, Link, LinksPage, PageMetadata},
+    links_query::{encode_cursor, BuiltQuery},
+};
+use crate::{
+    config::Config,
+    metrics,
+    storage::storage_service::trash_key,
+    tags::tags_db_service::{parse_tags, tags_column, Tagged},
+    utils::get_now_time,
+};
 use log::error;
-use rusqlite::{params, types::Value, Connection, Result};
-use std::env;
+use rusqlite::{
+    params, params_from_iter, types::Value, Connection, OptionalExtension, Result, Row,
+};
+
+/// Mediafiles of the link `?1` that no other link holds
+const ONLY_LINK_MEDIAFILES: &str = "
+    SELECT mediafile_id FROM mediafiles_links WHERE link_id = ?1
+        AND mediafile_id NOT IN (SELECT mediafile_id FROM mediafiles_links WHERE link_id != ?1)";
 
 pub struct LinksDbService {
     db_name: String,
 }
 
 impl LinksDbService {
-    pub fn new() -> Self {
-        let db_name = env::var("DB_NAME").expect("DB_NAME must be set");
-        Self { db_name }
+    pub fn new(config: &Config) -> Self {
+        Self {
+            db_name: config.db_name.clone(),
+        }
     }
 
     fn open_connection(&self) -> Result<Connection> {
-        Connection::open(&self.db_name)
+        let mut conn = Connection::open(&self.db_name)?;
+        conn.profile(Some(metrics::observe_query));
+        Ok(conn)
     }
 
-    pub fn create_one(&self, path: &str, name: &str) -> Result<&str> {
+    #[allow(clippy::needless_return)]
+    pub fn create_one(&self, user_id: usize, path: &str, name: &str) -> Result<&str> {
         let conn = self.open_connection()?;
 
         return match conn.execute(
-            "INSERT INTO links (path, name, is_reachable) VALUES (?, ?, 1)",
-            params![path, name],
+            "INSERT INTO links (user_id, path, name, is_reachable) VALUES (?, ?, ?, 1)",
+            params![user_id, path, name],
         ) {
             Ok(changes) => {
                 if changes == 1 {
@@ -39,82 +58,283 @@ impl LinksDbService {
         };
     }
 
-    pub fn get_all(&self, is_reachable: bool, show_duplicate: bool) -> Result<Vec<Link>> {
+    /// One page of links matching the query, with the total number of matching links
+    pub fn get_page(&self, query: &BuiltQuery) -> Result<LinksPage> {
         let conn = self.open_connection()?;
-        let query = if show_duplicate {
-            "SELECT l.*, d.path AS duplicate_path 
-                FROM links AS l
-                LEFT JOIN links AS d
-                ON l.duplicate_id = d.id
-                WHERE l.is_reachable = ? AND l.duplicate_id IS NOT NULL 
-                ORDER BY l.is_downloaded"
+
+        let total: usize = conn.query_row(
+            &query.count,
+            params_from_iter(query.count_values.iter()),
+            |row| row.get(0),
+        )?;
+
+        let mut stmt = conn.prepare(&query.select)?;
+        let rows = stmt.query_map(params_from_iter(query.select_values.iter()), |row| {
+            let sort_values = (0..query.sort_keys)
+                .map(|i| row.get::<_, Value>(format!("sort_{}", i).as_str()))
+                .collect::<Result<Vec<_>>>()?;
+            Ok((map_link(row)?, sort_values))
+        })?;
+        let mut rows = rows.collect::<Result<Vec<_>>>()?;
+
+        let next_cursor = if rows.len() > query.limit {
+            rows.truncate(query.limit);
+            rows.last()
+                .map(|(_, sort_values)| encode_cursor(sort_values))
         } else {
-            "SELECT * 
-                FROM links 
-                WHERE is_reachable = ? AND duplicate_id IS NULL 
-                ORDER BY is_downloaded"
+            None
+        };
+
+        Ok(LinksPage {
+            items: rows.into_iter().map(|(link, _)| link).collect(),
+            total,
+            limit: query.limit,
+            offset: query.offset,
+            next_cursor,
+        })
+    }
+
+    /// Returns every link regardless of reachability or duplicate state
+    pub fn get_list(&self) -> Result<Vec<Link>> {
+        let conn = self.open_connection()?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT *, {} FROM links WHERE deleted_at IS NULL ORDER BY id",
+            tags_column(Tagged::Links, "links.id")
+        ))?;
+
+        let rows = stmt.query_map([], map_link)?;
+
+        let result: Result<Vec<_>, _> = rows.collect();
+        result
+    }
+
+    /// Moves the link to the trash with the mediafiles no other link in use holds, returns the
+    /// paths of those mediafiles, none when the link was not changed
+    pub fn soft_remove(&self, id: usize) -> Result<Option<Vec<String>>> {
+        let mut conn = self.open_connection()?;
+        let tx = conn.transaction()?;
+        let now = get_now_time();
+
+        let changes = tx.execute(
+            "UPDATE links SET deleted_at = ? WHERE id = ? AND deleted_at IS NULL",
+            params![now, id],
+        )?;
+        if changes == 0 {
+            return Ok(None);
+        }
+
+        let paths = {
+            let mut stmt = tx.prepare(
+                "UPDATE mediafiles SET deleted_at = ?2
+                    WHERE deleted_at IS NULL AND id IN (
+                        SELECT mediafile_id FROM mediafiles_links WHERE link_id = ?1
+                            AND mediafile_id NOT IN (
+                                SELECT ml.mediafile_id FROM mediafiles_links ml
+                                JOIN links l ON l.id = ml.link_id
+                                WHERE l.id != ?1 AND l.deleted_at IS NULL
+                            )
+                    )
+                    RETURNING path",
+            )?;
+            let rows = stmt.query_map(params![id, now], |row| row.get(0))?;
+            rows.collect::<Result<Vec<String>>>()?
         };
-        let mut stmt = conn.prepare(query)?;
-
-        let rows = stmt.query_map([is_reachable], |row| {
-            Ok(Link {
-                id: row.get(0)?,
-                path: row.get(1)?,
-                name: row.get(2)?,
-                is_downloaded: row.get(3)?,
-                progress: row.get(4)?,
-                downloaded_mediafiles: row.get(5)?,
-                mediafiles: row.get(6)?,
-                date_update: row.get(7)?,
-                date_create: row.get(8)?,
-                is_reachable: row.get(9)?,
-                duplicate_id: row.get(10)?,
-                duplicate_path: row.get(11).ok(),
+        tx.commit()?;
+
+        Ok(Some(paths))
+    }
+
+    /// Takes the link out of the trash with its mediafiles trashed since, returns the paths of
+    /// those mediafiles, none when the link was not in the trash
+    pub fn restore(&self, id: usize) -> Result<Option<Vec<String>>> {
+        let mut conn = self.open_connection()?;
+        let tx = conn.transaction()?;
+
+        let deleted_at: Option<String> = tx
+            .query_row("SELECT deleted_at FROM links WHERE id = ?", [id], |row| {
+                row.get(0)
             })
-        })?;
+            .optional()?
+            .flatten();
+        let Some(deleted_at) = deleted_at else {
+            return Ok(None);
+        };
+
+        tx.execute("UPDATE links SET deleted_at = NULL WHERE id = ?", [id])?;
+        let paths = {
+            let mut stmt = tx.prepare(
+                "UPDATE mediafiles SET deleted_at = NULL
+                    WHERE deleted_at >= ?2
+                        AND id IN (SELECT mediafile_id FROM mediafiles_links WHERE link_id = ?1)
+                    RETURNING path",
+            )?;
+            let rows = stmt.query_map(params![id, deleted_at], |row| row.get(0))?;
+            rows.collect::<Result<Vec<String>>>()?
+        };
+        tx.commit()?;
+
+        Ok(Some(paths))
+    }
+
+    /// Links in the trash, of one owner when `user_id` is set, recently trashed first
+    pub fn get_trashed(&self, user_id: Option<usize>) -> Result<Vec<Link>> {
+        let conn = self.open_connection()?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT *, {} FROM links
+                WHERE deleted_at IS NOT NULL AND (?1 IS NULL OR user_id = ?1)
+                ORDER BY deleted_at DESC, id",
+            tags_column(Tagged::Links, "links.id")
+        ))?;
 
+        let rows = stmt.query_map([user_id], map_link)?;
         let result: Result<Vec<_>, _> = rows.collect();
         result
     }
 
-    pub fn remove(&self, id: usize) -> Result<&str> {
+    /// Ids of links trashed before `before`
+    pub fn get_trashed_before(&self, before: &str) -> Result<Vec<usize>> {
         let conn = self.open_connection()?;
+        let mut stmt =
+            conn.prepare("SELECT id FROM links WHERE deleted_at < ? ORDER BY deleted_at")?;
 
-        return match conn.execute("DELETE FROM links WHERE id = ?", [id]) {
-            Ok(changes) => {
-                if changes == 1 {
-                    Ok("One path removed")
-                } else {
-                    Ok("No path removed")
-                }
+        let rows = stmt.query_map([before], |row| row.get(0))?;
+        let result: Result<Vec<_>, _> = rows.collect();
+        result
+    }
+
+    /// Removes the link with everything recorded for it in one transaction. Mediafiles other
+    /// links hold stay, the others are removed and their files returned with the cover,
+    /// files of trashed mediafiles by their key in the trash
+    pub fn remove(&self, id: usize) -> Result<Vec<String>> {
+        let mut conn = self.open_connection()?;
+        let tx = conn.transaction()?;
+
+        let mut files: Vec<String> = {
+            let mut stmt = tx.prepare(&format!(
+                "SELECT path, deleted_at FROM mediafiles WHERE id IN ({})",
+                ONLY_LINK_MEDIAFILES
+            ))?;
+            let rows = stmt.query_map([id], stored_key)?;
+            rows.collect::<Result<_>>()?
+        };
+        let cover: Option<String> = tx
+            .query_row("SELECT cover_path FROM links WHERE id = ?", [id], |row| {
+                row.get(0)
+            })
+            .optional()?
+            .flatten();
+        files.extend(cover);
+
+        tx.execute(
+            &format!(
+                "DELETE FROM mediafile_tags WHERE mediafile_id IN ({})",
+                ONLY_LINK_MEDIAFILES
+            ),
+            [id],
+        )?;
+        tx.execute(
+            &format!(
+                "DELETE FROM mediafiles WHERE id IN ({})",
+                ONLY_LINK_MEDIAFILES
+            ),
+            [id],
+        )?;
+        tx.execute("DELETE FROM mediafiles_links WHERE link_id = ?", [id])?;
+        tx.execute("DELETE FROM link_tags WHERE link_id = ?", [id])?;
+        tx.execute("DELETE FROM collection_links WHERE link_id = ?", [id])?;
+        tx.execute("DELETE FROM link_checks WHERE link_id = ?", [id])?;
+        tx.execute(
+            "DELETE FROM crawl_items WHERE crawl_id IN (SELECT id FROM crawls WHERE link_id = ?)",
+            [id],
+        )?;
+        tx.execute("DELETE FROM crawls WHERE link_id = ?", [id])?;
+        tx.execute(
+            "UPDATE links SET duplicate_id = NULL WHERE duplicate_id = ?",
+            [id],
+        )?;
+        match tx.execute("DELETE FROM links WHERE id = ?", [id]) {
+            Ok(_) => {
+                tx.commit()?;
+                Ok(files)
             }
             Err(e) => {
                 error!("Error removing path: {}", e);
                 Err(e)
             }
+        }
+    }
+
+    /// Removes records left behind by links and mediafiles that no longer exist, and
+    /// mediafiles no link holds. Nothing is changed on a dry run
+    pub fn collect_garbage(&self, dry_run: bool) -> Result<GarbageReport> {
+        let mut conn = self.open_connection()?;
+        let tx = conn.transaction()?;
+        let mut report = GarbageReport::default();
+
+        for statement in [
+            "DELETE FROM mediafiles_links WHERE link_id NOT IN (SELECT id FROM links)
+                OR mediafile_id NOT IN (SELECT id FROM mediafiles)",
+            "DELETE FROM link_tags WHERE link_id NOT IN (SELECT id FROM links)",
+            "DELETE FROM collection_links WHERE link_id NOT IN (SELECT id FROM links)",
+            "DELETE FROM link_checks WHERE link_id NOT IN (SELECT id FROM links)",
+            "DELETE FROM crawls WHERE link_id NOT IN (SELECT id FROM links)",
+            "DELETE FROM crawl_items WHERE crawl_id NOT IN (SELECT id FROM crawls)",
+            "UPDATE links SET duplicate_id = NULL
+                WHERE duplicate_id IS NOT NULL AND duplicate_id NOT IN (SELECT id FROM links)",
+        ] {
+            report.rows += tx.execute(statement, [])?;
+        }
+
+        report.files = {
+            let mut stmt = tx.prepare(
+                "SELECT path, deleted_at FROM mediafiles
+                    WHERE id NOT IN (SELECT mediafile_id FROM mediafiles_links)",
+            )?;
+            let rows = stmt.query_map([], stored_key)?;
+            rows.collect::<Result<_>>()?
         };
+        report.mediafiles = tx.execute(
+            "DELETE FROM mediafiles WHERE id NOT IN (SELECT mediafile_id FROM mediafiles_links)",
+            [],
+        )?;
+        report.rows += tx.execute(
+            "DELETE FROM mediafile_tags WHERE mediafile_id NOT IN (SELECT id FROM mediafiles)",
+            [],
+        )?;
+
+        if !dry_run {
+            tx.commit()?;
+        }
+        Ok(report)
     }
 
     pub fn get_one(&self, id: usize) -> Result<Option<Link>> {
         let conn = self.open_connection()?;
-        let mut stmt = conn.prepare("SELECT * FROM links WHERE id = ?")?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT *, {} FROM links WHERE id = ?",
+            tags_column(Tagged::Links, "links.id")
+        ))?;
         let mut rows = stmt.query([id])?;
 
         if let Some(row) = rows.next()? {
-            let link = Link {
-                id: row.get(0)?,
-                path: row.get(1)?,
-                name: row.get(2)?,
-                is_downloaded: row.get(3)?,
-                progress: row.get(4)?,
-                downloaded_mediafiles: row.get(5)?,
-                mediafiles: row.get(6)?,
-                date_update: row.get(7)?,
-                date_create: row.get(8)?,
-                is_reachable: row.get(9)?,
-                duplicate_id: row.get(10)?,
-                duplicate_path: None,
-            };
+            let link = map_link(row)?;
+            Ok(Some(link))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Link of the owner with the path, paths are unique per owner
+    pub fn get_by_path(&self, user_id: usize, path: &str) -> Result<Option<Link>> {
+        let conn = self.open_connection()?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT *, {} FROM links WHERE user_id = ? AND path = ?",
+            tags_column(Tagged::Links, "links.id")
+        ))?;
+        let mut rows = stmt.query(params![user_id, path])?;
+
+        if let Some(row) = rows.next()? {
+            let link = map_link(row)?;
             Ok(Some(link))
         } else {
             Ok(None)
@@ -169,6 +389,109 @@ impl LinksDbService {
         })
     }
 
+    pub fn set_check_interval(&self, id: usize, check_interval: Option<&str>) -> Result<String> {
+        let conn = self.open_connection()?;
+        let changes = conn.execute(
+            "UPDATE links SET check_interval = ?, next_check_at = NULL, date_update = ? WHERE id = ?",
+            params![check_interval, get_now_time(), id],
+        )?;
+
+        Ok(if changes == 1 {
+            format!(
+                "Check interval of link id {} set to {}",
+                id,
+                check_interval.unwrap_or("default")
+            )
+        } else {
+            "No path updated".to_string()
+        })
+    }
+
+    /// Returns links whose next check time has come or was never planned
+    pub fn get_due_for_check(&self, now: &str) -> Result<Vec<Link>> {
+        let conn = self.open_connection()?;
+        let mut stmt = conn.prepare(
+            "SELECT * FROM links
+                WHERE deleted_at IS NULL AND (next_check_at IS NULL OR next_check_at <= ?)
+                ORDER BY next_check_at",
+        )?;
+
+        let rows = stmt.query_map([now], map_link)?;
+
+        let result: Result<Vec<_>, _> = rows.collect();
+        result
+    }
+
+    /// Applies the reachability policy to the outcome of a page request: a link becomes
+    /// unreachable on a permanent failure or after `unreachable_after` consecutive failures,
+    /// and reachable again on success
+    pub fn update_reachability(
+        &self,
+        id: usize,
+        page_found: bool,
+        permanent_failure: bool,
+        unreachable_after: usize,
+    ) -> Result<()> {
+        let conn = self.open_connection()?;
+
+        conn.execute(
+            "UPDATE links
+                SET is_reachable = CASE
+                        WHEN ?1 THEN 1
+                        WHEN ?2 OR check_failures + 1 >= ?3 THEN 0
+                        ELSE is_reachable
+                    END,
+                    check_failures = CASE WHEN ?1 THEN 0 ELSE check_failures + 1 END
+                WHERE id = ?4",
+            params![page_found, permanent_failure, unreachable_after, id],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn plan_next_check(&self, id: usize, next_check_at: &str) -> Result<()> {
+        let conn = self.open_connection()?;
+
+        conn.execute(
+            "UPDATE links SET last_checked_at = ?, next_check_at = ? WHERE id = ?",
+            params![get_now_time(), next_check_at, id],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn update_metadata(&self, id: usize, metadata: &PageMetadata) -> Result<()> {
+        let conn = self.open_connection()?;
+
+        conn.execute(
+            "UPDATE links
+                SET title = ?, og_title = ?, description = ?, og_image = ?, author = ?, published_at = ?
+                WHERE id = ?",
+            params![
+                metadata.title,
+                metadata.og_title,
+                metadata.description,
+                metadata.og_image,
+                metadata.author,
+                metadata.published_at,
+                id
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn set_cover(&self, id: usize, cover_path: Option<&str>) -> Result<()> {
+        let conn = self.open_connection()?;
+
+        conn.execute(
+            "UPDATE links SET cover_path = ? WHERE id = ?",
+            params![cover_path, id],
+        )?;
+
+        Ok(())
+    }
+
     pub fn update_files_number(
         &self,
         id: usize,
@@ -192,3 +515,48 @@ impl LinksDbService {
         })
     }
 }
+
+fn map_link(row: &Row) -> Result<Link> {
+    Ok(Link {
+        id: row.get("id")?,
+        user_id: row.get("user_id")?,
+        path: row.get("path")?,
+        name: row.get("name")?,
+        is_downloaded: row.get("is_downloaded")?,
+        progress: row.get("progress")?,
+        downloaded_mediafiles: row.get("downloaded_mediafiles")?,
+        mediafiles: row.get("mediafiles")?,
+        date_update: row.get("date_update")?,
+        date_create: row.get("date_create")?,
+        is_reachable: row.get("is_reachable")?,
+        duplicate_id: row.get("duplicate_id")?,
+        duplicate_path: row.get("duplicate_path").ok(),
+        check_interval: row.get("check_interval")?,
+        last_checked_at: row.get("last_checked_at")?,
+        next_check_at: row.get("next_check_at")?,
+        check_failures: row.get("check_failures")?,
+        display_name: row
+            .get::<_, Option<String>>("og_title")?
+            .or(row.get("title")?)
+            .unwrap_or(row.get("name")?),
+        title: row.get("title")?,
+        og_title: row.get("og_title")?,
+        description: row.get("description")?,
+        og_image: row.get("og_image")?,
+        author: row.get("author")?,
+        published_at: row.get("published_at")?,
+        cover_path: row.get("cover_path")?,
+        deleted_at: row.get("deleted_at")?,
+        tags: parse_tags(row),
+    })
+}
+
+/// Key of the file of a mediafile row, in the trash when the mediafile is trashed
+fn stored_key(row: &Row) -> Result<String> {
+    let path: String = row.get("path")?;
+    let deleted_at: Option<String> = row.get("deleted_at")?;
+    Ok(match deleted_at {
+        Some(_) => trash_key(&path),
+        None => path,
+    })
+}
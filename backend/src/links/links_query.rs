@@ -0,0 +1,393 @@
+use crate::tags::{
+    dto::TagFilter,
+    tags_db_service::{tag_filter_conditions, tags_column, Tagged},
+};
+use rusqlite::types::Value;
+use serde::Deserialize;
+
+const DEFAULT_LIMIT: usize = 100;
+const MAX_LIMIT: usize = 1000;
+
+/// Query string of `GET /links`, every filter is optional
+#[derive(Debug, Default, Deserialize)]
+pub struct LinksQuery {
+    /// Full-text search on name, path, title and description
+    pub q: Option<String>,
+    #[serde(rename = "isReachable")]
+    pub is_reachable: Option<bool>,
+    #[serde(rename = "isDownloaded")]
+    pub is_downloaded: Option<bool>,
+    /// Only duplicates when true, only originals when false
+    #[serde(rename = "isDuplicate")]
+    pub is_duplicate: Option<bool>,
+    #[serde(rename = "minProgress")]
+    pub min_progress: Option<usize>,
+    #[serde(rename = "maxProgress")]
+    pub max_progress: Option<usize>,
+    /// Links of the host and its subdomains
+    pub host: Option<String>,
+    #[serde(rename = "createdFrom")]
+    pub created_from: Option<String>,
+    #[serde(rename = "createdTo")]
+    pub created_to: Option<String>,
+    #[serde(rename = "updatedFrom")]
+    pub updated_from: Option<String>,
+    #[serde(rename = "updatedTo")]
+    pub updated_to: Option<String>,
+    /// Comma separated tags the links must all have
+    pub tags: Option<String>,
+    /// Comma separated tags the links must have at least one of
+    #[serde(rename = "anyTags")]
+    pub any_tags: Option<String>,
+    /// Comma separated tags the links must not have
+    #[serde(rename = "notTags")]
+    pub not_tags: Option<String>,
+    #[serde(rename = "collectionId")]
+    pub collection_id: Option<usize>,
+    /// Links of one owner, always the requesting user unless it is an admin
+    #[serde(rename = "userId")]
+    pub user_id: Option<usize>,
+    /// Comma separated sort fields, `-` in front sorts descending, like `-dateCreate,name`
+    pub sort: Option<String>,
+    pub limit: Option<usize>,
+    pub offset: Option<usize>,
+    /// `nextCursor` of the previous page, takes precedence over `offset`
+    pub cursor: Option<String>,
+}
+
+/// Statements and parameters of one page of links
+pub struct BuiltQuery {
+    /// Selects the page, the sort key values follow the link columns as `sort_0..sort_n`
+    pub select: String,
+    pub select_values: Vec<Value>,
+    pub count: String,
+    pub count_values: Vec<Value>,
+    pub sort_keys: usize,
+    pub limit: usize,
+    pub offset: Option<usize>,
+}
+
+/// Sortable fields with their SQL expressions, nulls are mapped to a value to keep cursors simple
+const SORT_FIELDS: &[(&str, &str)] = &[
+    ("id", "l.id"),
+    ("name", "COALESCE(l.name, '')"),
+    ("displayName", "COALESCE(l.og_title, l.title, l.name, '')"),
+    ("path", "l.path"),
+    ("title", "COALESCE(l.title, '')"),
+    ("progress", "COALESCE(l.progress, 0)"),
+    ("mediafiles", "COALESCE(l.mediafiles, 0)"),
+    (
+        "downloadedMediafiles",
+        "COALESCE(l.downloaded_mediafiles, 0)",
+    ),
+    ("isDownloaded", "l.is_downloaded"),
+    ("isReachable", "l.is_reachable"),
+    ("dateCreate", "COALESCE(l.date_create, '')"),
+    ("dateUpdate", "COALESCE(l.date_update, '')"),
+    ("lastCheckedAt", "COALESCE(l.last_checked_at, '')"),
+    ("publishedAt", "COALESCE(l.published_at, '')"),
+];
+
+impl LinksQuery {
+    pub fn build(&self) -> Result<BuiltQuery, String> {
+        let limit = self.limit.unwrap_or(DEFAULT_LIMIT);
+        if limit == 0 || limit > MAX_LIMIT {
+            return Err(format!("Limit must be between 1 and {}", MAX_LIMIT));
+        }
+
+        let (mut conditions, mut values) = self.conditions()?;
+        let count = format!(
+            "SELECT COUNT(*) FROM links AS l WHERE {}",
+            join_conditions(&conditions)
+        );
+        let count_values = values.clone();
+
+        let sort = self.sort_keys()?;
+        if let Some(cursor) = &self.cursor {
+            let cursor_values = decode_cursor(cursor)?;
+            if cursor_values.len() != sort.len() {
+                return Err("Cursor does not match the sort".to_string());
+            }
+            let (condition, condition_values) = keyset_condition(&sort, cursor_values);
+            conditions.push(condition);
+            values.extend(condition_values);
+        }
+
+        let order_by = sort
+            .iter()
+            .map(|(expression, descending)| {
+                format!(
+                    "{} {}",
+                    expression,
+                    if *descending { "DESC" } else { "ASC" }
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        let sort_columns = sort
+            .iter()
+            .enumerate()
+            .map(|(i, (expression, _))| format!("{} AS sort_{}", expression, i))
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        // One row more than the limit tells whether there is a next page
+        let mut select = format!(
+            "SELECT l.*, d.path AS duplicate_path, {}, {}
+                FROM links AS l
+                LEFT JOIN links AS d ON l.duplicate_id = d.id
+                WHERE {}
+                ORDER BY {}
+                LIMIT {}",
+            tags_column(Tagged::Links, "l.id"),
+            sort_columns,
+            join_conditions(&conditions),
+            order_by,
+            limit + 1
+        );
+        let offset = if self.cursor.is_none() {
+            self.offset
+        } else {
+            None
+        };
+        if let Some(offset) = offset {
+            select.push_str(&format!(" OFFSET {}", offset));
+        }
+
+        Ok(BuiltQuery {
+            select,
+            select_values: values,
+            count,
+            count_values,
+            sort_keys: sort.len(),
+            limit,
+            offset,
+        })
+    }
+
+    fn conditions(&self) -> Result<(Vec<String>, Vec<Value>), String> {
+        let tag_filter = TagFilter::from_query(
+            self.tags.as_deref(),
+            self.any_tags.as_deref(),
+            self.not_tags.as_deref(),
+        );
+        let (mut conditions, mut values) =
+            tag_filter_conditions(&tag_filter, Tagged::Links, "l.id");
+        conditions.push("l.deleted_at IS NULL".to_string());
+
+        if let Some(q) = self.q.as_deref().and_then(fts_query) {
+            conditions
+                .push("l.id IN (SELECT rowid FROM links_fts WHERE links_fts MATCH ?)".to_string());
+            values.push(Value::from(q));
+        }
+
+        if let Some(is_reachable) = self.is_reachable {
+            conditions.push("l.is_reachable = ?".to_string());
+            values.push(Value::from(is_reachable));
+        }
+
+        if let Some(is_downloaded) = self.is_downloaded {
+            conditions.push("l.is_downloaded = ?".to_string());
+            values.push(Value::from(is_downloaded));
+        }
+
+        match self.is_duplicate {
+            Some(true) => conditions.push("l.duplicate_id IS NOT NULL".to_string()),
+            Some(false) => conditions.push("l.duplicate_id IS NULL".to_string()),
+            None => {}
+        }
+
+        if let Some(min_progress) = self.min_progress {
+            conditions.push("COALESCE(l.progress, 0) >= ?".to_string());
+            values.push(Value::from(min_progress as i64));
+        }
+
+        if let Some(max_progress) = self.max_progress {
+            conditions.push("COALESCE(l.progress, 0) <= ?".to_string());
+            values.push(Value::from(max_progress as i64));
+        }
+
+        if let Some(host) = self
+            .host
+            .as_deref()
+            .map(str::trim)
+            .filter(|host| !host.is_empty())
+        {
+            let host = host
+                .to_lowercase()
+                .replace('\\', "\\\\")
+                .replace('%', "\\%")
+                .replace('_', "\\_");
+            let patterns = [
+                "://{}",
+                "://{}/%",
+                "://{}:%",
+                "://%.{}",
+                "://%.{}/%",
+                "://%.{}:%",
+            ];
+            conditions.push(format!(
+                "({})",
+                vec!["l.path LIKE ? ESCAPE '\\'"; patterns.len()].join(" OR ")
+            ));
+            values.extend(
+                patterns
+                    .iter()
+                    .map(|pattern| Value::from(format!("%{}", pattern.replace("{}", &host)))),
+            );
+        }
+
+        for (column, from, to) in [
+            ("l.date_create", &self.created_from, &self.created_to),
+            ("l.date_update", &self.updated_from, &self.updated_to),
+        ] {
+            if let Some(from) = from {
+                conditions.push(format!("{} >= ?", column));
+                values.push(Value::from(from.trim().to_string()));
+            }
+            if let Some(to) = to {
+                // A bare date includes the whole day
+                let to = to.trim();
+                let to = if to.len() == 10 {
+                    format!("{} 23:59:59", to)
+                } else {
+                    to.to_string()
+                };
+                conditions.push(format!("{} <= ?", column));
+                values.push(Value::from(to));
+            }
+        }
+
+        if let Some(collection_id) = self.collection_id {
+            conditions.push(
+                "l.id IN (SELECT link_id FROM collection_links WHERE collection_id = ?)"
+                    .to_string(),
+            );
+            values.push(Value::from(collection_id as i64));
+        }
+
+        if let Some(user_id) = self.user_id {
+            conditions.push("l.user_id = ?".to_string());
+            values.push(Value::from(user_id as i64));
+        }
+
+        Ok((conditions, values))
+    }
+
+    /// Sort expressions with their direction, `id` is always the last key to make the order total
+    fn sort_keys(&self) -> Result<Vec<(&'static str, bool)>, String> {
+        let mut keys = Vec::new();
+
+        for field in self.sort.as_deref().unwrap_or("id").split(',') {
+            let field = field.trim();
+            if field.is_empty() {
+                continue;
+            }
+            let (name, descending) = match field.strip_prefix('-') {
+                Some(name) => (name, true),
+                None => (field.strip_prefix('+').unwrap_or(field), false),
+            };
+            let expression = SORT_FIELDS
+                .iter()
+                .find(|(field, _)| *field == name)
+                .map(|(_, expression)| *expression)
+                .ok_or_else(|| format!("Unknown sort field {}", name))?;
+            keys.push((expression, descending));
+        }
+
+        if !keys.iter().any(|(expression, _)| *expression == "l.id") {
+            keys.push(("l.id", false));
+        }
+
+        Ok(keys)
+    }
+}
+
+fn join_conditions(conditions: &[String]) -> String {
+    if conditions.is_empty() {
+        "1".to_string()
+    } else {
+        conditions.join(" AND ")
+    }
+}
+
+/// FTS5 query matching every word of the search as a prefix, `None` for a blank search
+fn fts_query(q: &str) -> Option<String> {
+    let words: Vec<String> = q
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| format!("\"{}\"*", word))
+        .collect();
+
+    (!words.is_empty()).then(|| words.join(" "))
+}
+
+/// Rows after the cursor row in the sort order:
+/// `k1 > v1 OR (k1 = v1 AND k2 > v2) OR ...` with `<` for descending keys
+fn keyset_condition(sort: &[(&str, bool)], cursor_values: Vec<Value>) -> (String, Vec<Value>) {
+    let mut alternatives = Vec::new();
+    let mut values = Vec::new();
+
+    for (i, (expression, descending)) in sort.iter().enumerate() {
+        let mut parts = Vec::new();
+        for (j, (previous, _)) in sort[..i].iter().enumerate() {
+            parts.push(format!("{} = ?", previous));
+            values.push(cursor_values[j].clone());
+        }
+        parts.push(format!(
+            "{} {} ?",
+            expression,
+            if *descending { "<" } else { ">" }
+        ));
+        values.push(cursor_values[i].clone());
+        alternatives.push(format!("({})", parts.join(" AND ")));
+    }
+
+    (format!("({})", alternatives.join(" OR ")), values)
+}
+
+/// Opaque cursor holding the sort key values of the last row of a page
+pub fn encode_cursor(values: &[Value]) -> String {
+    let json: Vec<serde_json::Value> = values
+        .iter()
+        .map(|value| match value {
+            Value::Integer(value) => serde_json::Value::from(*value),
+            Value::Real(value) => serde_json::Value::from(*value),
+            Value::Text(value) => serde_json::Value::from(value.clone()),
+            _ => serde_json::Value::Null,
+        })
+        .collect();
+
+    serde_json::to_string(&json)
+        .unwrap_or_default()
+        .bytes()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn decode_cursor(cursor: &str) -> Result<Vec<Value>, String> {
+    let invalid = || "Invalid cursor".to_string();
+
+    let bytes = (0..cursor.len())
+        .step_by(2)
+        .map(|i| {
+            cursor
+                .get(i..i + 2)
+                .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+        })
+        .collect::<Option<Vec<u8>>>()
+        .ok_or_else(invalid)?;
+    let json: Vec<serde_json::Value> = serde_json::from_slice(&bytes).map_err(|_| invalid())?;
+
+    json.into_iter()
+        .map(|value| match value {
+            serde_json::Value::Number(number) => number
+                .as_i64()
+                .map(Value::Integer)
+                .or(number.as_f64().map(Value::Real))
+                .ok_or_else(invalid),
+            serde_json::Value::String(text) => Ok(Value::Text(text)),
+            _ => Err(invalid()),
+        })
+        .collect()
+}
@@ -1,51 +1,118 @@
-use super::config;
 use crate::{
+    auth::{auth_service::AuthService, dto::Access},
+    config::Config,
+    crawls::crawls_service::CrawlsService,
+    extract,
+    http_client::{HttpClient, ReadError},
     mediafiles::{
-        dto::CreateDto,
-        mediafiles_service::{download_file, get_hash_size_by_path, MediafilesService},
+        dto::{CreateDto, DownloadError, Mediafile},
+        mediafiles_service::MediafilesService,
+    },
+    metrics::{self, GaugeGuard},
+    pagination::{self, GalleryPage},
+    rewrite,
+    sites::{dto::Site, sites_service::SitesService},
+    storage::{
+        dto::FileDisposal,
+        storage_service::{
+            content_key, dispose, get_hash_size, is_content_key, key_name, move_to_store,
+            move_to_trash, object_key, trash_key, Storage, INCOMING_DIR, TRASH_DIR,
+        },
+    },
+    utils::{
+        error_response, get_now_time, get_time_after, next_job_id, parse_interval,
+        server_error_response, success_response,
     },
-    utils::{error_response, server_error_response, success_response},
 };
-use axum::{http::StatusCode, response::IntoResponse, Json};
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
 use futures::stream::{FuturesUnordered, StreamExt};
 use log::{debug, error, info, warn};
 use regex::Regex;
-use reqwest;
-use select::{
-    document::Document,
-    predicate::{Name, Or},
-};
+use reqwest::Url;
 use std::{
-    collections::HashSet,
-    fs::{create_dir_all, read_dir},
-    path::{Path, PathBuf},
+    collections::{HashMap, HashSet},
+    path::Path,
     sync::Arc,
+    time::Instant,
 };
 use tokio::spawn;
+use tracing::{info_span, instrument, Instrument};
 
-use super::dto::CreateLinkDto;
+use super::download_permits::DownloadPermits;
+use super::dto::{
+    CreateLinkDto, ErrorClass, GarbageReport, IResult, Link, PageFetch, RemoveLinkQuery,
+    VerifyReport,
+};
+use super::link_checks_db_service::LinkChecksDbService;
 use super::links_db_service::LinksDbService;
+use super::links_query::LinksQuery;
+
+/// Directory inside the storage root holding link covers, named by link id
+const COVERS_DIR: &str = ".covers";
 
 #[derive(Clone)]
 pub struct LinksService {
     links_db_service: Arc<LinksDbService>,
+    link_checks_db_service: Arc<LinkChecksDbService>,
     mediafiles_service: Arc<MediafilesService>,
+    crawls_service: Arc<CrawlsService>,
+    sites_service: Arc<SitesService>,
+    auth_service: Arc<AuthService>,
+    config: Arc<Config>,
+    http: Arc<HttpClient>,
+    storage: Arc<dyn Storage>,
+    download_permits: Arc<DownloadPermits>,
 }
 
 impl LinksService {
-    pub fn new() -> Self {
+    pub fn new(
+        config: Arc<Config>,
+        http: Arc<HttpClient>,
+        storage: Arc<dyn Storage>,
+        download_permits: Arc<DownloadPermits>,
+    ) -> Self {
         Self {
-            links_db_service: Arc::new(LinksDbService::new()),
-            mediafiles_service: Arc::new(MediafilesService::new()),
+            links_db_service: Arc::new(LinksDbService::new(&config)),
+            link_checks_db_service: Arc::new(LinkChecksDbService::new(&config)),
+            mediafiles_service: Arc::new(MediafilesService::new(
+                &config,
+                Arc::clone(&http),
+                Arc::clone(&storage),
+            )),
+            crawls_service: Arc::new(CrawlsService::new(&config)),
+            sites_service: Arc::new(SitesService::new(Arc::clone(&config))),
+            auth_service: Arc::new(AuthService::new(&config)),
+            config,
+            http,
+            storage,
+            download_permits,
         }
     }
 
-    pub async fn create_one(&self, dto: CreateLinkDto) -> impl IntoResponse {
+    /// Creates a link owned by the requesting user
+    pub async fn create_one(&self, access: Access, dto: CreateLinkDto) -> impl IntoResponse {
         if let Some(url_parts) = check_url(&dto.path) {
             let name = url_parts[1].trim();
             info!("creating link, name: {}, path: {}", &name, &dto.path);
 
-            match &self.links_db_service.create_one(&dto.path, name) {
+            // Adding a link again takes it back out of the trash with its files and records
+            if let Ok(Some(link)) = self.links_db_service.get_by_path(access.user_id, &dto.path) {
+                if link.deleted_at.is_some() {
+                    return match self.restore_link(link.id).await {
+                        Ok(m) => Ok(success_response(m)),
+                        Err(e) => Err(server_error_response(e)),
+                    };
+                }
+            }
+
+            match &self
+                .links_db_service
+                .create_one(access.user_id, &dto.path, name)
+            {
                 Ok(m) => Ok(success_response(m.to_string())),
                 Err(e) => Err(server_error_response(e.to_string())),
             }
@@ -57,110 +124,308 @@ impl LinksService {
         }
     }
 
-    pub async fn get_all(&self, is_reachable: bool, show_duplicate: bool) -> impl IntoResponse {
-        info!("Getting all links is_reachable: {}", &is_reachable);
+    /// Links of the requesting user, admins see every link unless they filter by `userId`
+    pub async fn get_all(&self, access: Access, mut query: LinksQuery) -> impl IntoResponse {
+        if !access.is_admin {
+            query.user_id = Some(access.user_id);
+        }
+        info!("Getting links {:?}", &query);
+
+        let query = query
+            .build()
+            .map_err(|e| error_response(e, StatusCode::BAD_REQUEST))?;
 
-        match self.links_db_service.get_all(is_reachable, show_duplicate) {
-            Ok(links) => return Ok((StatusCode::OK, Json(links))),
+        match self.links_db_service.get_page(&query) {
+            Ok(page) => Ok((StatusCode::OK, Json(page))),
             Err(e) => {
                 error!("Error getting links: {}", e);
-                return Err(server_error_response("Error getting links".to_string()));
+                Err(server_error_response("Error getting links".to_string()))
             }
-        };
+        }
+    }
+
+    /// Links with the given ids in id order, every link when `ids` is empty
+    pub fn get_links(&self, ids: &[usize]) -> Result<Vec<Link>, String> {
+        if ids.is_empty() {
+            return self.links_db_service.get_list().map_err(|e| e.to_string());
+        }
+
+        ids.iter()
+            .map(|&id| match self.links_db_service.get_one(id) {
+                Ok(Some(link)) if link.deleted_at.is_none() => Ok(link),
+                Ok(Some(_)) => Err(format!("Link with id {} is in the trash", id)),
+                Ok(None) => Err(format!("Link with id {} not found", id)),
+                Err(e) => Err(e.to_string()),
+            })
+            .collect()
+    }
+
+    pub fn get_by_path(&self, user_id: usize, path: &str) -> Result<Option<Link>, String> {
+        self.links_db_service
+            .get_by_path(user_id, path)
+            .map_err(|e| e.to_string())
+    }
+
+    /// The link when `access` may see it, a link of another user or in the trash is not found
+    /// like a missing one
+    fn get_link(&self, access: Access, id: usize) -> Result<Link, (StatusCode, Json<IResult>)> {
+        self.get_link_with_trashed(access, id)
+            .and_then(|link| match link.deleted_at {
+                None => Ok(link),
+                Some(_) => {
+                    warn!("Link with id {} is in the trash", &id);
+                    Err(error_response(
+                        "Link not found".to_string(),
+                        StatusCode::NOT_FOUND,
+                    ))
+                }
+            })
+    }
+
+    fn get_link_with_trashed(
+        &self,
+        access: Access,
+        id: usize,
+    ) -> Result<Link, (StatusCode, Json<IResult>)> {
+        match self.links_db_service.get_one(id) {
+            Ok(Some(link)) if access.owns(link.user_id) => Ok(link),
+            Ok(_) => {
+                warn!("Link with id {} not found", &id);
+                Err(error_response(
+                    "Link not found".to_string(),
+                    StatusCode::NOT_FOUND,
+                ))
+            }
+            Err(e) => Err(server_error_response(e.to_string())),
+        }
+    }
+
+    /// Links whose next check time has come
+    pub fn get_due_links(&self) -> Result<Vec<Link>, String> {
+        self.links_db_service
+            .get_due_for_check(&get_now_time())
+            .map_err(|e| e.to_string())
     }
 
-    pub async fn remove(&self, id: usize) -> impl IntoResponse {
+    /// Moves the link to the trash. A permanent remove also deletes its records and the
+    /// mediafiles no other link holds, their files are kept, deleted or trashed by `files`
+    pub async fn remove(&self, access: Access, query: RemoveLinkQuery) -> impl IntoResponse {
+        let id = query.id;
         info!("Removing link with id: {}", &id);
 
-        match &self.links_db_service.remove(id) {
-            Ok(m) => Ok(success_response(m.to_string())),
+        let result = if query.permanent.unwrap_or(false) {
+            self.get_link_with_trashed(access, id)?;
+            self.purge_link(id, query.files.unwrap_or_default()).await
+        } else {
+            self.get_link(access, id)?;
+            self.trash_link(id).await
+        };
+
+        result.map(success_response).map_err(server_error_response)
+    }
+
+    /// Takes a link back out of the trash
+    pub async fn restore(&self, access: Access, id: usize) -> impl IntoResponse {
+        info!("Restoring link with id: {}", &id);
+        self.get_link_with_trashed(access, id)?;
+
+        self.restore_link(id)
+            .await
+            .map(success_response)
+            .map_err(server_error_response)
+    }
+
+    /// Moves the link to the trash with the files of the mediafiles only it holds
+    pub async fn trash_link(&self, id: usize) -> Result<String, String> {
+        match self.links_db_service.soft_remove(id) {
+            Ok(Some(paths)) => {
+                move_to_trash(self.storage.as_ref(), &paths, false).await;
+                Ok(format!(
+                    "One path moved to the trash with {} mediafiles",
+                    paths.len()
+                ))
+            }
+            Ok(None) => Ok("No path moved to the trash".to_string()),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    /// Takes the link out of the trash with the mediafiles trashed with it
+    pub async fn restore_link(&self, id: usize) -> Result<String, String> {
+        match self.links_db_service.restore(id) {
+            Ok(Some(paths)) => {
+                move_to_trash(self.storage.as_ref(), &paths, true).await;
+                Ok(format!("One path restored with {} mediafiles", paths.len()))
+            }
+            Ok(None) => Ok("No path restored".to_string()),
             Err(e) => Err(e.to_string()),
         }
     }
 
-    pub async fn tag_unreachable(&self, id: usize, is_reachable: bool) -> impl IntoResponse {
+    /// Removes the link with its records for good
+    pub async fn purge_link(&self, id: usize, files: FileDisposal) -> Result<String, String> {
+        let keys = self
+            .links_db_service
+            .remove(id)
+            .map_err(|e| e.to_string())?;
+        let handled = dispose(self.storage.as_ref(), &keys, files).await;
+        info!(
+            "{} of {} files of link {} disposed",
+            handled.len(),
+            keys.len(),
+            id
+        );
+        Ok("One path removed".to_string())
+    }
+
+    /// Links in the trash, every user's when `user_id` is none
+    pub fn get_trashed(&self, user_id: Option<usize>) -> Result<Vec<Link>, String> {
+        self.links_db_service
+            .get_trashed(user_id)
+            .map_err(|e| e.to_string())
+    }
+
+    /// Ids of links trashed before `before`
+    pub fn get_trashed_before(&self, before: &str) -> Result<Vec<usize>, String> {
+        self.links_db_service
+            .get_trashed_before(before)
+            .map_err(|e| e.to_string())
+    }
+
+    /// Removes records nothing refers to anymore and the files of mediafiles no link holds
+    pub async fn collect_garbage(
+        &self,
+        access: Access,
+        dry_run: bool,
+        files: Option<FileDisposal>,
+    ) -> impl IntoResponse {
+        if !access.is_admin {
+            return Err(error_response(
+                "Only admins can collect garbage".to_string(),
+                StatusCode::FORBIDDEN,
+            ));
+        }
+
+        self.run_garbage_collection(dry_run, files.unwrap_or(FileDisposal::Trash))
+            .await
+            .map(|report| (StatusCode::OK, Json(report)))
+            .map_err(server_error_response)
+    }
+
+    pub async fn run_garbage_collection(
+        &self,
+        dry_run: bool,
+        files: FileDisposal,
+    ) -> Result<GarbageReport, String> {
+        let report = self
+            .links_db_service
+            .collect_garbage(dry_run)
+            .map_err(|e| e.to_string())?;
+        info!(
+            "Garbage {}: {} rows, {} mediafiles, {} files",
+            if dry_run { "found" } else { "collected" },
+            report.rows,
+            report.mediafiles,
+            report.files.len()
+        );
+
+        if !dry_run {
+            let handled = dispose(self.storage.as_ref(), &report.files, files).await;
+            info!("{} of {} files disposed", handled.len(), report.files.len());
+        }
+        Ok(report)
+    }
+
+    pub async fn tag_unreachable(
+        &self,
+        access: Access,
+        id: usize,
+        is_reachable: bool,
+    ) -> impl IntoResponse {
         info!("Tagging link with id: {} as {}", &id, &is_reachable);
+        self.get_link(access, id)?;
 
         match self.links_db_service.tag_unreachable(id, is_reachable) {
             Ok(m) => Ok(success_response(m)),
-            Err(e) => Err(e.to_string()),
+            Err(e) => Err(server_error_response(e.to_string())),
         }
     }
 
-    pub async fn download(&self, id: usize) -> impl IntoResponse {
+    #[instrument(skip_all, fields(link_id = id))]
+    pub async fn download(&self, access: Access, id: usize) -> impl IntoResponse {
         info!("Downloading link with id: {}", &id);
 
-        let link = match self.links_db_service.get_one(id) {
-            Ok(link) => match link {
-                Some(link) => link,
-                None => {
-                    warn!("Link with id {} not found", &id);
-                    return Err(error_response(
-                        "Link not found".to_string(),
-                        StatusCode::NOT_FOUND,
-                    ));
-                }
-            },
-            Err(e) => return Err(server_error_response(e.to_string())),
-        };
+        let link = self.get_link(access, id)?;
 
         info!("Link with path: {} exist in DB", &link.path);
 
-        let page = get_page(&link.path)
+        let site = self.get_site(&link);
+        let pages = self
+            .fetch_gallery(&link, &site)
             .await
-            .map_err(|e| (error_response(e.to_string(), StatusCode::NOT_FOUND)))?;
+            .map_err(|e| error_response(e, StatusCode::NOT_FOUND))?;
 
-        let media_urls = get_media_urls(&page);
+        let media_urls = get_gallery_download_urls(&pages, &site, &self.config.extensions);
         let total = media_urls.len();
 
         info!(
-            "Media urls count: {} on page {}",
+            "Media urls count: {} on {} pages of {}",
             &media_urls.len(),
+            pages.len(),
             &link.path
         );
 
-        let dir_path = create_directory(&link.name)
-            .await
-            .map_err(|e| server_error_response(e))?;
-
-        let downloaded: Vec<CreateDto> = download_files_multi(media_urls, &dir_path, link.id)
+        let diff = self
+            .crawls_service
+            .record(link.id, &gallery_text(&pages), &media_urls)
             .await
-            .map_err(|e| {
-                error!("Error downloading files: {}", e);
-                server_error_response(e)
-            })?;
+            .map_err(server_error_response)?;
 
-        let downloaded_count = downloaded.len();
-
-        info!("Downloaded files: {}, from {}", downloaded_count, total);
+        info!(
+            "Crawl {}: {} new, {} moved, {} removed media on page {}",
+            diff.crawl_id,
+            diff.added.len(),
+            diff.moved,
+            diff.removed.len(),
+            &link.path
+        );
 
-        let existing_records: HashSet<(String, String)> = self
+        let existing_records = self
             .mediafiles_service
             .get_all_by_link_id(id)
             .await
-            .map_err(|e| server_error_response(format!("Failed to get mediafiles: {}", e)))?
+            .map_err(|e| server_error_response(format!("Failed to get mediafiles: {}", e)))?;
+
+        // Media already stored from its source url is not fetched again, even when the
+        // crawl reports it as added
+        let mut stored: HashSet<&str> = HashSet::new();
+        for record in &existing_records {
+            if let Some(source_url) = &record.source_url {
+                if self.storage.exists(&record.path).await.unwrap_or(false) {
+                    stored.insert(source_url);
+                }
+            }
+        }
+        let pending: Vec<(usize, String)> = media_urls
             .iter()
-            .map(|record| (record.hash.clone(), record.path.clone()))
+            .enumerate()
+            .filter(|(_, url)| !stored.contains(url.as_str()))
+            .map(|(position, url)| (position, url.clone()))
             .collect();
+        let skipped = total - pending.len();
 
-        // Identify missing records and add them to the database
-        let new_records: Vec<CreateDto> = downloaded
-            .into_iter()
-            .filter(|file| !existing_records.contains(&(file.hash.clone(), file.path.clone())))
-            .collect();
+        let downloaded_count = skipped
+            + self
+                .download_media(&link, &site, pending)
+                .await
+                .map_err(server_error_response)?;
 
-        for new_record in new_records {
-            let path = new_record.path.clone();
-            match self.mediafiles_service.create_one(new_record).await {
-                Ok(_) => info!("Inserted new mediafile record: {}", path),
-                Err(e) => error!("Failed to insert mediafile record: {}, error: {}", path, e),
-            }
-        }
+        info!("Downloaded files: {}, from {}", downloaded_count, total);
 
         let progress = calculate_progress(total, downloaded_count);
 
         let is_downloaded = downloaded_count == total;
-        return match self.links_db_service.update_files_number(
+        match self.links_db_service.update_files_number(
             id,
             total,
             downloaded_count,
@@ -172,96 +437,275 @@ impl LinksService {
                 downloaded_count
             ))),
             Err(e) => Err(server_error_response(e.to_string())),
-        };
+        }
     }
 
-    pub async fn check_downloaded(&self, id: usize) -> impl IntoResponse {
+    #[instrument(skip_all, fields(link_id = id))]
+    pub async fn check_downloaded(&self, access: Access, id: usize) -> impl IntoResponse {
         info!("Checking if link with id: {} is downloaded", &id);
 
-        let link = match self.links_db_service.get_one(id) {
-            Ok(link) => match link {
-                Some(link) => link,
-                None => {
-                    info!("Link with id {} not found", &id);
-                    return Err(error_response(
-                        "Link not found".to_string(),
-                        StatusCode::NOT_FOUND,
-                    ));
-                }
+        let link = self.get_link(access, id)?;
+
+        info!("Link with path: {} exist in DB", &link.path);
+
+        match self.check_link(&link).await {
+            Ok(m) => Ok(success_response(m)),
+            Err(e) => Err(server_error_response(e)),
+        }
+    }
+
+    /// Requests the link page without recording it as a check
+    async fn fetch_page(&self, link: &Link, site: &Site) -> PageFetch {
+        let fetch = get_page(&self.http, &link.path, None, &site.headers).await;
+
+        if let Some(error) = &fetch.error {
+            warn!("Link id {}: {}", link.id, error);
+        }
+
+        fetch
+    }
+
+    /// Stores a fetch of the link page in the link checks history
+    /// and applies the reachability policy to the link
+    fn record_check(&self, link: &Link, fetch: &PageFetch) {
+        if let Err(e) = self.link_checks_db_service.create_one(link.id, fetch) {
+            error!("Error storing check of link id {}: {}", link.id, e);
+        }
+
+        let permanent_failure = fetch.error_class.is_some_and(|class| {
+            self.config
+                .permanent_errors
+                .iter()
+                .any(|permanent| permanent == class.as_str())
+        });
+        if let Err(e) = self.links_db_service.update_reachability(
+            link.id,
+            fetch.page.is_some(),
+            permanent_failure,
+            self.config.unreachable_after_failures,
+        ) {
+            error!("Error updating reachability of link id {}: {}", link.id, e);
+        }
+    }
+
+    /// Fetches the link page and the pages following it by the site pagination
+    pub async fn fetch_gallery(
+        &self,
+        link: &Link,
+        site: &Site,
+    ) -> Result<Vec<GalleryPage>, String> {
+        let fetch = self.fetch_page(link, site).await;
+        self.follow_gallery(link, site, fetch).await
+    }
+
+    /// Reads the fetched link page and follows the pages after it by the site pagination
+    async fn follow_gallery(
+        &self,
+        link: &Link,
+        site: &Site,
+        fetch: PageFetch,
+    ) -> Result<Vec<GalleryPage>, String> {
+        let first = GalleryPage {
+            url: link.path.clone(),
+            text: fetch.into_result()?,
+        };
+        self.capture_metadata(link, site, &first.text).await;
+
+        let pages = pagination::follow(
+            &site.pagination,
+            self.config.max_pages,
+            &link.path,
+            first,
+            |url, referer| async move {
+                let text = get_page(&self.http, &url, Some(&referer), &site.headers)
+                    .await
+                    .into_result()?;
+                Ok(GalleryPage { url, text })
             },
-            Err(e) => return Err(server_error_response(e.to_string())),
+        )
+        .await;
+
+        if pages.len() > 1 {
+            info!("Followed {} pages of {}", pages.len(), &link.path);
+        }
+
+        Ok(pages)
+    }
+
+    /// Stores the page metadata of the link and downloads its `og:image` as the cover
+    /// when the link has none or the image changed
+    async fn capture_metadata(&self, link: &Link, site: &Site, page: &str) {
+        let mut metadata = extract::page_metadata(page);
+        metadata.og_image = metadata
+            .og_image
+            .map(|url| get_download_url(&url, &link.path));
+
+        if let Err(e) = self.links_db_service.update_metadata(link.id, &metadata) {
+            error!("Error storing metadata of link id {}: {}", link.id, e);
+        }
+
+        let og_image = match &metadata.og_image {
+            Some(og_image) => og_image,
+            None => return,
         };
+        let has_cover = match &link.cover_path {
+            Some(cover_path) => self.storage.exists(cover_path).await.unwrap_or(false),
+            None => false,
+        };
+        if has_cover && link.og_image.as_ref() == Some(og_image) {
+            return;
+        }
 
-        info!("Link with path: {} exist in DB", &link.path);
+        let extension = Path::new(&get_file_name(
+            og_image.split(['?', '#']).next().unwrap_or_default(),
+        ))
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_lowercase())
+        .unwrap_or_else(|| "jpg".to_string());
+        let cover_key = object_key(COVERS_DIR, &format!("{}.{}", link.id, extension));
+
+        match self
+            .mediafiles_service
+            .fetch_to_storage(og_image, &cover_key, Some(&link.path), &site.headers)
+            .await
+        {
+            Ok(_) => {
+                info!("Cover of link id {} saved to {}", link.id, cover_key);
+                if let Err(e) = self.links_db_service.set_cover(link.id, Some(&cover_key)) {
+                    error!("Error storing cover of link id {}: {}", link.id, e);
+                }
+            }
+            Err(e) => warn!("Failed to download cover {}: {}", og_image, e),
+        }
+    }
+
+    pub async fn get_checks(&self, access: Access, id: usize, limit: usize) -> impl IntoResponse {
+        self.get_link(access, id)?;
+
+        match self.link_checks_db_service.get_all_by_link_id(id, limit) {
+            Ok(checks) => Ok((StatusCode::OK, Json(checks))),
+            Err(e) => {
+                error!("Error getting link checks: {}", e);
+                Err(server_error_response(
+                    "Error getting link checks".to_string(),
+                ))
+            }
+        }
+    }
 
-        let dir_path = Path::new("result").join(&link.name);
-        let dir_exists = dir_path.exists();
+    /// Profile of the link host
+    fn get_site(&self, link: &Link) -> Site {
+        self.sites_service.find_for_url(&link.path)
+    }
+
+    /// Compares the link page with its directory and records the check outcome
+    #[instrument(skip_all, fields(link_id = link.id))]
+    pub async fn check_link(&self, link: &Link) -> Result<String, String> {
+        let prefix = link_prefix(link);
+        let stored_count = self.count_stored(link).await?;
+        let dir_exists = stored_count > 0;
 
         if dir_exists {
-            info!("Directory: {} exists", &dir_path.to_string_lossy());
+            info!("Directory: {} exists", &prefix);
         }
 
-        let page = match get_page(&link.path).await {
-            Ok(page) => Some(page),
-            Err(_) => None,
-        };
+        // Only the first page of a scheduled or requested check counts as a check of the link
+        let site = self.get_site(link);
+        let fetch = self.fetch_page(link, &site).await;
+        self.record_check(link, &fetch);
+        let pages = self.follow_gallery(link, &site, fetch).await.ok();
 
-        if page.is_some() {
+        if pages.is_some() {
             info!("Page: {} is exists", &link.path);
         }
 
-        if !dir_exists && page.is_none() {
-            return Ok(success_response(format!(
-                "{} does not exist and page not found",
-                dir_path.display()
-            )));
-        } else if dir_exists && page.is_none() {
-            return match self
-                .handle_downloaded_dir_without_page(link.id, &dir_path)
-                .await
-            {
-                Ok(m) => Ok(success_response(m)),
-                Err(e) => Err(server_error_response(e)),
-            };
-        } else if dir_exists && page.is_some() {
-            return match self
-                .handle_dir_and_page(link.id, &dir_path, page.as_ref().unwrap())
-                .await
-            {
-                Ok(m) => Ok(success_response(m)),
-                Err(e) => Err(server_error_response(e)),
-            };
-        } else {
-            return match self
-                .handle_page_without_dir(link.id, page.as_ref().unwrap())
-                .await
-            {
-                Ok(m) => Ok(success_response(m)),
-                Err(e) => Err(server_error_response(e)),
-            };
+        let interval = link
+            .check_interval
+            .as_deref()
+            .and_then(parse_interval)
+            .unwrap_or(self.config.check_interval);
+        match get_time_after(interval) {
+            Some(next_check_at) => {
+                if let Err(e) = self
+                    .links_db_service
+                    .plan_next_check(link.id, &next_check_at)
+                {
+                    error!("Error planning next check of link id {}: {}", link.id, e);
+                }
+            }
+            None => error!("Check interval of link id {} is out of range", link.id),
+        }
+
+        match (dir_exists, pages) {
+            (false, None) => Ok(format!("{} does not exist and page not found", prefix)),
+            (true, None) => {
+                self.handle_downloaded_dir_without_page(link.id, &prefix, stored_count)
+                    .await
+            }
+            (true, Some(pages)) => self.handle_dir_and_page(link, &site, &pages).await,
+            (false, Some(pages)) => self.handle_page_without_dir(link, &site, &pages).await,
+        }
+    }
+
+    /// Checks every link whose next check time has come, returns the number of checked links
+    pub async fn check_due_links(&self) -> Result<usize, String> {
+        let links = self.get_due_links()?;
+
+        let queue_depth = metrics::JOB_QUEUE_DEPTH.with_label_values(&["checks"]);
+        queue_depth.set(links.len() as i64);
+
+        for link in &links {
+            match self.check_link(link).await {
+                Ok(m) => info!("Scheduled check: {}", m),
+                Err(e) => error!("Scheduled check of link id {} failed: {}", link.id, e),
+            }
+            queue_depth.dec();
+        }
+
+        Ok(links.len())
+    }
+
+    pub async fn set_check_interval(
+        &self,
+        access: Access,
+        id: usize,
+        interval: Option<String>,
+    ) -> impl IntoResponse {
+        self.get_link(access, id)?;
+        let interval = interval.filter(|interval| !interval.trim().is_empty());
+
+        if let Some(interval) = &interval {
+            if parse_interval(interval).is_none() {
+                return Err(error_response(
+                    format!(
+                        "{} is not a valid interval, like 30m, 6h or 1d of at most 100 years",
+                        interval
+                    ),
+                    StatusCode::BAD_REQUEST,
+                ));
+            }
+        }
+
+        match self
+            .links_db_service
+            .set_check_interval(id, interval.as_deref())
+        {
+            Ok(m) => Ok(success_response(m)),
+            Err(e) => Err(server_error_response(e.to_string())),
         }
     }
 
-    pub async fn scan_files_for_link(&self, id: usize) -> impl IntoResponse {
+    #[instrument(skip_all, fields(link_id = id))]
+    pub async fn scan_files_for_link(&self, access: Access, id: usize) -> impl IntoResponse {
         info!("Adding files to link with id: {}", &id);
 
-        let link = match self.links_db_service.get_one(id) {
-            Ok(link) => match link {
-                Some(link) => link,
-                None => {
-                    return Err(error_response(
-                        "Link not found".to_string(),
-                        StatusCode::NOT_FOUND,
-                    ))
-                }
-            },
-            Err(e) => return Err(server_error_response(e.to_string())),
-        };
+        let link = self.get_link(access, id)?;
 
-        let dir_path = Path::new("result").join(&link.name);
-        debug!("Directory path: {}", &dir_path.to_string_lossy());
-        let mediafiles_names = match read_dir(dir_path) {
-            Ok(entries) => entries,
+        let prefix = link_prefix(&link);
+        debug!("Storage prefix: {}", &prefix);
+        let keys = match self.storage.list(&prefix).await {
+            Ok(keys) => keys,
             Err(e) => {
                 return Err(server_error_response(format!(
                     "Failed to read directory: {}",
@@ -282,48 +726,73 @@ impl LinksService {
         let existed_records_count = existing_records.len();
         let mut new_records_count: usize = 0;
 
-        for mediafile_name in mediafiles_names {
-            let mediafile_name = match mediafile_name {
-                Ok(entry) => entry,
+        // Page entries let scanned files keep track of where they were downloaded from
+        let site = self.get_site(&link);
+        let page_entries: HashMap<String, (usize, String)> =
+            match self.fetch_gallery(&link, &site).await {
+                Ok(pages) => get_gallery_download_urls(&pages, &site, &self.config.extensions)
+                    .into_iter()
+                    .enumerate()
+                    .map(|(position, url)| (get_file_name(&url), (position, url)))
+                    .collect(),
                 Err(e) => {
-                    error!("Error reading directory entry: {}", e);
-                    continue;
+                    warn!("Scanning {} without page entries: {}", &link.path, e);
+                    HashMap::new()
                 }
             };
-            let file_path = mediafile_name.path();
 
-            let (hash, size) = match get_hash_size_by_path(&file_path).await {
-                Ok((hash, size)) => (hash, size),
+        for key in keys {
+            let (hash, size) = match get_hash_size(self.storage.as_ref(), &key).await {
+                Ok(Some((hash, size))) => (hash, size),
+                Ok(None) => continue,
                 Err(op) => {
                     error!("Error calculating hash and size: {}", op);
                     continue;
                 }
             };
 
-            let path_str = file_path.to_string_lossy().to_string();
+            let name = key_name(&key).to_string();
+            // Files put into the folder of a link move to the store with the content addressed layout
+            let key = if is_content_layout(&self.config) {
+                match move_to_store(self.storage.as_ref(), &key, &hash, &name).await {
+                    Ok(key) => key,
+                    Err(e) => {
+                        error!("Error moving {} to the store: {}", key, e);
+                        continue;
+                    }
+                }
+            } else {
+                key
+            };
 
-            if existing_records.contains(&(hash.clone(), path_str.clone())) {
-                debug!("File with path {} already exists, skipping", path_str);
+            if existing_records.contains(&(hash.clone(), key.clone())) {
+                debug!("File with path {} already exists, skipping", key);
                 continue;
             }
 
+            let page_entry = page_entries.get(&name);
+
             match self
-                .mediafiles_service
-                .create_one(CreateDto {
-                    name: file_path.file_name().unwrap().to_string_lossy().to_string(),
-                    path: path_str,
-                    hash,
-                    size,
-                    link_id: link.id,
-                })
+                .store_mediafile(
+                    CreateDto {
+                        name,
+                        path: key.clone(),
+                        hash,
+                        size,
+                        link_id: link.id,
+                        source_url: page_entry.map(|(_, url)| url.clone()),
+                        position_on_page: page_entry.map(|(position, _)| *position),
+                        ..Default::default()
+                    },
+                    false,
+                )
                 .await
             {
                 Ok(_) => {
                     new_records_count += 1;
                     debug!(
                         "Record for {} file created successfully, link id: {}",
-                        file_path.display(),
-                        &link.id
+                        key, &link.id
                     )
                 }
                 Err(e) => error!("Error creating mediafile: {}", e),
@@ -336,22 +805,33 @@ impl LinksService {
         )))
     }
 
-    pub async fn scan_files(&self) -> impl IntoResponse {
+    pub async fn scan_files(&self, access: Access) -> impl IntoResponse {
         let links_id = self
             .links_db_service
-            .get_all(true, true)
+            .get_list()
             .unwrap()
             .into_iter()
+            .filter(|link| link.is_reachable && access.owns(link.user_id))
             .map(|link| link.id);
 
         for id in links_id {
-            self.scan_files_for_link(id).await;
+            self.scan_files_for_link(access, id).await;
         }
 
         success_response("".to_string())
     }
 
-    pub async fn add_duplicate(&self, link_id: usize, duplicate_id: usize) -> impl IntoResponse {
+    pub async fn add_duplicate(
+        &self,
+        access: Access,
+        link_id: usize,
+        duplicate_id: usize,
+    ) -> impl IntoResponse {
+        self.get_link(access, link_id)?;
+        if duplicate_id != 0 {
+            self.get_link(access, duplicate_id)?;
+        }
+
         match self.links_db_service.add_duplicate(link_id, duplicate_id) {
             Ok(m) => Ok(success_response(m)),
             Err(e) => {
@@ -364,197 +844,1071 @@ impl LinksService {
         }
     }
 
-    async fn handle_downloaded_dir_without_page(
+    pub async fn verify(
         &self,
-        link_id: usize,
-        dir_path: &Path,
-    ) -> Result<String, String> {
-        let mediafiles = read_dir(dir_path).unwrap().count();
-
-        if mediafiles > 0 {
-            match self
+        access: Access,
+        id: Option<usize>,
+        redownload_missing: bool,
+        redownload_modified: bool,
+    ) -> impl IntoResponse {
+        info!("Verifying mediafiles, link id: {:?}", &id);
+
+        let links = match id {
+            Some(id) => match self.get_link(access, id) {
+                Ok(link) => vec![link],
+                Err(e) => return Err(e),
+            },
+            None => self
                 .links_db_service
-                .update_files_number(link_id, 0, mediafiles, true, 100)
+                .get_list()
+                .map_err(|e| server_error_response(e.to_string()))?
+                .into_iter()
+                .filter(|link| access.owns(link.user_id))
+                .collect(),
+        };
+
+        let mut reports = Vec::new();
+        for link in &links {
+            let report = self
+                .verify_link(link, redownload_missing, redownload_modified)
+                .await
+                .map_err(server_error_response)?;
+
+            info!(
+                "Link id: {}, checked {}, missing {}, modified {}, orphaned {}, redownloaded {}",
+                link.id,
+                report.checked,
+                report.missing.len(),
+                report.modified.len(),
+                report.orphaned.len(),
+                report.redownloaded.len()
+            );
+            reports.push(report);
+        }
+
+        // Directories without a link can only be found when the whole storage is checked
+        if id.is_none() && access.is_admin {
+            if let Some(report) = self
+                .find_unknown_dirs(&links)
+                .await
+                .map_err(server_error_response)?
             {
-                Ok(_) => Ok(format!(
-                    "id: {}, Files detected in directory, download marked as complete",
-                    link_id,
-                )),
-                Err(e) => Err(e.to_string()),
+                reports.push(report);
             }
-        } else {
-            Ok(format!("{} directory is empty", dir_path.display()))
         }
+
+        Ok((StatusCode::OK, Json(reports)))
     }
 
-    async fn handle_dir_and_page(
-        &self,
-        link_id: usize,
-        dir_path: &Path,
-        page: &str,
-    ) -> Result<String, String> {
-        let mediafiles = count_media_files(page);
-        let existed_files_count = read_dir(dir_path).unwrap().count();
-        let progress = (existed_files_count * 100) / mediafiles;
-        let is_downloaded = existed_files_count == mediafiles;
+    /// Files in directories of the storage that belong to none of `links`,
+    /// files in the store count when no mediafile holds them
+    pub async fn find_unknown_dirs(&self, links: &[Link]) -> Result<Option<VerifyReport>, String> {
+        let known: HashSet<String> = self
+            .mediafiles_service
+            .get_paths()
+            .await?
+            .into_iter()
+            .collect();
+        find_unknown_dirs(self.storage.as_ref(), links, &known).await
+    }
 
-        match self.links_db_service.update_files_number(
-            link_id,
-            mediafiles,
+    /// Marks the mediafiles of a file or folder that disappeared from the storage as missing
+    pub async fn sync_removed(&self, key: &str) -> Result<usize, String> {
+        let missing = self.mediafiles_service.mark_missing(key).await?;
+        if missing > 0 {
+            info!("{} mediafiles missing after {} was removed", missing, key);
+        }
+        Ok(missing)
+    }
+
+    /// Brings the records in line with a file that appeared or changed in the storage. A known
+    /// file is found again or gets its final hash, a file with the content of a missing mediafile
+    /// is that file renamed, any other file in the folder of one of `links` is added to it
+    pub async fn sync_file(&self, key: &str, links: &[Link]) -> Result<Option<String>, String> {
+        if let Some(record) = self.mediafiles_service.get_by_path(key).await? {
+            if record.deleted_at.is_some() {
+                return Ok(None);
+            }
+            if record.missing_at.is_some() {
+                self.mediafiles_service.mark_found(record.id, key).await?;
+                return Ok(Some(format!("Found {} again", key)));
+            }
+
+            // A file seen while it was still being written gets the hash of its final content
+            return match get_hash_size(self.storage.as_ref(), key).await? {
+                Some((hash, size)) if hash != record.hash || size != record.size => {
+                    self.mediafiles_service
+                        .update_file(record.id, key, &hash, size)
+                        .await?;
+                    Ok(Some(format!("Updated {}", key)))
+                }
+                _ => Ok(None),
+            };
+        }
+        // The store is written by downloads and scans only, its files come with their records
+        if is_content_key(key) {
+            return Ok(None);
+        }
+
+        let Some((hash, size)) = get_hash_size(self.storage.as_ref(), key).await? else {
+            return Ok(None);
+        };
+        let owner = links
+            .iter()
+            .filter(|link| key.starts_with(&link_prefix(link)))
+            .max_by_key(|link| link.name.len());
+        let name = key_name(key).to_string();
+
+        let missing = self
+            .mediafiles_service
+            .get_missing_by_hash(&hash, size)
+            .await?;
+        if let Some(record) = missing.first() {
+            let target = self.stored_key(key, &hash, &name).await?;
+            self.mediafiles_service
+                .mark_found(record.id, &target)
+                .await?;
+            if let Some(link) = owner {
+                self.mediafiles_service.attach(record.id, link.id).await?;
+            }
+            return Ok(Some(format!("{} renamed to {}", record.path, target)));
+        }
+
+        // Files outside the folders of links are left for `verify` to report
+        let Some(link) = owner else {
+            return Ok(None);
+        };
+        let target = self.stored_key(key, &hash, &name).await?;
+        self.store_mediafile(
+            CreateDto {
+                name,
+                path: target,
+                hash,
+                size,
+                link_id: link.id,
+                ..Default::default()
+            },
+            false,
+        )
+        .await
+        .map(Some)
+    }
+
+    /// Key a file found in the storage is kept at, in the store with the content addressed layout
+    async fn stored_key(&self, key: &str, hash: &str, name: &str) -> Result<String, String> {
+        if is_content_layout(&self.config) {
+            move_to_store(self.storage.as_ref(), key, hash, name).await
+        } else {
+            Ok(key.to_string())
+        }
+    }
+
+    /// Compares the whole storage with the records, for when changes may have been missed
+    pub async fn sync_storage(&self) -> Result<(usize, usize), String> {
+        let links = self.get_links(&[])?;
+        let keys: HashSet<String> = self
+            .storage
+            .list("")
+            .await?
+            .into_iter()
+            .filter(|key| !is_internal_key(key))
+            .collect();
+        let known: HashSet<String> = self
+            .mediafiles_service
+            .get_paths()
+            .await?
+            .into_iter()
+            .collect();
+
+        let mut missing = 0;
+        for key in known.difference(&keys) {
+            missing += self.sync_removed(key).await?;
+        }
+        let mut synced = 0;
+        for key in keys.difference(&known) {
+            match self.sync_file(key, &links).await {
+                Ok(Some(m)) => {
+                    debug!("{}", m);
+                    synced += 1;
+                }
+                Ok(None) => {}
+                Err(e) => error!("Failed to sync {}: {}", key, e),
+            }
+        }
+
+        info!(
+            "Storage synced, {} files added or found, {} mediafiles missing",
+            synced, missing
+        );
+        Ok((synced, missing))
+    }
+
+    /// Moves the files of a link from its folder to the content addressed store. A file is only
+    /// removed from the folder once the store holds it and its mediafile points there, files
+    /// without a mediafile stay in the folder
+    #[instrument(skip_all, fields(link_id = link.id))]
+    pub async fn move_to_store(&self, link: &Link, dry_run: bool) -> Result<String, String> {
+        let records = self.mediafiles_service.get_all_by_link_id(link.id).await?;
+
+        let (mut moved, mut merged, mut missing) = (0, 0, 0);
+        for record in records
+            .iter()
+            .filter(|record| !is_content_key(&record.path))
+        {
+            let (hash, size) = match get_hash_size(self.storage.as_ref(), &record.path).await? {
+                Some(hash_size) => hash_size,
+                None => {
+                    warn!("{} is missing, it stays out of the store", record.path);
+                    missing += 1;
+                    continue;
+                }
+            };
+            let target = content_key(&hash, &record.name);
+            if dry_run {
+                info!("{} would move to {}", record.path, target);
+                moved += 1;
+                continue;
+            }
+
+            // A mediafile already holding the content takes over the links and tags of this one
+            if let Some(holder) = self.mediafiles_service.get_by_path(&target).await? {
+                self.mediafiles_service.merge(record.id, holder.id).await?;
+                self.storage.delete(&record.path).await?;
+                merged += 1;
+                continue;
+            }
+
+            let stored = self.storage.exists(&target).await?;
+            if !stored {
+                self.storage.rename(&record.path, &target).await?;
+            }
+            if let Err(e) = self
+                .mediafiles_service
+                .update_file(record.id, &target, &hash, size)
+                .await
+            {
+                if !stored {
+                    self.storage.rename(&target, &record.path).await?;
+                }
+                return Err(format!("Failed to update mediafile {}: {}", record.id, e));
+            }
+            if stored {
+                self.storage.delete(&record.path).await?;
+            }
+            info!("{} moved to {}", record.path, target);
+            moved += 1;
+        }
+
+        let tracked: HashSet<&str> = records.iter().map(|record| record.path.as_str()).collect();
+        let untracked = self
+            .storage
+            .list(&link_prefix(link))
+            .await?
+            .iter()
+            .filter(|key| !tracked.contains(key.as_str()))
+            .count();
+        let summary = format!(
+            "{} moved, {} merged with stored files, {} missing, {} untracked files left in {}",
+            moved,
+            merged,
+            missing,
+            untracked,
+            link_prefix(link)
+        );
+        if missing > 0 {
+            Err(summary)
+        } else {
+            Ok(summary)
+        }
+    }
+
+    /// Streams a file of the link by its name, the per-link view of the content addressed store
+    pub async fn serve_file(
+        &self,
+        access: Access,
+        id: usize,
+        name: &str,
+        range: Option<&str>,
+    ) -> Result<Response, (StatusCode, Json<IResult>)> {
+        self.get_link(access, id)?;
+
+        let record = self
+            .mediafiles_service
+            .get_all_by_link_id(id)
+            .await
+            .map_err(server_error_response)?
+            .into_iter()
+            .find(|record| record.name == name)
+            .ok_or_else(|| {
+                error_response("Mediafile not found".to_string(), StatusCode::NOT_FOUND)
+            })?;
+
+        self.mediafiles_service
+            .serve_file(access, record.id, range)
+            .await
+    }
+
+    #[instrument(skip_all, fields(link_id = link.id))]
+    pub async fn verify_link(
+        &self,
+        link: &Link,
+        redownload_missing: bool,
+        redownload_modified: bool,
+    ) -> Result<VerifyReport, String> {
+        let records = self
+            .mediafiles_service
+            .get_all_by_link_id(link.id)
+            .await
+            .map_err(|e| format!("Failed to get mediafiles: {}", e))?;
+
+        let mut report = VerifyReport {
+            link_id: Some(link.id),
+            checked: records.len(),
+            ..Default::default()
+        };
+        let mut broken: Vec<&Mediafile> = Vec::new();
+
+        for record in &records {
+            match get_hash_size(self.storage.as_ref(), &record.path).await {
+                Ok(None) => {
+                    report.missing.push(record.path.clone());
+                    if redownload_missing {
+                        broken.push(record);
+                    }
+                }
+                Ok(Some((hash, size))) if hash == record.hash && size == record.size => {}
+                Ok(Some(_)) => {
+                    report.modified.push(record.path.clone());
+                    if redownload_modified {
+                        broken.push(record);
+                    }
+                }
+                Err(e) => {
+                    error!(
+                        "Error calculating hash and size: {}, path {}",
+                        e, record.path
+                    );
+                    report.modified.push(record.path.clone());
+                }
+            }
+        }
+
+        let known_paths: HashSet<&str> = records.iter().map(|r| r.path.as_str()).collect();
+        report.orphaned = self
+            .storage
+            .list(&link_prefix(link))
+            .await?
+            .into_iter()
+            .filter(|path| !known_paths.contains(path.as_str()))
+            .collect();
+
+        if !broken.is_empty() {
+            report.redownloaded = self.redownload(link, &broken).await?;
+        }
+
+        Ok(report)
+    }
+
+    async fn redownload(&self, link: &Link, records: &[&Mediafile]) -> Result<Vec<String>, String> {
+        let site = self.get_site(link);
+
+        // Records created before source urls were stored are matched to the page by file name
+        let page_urls: HashMap<String, String> =
+            if records.iter().any(|record| record.source_url.is_none()) {
+                let pages = self.fetch_gallery(link, &site).await?;
+                get_gallery_download_urls(&pages, &site, &self.config.extensions)
+                    .into_iter()
+                    .map(|url| (get_file_name(&url), url))
+                    .collect()
+            } else {
+                HashMap::new()
+            };
+
+        let mut redownloaded = Vec::new();
+        for record in records {
+            let url = match record
+                .source_url
+                .as_ref()
+                .or_else(|| page_urls.get(&record.name))
+            {
+                Some(url) => url.clone(),
+                None => {
+                    warn!("{} is no longer on page {}", record.name, link.path);
+                    continue;
+                }
+            };
+
+            info!("Redownloading {} to {}", &url, &record.path);
+
+            // A stored file named by its hash is replaced only once the new one is complete
+            let content_layout = is_content_layout(&self.config);
+            let key = if content_layout {
+                incoming_key(&record.name)
+            } else {
+                record.path.clone()
+            };
+
+            match self
+                .mediafiles_service
+                .download_file(
+                    &url,
+                    &key,
+                    link.id,
+                    record.position_on_page,
+                    Some(&link.path),
+                    &site.headers,
+                )
+                .await
+            {
+                Ok(mut mediafile) => {
+                    if content_layout {
+                        match move_to_store(
+                            self.storage.as_ref(),
+                            &key,
+                            &mediafile.hash,
+                            &record.name,
+                        )
+                        .await
+                        {
+                            Ok(path) => {
+                                mediafile.name = record.name.clone();
+                                mediafile.path = path;
+                            }
+                            Err(e) => {
+                                error!("Failed to move {} to the store: {}", key, e);
+                                continue;
+                            }
+                        }
+                        // Different content is named differently, the record follows its file
+                        if mediafile.path != record.path {
+                            if let Err(e) = self
+                                .mediafiles_service
+                                .update_file(
+                                    record.id,
+                                    &mediafile.path,
+                                    &mediafile.hash,
+                                    mediafile.size,
+                                )
+                                .await
+                            {
+                                warn!(
+                                    "Failed to move mediafile record: {}, error: {}",
+                                    record.path, e
+                                );
+                            }
+                        }
+                    }
+                    match self.store_mediafile(mediafile, false).await {
+                        Ok(_) => redownloaded.push(record.path.clone()),
+                        Err(e) => error!(
+                            "Failed to update mediafile record: {}, error: {}",
+                            record.path, e
+                        ),
+                    }
+                }
+                Err(e) => error!("Failed to redownload {}: {}", url, e),
+            }
+        }
+
+        Ok(redownloaded)
+    }
+
+    /// Number of stored files of a link, the files in its folder or, with the content
+    /// addressed layout, its mediafiles
+    async fn count_stored(&self, link: &Link) -> Result<usize, String> {
+        if is_content_layout(&self.config) {
+            Ok(self
+                .mediafiles_service
+                .get_all_by_link_id(link.id)
+                .await?
+                .len())
+        } else {
+            Ok(self.storage.list(&link_prefix(link)).await?.len())
+        }
+    }
+
+    async fn handle_downloaded_dir_without_page(
+        &self,
+        link_id: usize,
+        prefix: &str,
+        mediafiles: usize,
+    ) -> Result<String, String> {
+        if mediafiles > 0 {
+            match self
+                .links_db_service
+                .update_files_number(link_id, 0, mediafiles, true, 100)
+            {
+                Ok(_) => Ok(format!(
+                    "id: {}, Files detected in directory, download marked as complete",
+                    link_id,
+                )),
+                Err(e) => Err(e.to_string()),
+            }
+        } else {
+            Ok(format!("{} directory is empty", prefix))
+        }
+    }
+
+    async fn handle_dir_and_page(
+        &self,
+        link: &Link,
+        site: &Site,
+        pages: &[GalleryPage],
+    ) -> Result<String, String> {
+        let media_urls = get_gallery_download_urls(pages, site, &self.config.extensions);
+        let mediafiles = media_urls.len();
+        let diff = self
+            .crawls_service
+            .record(link.id, &gallery_text(pages), &media_urls)
+            .await?;
+
+        // The first crawl only takes a snapshot, later ones fetch media added to the page
+        if diff.has_previous && !diff.added.is_empty() {
+            let added: HashSet<&str> = diff.added.iter().map(String::as_str).collect();
+            let pending: Vec<(usize, String)> = media_urls
+                .iter()
+                .enumerate()
+                .filter(|(_, url)| added.contains(url.as_str()))
+                .map(|(position, url)| (position, url.clone()))
+                .collect();
+
+            info!(
+                "id: {}, {} new media files on page, downloading",
+                link.id,
+                pending.len()
+            );
+            self.download_media(link, site, pending).await?;
+        }
+
+        for url in &diff.removed {
+            warn!("id: {}, {} was removed from page", link.id, url);
+        }
+
+        let existed_files_count = self.count_stored(link).await?;
+        let progress = calculate_progress(mediafiles, existed_files_count);
+        let is_downloaded = existed_files_count == mediafiles;
+
+        match self.links_db_service.update_files_number(
+            link.id,
+            mediafiles,
             existed_files_count,
             is_downloaded,
             progress,
         ) {
             Ok(_) => Ok(format!(
-                "id: {}, Downloaded {} out of {} media files",
-                link_id, existed_files_count, mediafiles
+                "id: {}, Downloaded {} out of {} media files, {} new, {} removed",
+                link.id,
+                existed_files_count,
+                mediafiles,
+                diff.added.len(),
+                diff.removed.len()
             )),
             Err(e) => Err(e.to_string()),
         }
     }
 
-    async fn handle_page_without_dir(&self, link_id: usize, page: &str) -> Result<String, String> {
-        let mediafiles = count_media_files(page);
+    async fn handle_page_without_dir(
+        &self,
+        link: &Link,
+        site: &Site,
+        pages: &[GalleryPage],
+    ) -> Result<String, String> {
+        let media_urls = get_gallery_download_urls(pages, site, &self.config.extensions);
+        let mediafiles = media_urls.len();
+        self.crawls_service
+            .record(link.id, &gallery_text(pages), &media_urls)
+            .await?;
 
         match self
             .links_db_service
-            .update_files_number(link_id, mediafiles, 0, false, 0)
+            .update_files_number(link.id, mediafiles, 0, false, 0)
         {
-            Ok(_) => Ok(format!("id: {}, Not downloaded yet", link_id)),
+            Ok(_) => Ok(format!("id: {}, Not downloaded yet", link.id)),
             Err(e) => Err(e.to_string()),
         }
     }
-}
 
-async fn download_files_multi(
-    urls: Vec<String>,
-    dir_path: &Path,
-    link_id: usize,
-) -> Result<Vec<CreateDto>, String> {
-    let download_futures = urls.into_iter().map(|url| {
-        let dir_path = dir_path.to_path_buf(); // Клонируем путь для использования в разных потоках
-
-        spawn(async move {
-            let file_name = Regex::new(r".+/").unwrap().replace(&url, "").to_string();
-            let file_path = dir_path.join(&file_name);
-
-            if file_path.exists() {
-                // нашли и обсчитали файл
-                match get_hash_size_by_path(&file_path).await {
-                    Ok((hash, size)) => {
-                        return Ok(CreateDto {
-                            name: file_name,
-                            path: file_path.to_string_lossy().to_string(),
-                            hash,
-                            size,
-                            link_id,
-                        });
-                    }
-                    Err(e) => {
-                        let m = format!(
-                            "Error calculating hash and size: {}, path {}",
-                            e,
-                            file_path.display()
-                        );
-                        error!("{}", m);
-                        return Err(m);
-                    }
-                };
-            }
+    /// Downloads `(position, url)` pairs into the link directory and records them,
+    /// returns the number of files that ended up in storage
+    async fn download_media(
+        &self,
+        link: &Link,
+        site: &Site,
+        pending: Vec<(usize, String)>,
+    ) -> Result<usize, String> {
+        // Nothing is fetched for a user whose links already fill its quota
+        self.auth_service.check_quota(link.user_id)?;
+
+        let downloaded: Vec<CreateDto> = self
+            .download_files_multi(pending, &link_prefix(link), link.id, &link.path, site)
+            .await
+            .map_err(|e| {
+                error!("Error downloading files: {}", e);
+                e
+            })?;
+        let downloaded_count = downloaded.len();
 
-            if !is_valid_extension(&file_name) {
-                let m = format!("{} is not an image", file_name);
-                warn!("{}", m);
-                return Err(m);
+        let existing_records: HashMap<(String, String), Mediafile> = self
+            .mediafiles_service
+            .get_all_by_link_id(link.id)
+            .await
+            .map_err(|e| format!("Failed to get mediafiles: {}", e))?
+            .into_iter()
+            .map(|record| ((record.hash.clone(), record.path.clone()), record))
+            .collect();
+
+        // Identify missing records and add them to the database,
+        // records created before source urls were stored get them filled in
+        for file in downloaded {
+            let path = file.path.clone();
+            match existing_records.get(&(file.hash.clone(), file.path.clone())) {
+                Some(record) => {
+                    if let (None, Some(source_url)) = (&record.source_url, &file.source_url) {
+                        if let Err(e) = self
+                            .mediafiles_service
+                            .update_source(record.id, source_url, file.position_on_page)
+                            .await
+                        {
+                            error!("Failed to update mediafile source: {}, error: {}", path, e);
+                        }
+                    }
+                }
+                None => match self.store_mediafile(file, true).await {
+                    Ok(m) => info!("{}: {}", m, path),
+                    Err(e) => error!("Failed to insert mediafile record: {}, error: {}", path, e),
+                },
             }
+        }
 
-            let download_url = if !url.starts_with("http") {
-                format!("{}{}", *config::ROOT_URL, url)
-            } else {
-                url.clone()
-            };
+        Ok(downloaded_count)
+    }
 
-            info!("Downloading {} to {}", &download_url, file_path.display());
+    /// Records a file of a link. A file another link already recorded at the same path, or with
+    /// the same content when `share_content` is set, is shared with the link instead, so the
+    /// libraries of different users store identical files once
+    async fn store_mediafile(
+        &self,
+        file: CreateDto,
+        share_content: bool,
+    ) -> Result<String, String> {
+        let mut stored = self.mediafiles_service.get_by_path(&file.path).await?;
+        if stored.is_none() && share_content {
+            for record in self
+                .mediafiles_service
+                .get_by_hash(&file.hash, file.size)
+                .await?
+            {
+                if self.storage.exists(&record.path).await? {
+                    stored = Some(record);
+                    break;
+                }
+            }
+        }
 
-            match download_file(&download_url, &file_path, link_id).await {
-                Ok(mediafile) => {
-                    info!(
-                        "Link_id: {}, {} bytes downloaded and saved to {}",
-                        link_id,
-                        mediafile.size,
-                        &file_path.display(),
-                    );
-                    return Ok(mediafile);
+        match stored {
+            // A file stored again where a trashed mediafile kept its file takes it out of the trash
+            Some(record) if record.deleted_at.is_some() => {
+                self.mediafiles_service
+                    .update_file(record.id, &file.path, &file.hash, file.size)
+                    .await?;
+                self.mediafiles_service.restore_record(record.id).await?;
+                self.mediafiles_service
+                    .attach(record.id, file.link_id)
+                    .await?;
+                if let Err(e) = self.storage.delete(&trash_key(&record.path)).await {
+                    warn!("Failed to remove trashed file {}: {}", record.path, e);
                 }
-                Err(e) => {
-                    let m = format!("Failed to download {}: {}", url, e);
-                    error!("{}", m);
-                    return Err(m);
+                Ok(format!("Restored mediafile {}", record.path))
+            }
+            // The same file stored again, a download refreshes the metadata of its record
+            Some(record) if record.path == file.path => {
+                self.mediafiles_service.refresh(record.id, &file).await?;
+                self.mediafiles_service
+                    .attach(record.id, file.link_id)
+                    .await?;
+                Ok(format!("Updated mediafile {}", record.path))
+            }
+            Some(record) => {
+                if let Err(e) = self.storage.delete(&file.path).await {
+                    warn!("Failed to remove duplicate file {}: {}", file.path, e);
                 }
+                self.mediafiles_service
+                    .attach(record.id, file.link_id)
+                    .await?;
+                Ok(format!("Shared mediafile {}", record.path))
             }
-        })
-    });
+            None => {
+                self.mediafiles_service.create_one(file).await?;
+                Ok("Inserted new mediafile record".to_string())
+            }
+        }
+    }
 
-    // Запускаем все загрузки параллельно
-    let results: Vec<Result<CreateDto, String>> = FuturesUnordered::from_iter(download_futures)
-        .filter_map(|res| async move { res.ok() })
-        .collect::<Vec<_>>()
-        .await;
+    pub async fn get_crawls(&self, access: Access, id: usize) -> impl IntoResponse {
+        self.get_link(access, id)?;
+
+        match self.crawls_service.get_all_by_link_id(id).await {
+            Ok(crawls) => Ok((StatusCode::OK, Json(crawls))),
+            Err(e) => {
+                error!("Error getting crawls: {}", e);
+                Err(server_error_response("Error getting crawls".to_string()))
+            }
+        }
+    }
+
+    async fn download_files_multi(
+        &self,
+        urls: Vec<(usize, String)>,
+        prefix: &str,
+        link_id: usize,
+        referer: &str,
+        site: &Site,
+    ) -> Result<Vec<CreateDto>, String> {
+        let site = Arc::new(site.clone());
+        // The rules are compiled once for all media of the link
+        let rewrite_rules = Arc::new(rewrite::with_global(
+            &site.rewrite_rules,
+            &self.config.rewrite_rules,
+        ));
+        let download_futures = urls.into_iter().map(|(position, download_url)| {
+            let prefix = prefix.to_string(); // Клонируем префикс для использования в разных потоках
+            let referer = referer.to_string();
+            let site = Arc::clone(&site);
+            let rewrite_rules = Arc::clone(&rewrite_rules);
+            let download_permits = Arc::clone(&self.download_permits);
+            let config = Arc::clone(&self.config);
+            let http = Arc::clone(&self.http);
+            let mediafiles_service = Arc::clone(&self.mediafiles_service);
+            let storage = Arc::clone(&self.storage);
+
+            // Started inside the link span, so download lines carry both the link and the job id
+            let span = info_span!("download", job_id = next_job_id(), position);
+
+            spawn(
+                async move {
+                    let file_name = get_file_name(&download_url);
+                    let content_layout = is_content_layout(&config);
+                    let key = if content_layout {
+                        incoming_key(&file_name)
+                    } else {
+                        object_key(&prefix, &file_name)
+                    };
+
+                    // Incoming keys are new for every download, a file of the content layout is
+                    // found by the url it was downloaded from instead
+                    let stored = if content_layout {
+                        mediafiles_service
+                            .get_stored_by_source_url(&download_url)
+                            .await
+                            .map(|record| {
+                                record.map(|record| (record.hash, record.size, record.path))
+                            })
+                    } else {
+                        get_hash_size(storage.as_ref(), &key)
+                            .await
+                            .map(|found| found.map(|(hash, size)| (hash, size, key.clone())))
+                    };
+                    match stored {
+                        // нашли и обсчитали файл
+                        Ok(Some((hash, size, key))) => {
+                            return Ok(CreateDto {
+                                name: file_name,
+                                path: key,
+                                hash,
+                                size,
+                                link_id,
+                                source_url: Some(download_url),
+                                position_on_page: Some(position),
+                                ..Default::default()
+                            });
+                        }
+                        Ok(None) => {}
+                        Err(e) => {
+                            let m = format!("Error looking for a stored file: {}, key {}", e, key);
+                            error!("{}", m);
+                            return Err(m);
+                        }
+                    }
+
+                    let extensions = site.extensions.as_ref().unwrap_or(&config.extensions);
+                    if !is_valid_extension(&file_name, extensions) {
+                        let m = format!("{} is not an image", file_name);
+                        warn!("{}", m);
+                        return Err(m);
+                    }
+
+                    let queued =
+                        GaugeGuard::new(metrics::JOB_QUEUE_DEPTH.with_label_values(&["downloads"]));
+                    // The site permit comes first so that waiting for it holds no global permit
+                    let _site_permit = download_permits.acquire_site(&site).await?;
+                    let _global_permit = download_permits.acquire().await?;
+                    drop(queued);
+
+                    let _active = GaugeGuard::new(metrics::ACTIVE_DOWNLOADS.clone());
+                    metrics::DOWNLOADS_STARTED.inc();
+
+                    // Rewritten urls are preferred, the original one is the fallback
+                    let mut result = Err(DownloadError::new(
+                        ErrorClass::Other,
+                        "No url to download".to_string(),
+                    ));
+                    for url in rewrite::resolve(
+                        &http,
+                        &download_url,
+                        &rewrite_rules,
+                        Some(&referer),
+                        &site.headers,
+                    )
+                    .await
+                    {
+                        info!("Downloading {} to {}", &url, key);
+
+                        result = mediafiles_service
+                            .download_file(
+                                &url,
+                                &key,
+                                link_id,
+                                Some(position),
+                                Some(&referer),
+                                &site.headers,
+                            )
+                            .await;
+
+                        if result.is_ok() {
+                            break;
+                        }
+                    }
+
+                    // With the content addressed layout the file is named by its hash once complete
+                    if let (true, Ok(mediafile)) = (content_layout, &mut result) {
+                        match move_to_store(storage.as_ref(), &key, &mediafile.hash, &file_name)
+                            .await
+                        {
+                            Ok(path) => {
+                                mediafile.name = file_name;
+                                mediafile.path = path;
+                            }
+                            Err(e) => {
+                                result = Err(DownloadError::new(ErrorClass::Other, e));
+                            }
+                        }
+                    }
+
+                    match result {
+                        Ok(mediafile) => {
+                            metrics::DOWNLOADS_SUCCEEDED.inc();
+                            metrics::DOWNLOADED_BYTES.inc_by(mediafile.size as u64);
+                            info!(
+                                "Link_id: {}, {} bytes downloaded and saved to {}",
+                                link_id, mediafile.size, &mediafile.path,
+                            );
+                            Ok(mediafile)
+                        }
+                        Err(e) => {
+                            metrics::DOWNLOADS_FAILED
+                                .with_label_values(&[e.class.as_str()])
+                                .inc();
+                            let m = format!("Failed to download {}: {}", download_url, e);
+                            error!("{}", m);
+                            Err(m)
+                        }
+                    }
+                }
+                .instrument(span),
+            )
+        });
+
+        // Запускаем все загрузки параллельно
+        let results: Vec<Result<CreateDto, String>> = FuturesUnordered::from_iter(download_futures)
+            .filter_map(|res| async move { res.ok() })
+            .collect::<Vec<_>>()
+            .await;
+
+        // Фильтруем результаты, чтобы исключить `None`, и собираем в `Vec<CreateDto>`
+        #[allow(clippy::manual_ok_err)]
+        let downloaded_files: Vec<CreateDto> = results
+            .into_iter()
+            .filter_map(|dto| if let Ok(dto) = dto { Some(dto) } else { None })
+            .collect();
+
+        Ok(downloaded_files)
+    }
+}
+
+fn get_file_name(url: &str) -> String {
+    Regex::new(r".+/").unwrap().replace(url, "").to_string()
+}
+
+/// Resolves a media url found on a page against `base_url`
+fn get_download_url(url: &str, base_url: &str) -> String {
+    Url::parse(base_url)
+        .and_then(|base| base.join(url))
+        .map(|url| url.to_string())
+        .unwrap_or_else(|_| url.to_string())
+}
+
+fn is_content_layout(config: &Config) -> bool {
+    config.storage_layout == "content"
+}
+
+/// Key a download is written to before its hash is known
+fn incoming_key(file_name: &str) -> String {
+    object_key(INCOMING_DIR, &format!("{}-{}", next_job_id(), file_name))
+}
+
+/// Storage prefix of the files of a link
+fn link_prefix(link: &Link) -> String {
+    format!("{}/", link.name)
+}
+
+/// Keys of covers, trashed files and downloads in progress, none of them is a mediafile
+pub fn is_internal_key(key: &str) -> bool {
+    [COVERS_DIR, TRASH_DIR, INCOMING_DIR]
+        .iter()
+        .any(|dir| key.starts_with(&format!("{}/", dir)))
+}
 
-    // Фильтруем результаты, чтобы исключить `None`, и собираем в `Vec<CreateDto>`
-    let downloaded_files: Vec<CreateDto> = results
+/// Collects files stored in directories of the storage that do not belong to any link
+async fn find_unknown_dirs(
+    storage: &dyn Storage,
+    links: &[Link],
+    known: &HashSet<String>,
+) -> Result<Option<VerifyReport>, String> {
+    let prefixes: Vec<String> = links.iter().map(link_prefix).collect();
+    let covers = format!("{}/", COVERS_DIR);
+    let trash = format!("{}/", TRASH_DIR);
+
+    let orphaned: Vec<String> = storage
+        .list("")
+        .await?
         .into_iter()
-        .filter_map(|dto| if let Ok(dto) = dto { Some(dto) } else { None })
+        .filter(|key| key.contains('/') && !key.starts_with(&covers) && !key.starts_with(&trash))
+        .filter(|key| !prefixes.iter().any(|prefix| key.starts_with(prefix)))
+        .filter(|key| !known.contains(key))
         .collect();
 
-    Ok(downloaded_files)
+    Ok(if orphaned.is_empty() {
+        None
+    } else {
+        Some(VerifyReport {
+            orphaned,
+            ..Default::default()
+        })
+    })
 }
 
-async fn create_directory(name: &str) -> Result<PathBuf, String> {
-    let dir_path = Path::new("result").join(name);
-    if !dir_path.exists() {
-        let _ = create_dir_all(&dir_path).map_err(|e| format!("Failed to create directory: {}", e));
-    }
-    Ok(dir_path)
+async fn get_page(
+    http: &HttpClient,
+    url: &str,
+    referer: Option<&str>,
+    headers: &HashMap<String, String>,
+) -> PageFetch {
+    let fetch = request_page(http, url, referer, headers).await;
+
+    let result = fetch.error_class.map_or("ok", |class| class.as_str());
+    metrics::PAGE_REQUESTS.with_label_values(&[result]).inc();
+    metrics::PAGE_REQUEST_DURATION
+        .with_label_values(&[result])
+        .observe(fetch.latency_ms as f64 / 1000.0);
+
+    fetch
 }
 
-async fn get_page(url: &str) -> Result<String, String> {
-    reqwest::get(url)
-        .await
-        .map_err(|e| format!("Failed to fetch page: {}", e))?
-        .text()
-        .await
-        .map_err(|e| format!("Failed to read page text: {}", e))
+async fn request_page(
+    http: &HttpClient,
+    url: &str,
+    referer: Option<&str>,
+    headers: &HashMap<String, String>,
+) -> PageFetch {
+    let started = Instant::now();
+    let failed =
+        |class: ErrorClass, error: String, status_code: Option<u16>, final_url| PageFetch {
+            page: None,
+            status_code,
+            error_class: Some(class),
+            error: Some(error),
+            latency_ms: started.elapsed().as_millis() as usize,
+            final_url,
+        };
+
+    let response = match http.get(url, referer, headers).send().await {
+        Ok(response) => response,
+        Err(e) => {
+            return failed(
+                ErrorClass::from_request_error(&e),
+                format!("Failed to fetch page: {}", e),
+                None,
+                e.url().map(|url| url.to_string()),
+            )
+        }
+    };
+
+    let status = response.status();
+    let final_url = Some(response.url().to_string());
+
+    if !status.is_success() {
+        return failed(
+            ErrorClass::from_status(status.as_u16()),
+            format!("Page responded with {}", status),
+            Some(status.as_u16()),
+            final_url,
+        );
+    }
+
+    match http.read_text(response).await {
+        Ok(page) => PageFetch {
+            page: Some(page),
+            status_code: Some(status.as_u16()),
+            error_class: None,
+            error: None,
+            latency_ms: started.elapsed().as_millis() as usize,
+            final_url,
+        },
+        Err(e) => failed(
+            match e {
+                ReadError::Timeout(_) => ErrorClass::Timeout,
+                ReadError::TooLarge(_) | ReadError::Failed(_) => ErrorClass::Body,
+            },
+            format!("Failed to read page text: {}", e),
+            Some(status.as_u16()),
+            final_url,
+        ),
+    }
 }
 
 fn calculate_progress(total: usize, downloaded: usize) -> usize {
     ((downloaded as f64 / total as f64) * 100.0).round() as usize
 }
 
-fn get_media_urls(page: &str) -> Vec<String> {
-    let mut media_urls = Vec::new();
-    process_media_urls(page, |url| media_urls.push(url.to_string()));
-    media_urls
-}
+/// Absolute urls of the media of all gallery pages in page order, without repeats
+fn get_gallery_download_urls(
+    pages: &[GalleryPage],
+    site: &Site,
+    default_extensions: &[String],
+) -> Vec<String> {
+    let mut seen = HashSet::new();
+    let mut download_urls = Vec::new();
+
+    for page in pages {
+        let base_url = site.root_url.as_deref().unwrap_or(&page.url);
+        for url in extract::media_urls(&page.text, site, default_extensions) {
+            let url = get_download_url(&url, base_url);
+            if seen.insert(url.clone()) {
+                download_urls.push(url);
+            }
+        }
+    }
 
-fn count_media_files(page: &str) -> usize {
-    let mut count = 0;
-    process_media_urls(page, |_| count += 1);
-    count
+    download_urls
 }
 
-fn process_media_urls<F>(page: &str, mut f: F)
-where
-    F: FnMut(&str),
-{
-    let document = Document::from(page);
-
-    for node in document
-        .find(Or(Name("img"), Name("video")))
-        .filter_map(|n| n.attr("src"))
-    {
-        f(node);
-    }
+/// Text of all gallery pages, a single page gallery is the page itself
+fn gallery_text(pages: &[GalleryPage]) -> String {
+    pages
+        .iter()
+        .map(|page| page.text.as_str())
+        .collect::<Vec<_>>()
+        .join("\n")
 }
 
 fn check_url(url: &str) -> Option<Vec<String>> {
@@ -573,8 +1927,156 @@ fn check_url(url: &str) -> Option<Vec<String>> {
     }
 }
 
-fn is_valid_extension(file_name: &str) -> bool {
-    config::EXTENSIONS
-        .iter()
-        .any(|ext| file_name.ends_with(ext))
+fn is_valid_extension(file_name: &str, extensions: &[String]) -> bool {
+    extensions.iter().any(|ext| file_name.ends_with(ext))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::tests::config, storage::local_storage::LocalStorage};
+    use axum::{routing::get, Router, Server};
+    use std::{
+        fs,
+        net::TcpListener,
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    /// Serves any `.jpg`, returns the most requests that were answered at once
+    fn serve() -> (String, Arc<AtomicUsize>) {
+        let active = Arc::new(AtomicUsize::new(0));
+        let most = Arc::new(AtomicUsize::new(0));
+        let (counted, result) = (Arc::clone(&active), Arc::clone(&most));
+        let app = Router::new().route(
+            "/:name",
+            get(move || {
+                let (active, most) = (Arc::clone(&counted), Arc::clone(&most));
+                async move {
+                    most.fetch_max(active.fetch_add(1, Ordering::SeqCst) + 1, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                    active.fetch_sub(1, Ordering::SeqCst);
+                    vec![0u8; 100]
+                }
+            }),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(
+            Server::from_tcp(listener)
+                .unwrap()
+                .serve(app.into_make_service()),
+        );
+        (format!("http://{}", address), result)
+    }
+
+    /// Service with its own `LocalStorage` in `root` and the given download permits
+    fn service(config: &Arc<Config>, root: &Path, permits: &Arc<DownloadPermits>) -> LinksService {
+        LinksService::new(
+            Arc::clone(config),
+            Arc::new(HttpClient::new(config).unwrap()),
+            Arc::new(LocalStorage::new(root)),
+            Arc::clone(permits),
+        )
+    }
+
+    fn urls(address: &str, name: &str) -> Vec<(usize, String)> {
+        (0..3)
+            .map(|i| (i, format!("{}/{}-{}.jpg", address, name, i)))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn services_share_the_download_limit() {
+        let (address, most) = serve();
+        let root = std::env::temp_dir().join(format!("download-limit-{}", std::process::id()));
+        let config = Arc::new(config(&["max_concurrent_downloads=1"]));
+        let permits = Arc::new(DownloadPermits::new(&config));
+        let (first, second) = (
+            service(&config, &root, &permits),
+            service(&config, &root, &permits),
+        );
+        let site = Site::default();
+
+        let (first, second) = tokio::join!(
+            first.download_files_multi(urls(&address, "first"), "first/", 1, &address, &site),
+            second.download_files_multi(urls(&address, "second"), "second/", 2, &address, &site),
+        );
+        let _ = std::fs::remove_dir_all(&root);
+
+        assert_eq!(first.unwrap().len(), 3);
+        assert_eq!(second.unwrap().len(), 3);
+        assert_eq!(most.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn links_of_a_host_share_its_concurrency() {
+        let (address, most) = serve();
+        let root = std::env::temp_dir().join(format!("site-limit-{}", std::process::id()));
+        let config = Arc::new(config(&[]));
+        let service = service(&config, &root, &Arc::new(DownloadPermits::new(&config)));
+        let site = Site {
+            host: "127.0.0.1".to_string(),
+            concurrency: Some(1),
+            ..Default::default()
+        };
+
+        let (first, second) = tokio::join!(
+            service.download_files_multi(urls(&address, "first"), "first/", 1, &address, &site),
+            service.download_files_multi(urls(&address, "second"), "second/", 2, &address, &site),
+        );
+        let _ = std::fs::remove_dir_all(&root);
+
+        assert_eq!(first.unwrap().len(), 3);
+        assert_eq!(second.unwrap().len(), 3);
+        assert_eq!(most.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn stored_urls_are_not_fetched_again_in_the_content_layout() {
+        let (address, most) = serve();
+        let root = std::env::temp_dir().join(format!("content-layout-{}", std::process::id()));
+        let db_name = root.join("test.db");
+        fs::create_dir_all(&root).unwrap();
+        fs::write(&db_name, "").unwrap();
+        let config = Arc::new(config(&[
+            &format!("db_name={}", db_name.display()),
+            "storage_layout=content",
+        ]));
+        crate::init_db::init_db_tables(&config).unwrap();
+        let service = service(&config, &root, &Arc::new(DownloadPermits::new(&config)));
+        let url = format!("{}/1.jpg", address);
+        let key = content_key("abc", "1.jpg");
+        for path in ["first", "second"] {
+            service
+                .links_db_service
+                .create_one(0, &format!("{}/{}", address, path), path)
+                .unwrap();
+        }
+        fs::create_dir_all(root.join(&key).parent().unwrap()).unwrap();
+        fs::write(root.join(&key), "stored").unwrap();
+        service
+            .mediafiles_service
+            .create_one(CreateDto {
+                name: "1.jpg".to_string(),
+                path: key.clone(),
+                hash: "abc".to_string(),
+                size: 6,
+                link_id: 1,
+                source_url: Some(url.clone()),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+
+        let found = service
+            .download_files_multi(vec![(0, url)], "", 2, &address, &Site::default())
+            .await;
+        let _ = fs::remove_dir_all(&root);
+
+        let found = found.unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].path, key);
+        assert_eq!(most.load(Ordering::SeqCst), 0);
+    }
 }
@@ -1,5 +1,7 @@
+pub mod download_permits;
 pub mod dto;
+pub mod link_checks_db_service;
 pub mod links_controller;
-pub mod links_service;
 pub mod links_db_service;
-use super::config;
\ No newline at end of file
+pub mod links_query;
+pub mod links_service;
@@ -1,43 +1,230 @@
-use axum::{response::Html, routing::get_service, Router, Server};
-use config::init_log;
-use log::{error, info};
-use std::net::{SocketAddr, TcpListener};
-use tower_http::services::{ServeDir, ServeFile};
+use axum::{
+    http::Request,
+    middleware,
+    response::Html,
+    routing::{get, get_service},
+    Router, Server,
+};
+use clap::Parser;
+use cli::{cli_service::CliService, dto::Command};
+use config::{init_log, Cli, Config};
+use http_client::HttpClient;
+use log::{error, info, warn};
+use std::{
+    net::{SocketAddr, TcpListener},
+    process,
+    sync::Arc,
+};
+use storage::storage_service::Storage;
+use tower::ServiceBuilder;
+use tower_http::{
+    request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer},
+    services::{ServeDir, ServeFile},
+    trace::{DefaultOnResponse, TraceLayer},
+};
+use tracing::{info_span, Level, Span};
 
+mod auth;
+mod cli;
+mod collections;
 mod config;
+mod crawls;
+mod extract;
+mod http_client;
 mod init_db;
 mod links;
 mod mediafiles;
+mod metrics;
+mod pagination;
+mod rewrite;
+mod scheduler;
+mod sites;
+mod stats;
+mod storage;
+mod tags;
+mod trash;
 mod utils;
+mod watcher;
+use auth::{auth_controller::auth_routes, auth_service::AuthService};
+use collections::collections_controller::collections_routes;
 use init_db::init_db_tables;
-use links::links_controller::links_routes;
+use links::{
+    download_permits::DownloadPermits, links_controller::links_routes, links_service::LinksService,
+};
 use mediafiles::mediafiles_controller::mediafiles_routes;
+use sites::sites_controller::sites_routes;
+use stats::stats_controller::stats_routes;
+use tags::tags_controller::tags_routes;
+use trash::{trash_controller::trash_routes, trash_service::TrashService};
 
 #[tokio::main]
 async fn main() {
-    config::init();
+    let mut cli = Cli::parse();
+    let command = cli.command.take().unwrap_or(Command::Serve);
+    let config = match Config::load(&cli) {
+        Ok(config) => Arc::new(config),
+        Err(errors) => {
+            eprintln!("Invalid configuration:");
+            for e in errors {
+                eprintln!("  - {}", e);
+            }
+            process::exit(1);
+        }
+    };
 
-    init_log();
+    init_log(&config);
 
-    info!("Starting server on PORT {}", *config::PORT);
+    metrics::init();
 
-    if let Err(e) = init_db_tables() {
+    let http = match HttpClient::new(&config) {
+        Ok(http) => Arc::new(http),
+        Err(e) => {
+            error!("Error creating HTTP client: {}", e);
+            process::exit(1);
+        }
+    };
+
+    let storage = match storage::storage_service::new_storage(&config).await {
+        Ok(storage) => storage,
+        Err(e) => {
+            error!("Error creating storage: {}", e);
+            process::exit(1);
+        }
+    };
+
+    if let Err(e) = init_db_tables(&config) {
         error!("Error creating tables: {}", e);
-        return;
+        process::exit(1);
+    }
+
+    let download_permits = Arc::new(DownloadPermits::new(&config));
+
+    match command {
+        Command::Serve => serve(config, http, storage, download_permits).await,
+        command => {
+            if let Err(e) = CliService::new(config, http, storage, download_permits)
+                .run(command)
+                .await
+            {
+                eprintln!("{}", e);
+                process::exit(1);
+            }
+        }
+    }
+}
+
+async fn serve(
+    config: Arc<Config>,
+    http: Arc<HttpClient>,
+    storage: Arc<dyn Storage>,
+    download_permits: Arc<DownloadPermits>,
+) {
+    info!("Starting server on PORT {}", config.port);
+
+    if config.scheduler_enabled {
+        scheduler::scheduler_service::start(
+            Arc::new(LinksService::new(
+                Arc::clone(&config),
+                Arc::clone(&http),
+                Arc::clone(&storage),
+                Arc::clone(&download_permits),
+            )),
+            &config,
+        );
     }
+    if let Some(every) = config.gc_interval {
+        scheduler::scheduler_service::start_gc(
+            Arc::new(LinksService::new(
+                Arc::clone(&config),
+                Arc::clone(&http),
+                Arc::clone(&storage),
+                Arc::clone(&download_permits),
+            )),
+            every,
+        );
+    }
+    if config.watch_enabled {
+        if let Err(e) = watcher::watcher_service::start(
+            Arc::new(LinksService::new(
+                Arc::clone(&config),
+                Arc::clone(&http),
+                Arc::clone(&storage),
+                Arc::clone(&download_permits),
+            )),
+            Arc::clone(&storage),
+            &config,
+        ) {
+            error!("{}", e);
+        }
+    }
+    scheduler::scheduler_service::start_trash_purge(Arc::new(TrashService::new(
+        Arc::clone(&config),
+        Arc::clone(&http),
+        Arc::clone(&storage),
+        Arc::clone(&download_permits),
+    )));
 
-    let addr = SocketAddr::from(([127, 0, 0, 1], *config::PORT));
-    let app = Router::new()
+    let auth = Arc::new(AuthService::new(&config));
+    if auth.is_enabled() {
+        match auth.count_users() {
+            Ok(0) => warn!("Authentication is enabled but there are no users, create one with `parsePhoto create-user <username>`"),
+            Ok(_) => {}
+            Err(e) => error!("Error counting users: {}", e),
+        }
+    }
+
+    let addr = SocketAddr::new(config.bind_address, config.port);
+    let mut routes = Router::new()
         .route(
             "/",
             get_service(ServeFile::new("web/index.html"))
                 .handle_error(|_| async { Html("Error loading index.html") }),
         )
         .nest_service("/static", ServeDir::new("web/static"))
-        .merge(links_routes())
-        .merge(mediafiles_routes());
+        .merge(links_routes(
+            Arc::clone(&config),
+            Arc::clone(&http),
+            Arc::clone(&storage),
+            Arc::clone(&download_permits),
+            Arc::clone(&auth),
+        ))
+        .merge(mediafiles_routes(
+            Arc::clone(&config),
+            Arc::clone(&http),
+            Arc::clone(&storage),
+            Arc::clone(&auth),
+        ))
+        .merge(trash_routes(
+            Arc::clone(&config),
+            http,
+            storage,
+            download_permits,
+            Arc::clone(&auth),
+        ))
+        .merge(sites_routes(Arc::clone(&config), Arc::clone(&auth)))
+        .merge(tags_routes(Arc::clone(&config), Arc::clone(&auth)))
+        .merge(collections_routes(Arc::clone(&config), Arc::clone(&auth)))
+        .merge(stats_routes(Arc::clone(&config), Arc::clone(&auth)))
+        .route("/metrics", get(metrics::render));
+    if auth.is_enabled() {
+        routes = routes.merge(auth_routes(auth));
+    }
 
-    let listener = TcpListener::bind(&addr).expect("Failed to bind PORT");
+    let app = routes
+        .layer(middleware::from_fn(metrics::track_http))
+        .layer(
+            ServiceBuilder::new()
+                .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
+                .layer(
+                    TraceLayer::new_for_http()
+                        .make_span_with(request_span)
+                        .on_response(DefaultOnResponse::new().level(Level::INFO)),
+                )
+                .layer(PropagateRequestIdLayer::x_request_id()),
+        );
+
+    #[allow(clippy::needless_borrows_for_generic_args)]
+    let listener = TcpListener::bind(&addr).expect("Failed to bind address");
 
     info!("Listening on {}", addr);
     Server::from_tcp(listener)
@@ -46,3 +233,19 @@ async fn main() {
         .await
         .unwrap();
 }
+
+/// Span of a request, every log line written while handling it carries the request id
+fn request_span<B>(request: &Request<B>) -> Span {
+    let request_id = request
+        .headers()
+        .get("x-request-id")
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+
+    info_span!(
+        "request",
+        method = %request.method(),
+        uri = %request.uri(),
+        request_id
+    )
+}
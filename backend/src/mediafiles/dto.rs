@@ -1,4 +1,6 @@
+use crate::{links::dto::ErrorClass, storage::dto::FileDisposal};
 use serde::{Deserialize, Serialize};
+use std::fmt;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Mediafile {
@@ -9,12 +11,79 @@ pub struct Mediafile {
     pub size: usize,
     #[serde(rename = "dateAdded")]
     pub date_added: String,
+    #[serde(rename = "sourceUrl")]
+    pub source_url: Option<String>,
+    #[serde(rename = "positionOnPage")]
+    pub position_on_page: Option<usize>,
+    pub etag: Option<String>,
+    #[serde(rename = "lastModified")]
+    pub last_modified: Option<String>,
+    #[serde(rename = "contentType")]
+    pub content_type: Option<String>,
+    #[serde(rename = "downloadedAt")]
+    pub downloaded_at: Option<String>,
+    /// When the mediafile was moved to the trash
+    #[serde(rename = "deletedAt")]
+    pub deleted_at: Option<String>,
+    /// When the watcher saw the file disappear from the storage
+    #[serde(rename = "missingAt")]
+    pub missing_at: Option<String>,
+    pub tags: Vec<String>,
 }
 
+#[derive(Default)]
 pub struct CreateDto {
     pub name: String,
     pub path: String,
     pub hash: String,
     pub size: usize,
     pub link_id: usize,
+    pub source_url: Option<String>,
+    pub position_on_page: Option<usize>,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    pub content_type: Option<String>,
+    pub downloaded_at: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct LinkIdQuery {
+    #[serde(rename = "linkId")]
+    pub link_id: usize,
+    /// Comma separated tags the mediafiles must all have
+    pub tags: Option<String>,
+    /// Comma separated tags the mediafiles must have at least one of
+    #[serde(rename = "anyTags")]
+    pub any_tags: Option<String>,
+    /// Comma separated tags the mediafiles must not have
+    #[serde(rename = "notTags")]
+    pub not_tags: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct RemoveQuery {
+    pub id: usize,
+    /// Removes the mediafile with its records instead of moving it to the trash
+    pub permanent: Option<bool>,
+    /// What happens to the file when the mediafile is removed permanently
+    pub files: Option<FileDisposal>,
+}
+
+/// Failed media request, classified like page request failures
+#[derive(Debug)]
+pub struct DownloadError {
+    pub class: ErrorClass,
+    pub message: String,
+}
+
+impl DownloadError {
+    pub fn new(class: ErrorClass, message: String) -> Self {
+        Self { class, message }
+    }
+}
+
+impl fmt::Display for DownloadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
 }
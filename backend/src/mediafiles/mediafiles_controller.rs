@@ -1,28 +1,106 @@
 use std::sync::Arc;
 
 use axum::{
-    extract::{Query, State},
+    extract::{Path, Query, State},
+    http::{header, HeaderMap},
+    middleware,
     response::IntoResponse,
-    routing,
+    routing, Extension,
 };
 
-use crate::links::dto::IdDto;
+use crate::{
+    auth::{
+        auth_service::{require_read, require_write, AuthService},
+        dto::Access,
+    },
+    config::Config,
+    http_client::HttpClient,
+    links::dto::IdDto,
+    storage::storage_service::Storage,
+    tags::dto::TagFilter,
+};
 
-use super::mediafiles_service::MediafilesService;
+use super::{
+    dto::{LinkIdQuery, RemoveQuery},
+    mediafiles_service::MediafilesService,
+};
 
 pub struct MediafilesController {}
 
 impl MediafilesController {
+    pub async fn get_list(
+        State(service): State<Arc<MediafilesService>>,
+        Extension(access): Extension<Access>,
+        Query(query): Query<LinkIdQuery>,
+    ) -> impl IntoResponse {
+        service
+            .get_list_by_link_id(
+                access,
+                query.link_id,
+                TagFilter::from_query(
+                    query.tags.as_deref(),
+                    query.any_tags.as_deref(),
+                    query.not_tags.as_deref(),
+                ),
+            )
+            .await
+    }
+
+    pub async fn get_file(
+        State(service): State<Arc<MediafilesService>>,
+        Extension(access): Extension<Access>,
+        Path(id): Path<usize>,
+        headers: HeaderMap,
+    ) -> impl IntoResponse {
+        let range = headers
+            .get(header::RANGE)
+            .and_then(|value| value.to_str().ok());
+        service.serve_file(access, id, range).await
+    }
+
     pub async fn remove(
         State(service): State<Arc<MediafilesService>>,
+        Extension(access): Extension<Access>,
+        Query(query): Query<RemoveQuery>,
+    ) -> impl IntoResponse {
+        service.remove(access, query).await
+    }
+
+    pub async fn restore(
+        State(service): State<Arc<MediafilesService>>,
+        Extension(access): Extension<Access>,
         Query(query): Query<IdDto>,
     ) -> impl IntoResponse {
-        service.remove(query.id).await
+        service.restore(access, query.id).await
     }
 }
 
-pub fn mediafiles_routes() -> axum::Router {
-    axum::Router::new()
+pub fn mediafiles_routes(
+    config: Arc<Config>,
+    http: Arc<HttpClient>,
+    storage: Arc<dyn Storage>,
+    auth: Arc<AuthService>,
+) -> axum::Router {
+    let read = axum::Router::new()
+        .route("/mediafiles", routing::get(MediafilesController::get_list))
+        .route(
+            "/mediafiles/:id/file",
+            routing::get(MediafilesController::get_file),
+        )
+        .route_layer(middleware::from_fn_with_state(
+            Arc::clone(&auth),
+            require_read,
+        ));
+    let write = axum::Router::new()
         .route("/mediafiles", routing::delete(MediafilesController::remove))
-        .with_state(Arc::new(MediafilesService::new()))
+        .route(
+            "/mediafiles/restore",
+            routing::get(MediafilesController::restore),
+        )
+        .route_layer(middleware::from_fn_with_state(auth, require_write));
+
+    axum::Router::new()
+        .merge(read)
+        .merge(write)
+        .with_state(Arc::new(MediafilesService::new(&config, http, storage)))
 }
@@ -1,24 +1,36 @@
-use std::env;
-
 use super::dto::{CreateDto, Mediafile};
-use crate::utils::get_now_time;
+use crate::{
+    config::Config,
+    metrics,
+    tags::{
+        dto::TagFilter,
+        tags_db_service::{parse_tags, tag_filter_conditions, tags_column, Tagged},
+    },
+    utils::get_now_time,
+};
 use log::error;
-use rusqlite::{params, Connection, Result};
+use rusqlite::{
+    params, params_from_iter, types::Value, Connection, OptionalExtension, Result, Row,
+};
 
 pub struct MediafilesDbService {
     db_name: String,
 }
 
 impl MediafilesDbService {
-    pub fn new() -> Self {
-        let db_name = env::var("DB_NAME").expect("DB_NAME must be set");
-        Self { db_name }
+    pub fn new(config: &Config) -> Self {
+        Self {
+            db_name: config.db_name.clone(),
+        }
     }
 
     fn open_connection(&self) -> Result<Connection> {
-        Connection::open(&self.db_name)
+        let mut conn = Connection::open(&self.db_name)?;
+        conn.profile(Some(metrics::observe_query));
+        Ok(conn)
     }
 
+    #[allow(clippy::needless_return)]
     pub fn create_one(&self, dto: &CreateDto) -> Result<&str> {
         let mut conn = self.open_connection()?;
         let date_added = get_now_time();
@@ -26,8 +38,23 @@ impl MediafilesDbService {
         let tx = conn.transaction()?;
 
         match tx.execute(
-            "INSERT INTO mediafiles (path, name, hash, size, date_added) VALUES(?, ?, ?, ?, ?)",
-            params![dto.path, dto.name, dto.hash, dto.size, date_added],
+            "INSERT INTO mediafiles (
+                path, name, hash, size, date_added, source_url, position_on_page,
+                etag, last_modified, content_type, downloaded_at
+            ) VALUES(?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            params![
+                dto.path,
+                dto.name,
+                dto.hash,
+                dto.size,
+                date_added,
+                dto.source_url,
+                dto.position_on_page,
+                dto.etag,
+                dto.last_modified,
+                dto.content_type,
+                dto.downloaded_at
+            ],
         ) {
             Ok(_) => {
                 // Получаем ID последней вставленной записи
@@ -57,51 +84,396 @@ impl MediafilesDbService {
         };
     }
 
-    pub fn remove(&self, id: usize) -> Result<&str> {
+    /// Moves the mediafile to the trash, its records and file stay until it is removed
+    pub fn soft_remove(&self, id: usize) -> Result<&str> {
         let conn = self.open_connection()?;
 
-        match conn.execute(
-            "
-            DELETE FROM mediafiles WHERE id = ?;
-            DELETE FROM mediafiles_links WHERE mediafile_id = ?;
-            ",
-            [id, id],
-        ) {
+        let changes = conn.execute(
+            "UPDATE mediafiles SET deleted_at = ? WHERE id = ? AND deleted_at IS NULL",
+            params![get_now_time(), id],
+        )?;
+
+        Ok(if changes == 1 {
+            "One mediafile moved to the trash"
+        } else {
+            "No mediafile moved to the trash"
+        })
+    }
+
+    pub fn restore(&self, id: usize) -> Result<&str> {
+        let conn = self.open_connection()?;
+
+        let changes = conn.execute(
+            "UPDATE mediafiles SET deleted_at = NULL WHERE id = ? AND deleted_at IS NOT NULL",
+            [id],
+        )?;
+
+        Ok(if changes == 1 {
+            "One mediafile restored"
+        } else {
+            "No mediafile restored"
+        })
+    }
+
+    /// Mediafiles in the trash, held by links of one owner when `user_id` is set,
+    /// recently trashed first
+    pub fn get_trashed(&self, user_id: Option<usize>) -> Result<Vec<Mediafile>> {
+        let conn = self.open_connection()?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT m.*, {} FROM mediafiles m
+                WHERE m.deleted_at IS NOT NULL AND (?1 IS NULL OR m.id IN (
+                    SELECT ml.mediafile_id FROM mediafiles_links ml
+                    JOIN links l ON l.id = ml.link_id
+                    WHERE l.user_id = ?1
+                ))
+                ORDER BY m.deleted_at DESC, m.id",
+            tags_column(Tagged::Mediafiles, "m.id")
+        ))?;
+
+        let rows = stmt.query_map([user_id], map_mediafile)?;
+        let result: Result<Vec<_>, _> = rows.collect();
+        result
+    }
+
+    /// Ids of mediafiles trashed before `before`
+    pub fn get_trashed_before(&self, before: &str) -> Result<Vec<usize>> {
+        let conn = self.open_connection()?;
+        let mut stmt =
+            conn.prepare("SELECT id FROM mediafiles WHERE deleted_at < ? ORDER BY deleted_at")?;
+
+        let rows = stmt.query_map([before], |row| row.get(0))?;
+        let result: Result<Vec<_>, _> = rows.collect();
+        result
+    }
+
+    /// Removes the mediafile with its links and tags in one transaction
+    pub fn remove(&self, id: usize) -> Result<&str> {
+        let mut conn = self.open_connection()?;
+        let tx = conn.transaction()?;
+
+        tx.execute("DELETE FROM mediafiles_links WHERE mediafile_id = ?", [id])?;
+        tx.execute("DELETE FROM mediafile_tags WHERE mediafile_id = ?", [id])?;
+        match tx.execute("DELETE FROM mediafiles WHERE id = ?", [id]) {
             Ok(changes) => {
+                tx.commit()?;
                 if changes == 1 {
-                    return Ok("One mediafile removed");
+                    Ok("One mediafile removed")
                 } else {
-                    return Ok("No mediafile removed");
+                    Ok("No mediafile removed")
                 }
             }
             Err(e) => {
                 error!("Error removing mediafile: {}", e);
-                return Err(e);
+                Err(e)
             }
-        };
+        }
     }
 
-    pub fn get_all_by_link_id(&self, link_id: usize) -> Result<Vec<Mediafile>> {
+    /// Points the mediafile to another stored file
+    pub fn update_file(&self, id: usize, path: &str, hash: &str, size: usize) -> Result<&str> {
         let conn = self.open_connection()?;
-        let mut stmt = conn.prepare(
+
+        let changes = conn.execute(
+            "UPDATE mediafiles SET path = ?, hash = ?, size = ? WHERE id = ?",
+            params![path, hash, size, id],
+        )?;
+
+        Ok(if changes == 1 {
+            "One mediafile updated"
+        } else {
+            "No mediafile updated"
+        })
+    }
+
+    /// Points the mediafile at a file stored again. The metadata of a download replaces the
+    /// stored one, a file found without downloading it keeps the metadata it had
+    pub fn refresh(&self, id: usize, dto: &CreateDto) -> Result<&str> {
+        let conn = self.open_connection()?;
+
+        let changes = conn.execute(
+            "UPDATE mediafiles SET path = ?2, hash = ?3, size = ?4,
+                source_url = COALESCE(?5, source_url),
+                position_on_page = COALESCE(?6, position_on_page),
+                etag = CASE WHEN ?10 IS NULL THEN etag ELSE ?7 END,
+                last_modified = CASE WHEN ?10 IS NULL THEN last_modified ELSE ?8 END,
+                content_type = CASE WHEN ?10 IS NULL THEN content_type ELSE ?9 END,
+                downloaded_at = COALESCE(?10, downloaded_at)
+            WHERE id = ?1",
+            params![
+                id,
+                dto.path,
+                dto.hash,
+                dto.size,
+                dto.source_url,
+                dto.position_on_page,
+                dto.etag,
+                dto.last_modified,
+                dto.content_type,
+                dto.downloaded_at
+            ],
+        )?;
+
+        Ok(if changes == 1 {
+            "One mediafile updated"
+        } else {
+            "No mediafile updated"
+        })
+    }
+
+    /// Moves the links and tags of a mediafile to another one holding the same file,
+    /// then removes it
+    pub fn merge(&self, from_id: usize, into_id: usize) -> Result<&str> {
+        let mut conn = self.open_connection()?;
+        let tx = conn.transaction()?;
+
+        tx.execute(
+            "INSERT OR IGNORE INTO mediafiles_links (link_id, mediafile_id)
+                SELECT link_id, ?2 FROM mediafiles_links WHERE mediafile_id = ?1",
+            [from_id, into_id],
+        )?;
+        tx.execute(
+            "INSERT OR IGNORE INTO mediafile_tags (mediafile_id, tag_id)
+                SELECT ?2, tag_id FROM mediafile_tags WHERE mediafile_id = ?1",
+            [from_id, into_id],
+        )?;
+        tx.execute(
+            "DELETE FROM mediafiles_links WHERE mediafile_id = ?",
+            [from_id],
+        )?;
+        tx.execute(
+            "DELETE FROM mediafile_tags WHERE mediafile_id = ?",
+            [from_id],
+        )?;
+        let changes = tx.execute("DELETE FROM mediafiles WHERE id = ?", [from_id])?;
+        tx.commit()?;
+
+        Ok(if changes == 1 {
+            "One mediafile merged"
+        } else {
+            "No mediafile merged"
+        })
+    }
+
+    pub fn update_source(
+        &self,
+        id: usize,
+        source_url: &str,
+        position_on_page: Option<usize>,
+    ) -> Result<&str> {
+        let conn = self.open_connection()?;
+
+        let changes = conn.execute(
+            "UPDATE mediafiles SET source_url = ?, position_on_page = ? WHERE id = ?",
+            params![source_url, position_on_page, id],
+        )?;
+
+        Ok(if changes == 1 {
+            "One mediafile updated"
+        } else {
+            "No mediafile updated"
+        })
+    }
+
+    pub fn get_all_by_link_id(
+        &self,
+        link_id: usize,
+        tag_filter: &TagFilter,
+    ) -> Result<Vec<Mediafile>> {
+        let conn = self.open_connection()?;
+
+        let (conditions, mut values) =
+            tag_filter_conditions(tag_filter, Tagged::Mediafiles, "m.id");
+        values.insert(0, Value::from(link_id as i64));
+
+        let mut stmt = conn.prepare(&format!(
             "
-            SELECT m.id, m.path, m.name, m.hash, m.size, m.date_added
+            SELECT m.id, m.path, m.name, m.hash, m.size, m.date_added, m.source_url,
+                m.position_on_page, m.etag, m.last_modified, m.content_type, m.downloaded_at,
+                m.deleted_at, m.missing_at, {}
             FROM mediafiles m
             JOIN mediafiles_links ml ON m.id = ml.mediafile_id
-            WHERE ml.link_id = ?;
+            WHERE ml.link_id = ? AND m.deleted_at IS NULL{}
+            ORDER BY m.position_on_page, m.name;
             ",
+            tags_column(Tagged::Mediafiles, "m.id"),
+            conditions
+                .iter()
+                .map(|condition| format!(" AND {}", condition))
+                .collect::<String>()
+        ))?;
+        let rows = stmt.query_map(params_from_iter(values), map_mediafile)?;
+        let result: Result<Vec<_>, _> = rows.collect();
+        result
+    }
+
+    pub fn get_one(&self, id: usize) -> Result<Option<Mediafile>> {
+        let conn = self.open_connection()?;
+        conn.query_row("SELECT * FROM mediafiles WHERE id = ?", [id], map_mediafile)
+            .optional()
+    }
+
+    pub fn get_by_path(&self, path: &str) -> Result<Option<Mediafile>> {
+        let conn = self.open_connection()?;
+        conn.query_row(
+            "SELECT * FROM mediafiles WHERE path = ?",
+            [path],
+            map_mediafile,
+        )
+        .optional()
+    }
+
+    /// Paths of every mediafile not in the trash
+    pub fn get_paths(&self) -> Result<Vec<String>> {
+        let conn = self.open_connection()?;
+        let mut stmt = conn.prepare("SELECT path FROM mediafiles WHERE deleted_at IS NULL")?;
+
+        let rows = stmt.query_map([], |row| row.get(0))?;
+        let result: Result<Vec<_>, _> = rows.collect();
+        result
+    }
+
+    /// Mediafiles with the same content, stored by any link, trashed ones left out
+    pub fn get_by_hash(&self, hash: &str, size: usize) -> Result<Vec<Mediafile>> {
+        let conn = self.open_connection()?;
+        let mut stmt = conn.prepare(
+            "SELECT * FROM mediafiles
+                WHERE hash = ? AND size = ? AND deleted_at IS NULL ORDER BY id",
+        )?;
+
+        let rows = stmt.query_map(params![hash, size], map_mediafile)?;
+        let result: Result<Vec<_>, _> = rows.collect();
+        result
+    }
+
+    /// Mediafiles downloaded from `url`, by any link, trashed ones left out
+    pub fn get_by_source_url(&self, url: &str) -> Result<Vec<Mediafile>> {
+        let conn = self.open_connection()?;
+        let mut stmt = conn.prepare(
+            "SELECT * FROM mediafiles
+                WHERE source_url = ? AND deleted_at IS NULL ORDER BY id",
         )?;
-        let rows = stmt.query_map([link_id], |row| {
-            Ok(Mediafile {
-                id: row.get(0)?,
-                path: row.get(1)?,
-                name: row.get(2)?,
-                hash: row.get(3)?,
-                size: row.get(4)?,
-                date_added: row.get(5)?,
-            })
-        })?;
+
+        let rows = stmt.query_map([url], map_mediafile)?;
         let result: Result<Vec<_>, _> = rows.collect();
         result
     }
+
+    /// Marks the mediafiles stored at `key`, or anywhere below it, as missing, returns how many
+    /// were marked. Trashed mediafiles are left alone, their files are expected to be gone
+    pub fn mark_missing(&self, key: &str) -> Result<usize> {
+        let conn = self.open_connection()?;
+
+        conn.execute(
+            "UPDATE mediafiles SET missing_at = ?2
+                WHERE deleted_at IS NULL AND missing_at IS NULL
+                    AND (path = ?1 OR substr(path, 1, length(?1) + 1) = ?1 || '/')",
+            params![key, get_now_time()],
+        )
+    }
+
+    /// Points the mediafile to the file found at `path` and clears its missing state
+    pub fn mark_found(&self, id: usize, path: &str) -> Result<&str> {
+        let conn = self.open_connection()?;
+
+        let changes = conn.execute(
+            "UPDATE mediafiles SET path = ?, missing_at = NULL WHERE id = ?",
+            params![path, id],
+        )?;
+
+        Ok(if changes == 1 {
+            "One mediafile found"
+        } else {
+            "No mediafile found"
+        })
+    }
+
+    /// Missing mediafiles with the same content, a file that appeared with it is a renamed one
+    pub fn get_missing_by_hash(&self, hash: &str, size: usize) -> Result<Vec<Mediafile>> {
+        let conn = self.open_connection()?;
+        let mut stmt = conn.prepare(
+            "SELECT * FROM mediafiles
+                WHERE hash = ? AND size = ? AND missing_at IS NOT NULL AND deleted_at IS NULL
+                ORDER BY missing_at DESC, id",
+        )?;
+
+        let rows = stmt.query_map(params![hash, size], map_mediafile)?;
+        let result: Result<Vec<_>, _> = rows.collect();
+        result
+    }
+
+    /// Adds a stored mediafile to one more link
+    pub fn attach(&self, mediafile_id: usize, link_id: usize) -> Result<&str> {
+        let conn = self.open_connection()?;
+
+        let changes = conn.execute(
+            "INSERT OR IGNORE INTO mediafiles_links (link_id, mediafile_id) VALUES (?, ?)",
+            params![link_id, mediafile_id],
+        )?;
+
+        Ok(if changes == 1 {
+            "One mediafile attached"
+        } else {
+            "No mediafile attached"
+        })
+    }
+
+    /// Owner of the link, none when the link does not exist
+    pub fn get_link_owner(&self, link_id: usize) -> Result<Option<usize>> {
+        let conn = self.open_connection()?;
+        conn.query_row("SELECT user_id FROM links WHERE id = ?", [link_id], |row| {
+            row.get(0)
+        })
+        .optional()
+    }
+
+    /// Owners of the links holding the mediafile
+    pub fn get_owners(&self, mediafile_id: usize) -> Result<Vec<usize>> {
+        let conn = self.open_connection()?;
+        let mut stmt = conn.prepare(
+            "SELECT DISTINCT l.user_id FROM mediafiles_links ml
+                JOIN links l ON l.id = ml.link_id
+                WHERE ml.mediafile_id = ?",
+        )?;
+
+        let rows = stmt.query_map([mediafile_id], |row| row.get(0))?;
+        let result: Result<Vec<_>, _> = rows.collect();
+        result
+    }
+
+    /// Removes the mediafile from the links of one owner, other owners keep it
+    pub fn detach_owner(&self, mediafile_id: usize, user_id: usize) -> Result<&str> {
+        let conn = self.open_connection()?;
+
+        let changes = conn.execute(
+            "DELETE FROM mediafiles_links
+                WHERE mediafile_id = ? AND link_id IN (SELECT id FROM links WHERE user_id = ?)",
+            params![mediafile_id, user_id],
+        )?;
+
+        Ok(if changes > 0 {
+            "One mediafile removed from your links"
+        } else {
+            "No mediafile removed"
+        })
+    }
+}
+
+fn map_mediafile(row: &Row) -> Result<Mediafile> {
+    Ok(Mediafile {
+        id: row.get("id")?,
+        path: row.get("path")?,
+        name: row.get("name")?,
+        hash: row.get("hash")?,
+        size: row.get("size")?,
+        date_added: row.get("date_added")?,
+        source_url: row.get("source_url")?,
+        position_on_page: row.get("position_on_page")?,
+        etag: row.get("etag")?,
+        last_modified: row.get("last_modified")?,
+        content_type: row.get("content_type")?,
+        downloaded_at: row.get("downloaded_at")?,
+        deleted_at: row.get("deleted_at")?,
+        missing_at: row.get("missing_at")?,
+        tags: parse_tags(row),
+    })
 }
@@ -1,24 +1,48 @@
+use axum::{
+    body::{boxed, StreamBody},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use log::error;
+use reqwest::header::{HeaderMap, CONTENT_TYPE, ETAG, LAST_MODIFIED};
 use sha2::{Digest, Sha256};
 
 use super::{
-    dto::{CreateDto, Mediafile},
+    dto::{CreateDto, DownloadError, Mediafile, RemoveQuery},
     mediafiles_db_service::MediafilesDbService,
 };
+use crate::{
+    auth::dto::Access,
+    config::Config,
+    http_client::{HttpClient, ReadError},
+    links::dto::{ErrorClass, IResult},
+    storage::{
+        dto::{ByteRange, FileDisposal, StorageError},
+        storage_service::{dispose, key_name, move_to_trash, trash_key, Storage},
+    },
+    tags::dto::TagFilter,
+    utils::{error_response, get_now_time, server_error_response, success_response},
+};
+use futures::StreamExt;
 use std::{
-    fs::{read, File},
-    io::Write,
-    path::{Path, PathBuf},
-    sync::Arc,
+    collections::HashMap,
+    io,
+    sync::{Arc, Mutex},
 };
 
 pub struct MediafilesService {
     mediafiles_db_service: Arc<MediafilesDbService>,
+    http: Arc<HttpClient>,
+    storage: Arc<dyn Storage>,
 }
 
 impl MediafilesService {
-    pub fn new() -> Self {
+    pub fn new(config: &Config, http: Arc<HttpClient>, storage: Arc<dyn Storage>) -> Self {
         Self {
-            mediafiles_db_service: Arc::new(MediafilesDbService::new()),
+            mediafiles_db_service: Arc::new(MediafilesDbService::new(config)),
+            http,
+            storage,
         }
     }
 
@@ -29,74 +53,441 @@ impl MediafilesService {
             .map_err(|e| e.to_string())
     }
 
-    pub async fn remove(&self, id: usize) -> Result<String, String> {
-        self.mediafiles_db_service
+    /// Moves a mediafile to the trash, a mediafile other owners share is only removed from the
+    /// user's links. A permanent remove deletes its records, the file is kept, deleted or trashed
+    /// by `files`
+    pub async fn remove(&self, access: Access, query: RemoveQuery) -> impl IntoResponse {
+        let id = query.id;
+        let owners = self
+            .mediafiles_db_service
+            .get_owners(id)
+            .map_err(|e| server_error_response(e.to_string()))?;
+
+        if !access.is_admin {
+            if !owners.contains(&access.user_id) {
+                return Err(error_response(
+                    "Mediafile not found".to_string(),
+                    StatusCode::NOT_FOUND,
+                ));
+            }
+            if owners.len() > 1 {
+                return match self.mediafiles_db_service.detach_owner(id, access.user_id) {
+                    Ok(m) => Ok(success_response(m.to_string())),
+                    Err(e) => Err(server_error_response(e.to_string())),
+                };
+            }
+        }
+
+        let result = if query.permanent.unwrap_or(false) {
+            self.purge_mediafile(id, query.files.unwrap_or_default())
+                .await
+        } else {
+            self.trash_mediafile(id).await
+        };
+
+        result.map(success_response).map_err(server_error_response)
+    }
+
+    /// Takes a mediafile back out of the trash
+    pub async fn restore(&self, access: Access, id: usize) -> impl IntoResponse {
+        if !access.is_admin {
+            let owners = self
+                .mediafiles_db_service
+                .get_owners(id)
+                .map_err(|e| server_error_response(e.to_string()))?;
+            if !owners.contains(&access.user_id) {
+                return Err(error_response(
+                    "Mediafile not found".to_string(),
+                    StatusCode::NOT_FOUND,
+                ));
+            }
+        }
+
+        self.restore_mediafile(id)
+            .await
+            .map(success_response)
+            .map_err(server_error_response)
+    }
+
+    /// Moves the mediafile to the trash with its file
+    pub async fn trash_mediafile(&self, id: usize) -> Result<String, String> {
+        let Some(mediafile) = self.get_one(id).await? else {
+            return Ok("No mediafile moved to the trash".to_string());
+        };
+
+        let message = self
+            .mediafiles_db_service
+            .soft_remove(id)
+            .map_err(|e| e.to_string())?;
+        if mediafile.deleted_at.is_none() {
+            move_to_trash(self.storage.as_ref(), &[mediafile.path], false).await;
+        }
+        Ok(message.to_string())
+    }
+
+    /// Takes the mediafile out of the trash with its file
+    pub async fn restore_mediafile(&self, id: usize) -> Result<String, String> {
+        let Some(mediafile) = self.get_one(id).await? else {
+            return Ok("No mediafile restored".to_string());
+        };
+
+        let message = self.restore_record(id).await?;
+        if mediafile.deleted_at.is_some() {
+            move_to_trash(self.storage.as_ref(), &[mediafile.path], true).await;
+        }
+        Ok(message)
+    }
+
+    /// Removes the mediafile with its records for good, its file is kept, deleted or trashed
+    pub async fn purge_mediafile(&self, id: usize, files: FileDisposal) -> Result<String, String> {
+        let mediafile = self.get_one(id).await?;
+
+        let message = self
+            .mediafiles_db_service
             .remove(id)
+            .map_err(|e| e.to_string())?;
+        if let Some(mediafile) = mediafile {
+            let key = match mediafile.deleted_at {
+                Some(_) => trash_key(&mediafile.path),
+                None => mediafile.path,
+            };
+            dispose(self.storage.as_ref(), &[key], files).await;
+        }
+        Ok(message.to_string())
+    }
+
+    /// Clears the trashed state of the record, its file is left where it is
+    pub async fn restore_record(&self, id: usize) -> Result<String, String> {
+        self.mediafiles_db_service
+            .restore(id)
             .map(|s| s.to_string())
             .map_err(|e| e.to_string())
     }
 
-    pub async fn get_all_by_link_id(&self, link_id: usize) -> Result<Vec<Mediafile>, String> {
+    /// Mediafiles in the trash, of every user's links when `user_id` is none
+    pub fn get_trashed(&self, user_id: Option<usize>) -> Result<Vec<Mediafile>, String> {
         self.mediafiles_db_service
-            .get_all_by_link_id(link_id)
+            .get_trashed(user_id)
             .map_err(|e| e.to_string())
     }
-}
 
-pub async fn get_hash_size_by_path(path: &PathBuf) -> Result<(String, usize), String> {
-    let buffer = read(path).map_err(|err| err.to_string())?;
-    let (hash, size) = calculate_hash_size(&buffer).await;
+    /// Ids of mediafiles trashed before `before`
+    pub fn get_trashed_before(&self, before: &str) -> Result<Vec<usize>, String> {
+        self.mediafiles_db_service
+            .get_trashed_before(before)
+            .map_err(|e| e.to_string())
+    }
 
-    Ok((hash, size))
-}
+    /// Streams the stored file of a mediafile, or the part of it the `Range` header asks for
+    pub async fn serve_file(
+        &self,
+        access: Access,
+        id: usize,
+        range: Option<&str>,
+    ) -> Result<Response, (StatusCode, Json<IResult>)> {
+        let not_found = || error_response("Mediafile not found".to_string(), StatusCode::NOT_FOUND);
 
-pub async fn download_file(
-    url: &str,
-    file_path: &Path,
-    link_id: usize,
-) -> Result<CreateDto, String> {
-    let response = fetch_and_write_file(url, file_path).await?;
-    let (hash, size) = calculate_hash_size(&response).await;
-    let name = file_path
-        .file_name()
-        .and_then(|name| name.to_str())
-        .unwrap_or_default()
-        .to_string();
-
-    Ok(CreateDto {
-        name,
-        path: file_path.to_string_lossy().into_owned(),
-        hash,
-        size,
-        link_id,
-    })
-}
+        let mediafile = self
+            .mediafiles_db_service
+            .get_one(id)
+            .map_err(|e| server_error_response(e.to_string()))?
+            .filter(|mediafile| mediafile.deleted_at.is_none())
+            .ok_or_else(not_found)?;
+        let owners = self
+            .mediafiles_db_service
+            .get_owners(id)
+            .map_err(|e| server_error_response(e.to_string()))?;
+        if !access.is_admin && !owners.contains(&access.user_id) {
+            return Err(not_found());
+        }
+
+        // A header that can not be parsed is ignored and the whole file is sent
+        let range = range.and_then(ByteRange::parse);
+        let object = match self.storage.get(&mediafile.path, range).await {
+            Ok(object) => object,
+            Err(StorageError::NotFound) => {
+                return Err(error_response(
+                    format!("{} is missing in the storage", mediafile.path),
+                    StatusCode::NOT_FOUND,
+                ))
+            }
+            Err(StorageError::InvalidRange) => {
+                return Err(error_response(
+                    "Range not satisfiable".to_string(),
+                    StatusCode::RANGE_NOT_SATISFIABLE,
+                ))
+            }
+            Err(StorageError::Failed(e)) => {
+                error!("Error reading {}: {}", mediafile.path, e);
+                return Err(server_error_response("Error reading file".to_string()));
+            }
+        };
 
-pub async fn fetch_and_write_file(url: &str, file_path: &Path) -> Result<Vec<u8>, String> {
-    let response = reqwest::get(url)
-        .await
-        .map_err(|e| format!("Request failed: {}", e))?
-        .bytes()
-        .await
-        .map_err(|e| format!("Failed to read bytes: {}", e))?;
+        let content_type = mediafile
+            .content_type
+            .unwrap_or_else(|| "application/octet-stream".to_string());
+        let mut response = Response::builder()
+            .header(header::CONTENT_TYPE, content_type)
+            .header(header::ACCEPT_RANGES, "bytes");
+        response = match object.range {
+            Some((start, end)) => response
+                .status(StatusCode::PARTIAL_CONTENT)
+                .header(
+                    header::CONTENT_RANGE,
+                    format!("bytes {}-{}/{}", start, end, object.size),
+                )
+                .header(header::CONTENT_LENGTH, end - start + 1),
+            None => response
+                .status(StatusCode::OK)
+                .header(header::CONTENT_LENGTH, object.size),
+        };
 
-    let mut file = File::create(file_path).map_err(|e| format!("Failed to create file: {}", e))?;
+        response
+            .body(boxed(StreamBody::new(object.body)))
+            .map_err(|e| server_error_response(e.to_string()))
+    }
 
-    file.write_all(&response)
-        .map_err(|e| format!("Failed to write to file: {}", e))?;
-    file.flush()
-        .map_err(|e| format!("Failed to flush file: {}", e))?;
+    pub async fn get_one(&self, id: usize) -> Result<Option<Mediafile>, String> {
+        self.mediafiles_db_service
+            .get_one(id)
+            .map_err(|e| e.to_string())
+    }
 
-    Ok(response.to_vec())
-}
+    pub async fn get_by_path(&self, path: &str) -> Result<Option<Mediafile>, String> {
+        self.mediafiles_db_service
+            .get_by_path(path)
+            .map_err(|e| e.to_string())
+    }
+
+    pub async fn get_by_hash(&self, hash: &str, size: usize) -> Result<Vec<Mediafile>, String> {
+        self.mediafiles_db_service
+            .get_by_hash(hash, size)
+            .map_err(|e| e.to_string())
+    }
+
+    /// A mediafile downloaded from `url` whose file is still in the storage
+    pub async fn get_stored_by_source_url(&self, url: &str) -> Result<Option<Mediafile>, String> {
+        let records = self
+            .mediafiles_db_service
+            .get_by_source_url(url)
+            .map_err(|e| e.to_string())?;
+        for record in records {
+            if self.storage.exists(&record.path).await? {
+                return Ok(Some(record));
+            }
+        }
+
+        Ok(None)
+    }
+
+    pub async fn get_missing_by_hash(
+        &self,
+        hash: &str,
+        size: usize,
+    ) -> Result<Vec<Mediafile>, String> {
+        self.mediafiles_db_service
+            .get_missing_by_hash(hash, size)
+            .map_err(|e| e.to_string())
+    }
 
-pub async fn calculate_hash_size(data: &Vec<u8>) -> (String, usize) {
-    let mut hasher = Sha256::new();
+    pub async fn mark_missing(&self, key: &str) -> Result<usize, String> {
+        self.mediafiles_db_service
+            .mark_missing(key)
+            .map_err(|e| e.to_string())
+    }
+
+    pub async fn mark_found(&self, id: usize, path: &str) -> Result<String, String> {
+        self.mediafiles_db_service
+            .mark_found(id, path)
+            .map(|s| s.to_string())
+            .map_err(|e| e.to_string())
+    }
+
+    pub async fn attach(&self, mediafile_id: usize, link_id: usize) -> Result<String, String> {
+        self.mediafiles_db_service
+            .attach(mediafile_id, link_id)
+            .map(|s| s.to_string())
+            .map_err(|e| e.to_string())
+    }
+
+    pub async fn refresh(&self, id: usize, dto: &CreateDto) -> Result<String, String> {
+        self.mediafiles_db_service
+            .refresh(id, dto)
+            .map(|s| s.to_string())
+            .map_err(|e| e.to_string())
+    }
 
-    hasher.update(data);
+    pub async fn update_file(
+        &self,
+        id: usize,
+        path: &str,
+        hash: &str,
+        size: usize,
+    ) -> Result<String, String> {
+        self.mediafiles_db_service
+            .update_file(id, path, hash, size)
+            .map(|s| s.to_string())
+            .map_err(|e| e.to_string())
+    }
 
-    let hash = format!("{:x}", hasher.finalize());
-    let size = data.len();
+    pub async fn merge(&self, from_id: usize, into_id: usize) -> Result<String, String> {
+        self.mediafiles_db_service
+            .merge(from_id, into_id)
+            .map(|s| s.to_string())
+            .map_err(|e| e.to_string())
+    }
 
-    (hash, size)
+    pub async fn get_paths(&self) -> Result<Vec<String>, String> {
+        self.mediafiles_db_service
+            .get_paths()
+            .map_err(|e| e.to_string())
+    }
+
+    pub async fn update_source(
+        &self,
+        id: usize,
+        source_url: &str,
+        position_on_page: Option<usize>,
+    ) -> Result<String, String> {
+        self.mediafiles_db_service
+            .update_source(id, source_url, position_on_page)
+            .map(|s| s.to_string())
+            .map_err(|e| e.to_string())
+    }
+
+    pub async fn get_list_by_link_id(
+        &self,
+        access: Access,
+        link_id: usize,
+        tag_filter: TagFilter,
+    ) -> impl IntoResponse {
+        match self.mediafiles_db_service.get_link_owner(link_id) {
+            Ok(Some(owner_id)) if access.owns(owner_id) => {}
+            Ok(_) => {
+                return Err(error_response(
+                    "Link not found".to_string(),
+                    StatusCode::NOT_FOUND,
+                ))
+            }
+            Err(e) => return Err(server_error_response(e.to_string())),
+        }
+
+        match self
+            .mediafiles_db_service
+            .get_all_by_link_id(link_id, &tag_filter)
+        {
+            Ok(mediafiles) => Ok((StatusCode::OK, Json(mediafiles))),
+            Err(e) => {
+                error!("Error getting mediafiles: {}", e);
+                Err(server_error_response(
+                    "Error getting mediafiles".to_string(),
+                ))
+            }
+        }
+    }
+
+    pub async fn get_all_by_link_id(&self, link_id: usize) -> Result<Vec<Mediafile>, String> {
+        self.mediafiles_db_service
+            .get_all_by_link_id(link_id, &TagFilter::default())
+            .map_err(|e| e.to_string())
+    }
+
+    pub async fn download_file(
+        &self,
+        url: &str,
+        key: &str,
+        link_id: usize,
+        position_on_page: Option<usize>,
+        referer: Option<&str>,
+        request_headers: &HashMap<String, String>,
+    ) -> Result<CreateDto, DownloadError> {
+        let (hash, size, headers) = self
+            .fetch_to_storage(url, key, referer, request_headers)
+            .await?;
+        let header = |name| {
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(|value| value.to_string())
+        };
+
+        Ok(CreateDto {
+            name: key_name(key).to_string(),
+            path: key.to_string(),
+            hash,
+            size,
+            link_id,
+            source_url: Some(url.to_string()),
+            position_on_page,
+            etag: header(ETAG),
+            last_modified: header(LAST_MODIFIED),
+            content_type: header(CONTENT_TYPE),
+            downloaded_at: Some(get_now_time()),
+        })
+    }
+
+    /// Streams the body of `url` into the storage at `key`,
+    /// returns the hash and size of the stored body with the response headers
+    pub async fn fetch_to_storage(
+        &self,
+        url: &str,
+        key: &str,
+        referer: Option<&str>,
+        request_headers: &HashMap<String, String>,
+    ) -> Result<(String, usize, HeaderMap), DownloadError> {
+        let response = self
+            .http
+            .get(url, referer, request_headers)
+            .send()
+            .await
+            .map_err(|e| {
+                DownloadError::new(
+                    ErrorClass::from_request_error(&e),
+                    format!("Request failed: {}", e),
+                )
+            })?;
+
+        if !response.status().is_success() {
+            return Err(DownloadError::new(
+                ErrorClass::from_status(response.status().as_u16()),
+                format!("Server responded with {}", response.status()),
+            ));
+        }
+
+        let headers = response.headers().clone();
+
+        // The body is hashed on its way to the storage, a failed read keeps its error class
+        let hasher = Arc::new(Mutex::new(Sha256::new()));
+        let read_error = Arc::new(Mutex::new(None));
+        let body = self.http.body_stream(response).map({
+            let hasher = Arc::clone(&hasher);
+            let read_error = Arc::clone(&read_error);
+            move |chunk| match chunk {
+                Ok(chunk) => {
+                    hasher.lock().unwrap().update(&chunk);
+                    Ok(chunk)
+                }
+                Err(e) => {
+                    let class = match e {
+                        ReadError::Timeout(_) => ErrorClass::Timeout,
+                        ReadError::TooLarge(_) | ReadError::Failed(_) => ErrorClass::Body,
+                    };
+                    let message = format!("Failed to read bytes: {}", e);
+                    *read_error.lock().unwrap() = Some(DownloadError::new(class, message.clone()));
+                    Err(io::Error::other(message))
+                }
+            }
+        });
+
+        let size = self.storage.put(key, Box::pin(body)).await.map_err(|e| {
+            read_error
+                .lock()
+                .unwrap()
+                .take()
+                .unwrap_or_else(|| DownloadError::new(ErrorClass::Other, e))
+        })?;
+        let hash = format!("{:x}", hasher.lock().unwrap().clone().finalize());
+
+        Ok((hash, size as usize, headers))
+    }
 }
@@ -1,4 +1,4 @@
 pub mod dto;
 pub mod mediafiles_controller;
+pub mod mediafiles_db_service;
 pub mod mediafiles_service;
-pub mod mediafiles_db_service;
\ No newline at end of file
@@ -0,0 +1,198 @@
+use axum::{
+    extract::MatchedPath,
+    http::{header::CONTENT_TYPE, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use log::error;
+use once_cell::sync::Lazy;
+use prometheus::{
+    register_histogram_vec, register_int_counter, register_int_counter_vec, register_int_gauge,
+    register_int_gauge_vec, Encoder, HistogramVec, IntCounter, IntCounterVec, IntGauge,
+    IntGaugeVec, TextEncoder,
+};
+use regex::Regex;
+use std::time::{Duration, Instant};
+
+pub static HTTP_REQUESTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "parsephoto_http_requests_total",
+        "HTTP requests handled by the server",
+        &["method", "route", "status"]
+    )
+    .unwrap()
+});
+
+pub static HTTP_REQUEST_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "parsephoto_http_request_duration_seconds",
+        "Time to handle HTTP requests",
+        &["method", "route"]
+    )
+    .unwrap()
+});
+
+pub static DOWNLOADS_STARTED: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "parsephoto_downloads_started_total",
+        "Media downloads started"
+    )
+    .unwrap()
+});
+
+pub static DOWNLOADS_SUCCEEDED: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "parsephoto_downloads_succeeded_total",
+        "Media downloads saved to disk"
+    )
+    .unwrap()
+});
+
+pub static DOWNLOADS_FAILED: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "parsephoto_downloads_failed_total",
+        "Media downloads failed with every candidate url, by error class of the last attempt",
+        &["error_class"]
+    )
+    .unwrap()
+});
+
+pub static DOWNLOADED_BYTES: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "parsephoto_downloaded_bytes_total",
+        "Bytes of downloaded media"
+    )
+    .unwrap()
+});
+
+pub static ACTIVE_DOWNLOADS: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!("parsephoto_active_downloads", "Media downloads in progress").unwrap()
+});
+
+/// `downloads` are waiting for a site concurrency permit, `checks` are due links
+/// not yet checked by the scheduler
+pub static JOB_QUEUE_DEPTH: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "parsephoto_job_queue_depth",
+        "Jobs waiting to run",
+        &["queue"]
+    )
+    .unwrap()
+});
+
+pub static PAGE_REQUESTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "parsephoto_page_requests_total",
+        "Gallery page requests, `ok` or the error class",
+        &["result"]
+    )
+    .unwrap()
+});
+
+pub static PAGE_REQUEST_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "parsephoto_page_request_duration_seconds",
+        "Time to fetch and read gallery pages",
+        &["result"]
+    )
+    .unwrap()
+});
+
+pub static DB_QUERY_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "parsephoto_db_query_duration_seconds",
+        "Time to run SQL statements",
+        &["statement", "table"],
+        vec![0.0001, 0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0]
+    )
+    .unwrap()
+});
+
+static STATEMENT_TABLE: Lazy<Regex> = Lazy::new(|| {
+    // SQLite internal statements, like the FTS5 ones, quote names and add the schema
+    Regex::new(r#"(?i)\b(?:FROM|INTO|UPDATE)\s+(?:['"]?\w+['"]?\.)?['"]?(\w+)"#).unwrap()
+});
+
+/// Registers every metric so that `/metrics` lists them before their first use
+pub fn init() {
+    Lazy::force(&HTTP_REQUESTS);
+    Lazy::force(&HTTP_REQUEST_DURATION);
+    Lazy::force(&DOWNLOADS_STARTED);
+    Lazy::force(&DOWNLOADS_SUCCEEDED);
+    Lazy::force(&DOWNLOADS_FAILED);
+    Lazy::force(&DOWNLOADED_BYTES);
+    Lazy::force(&ACTIVE_DOWNLOADS);
+    Lazy::force(&JOB_QUEUE_DEPTH);
+    Lazy::force(&PAGE_REQUESTS);
+    Lazy::force(&PAGE_REQUEST_DURATION);
+    Lazy::force(&DB_QUERY_DURATION);
+}
+
+/// Metrics in the Prometheus text format
+pub async fn render() -> impl IntoResponse {
+    let mut buffer = Vec::new();
+    let encoder = TextEncoder::new();
+
+    match encoder.encode(&prometheus::gather(), &mut buffer) {
+        Ok(()) => Ok(([(CONTENT_TYPE, encoder.format_type().to_string())], buffer)),
+        Err(e) => {
+            error!("Error encoding metrics: {}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Counts requests and their latency by route pattern, so `/links/:id` is one series
+pub async fn track_http<B>(request: Request<B>, next: Next<B>) -> Response {
+    let started = Instant::now();
+    let method = request.method().to_string();
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+
+    let response = next.run(request).await;
+
+    HTTP_REQUESTS
+        .with_label_values(&[&method, &route, response.status().as_str()])
+        .inc();
+    HTTP_REQUEST_DURATION
+        .with_label_values(&[&method, &route])
+        .observe(started.elapsed().as_secs_f64());
+
+    response
+}
+
+/// Profiler for SQLite connections, see `Connection::profile`
+pub fn observe_query(sql: &str, duration: Duration) {
+    let statement = sql
+        .split_whitespace()
+        .next()
+        .unwrap_or_default()
+        .to_lowercase();
+    let table = STATEMENT_TABLE
+        .captures(sql)
+        .map(|captures| captures[1].to_lowercase())
+        .unwrap_or_default();
+
+    DB_QUERY_DURATION
+        .with_label_values(&[&statement, &table])
+        .observe(duration.as_secs_f64());
+}
+
+/// Increments the gauge until dropped
+pub struct GaugeGuard(IntGauge);
+
+impl GaugeGuard {
+    pub fn new(gauge: IntGauge) -> Self {
+        gauge.inc();
+        Self(gauge)
+    }
+}
+
+impl Drop for GaugeGuard {
+    fn drop(&mut self) {
+        self.0.dec();
+    }
+}
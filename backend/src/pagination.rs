@@ -0,0 +1,203 @@
+use log::{debug, warn};
+use reqwest::Url;
+use scraper::{Html, Selector};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+
+/// How the pages following a gallery page are found
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Pagination {
+    /// CSS selector of the next page link, like `.pager a.next`
+    #[serde(rename = "nextSelector")]
+    pub next_selector: Option<String>,
+    /// Follows `<link rel="next">` and `<a rel="next">`
+    #[serde(rename = "relNext", default = "default_rel_next")]
+    pub rel_next: bool,
+    /// Next page url built from a page counter, `{url}` is the link url
+    /// and `{page}` the page number, like `{url}?page={page}` or `{url}/page/{page}`
+    #[serde(rename = "urlPattern")]
+    pub url_pattern: Option<String>,
+    /// Number of the second page for `urlPattern`
+    #[serde(rename = "startPage")]
+    pub start_page: Option<usize>,
+    /// Overrides the global `MAX_PAGES` when set
+    #[serde(rename = "maxPages")]
+    pub max_pages: Option<usize>,
+}
+
+impl Default for Pagination {
+    fn default() -> Self {
+        Self {
+            next_selector: None,
+            rel_next: default_rel_next(),
+            url_pattern: None,
+            start_page: None,
+            max_pages: None,
+        }
+    }
+}
+
+fn default_rel_next() -> bool {
+    true
+}
+
+impl Pagination {
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(next_selector) = &self.next_selector {
+            Selector::parse(next_selector)
+                .map_err(|e| format!("Invalid next page selector {}: {:?}", next_selector, e))?;
+        }
+
+        if let Some(url_pattern) = &self.url_pattern {
+            if !url_pattern.contains("{page}") {
+                return Err(format!("Url pattern {} has no {{page}}", url_pattern));
+            }
+        }
+
+        if self.max_pages == Some(0) {
+            return Err("Max pages must be greater than 0".to_string());
+        }
+
+        Ok(())
+    }
+
+    pub fn max_pages(&self, default: usize) -> usize {
+        self.max_pages.unwrap_or(default)
+    }
+}
+
+/// One fetched page of a gallery
+#[derive(Debug, Clone)]
+pub struct GalleryPage {
+    pub url: String,
+    pub text: String,
+}
+
+/// Url of the page after `page`, `page_number` is the 1-based number of `page`.
+/// The selector is preferred, then `rel=next`, then the url pattern
+pub fn next_page_url(
+    pagination: &Pagination,
+    link_url: &str,
+    page: &GalleryPage,
+    page_number: usize,
+) -> Option<String> {
+    let document = Html::parse_document(&page.text);
+
+    let selectors = pagination.next_selector.iter().map(String::as_str).chain(
+        pagination
+            .rel_next
+            .then_some(["link[rel~=next]", "a[rel~=next]"])
+            .into_iter()
+            .flatten(),
+    );
+
+    for selector in selectors {
+        let selector = match Selector::parse(selector) {
+            Ok(selector) => selector,
+            Err(e) => {
+                debug!("Invalid next page selector {}: {:?}", selector, e);
+                continue;
+            }
+        };
+
+        let href = document
+            .select(&selector)
+            .find_map(|element| element.value().attr("href"));
+        if let Some(url) = href.and_then(|href| resolve_url(&page.url, href)) {
+            return Some(url);
+        }
+    }
+
+    pagination.url_pattern.as_ref().map(|url_pattern| {
+        let page = pagination.start_page.unwrap_or(2) + page_number - 1;
+        url_pattern
+            .replace("{url}", link_url.trim_end_matches('/'))
+            .replace("{page}", &page.to_string())
+    })
+}
+
+/// Follows the pages after `first` until there is no next page, the site `maxPages`
+/// or `default_max_pages` is reached, a page repeats or a page fails.
+/// Pages are returned in order, `first` included
+pub async fn follow<F, Fut>(
+    pagination: &Pagination,
+    default_max_pages: usize,
+    link_url: &str,
+    first: GalleryPage,
+    mut fetch: F,
+) -> Vec<GalleryPage>
+where
+    F: FnMut(String, String) -> Fut,
+    Fut: std::future::Future<Output = Result<GalleryPage, String>>,
+{
+    let max_pages = pagination.max_pages(default_max_pages);
+    let mut visited: HashSet<String> = HashSet::from([normalize(&first.url)]);
+    let mut hashes: HashSet<Vec<u8>> = HashSet::from([hash_text(&first.text)]);
+    let mut pages = vec![first];
+
+    while pages.len() < max_pages {
+        let current = pages.last().expect("first page is always present");
+        let next_url = match next_page_url(pagination, link_url, current, pages.len()) {
+            Some(next_url) => next_url,
+            None => break,
+        };
+
+        if !visited.insert(normalize(&next_url)) {
+            debug!("Page {} was already visited, stopping pagination", next_url);
+            break;
+        }
+
+        let page = match fetch(next_url.clone(), current.url.clone()).await {
+            Ok(page) => page,
+            Err(e) => {
+                // The page counter running past the last page ends here as well
+                debug!("Pagination of {} stopped at {}: {}", link_url, next_url, e);
+                break;
+            }
+        };
+
+        // Redirects and servers ignoring an unknown page number return a seen page
+        let final_url = normalize(&page.url);
+        if final_url != normalize(&next_url) && !visited.insert(final_url)
+            || !hashes.insert(hash_text(&page.text))
+        {
+            debug!(
+                "Page {} repeats a previous page, stopping pagination",
+                next_url
+            );
+            break;
+        }
+
+        pages.push(page);
+    }
+
+    if pages.len() == max_pages {
+        warn!(
+            "Pagination of {} reached the limit of {} pages",
+            link_url, max_pages
+        );
+    }
+
+    pages
+}
+
+fn resolve_url(base: &str, href: &str) -> Option<String> {
+    let url = Url::parse(base).ok()?.join(href.trim()).ok()?;
+    matches!(url.scheme(), "http" | "https").then(|| url.to_string())
+}
+
+/// Url without its fragment and trailing slash, used for loop detection
+fn normalize(url: &str) -> String {
+    match Url::parse(url) {
+        Ok(mut url) => {
+            url.set_fragment(None);
+            url.to_string().trim_end_matches('/').to_string()
+        }
+        Err(_) => url.trim_end_matches('/').to_string(),
+    }
+}
+
+fn hash_text(text: &str) -> Vec<u8> {
+    Sha256::digest(text.as_bytes()).to_vec()
+}
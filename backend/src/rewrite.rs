@@ -0,0 +1,324 @@
+use crate::http_client::HttpClient;
+use log::{debug, warn};
+use regex::Regex;
+use reqwest::header::CONTENT_LENGTH;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::HashMap;
+
+/// Regex rewrite of a media url into higher resolution variants,
+/// like `/a/604/` to `/a/1280/`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RewriteRule {
+    pub pattern: String,
+    /// Replacements tried in order, `$1` style groups are expanded
+    #[serde(alias = "replacement", deserialize_with = "one_or_many")]
+    pub replacements: Vec<String>,
+    /// Confirms a candidate with a HEAD request before downloading it
+    #[serde(default)]
+    pub probe: bool,
+    /// Probed candidates with a smaller `Content-Length` are skipped
+    #[serde(rename = "minContentLength")]
+    pub min_content_length: Option<u64>,
+}
+
+impl RewriteRule {
+    pub fn compile(&self) -> Result<Regex, String> {
+        Regex::new(&self.pattern)
+            .map_err(|e| format!("Invalid rewrite pattern {}: {}", self.pattern, e))
+    }
+}
+
+/// Rewrite rule with its compiled pattern, built once and matched against many urls
+pub type CompiledRule = (Regex, RewriteRule);
+
+/// Compiles `rules` in order, failing on the first invalid pattern
+pub fn compile(rules: &[RewriteRule]) -> Result<Vec<CompiledRule>, String> {
+    rules
+        .iter()
+        .map(|rule| Ok((rule.compile()?, rule.clone())))
+        .collect()
+}
+
+/// Rules of a site followed by the global rules, site rules with an invalid pattern are skipped
+pub fn with_global(site_rules: &[RewriteRule], global_rules: &[CompiledRule]) -> Vec<CompiledRule> {
+    site_rules
+        .iter()
+        .filter_map(|rule| match rule.compile() {
+            Ok(pattern) => Some((pattern, rule.clone())),
+            Err(e) => {
+                warn!("{}", e);
+                None
+            }
+        })
+        .chain(global_rules.iter().cloned())
+        .collect()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Candidate {
+    pub url: String,
+    pub probe: bool,
+    #[serde(rename = "minContentLength")]
+    pub min_content_length: Option<u64>,
+}
+
+/// Rewritten variants of `url` in rule order, the original url is not included
+pub fn candidates(url: &str, rules: &[CompiledRule]) -> Vec<Candidate> {
+    let mut result: Vec<Candidate> = Vec::new();
+
+    for (pattern, rule) in rules {
+        if !pattern.is_match(url) {
+            continue;
+        }
+
+        for replacement in &rule.replacements {
+            let rewritten = pattern.replace(url, replacement.as_str()).to_string();
+            if rewritten != url && !result.iter().any(|c| c.url == rewritten) {
+                result.push(Candidate {
+                    url: rewritten,
+                    probe: rule.probe,
+                    min_content_length: rule.min_content_length,
+                });
+            }
+        }
+    }
+
+    result
+}
+
+/// Urls to try for `url` in order: the candidates of `rules` without the ones failing
+/// their probe, and the original url last
+pub async fn resolve(
+    http: &HttpClient,
+    url: &str,
+    rules: &[CompiledRule],
+    referer: Option<&str>,
+    headers: &HashMap<String, String>,
+) -> Vec<String> {
+    let mut urls = Vec::new();
+    for candidate in candidates(url, rules) {
+        if !candidate.probe || probe(http, &candidate, referer, headers).await {
+            urls.push(candidate.url);
+        } else {
+            debug!("Rewritten url {} rejected by probe", candidate.url);
+        }
+    }
+
+    urls.push(url.to_string());
+    urls
+}
+
+/// Checks with a HEAD request that the candidate exists and is large enough
+async fn probe(
+    http: &HttpClient,
+    candidate: &Candidate,
+    referer: Option<&str>,
+    headers: &HashMap<String, String>,
+) -> bool {
+    let response = match http.head(&candidate.url, referer, headers).send().await {
+        Ok(response) if response.status().is_success() => response,
+        _ => return false,
+    };
+
+    match candidate.min_content_length {
+        Some(min_content_length) => response
+            .headers()
+            .get(CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<u64>().ok())
+            .is_some_and(|length| length >= min_content_length),
+        None => true,
+    }
+}
+
+fn one_or_many<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(replacement) => vec![replacement],
+        OneOrMany::Many(replacements) => replacements,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::tests::config;
+    use axum::{routing::get, Router, Server};
+    use std::net::TcpListener;
+
+    fn rule(pattern: &str, replacements: &[&str]) -> RewriteRule {
+        RewriteRule {
+            pattern: pattern.to_string(),
+            replacements: replacements.iter().map(|r| r.to_string()).collect(),
+            probe: false,
+            min_content_length: None,
+        }
+    }
+
+    #[test]
+    fn candidates_of_each_rule() {
+        let cases: &[(&str, RewriteRule, &str, &[&str])] = &[
+            (
+                "thumbnail to original",
+                rule("/a/604/", &["/a/1280/"]),
+                "https://example.com/a/604/photo.jpg",
+                &["https://example.com/a/1280/photo.jpg"],
+            ),
+            (
+                "thumbnail suffix to ordered variants",
+                rule(r"_(\d+)\.jpg$", &["_orig.jpg", "_1280.jpg"]),
+                "https://example.com/photo_320.jpg",
+                &[
+                    "https://example.com/photo_orig.jpg",
+                    "https://example.com/photo_1280.jpg",
+                ],
+            ),
+            (
+                "groups are expanded",
+                rule(r"/thumbs/(\w+)/", &["/full/$1/"]),
+                "https://example.com/thumbs/abc/1.png",
+                &["https://example.com/full/abc/1.png"],
+            ),
+            (
+                "query string stripping",
+                rule(r"\?.*$", &[""]),
+                "https://example.com/photo.jpg?w=320&h=240",
+                &["https://example.com/photo.jpg"],
+            ),
+            (
+                "no match passes the url through",
+                rule("/a/604/", &["/a/1280/"]),
+                "https://example.com/b/604/photo.jpg",
+                &[],
+            ),
+            (
+                "a replacement giving the same url is no candidate",
+                rule(r"\?.*$", &[""]),
+                "https://example.com/photo.jpg",
+                &[],
+            ),
+        ];
+
+        for (name, rule, url, expected) in cases {
+            let rules = compile(std::slice::from_ref(rule)).unwrap();
+            let urls: Vec<String> = candidates(url, &rules).into_iter().map(|c| c.url).collect();
+            assert_eq!(&urls, expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn candidates_keep_rule_order_without_repeats() {
+        let rules = compile(&[
+            rule("/a/604/", &["/a/1280/", "/a/807/"]),
+            rule(r"/\d+/", &["/1280/"]),
+        ])
+        .unwrap();
+
+        let urls: Vec<String> = candidates("https://example.com/a/604/1.jpg", &rules)
+            .into_iter()
+            .map(|c| c.url)
+            .collect();
+
+        assert_eq!(
+            urls,
+            [
+                "https://example.com/a/1280/1.jpg",
+                "https://example.com/a/807/1.jpg",
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_patterns_are_reported() {
+        let error = compile(&[rule("(", &["x"])]).unwrap_err();
+        assert!(error.contains("Invalid rewrite pattern ("), "{}", error);
+
+        let rules = with_global(&[rule("(", &["x"]), rule("a", &["b"])], &[]);
+        assert_eq!(rules.len(), 1);
+    }
+
+    /// Serves `/big.jpg` with 5000 bytes and `/small.jpg` with 10 bytes, other paths are 404
+    fn serve() -> String {
+        let app = Router::new()
+            .route("/big.jpg", get(|| async { vec![0u8; 5000] }))
+            .route("/small.jpg", get(|| async { vec![0u8; 10] }));
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(
+            Server::from_tcp(listener)
+                .unwrap()
+                .serve(app.into_make_service()),
+        );
+        format!("http://{}", address)
+    }
+
+    #[tokio::test]
+    async fn resolve_falls_back_to_the_original_url() {
+        let rules = compile(&[rule("/a/604/", &["/a/1280/"])]).unwrap();
+        let http = HttpClient::new(&config(&[])).unwrap();
+        let headers = HashMap::new();
+
+        assert_eq!(
+            resolve(
+                &http,
+                "https://example.com/a/604/1.jpg",
+                &rules,
+                None,
+                &headers
+            )
+            .await,
+            [
+                "https://example.com/a/1280/1.jpg",
+                "https://example.com/a/604/1.jpg",
+            ]
+        );
+        assert_eq!(
+            resolve(&http, "https://example.com/b/1.jpg", &rules, None, &headers).await,
+            ["https://example.com/b/1.jpg"]
+        );
+    }
+
+    #[tokio::test]
+    async fn resolve_skips_candidates_failing_their_probe() {
+        let root = serve();
+        let url = format!("{}/thumb.jpg", root);
+        let http = HttpClient::new(&config(&[])).unwrap();
+        let headers = HashMap::new();
+        let probed = |min_content_length| RewriteRule {
+            probe: true,
+            min_content_length,
+            ..rule("/thumb.jpg", &["/missing.jpg", "/small.jpg", "/big.jpg"])
+        };
+
+        // A missing candidate is skipped, any size passes without a minimum
+        let rules = compile(&[probed(None)]).unwrap();
+        assert_eq!(
+            resolve(&http, &url, &rules, None, &headers).await,
+            [
+                format!("{}/small.jpg", root),
+                format!("{}/big.jpg", root),
+                url.clone(),
+            ]
+        );
+
+        // Smaller candidates are skipped
+        let rules = compile(&[probed(Some(1000))]).unwrap();
+        assert_eq!(
+            resolve(&http, &url, &rules, None, &headers).await,
+            [format!("{}/big.jpg", root), url.clone()]
+        );
+
+        // With no candidate left the original url is the only one
+        let rules = compile(&[probed(Some(10_000))]).unwrap();
+        assert_eq!(resolve(&http, &url, &rules, None, &headers).await, [url]);
+    }
+}
@@ -0,0 +1,82 @@
+use crate::{
+    config::Config, links::links_service::LinksService, storage::dto::FileDisposal,
+    trash::trash_service::TrashService, utils::next_job_id,
+};
+use log::{error, info};
+use std::{sync::Arc, time::Duration};
+use tokio::{spawn, time};
+use tracing::{info_span, Instrument};
+
+/// How often items trashed longer than `TRASH_RETENTION` are looked for
+const TRASH_PURGE_EVERY: Duration = Duration::from_secs(60 * 60);
+
+/// Starts the background task that periodically re-checks links due for a check
+pub fn start(links_service: Arc<LinksService>, config: &Config) {
+    let tick = config
+        .scheduler_tick
+        .to_std()
+        .expect("SCHEDULER_TICK must be positive");
+
+    info!(
+        "Scheduler started, default check interval {} minutes",
+        config.check_interval.num_minutes()
+    );
+
+    spawn(async move {
+        let mut interval = time::interval(tick);
+        interval.set_missed_tick_behavior(time::MissedTickBehavior::Skip);
+
+        loop {
+            interval.tick().await;
+
+            let span = info_span!("scheduler", job_id = next_job_id());
+            match links_service.check_due_links().instrument(span).await {
+                Ok(0) => {}
+                Ok(checked) => info!("Scheduler checked {} links", checked),
+                Err(e) => error!("Scheduler failed to check links: {}", e),
+            }
+        }
+    });
+}
+
+/// Starts the background task that collects orphaned records and mediafiles every `every`,
+/// their files go to the trash
+pub fn start_gc(links_service: Arc<LinksService>, every: chrono::Duration) {
+    let every = every.to_std().expect("GC_INTERVAL must be positive");
+    info!("Garbage collection every {} minutes", every.as_secs() / 60);
+
+    spawn(async move {
+        let mut interval = time::interval(every);
+        interval.set_missed_tick_behavior(time::MissedTickBehavior::Skip);
+
+        loop {
+            interval.tick().await;
+
+            let span = info_span!("gc", job_id = next_job_id());
+            if let Err(e) = links_service
+                .run_garbage_collection(false, FileDisposal::Trash)
+                .instrument(span)
+                .await
+            {
+                error!("Garbage collection failed: {}", e);
+            }
+        }
+    });
+}
+
+/// Starts the background task that removes items trashed longer than the retention period
+pub fn start_trash_purge(trash_service: Arc<TrashService>) {
+    spawn(async move {
+        let mut interval = time::interval(TRASH_PURGE_EVERY);
+        interval.set_missed_tick_behavior(time::MissedTickBehavior::Skip);
+
+        loop {
+            interval.tick().await;
+
+            let span = info_span!("trash_purge", job_id = next_job_id());
+            if let Err(e) = trash_service.purge_expired().instrument(span).await {
+                error!("Trash purge failed: {}", e);
+            }
+        }
+    });
+}
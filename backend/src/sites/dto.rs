@@ -0,0 +1,73 @@
+use crate::{pagination::Pagination, rewrite::RewriteRule};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Per-host settings used while processing links of that host
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Site {
+    pub id: usize,
+    pub host: String,
+    /// Base for relative media urls, the link page url is used when empty
+    #[serde(rename = "rootUrl")]
+    pub root_url: Option<String>,
+    pub extractor: Extractor,
+    /// JSONPath-like selectors of media urls in embedded page JSON, like `$..images[*].src`
+    #[serde(rename = "mediaSelectors")]
+    pub media_selectors: Vec<String>,
+    #[serde(rename = "rewriteRules")]
+    pub rewrite_rules: Vec<RewriteRule>,
+    /// Overrides the global `EXTENSIONS` when set
+    pub extensions: Option<Vec<String>>,
+    /// Maximum number of parallel media downloads, unlimited when empty
+    pub concurrency: Option<usize>,
+    pub headers: HashMap<String, String>,
+    pub pagination: Pagination,
+    #[serde(rename = "dateCreate")]
+    pub date_create: Option<String>,
+    #[serde(rename = "dateUpdate")]
+    pub date_update: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Extractor {
+    /// `src` of `img` and `video` tags, embedded page JSON when there are none
+    #[default]
+    Html,
+    /// Embedded page JSON only: `__NEXT_DATA__`, `application/ld+json` and `window.__X__` scripts
+    Json,
+}
+
+impl Extractor {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Extractor::Html => "html",
+            Extractor::Json => "json",
+        }
+    }
+
+    pub fn parse(extractor: &str) -> Self {
+        serde_json::from_value(serde_json::Value::String(extractor.to_string())).unwrap_or_default()
+    }
+}
+
+#[derive(Deserialize)]
+pub struct SiteDto {
+    pub host: String,
+    #[serde(rename = "rootUrl")]
+    pub root_url: Option<String>,
+    pub extractor: Option<Extractor>,
+    #[serde(rename = "mediaSelectors")]
+    pub media_selectors: Option<Vec<String>>,
+    #[serde(rename = "rewriteRules")]
+    pub rewrite_rules: Option<Vec<RewriteRule>>,
+    pub extensions: Option<Vec<String>>,
+    pub concurrency: Option<usize>,
+    pub headers: Option<HashMap<String, String>>,
+    pub pagination: Option<Pagination>,
+}
+
+#[derive(Deserialize)]
+pub struct RewritePreviewQuery {
+    pub url: String,
+}
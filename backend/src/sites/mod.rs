@@ -0,0 +1,4 @@
+pub mod dto;
+pub mod sites_controller;
+pub mod sites_db_service;
+pub mod sites_service;
@@ -0,0 +1,85 @@
+use axum::{
+    extract::{Query, State},
+    middleware,
+    response::IntoResponse,
+    routing::{delete, get, post, put},
+    Extension, Json, Router,
+};
+
+use std::sync::Arc;
+
+use super::{
+    dto::{RewritePreviewQuery, SiteDto},
+    sites_service::SitesService,
+};
+use crate::{
+    auth::{
+        auth_service::{require_read, require_write, AuthService},
+        dto::Access,
+    },
+    config::Config,
+    links::dto::IdDto,
+};
+
+pub struct SitesController {}
+
+impl SitesController {
+    pub async fn create(
+        State(service): State<Arc<SitesService>>,
+        Extension(access): Extension<Access>,
+        Json(dto): Json<SiteDto>,
+    ) -> impl IntoResponse {
+        service.create_one(access, dto).await
+    }
+
+    pub async fn get_all(State(service): State<Arc<SitesService>>) -> impl IntoResponse {
+        service.get_all().await
+    }
+
+    pub async fn update(
+        State(service): State<Arc<SitesService>>,
+        Extension(access): Extension<Access>,
+        Query(query): Query<IdDto>,
+        Json(dto): Json<SiteDto>,
+    ) -> impl IntoResponse {
+        service.update_one(access, query.id, dto).await
+    }
+
+    pub async fn rewrite_preview(
+        State(service): State<Arc<SitesService>>,
+        Query(query): Query<RewritePreviewQuery>,
+    ) -> impl IntoResponse {
+        service.rewrite_preview(query.url).await
+    }
+
+    pub async fn remove(
+        State(service): State<Arc<SitesService>>,
+        Extension(access): Extension<Access>,
+        Query(query): Query<IdDto>,
+    ) -> impl IntoResponse {
+        service.remove(access, query.id).await
+    }
+}
+
+pub fn sites_routes(config: Arc<Config>, auth: Arc<AuthService>) -> Router {
+    let read = Router::new()
+        .route("/sites", get(SitesController::get_all))
+        .route(
+            "/sites/rewrite_preview",
+            get(SitesController::rewrite_preview),
+        )
+        .route_layer(middleware::from_fn_with_state(
+            Arc::clone(&auth),
+            require_read,
+        ));
+    let write = Router::new()
+        .route("/sites", post(SitesController::create))
+        .route("/sites", put(SitesController::update))
+        .route("/sites", delete(SitesController::remove))
+        .route_layer(middleware::from_fn_with_state(auth, require_write));
+
+    Router::new()
+        .merge(read)
+        .merge(write)
+        .with_state(Arc::new(SitesService::new(config)))
+}
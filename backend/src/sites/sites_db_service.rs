@@ -0,0 +1,164 @@
+use super::dto::{Extractor, Site, SiteDto};
+use crate::{config::Config, metrics, utils::get_now_time};
+use log::error;
+use rusqlite::{params, Connection, OptionalExtension, Result, Row};
+
+pub struct SitesDbService {
+    db_name: String,
+}
+
+impl SitesDbService {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            db_name: config.db_name.clone(),
+        }
+    }
+
+    fn open_connection(&self) -> Result<Connection> {
+        let mut conn = Connection::open(&self.db_name)?;
+        conn.profile(Some(metrics::observe_query));
+        Ok(conn)
+    }
+
+    pub fn create_one(&self, dto: &SiteDto) -> Result<&str> {
+        let conn = self.open_connection()?;
+        let now = get_now_time();
+
+        match conn.execute(
+            "INSERT INTO sites (host, root_url, extractor, media_selectors, rewrite_rules, extensions, concurrency, headers, pagination, date_create, date_update)
+                VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            params![
+                dto.host.to_lowercase(),
+                dto.root_url,
+                dto.extractor.unwrap_or_default().as_str(),
+                to_json(&dto.media_selectors),
+                to_json(&dto.rewrite_rules),
+                dto.extensions.as_ref().map(|extensions| extensions.join(",")),
+                dto.concurrency,
+                to_json(&dto.headers),
+                to_json(&dto.pagination),
+                now,
+                now
+            ],
+        ) {
+            Ok(changes) => {
+                if changes == 1 {
+                    Ok("One site created")
+                } else {
+                    Ok("No site created")
+                }
+            }
+            Err(e) => {
+                error!("Error creating site: {}", e);
+                Err(e)
+            }
+        }
+    }
+
+    pub fn update_one(&self, id: usize, dto: &SiteDto) -> Result<&str> {
+        let conn = self.open_connection()?;
+
+        let changes = conn.execute(
+            "UPDATE sites
+                SET host = ?, root_url = ?, extractor = ?, media_selectors = ?, rewrite_rules = ?, extensions = ?,
+                    concurrency = ?, headers = ?, pagination = ?, date_update = ?
+                WHERE id = ?",
+            params![
+                dto.host.to_lowercase(),
+                dto.root_url,
+                dto.extractor.unwrap_or_default().as_str(),
+                to_json(&dto.media_selectors),
+                to_json(&dto.rewrite_rules),
+                dto.extensions
+                    .as_ref()
+                    .map(|extensions| extensions.join(",")),
+                dto.concurrency,
+                to_json(&dto.headers),
+                to_json(&dto.pagination),
+                get_now_time(),
+                id
+            ],
+        )?;
+
+        Ok(if changes == 1 {
+            "One site updated"
+        } else {
+            "No site updated"
+        })
+    }
+
+    pub fn get_all(&self) -> Result<Vec<Site>> {
+        let conn = self.open_connection()?;
+        let mut stmt = conn.prepare("SELECT * FROM sites ORDER BY host")?;
+
+        let rows = stmt.query_map([], map_site)?;
+
+        let result: Result<Vec<_>, _> = rows.collect();
+        result
+    }
+
+    pub fn get_by_host(&self, host: &str) -> Result<Option<Site>> {
+        let conn = self.open_connection()?;
+
+        conn.query_row("SELECT * FROM sites WHERE host = ?", [host], map_site)
+            .optional()
+    }
+
+    pub fn remove(&self, id: usize) -> Result<&str> {
+        let conn = self.open_connection()?;
+
+        match conn.execute("DELETE FROM sites WHERE id = ?", [id]) {
+            Ok(changes) => {
+                if changes == 1 {
+                    Ok("One site removed")
+                } else {
+                    Ok("No site removed")
+                }
+            }
+            Err(e) => {
+                error!("Error removing site: {}", e);
+                Err(e)
+            }
+        }
+    }
+}
+
+fn map_site(row: &Row) -> Result<Site> {
+    let media_selectors: Option<String> = row.get("media_selectors")?;
+    let rewrite_rules: Option<String> = row.get("rewrite_rules")?;
+    let extensions: Option<String> = row.get("extensions")?;
+    let headers: Option<String> = row.get("headers")?;
+    let pagination: Option<String> = row.get("pagination")?;
+
+    Ok(Site {
+        id: row.get("id")?,
+        host: row.get("host")?,
+        root_url: row.get("root_url")?,
+        extractor: Extractor::parse(&row.get::<_, String>("extractor")?),
+        media_selectors: from_json(media_selectors),
+        rewrite_rules: from_json(rewrite_rules),
+        extensions: extensions.map(|extensions| {
+            extensions
+                .split(',')
+                .map(|ext| ext.trim().to_string())
+                .collect()
+        }),
+        concurrency: row.get("concurrency")?,
+        headers: from_json(headers),
+        pagination: from_json(pagination),
+        date_create: row.get("date_create")?,
+        date_update: row.get("date_update")?,
+    })
+}
+
+fn to_json<T: serde::Serialize>(value: &Option<T>) -> Option<String> {
+    value
+        .as_ref()
+        .and_then(|value| serde_json::to_string(value).ok())
+}
+
+fn from_json<T: serde::de::DeserializeOwned + Default>(value: Option<String>) -> T {
+    value
+        .and_then(|value| serde_json::from_str(&value).ok())
+        .unwrap_or_default()
+}
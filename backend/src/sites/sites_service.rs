@@ -0,0 +1,152 @@
+use super::{
+    dto::{Site, SiteDto},
+    sites_db_service::SitesDbService,
+};
+use crate::{
+    auth::dto::Access,
+    config::Config,
+    extract,
+    http_client::get_host,
+    links::dto::IResult,
+    rewrite,
+    utils::{error_response, server_error_response, success_response},
+};
+use axum::{http::StatusCode, response::IntoResponse, Json};
+use log::{error, info};
+use std::sync::Arc;
+
+pub struct SitesService {
+    config: Arc<Config>,
+    sites_db_service: Arc<SitesDbService>,
+}
+
+impl SitesService {
+    pub fn new(config: Arc<Config>) -> Self {
+        Self {
+            sites_db_service: Arc::new(SitesDbService::new(&config)),
+            config,
+        }
+    }
+
+    /// Profiles apply to the downloads of every user, so only admins change them
+    pub async fn create_one(&self, access: Access, dto: SiteDto) -> impl IntoResponse {
+        if !access.is_admin {
+            return Err(admin_required());
+        }
+        validate(&dto).map_err(|e| error_response(e, StatusCode::BAD_REQUEST))?;
+        info!("Creating site profile for {}", &dto.host);
+
+        match self.sites_db_service.create_one(&dto) {
+            Ok(m) => Ok(success_response(m.to_string())),
+            Err(e) => Err(server_error_response(e.to_string())),
+        }
+    }
+
+    pub async fn update_one(&self, access: Access, id: usize, dto: SiteDto) -> impl IntoResponse {
+        if !access.is_admin {
+            return Err(admin_required());
+        }
+        validate(&dto).map_err(|e| error_response(e, StatusCode::BAD_REQUEST))?;
+        info!("Updating site profile with id: {}", &id);
+
+        match self.sites_db_service.update_one(id, &dto) {
+            Ok(m) => Ok(success_response(m.to_string())),
+            Err(e) => Err(server_error_response(e.to_string())),
+        }
+    }
+
+    pub async fn get_all(&self) -> impl IntoResponse {
+        match self.sites_db_service.get_all() {
+            Ok(sites) => Ok((StatusCode::OK, Json(sites))),
+            Err(e) => {
+                error!("Error getting sites: {}", e);
+                Err(server_error_response("Error getting sites".to_string()))
+            }
+        }
+    }
+
+    pub async fn remove(&self, access: Access, id: usize) -> impl IntoResponse {
+        if !access.is_admin {
+            return Err(admin_required());
+        }
+        info!("Removing site profile with id: {}", &id);
+
+        match self.sites_db_service.remove(id) {
+            Ok(m) => Ok(success_response(m.to_string())),
+            Err(e) => Err(server_error_response(e.to_string())),
+        }
+    }
+
+    /// Shows the urls a media url is rewritten to without probing them
+    pub async fn rewrite_preview(&self, url: String) -> impl IntoResponse {
+        let site = self.find_for_url(&url);
+        let rules = rewrite::with_global(&site.rewrite_rules, &self.config.rewrite_rules);
+
+        (StatusCode::OK, Json(rewrite::candidates(&url, &rules)))
+    }
+
+    /// Profile of the url host or of its closest parent domain,
+    /// a default profile when none is stored
+    pub fn find_for_url(&self, url: &str) -> Site {
+        let host = match get_host(url) {
+            Some(host) => host,
+            None => return Site::default(),
+        };
+
+        let mut domain = host.as_str();
+        loop {
+            match self.sites_db_service.get_by_host(domain) {
+                Ok(Some(site)) => return site,
+                Ok(None) => {}
+                Err(e) => {
+                    error!("Error getting site profile for {}: {}", domain, e);
+                    return Site::default();
+                }
+            }
+
+            domain = match domain.split_once('.') {
+                Some((_, parent)) if parent.contains('.') => parent,
+                _ => {
+                    return Site {
+                        host,
+                        ..Default::default()
+                    }
+                }
+            };
+        }
+    }
+}
+
+fn validate(dto: &SiteDto) -> Result<(), String> {
+    if dto.host.trim().is_empty() {
+        return Err("Host is required".to_string());
+    }
+
+    if dto.concurrency == Some(0) {
+        return Err("Concurrency must be greater than 0".to_string());
+    }
+
+    for rule in dto.rewrite_rules.iter().flatten() {
+        rule.compile()?;
+        if rule.replacements.is_empty() {
+            return Err(format!("Rewrite rule {} has no replacements", rule.pattern));
+        }
+    }
+
+    for selector in dto.media_selectors.iter().flatten() {
+        extract::parse_selector(selector)?;
+    }
+
+    if let Some(pagination) = &dto.pagination {
+        pagination.validate()?;
+    }
+
+    Ok(())
+}
+
+fn admin_required() -> (StatusCode, Json<IResult>) {
+    error_response(
+        "Only admins can manage site profiles".to_string(),
+        StatusCode::FORBIDDEN,
+    )
+}
@@ -0,0 +1,95 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Stats {
+    pub links: LinksStats,
+    pub mediafiles: MediafilesStats,
+    #[serde(rename = "bytesPerHost")]
+    pub bytes_per_host: Vec<HostStats>,
+    /// Links with the most stored bytes
+    #[serde(rename = "largestLinks")]
+    pub largest_links: Vec<LinkStats>,
+    #[serde(rename = "duplicateSavings")]
+    pub duplicate_savings: DuplicateSavings,
+    /// Page checks and media downloads per day, most recent first
+    pub daily: Vec<DailyStats>,
+    #[serde(rename = "fileTypes")]
+    pub file_types: Vec<FileTypeStats>,
+    #[serde(rename = "dateCreate")]
+    pub date_create: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LinksStats {
+    pub total: usize,
+    pub downloaded: usize,
+    #[serde(rename = "notDownloaded")]
+    pub not_downloaded: usize,
+    pub reachable: usize,
+    pub unreachable: usize,
+    pub duplicates: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MediafilesStats {
+    pub total: usize,
+    pub bytes: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HostStats {
+    pub host: String,
+    pub links: usize,
+    pub mediafiles: usize,
+    pub bytes: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LinkStats {
+    pub id: usize,
+    pub name: String,
+    pub path: String,
+    pub mediafiles: usize,
+    pub bytes: u64,
+}
+
+/// Space taken by files stored more than once
+#[derive(Debug, Clone, Serialize)]
+pub struct DuplicateSavings {
+    /// Extra copies of files with the same hash
+    pub files: usize,
+    /// Bytes freed by keeping one copy of each hash
+    pub bytes: u64,
+    /// Bytes of links marked as duplicates of other links
+    #[serde(rename = "duplicateLinksBytes")]
+    pub duplicate_links_bytes: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DailyStats {
+    pub day: String,
+    pub checks: usize,
+    #[serde(rename = "succeededChecks")]
+    pub succeeded_checks: usize,
+    /// Share of successful page checks, from 0 to 1
+    #[serde(rename = "successRate")]
+    pub success_rate: Option<f64>,
+    pub downloads: usize,
+    #[serde(rename = "downloadedBytes")]
+    pub downloaded_bytes: u64,
+}
+
+/// Mediafiles by MIME type, or by extension when the type is unknown
+#[derive(Debug, Clone, Serialize)]
+pub struct FileTypeStats {
+    #[serde(rename = "fileType")]
+    pub file_type: String,
+    pub mediafiles: usize,
+    pub bytes: u64,
+}
+
+#[derive(Deserialize)]
+pub struct StatsQuery {
+    /// Skips the cache
+    pub refresh: Option<bool>,
+}
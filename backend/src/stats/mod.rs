@@ -0,0 +1,4 @@
+pub mod dto;
+pub mod stats_controller;
+pub mod stats_db_service;
+pub mod stats_service;
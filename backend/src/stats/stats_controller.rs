@@ -0,0 +1,40 @@
+use axum::{
+    extract::{Query, State},
+    middleware,
+    response::IntoResponse,
+    routing::get,
+    Extension, Router,
+};
+
+use std::sync::Arc;
+
+use crate::{
+    auth::{
+        auth_service::{require_read, AuthService},
+        dto::Access,
+    },
+    config::Config,
+};
+
+use super::{dto::StatsQuery, stats_service::StatsService};
+
+pub struct StatsController {}
+
+impl StatsController {
+    pub async fn get_stats(
+        State(service): State<Arc<StatsService>>,
+        Extension(access): Extension<Access>,
+        Query(query): Query<StatsQuery>,
+    ) -> impl IntoResponse {
+        service
+            .get_stats(access, query.refresh.unwrap_or(false))
+            .await
+    }
+}
+
+pub fn stats_routes(config: Arc<Config>, auth: Arc<AuthService>) -> Router {
+    Router::new()
+        .route("/stats", get(StatsController::get_stats))
+        .route_layer(middleware::from_fn_with_state(auth, require_read))
+        .with_state(Arc::new(StatsService::new(&config)))
+}
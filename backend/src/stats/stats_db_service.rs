@@ -0,0 +1,253 @@
+use super::dto::{
+    DailyStats, DuplicateSavings, FileTypeStats, HostStats, LinkStats, LinksStats, MediafilesStats,
+    Stats,
+};
+use crate::{config::Config, metrics, utils::get_now_time};
+use rusqlite::{Connection, Result};
+
+/// Number of hosts, links and days listed in the stats
+const TOP: usize = 20;
+const DAYS: usize = 30;
+
+/// Host of `links.path` without the scheme and path
+const HOST: &str = "CASE WHEN instr(substr(l.path, instr(l.path, '://') + 3), '/') > 0
+        THEN substr(substr(l.path, instr(l.path, '://') + 3), 1,
+            instr(substr(l.path, instr(l.path, '://') + 3), '/') - 1)
+        ELSE substr(l.path, instr(l.path, '://') + 3)
+    END";
+
+/// Mediafiles held by a link of the user bound to `?1`
+const OWNED_MEDIAFILES: &str = "SELECT ml.mediafile_id FROM mediafiles_links ml
+        JOIN links ol ON ol.id = ml.link_id
+        WHERE ol.user_id = ?1";
+
+pub struct StatsDbService {
+    db_name: String,
+}
+
+impl StatsDbService {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            db_name: config.db_name.clone(),
+        }
+    }
+
+    fn open_connection(&self) -> Result<Connection> {
+        let mut conn = Connection::open(&self.db_name)?;
+        conn.profile(Some(metrics::observe_query));
+        Ok(conn)
+    }
+
+    /// Stats of the links and mediafiles of one owner when `user_id` is set, of all otherwise
+    pub fn get_stats(&self, user_id: Option<usize>) -> Result<Stats> {
+        let conn = self.open_connection()?;
+
+        Ok(Stats {
+            links: links_stats(&conn, user_id)?,
+            mediafiles: mediafiles_stats(&conn, user_id)?,
+            bytes_per_host: bytes_per_host(&conn, user_id)?,
+            largest_links: largest_links(&conn, user_id)?,
+            duplicate_savings: duplicate_savings(&conn, user_id)?,
+            daily: daily(&conn, user_id)?,
+            file_types: file_types(&conn, user_id)?,
+            date_create: get_now_time(),
+        })
+    }
+}
+
+fn links_stats(conn: &Connection, user_id: Option<usize>) -> Result<LinksStats> {
+    conn.query_row(
+        "SELECT COUNT(*),
+            COALESCE(SUM(is_downloaded = 1), 0),
+            COALESCE(SUM(is_reachable = 1), 0),
+            COALESCE(SUM(duplicate_id IS NOT NULL), 0)
+        FROM links
+        WHERE deleted_at IS NULL AND (?1 IS NULL OR user_id = ?1)",
+        [user_id],
+        |row| {
+            let total: usize = row.get(0)?;
+            let downloaded: usize = row.get(1)?;
+            let reachable: usize = row.get(2)?;
+            Ok(LinksStats {
+                total,
+                downloaded,
+                not_downloaded: total - downloaded,
+                reachable,
+                unreachable: total - reachable,
+                duplicates: row.get(3)?,
+            })
+        },
+    )
+}
+
+fn mediafiles_stats(conn: &Connection, user_id: Option<usize>) -> Result<MediafilesStats> {
+    conn.query_row(
+        &format!(
+            "SELECT COUNT(*), COALESCE(SUM(size), 0) FROM mediafiles
+            WHERE deleted_at IS NULL AND (?1 IS NULL OR id IN ({}))",
+            OWNED_MEDIAFILES
+        ),
+        [user_id],
+        |row| {
+            Ok(MediafilesStats {
+                total: row.get(0)?,
+                bytes: row.get(1)?,
+            })
+        },
+    )
+}
+
+fn bytes_per_host(conn: &Connection, user_id: Option<usize>) -> Result<Vec<HostStats>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} AS host, COUNT(DISTINCT l.id), COUNT(m.id), COALESCE(SUM(m.size), 0) AS bytes
+        FROM links l
+        LEFT JOIN mediafiles_links ml ON ml.link_id = l.id
+        LEFT JOIN mediafiles m ON m.id = ml.mediafile_id AND m.deleted_at IS NULL
+        WHERE l.deleted_at IS NULL AND (?1 IS NULL OR l.user_id = ?1)
+        GROUP BY host
+        ORDER BY bytes DESC
+        LIMIT {}",
+        HOST, TOP
+    ))?;
+
+    let rows = stmt.query_map([user_id], |row| {
+        Ok(HostStats {
+            host: row.get(0)?,
+            links: row.get(1)?,
+            mediafiles: row.get(2)?,
+            bytes: row.get(3)?,
+        })
+    })?;
+
+    rows.collect()
+}
+
+fn largest_links(conn: &Connection, user_id: Option<usize>) -> Result<Vec<LinkStats>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT l.id, COALESCE(l.og_title, l.title, l.name, ''), l.path,
+            COUNT(m.id), COALESCE(SUM(m.size), 0) AS bytes
+        FROM links l
+        JOIN mediafiles_links ml ON ml.link_id = l.id
+        JOIN mediafiles m ON m.id = ml.mediafile_id
+        WHERE l.deleted_at IS NULL AND m.deleted_at IS NULL AND (?1 IS NULL OR l.user_id = ?1)
+        GROUP BY l.id
+        ORDER BY bytes DESC
+        LIMIT {}",
+        TOP
+    ))?;
+
+    let rows = stmt.query_map([user_id], |row| {
+        Ok(LinkStats {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            path: row.get(2)?,
+            mediafiles: row.get(3)?,
+            bytes: row.get(4)?,
+        })
+    })?;
+
+    rows.collect()
+}
+
+fn duplicate_savings(conn: &Connection, user_id: Option<usize>) -> Result<DuplicateSavings> {
+    let (files, bytes) = conn.query_row(
+        &format!(
+            "SELECT COALESCE(SUM(copies - 1), 0), COALESCE(SUM(size * (copies - 1)), 0)
+            FROM (
+                SELECT MAX(size) AS size, COUNT(*) AS copies FROM mediafiles
+                WHERE deleted_at IS NULL AND (?1 IS NULL OR id IN ({}))
+                GROUP BY hash
+            )",
+            OWNED_MEDIAFILES
+        ),
+        [user_id],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )?;
+
+    let duplicate_links_bytes = conn.query_row(
+        "SELECT COALESCE(SUM(m.size), 0)
+        FROM links l
+        JOIN mediafiles_links ml ON ml.link_id = l.id
+        JOIN mediafiles m ON m.id = ml.mediafile_id
+        WHERE l.duplicate_id IS NOT NULL AND l.deleted_at IS NULL AND m.deleted_at IS NULL
+            AND (?1 IS NULL OR l.user_id = ?1)",
+        [user_id],
+        |row| row.get(0),
+    )?;
+
+    Ok(DuplicateSavings {
+        files,
+        bytes,
+        duplicate_links_bytes,
+    })
+}
+
+fn daily(conn: &Connection, user_id: Option<usize>) -> Result<Vec<DailyStats>> {
+    let mut stmt = conn.prepare(&format!(
+        "WITH checks AS (
+                SELECT date(date_create) AS day, COUNT(*) AS checks,
+                    SUM(error_class IS NULL) AS succeeded
+                FROM link_checks
+                WHERE ?1 IS NULL OR link_id IN (SELECT id FROM links WHERE user_id = ?1)
+                GROUP BY day
+            ),
+            downloads AS (
+                SELECT date(downloaded_at) AS day, COUNT(*) AS downloads, SUM(size) AS bytes
+                FROM mediafiles
+                WHERE downloaded_at IS NOT NULL AND (?1 IS NULL OR id IN ({}))
+                GROUP BY day
+            ),
+            days AS (SELECT day FROM checks UNION SELECT day FROM downloads)
+        SELECT days.day, COALESCE(checks.checks, 0), COALESCE(checks.succeeded, 0),
+            COALESCE(downloads.downloads, 0), COALESCE(downloads.bytes, 0)
+        FROM days
+        LEFT JOIN checks ON checks.day = days.day
+        LEFT JOIN downloads ON downloads.day = days.day
+        WHERE days.day IS NOT NULL
+        ORDER BY days.day DESC
+        LIMIT {}",
+        OWNED_MEDIAFILES, DAYS
+    ))?;
+
+    let rows = stmt.query_map([user_id], |row| {
+        let checks: usize = row.get(1)?;
+        let succeeded_checks: usize = row.get(2)?;
+        Ok(DailyStats {
+            day: row.get(0)?,
+            checks,
+            succeeded_checks,
+            success_rate: (checks > 0).then(|| succeeded_checks as f64 / checks as f64),
+            downloads: row.get(3)?,
+            downloaded_bytes: row.get(4)?,
+        })
+    })?;
+
+    rows.collect()
+}
+
+fn file_types(conn: &Connection, user_id: Option<usize>) -> Result<Vec<FileTypeStats>> {
+    // The extension is what is left of the name after trimming everything up to the last dot
+    let mut stmt = conn.prepare(&format!(
+        "SELECT COALESCE(
+                NULLIF(lower(trim(substr(content_type, 1, instr(content_type || ';', ';') - 1))), ''),
+                NULLIF('.' || lower(replace(name, rtrim(name, replace(name, '.', '')), '')), '.'),
+                'unknown'
+            ) AS file_type,
+            COUNT(*), COALESCE(SUM(size), 0) AS bytes
+        FROM mediafiles
+        WHERE deleted_at IS NULL AND (?1 IS NULL OR id IN ({}))
+        GROUP BY file_type
+        ORDER BY bytes DESC",
+        OWNED_MEDIAFILES
+    ))?;
+
+    let rows = stmt.query_map([user_id], |row| {
+        Ok(FileTypeStats {
+            file_type: row.get(0)?,
+            mediafiles: row.get(1)?,
+            bytes: row.get(2)?,
+        })
+    })?;
+
+    rows.collect()
+}
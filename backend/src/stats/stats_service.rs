@@ -0,0 +1,51 @@
+use super::{dto::Stats, stats_db_service::StatsDbService};
+use crate::{auth::dto::Access, config::Config, utils::server_error_response};
+use axum::{http::StatusCode, response::IntoResponse, Json};
+use log::{error, info};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+pub struct StatsService {
+    stats_db_service: Arc<StatsDbService>,
+    ttl: Duration,
+    /// Last computed stats of each user, of all users under `None`, with the time they were
+    /// computed
+    cache: Mutex<HashMap<Option<usize>, (Instant, Stats)>>,
+}
+
+impl StatsService {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            stats_db_service: Arc::new(StatsDbService::new(config)),
+            ttl: config.stats_cache_ttl.to_std().unwrap_or_default(),
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Stats of the requesting user's links, admins get the stats of every user
+    pub async fn get_stats(&self, access: Access, refresh: bool) -> impl IntoResponse {
+        let user_id = (!access.is_admin).then_some(access.user_id);
+        let mut cache = self.cache.lock().unwrap();
+
+        if let Some((computed, stats)) = cache.get(&user_id) {
+            if !refresh && computed.elapsed() < self.ttl {
+                return Ok((StatusCode::OK, Json(stats.clone())));
+            }
+        }
+
+        info!("Computing stats");
+        match self.stats_db_service.get_stats(user_id) {
+            Ok(stats) => {
+                cache.insert(user_id, (Instant::now(), stats.clone()));
+                Ok((StatusCode::OK, Json(stats)))
+            }
+            Err(e) => {
+                error!("Error computing stats: {}", e);
+                Err(server_error_response("Error computing stats".to_string()))
+            }
+        }
+    }
+}
@@ -0,0 +1,109 @@
+use bytes::Bytes;
+use futures::Stream;
+use serde::Deserialize;
+use std::{fmt, io, pin::Pin, str::FromStr};
+
+/// Body of an object written to or read from a storage
+pub type ByteStream = Pin<Box<dyn Stream<Item = io::Result<Bytes>> + Send>>;
+
+/// Part of an object like the `Range` header asks for, ends are inclusive
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteRange {
+    /// `bytes=100-` from the offset to the end
+    From(u64),
+    /// `bytes=100-199`
+    Inclusive(u64, u64),
+    /// `bytes=-500` the last bytes
+    Suffix(u64),
+}
+
+impl ByteRange {
+    /// Parses a single range of a `Range` header, several ranges are not supported
+    pub fn parse(header: &str) -> Option<Self> {
+        let (start, end) = header.trim().strip_prefix("bytes=")?.split_once('-')?;
+        let (start, end) = (start.trim(), end.trim());
+
+        match (start.is_empty(), end.is_empty()) {
+            (true, false) => end.parse().ok().map(ByteRange::Suffix),
+            (false, true) => start.parse().ok().map(ByteRange::From),
+            (false, false) => {
+                let (start, end) = (start.parse().ok()?, end.parse().ok()?);
+                (start <= end).then_some(ByteRange::Inclusive(start, end))
+            }
+            (true, true) => None,
+        }
+    }
+
+    /// Offsets of the first and the last byte in an object of `size` bytes,
+    /// none when the range is outside of the object
+    pub fn resolve(&self, size: u64) -> Option<(u64, u64)> {
+        let (start, end) = match *self {
+            ByteRange::From(start) => (start, size.checked_sub(1)?),
+            ByteRange::Inclusive(start, end) => (start, end.min(size.checked_sub(1)?)),
+            ByteRange::Suffix(length) => {
+                (size.checked_sub(length.min(size))?, size.checked_sub(1)?)
+            }
+        };
+        (start <= end).then_some((start, end))
+    }
+
+    /// Value of the `Range` header
+    pub fn header(&self) -> String {
+        match self {
+            ByteRange::From(start) => format!("bytes={}-", start),
+            ByteRange::Inclusive(start, end) => format!("bytes={}-{}", start, end),
+            ByteRange::Suffix(length) => format!("bytes=-{}", length),
+        }
+    }
+}
+
+/// What happens to stored files whose records are removed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FileDisposal {
+    #[default]
+    Keep,
+    Delete,
+    /// Moved under `.trash/` with the same key
+    Trash,
+}
+
+impl FromStr for FileDisposal {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "keep" => Ok(FileDisposal::Keep),
+            "delete" => Ok(FileDisposal::Delete),
+            "trash" => Ok(FileDisposal::Trash),
+            _ => Err(format!("{} is not keep, delete or trash", value)),
+        }
+    }
+}
+
+/// Object read from a storage
+pub struct StoredObject {
+    pub body: ByteStream,
+    /// Size of the whole object
+    pub size: u64,
+    /// First and last byte of `body` when a range was read
+    pub range: Option<(u64, u64)>,
+}
+
+#[derive(Debug)]
+pub enum StorageError {
+    NotFound,
+    /// The range starts after the end of the object
+    InvalidRange,
+    Failed(String),
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StorageError::NotFound => write!(f, "object not found"),
+            StorageError::InvalidRange => write!(f, "range not satisfiable"),
+            StorageError::Failed(e) => write!(f, "{}", e),
+        }
+    }
+}
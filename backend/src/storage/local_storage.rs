@@ -0,0 +1,195 @@
+use super::{
+    dto::{ByteRange, ByteStream, StorageError, StoredObject},
+    storage_service::Storage,
+};
+use crate::utils::next_job_id;
+use async_trait::async_trait;
+use futures::StreamExt;
+use std::{
+    io::{ErrorKind, SeekFrom},
+    path::{Component, Path, PathBuf},
+};
+use tokio::{
+    fs::{self, File},
+    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+};
+use tokio_util::io::ReaderStream;
+
+/// Files being written are named with this prefix until they are complete
+const TEMP_PREFIX: &str = ".tmp-";
+
+/// Files in a directory of the local filesystem, a key is a path relative to the root
+pub struct LocalStorage {
+    root: PathBuf,
+}
+
+impl LocalStorage {
+    pub fn new(root: &Path) -> Self {
+        Self {
+            root: root.to_path_buf(),
+        }
+    }
+
+    /// Path of the key, keys leaving the root are refused
+    fn path(&self, key: &str) -> Result<PathBuf, String> {
+        let relative = Path::new(key);
+        if key.is_empty()
+            || relative
+                .components()
+                .any(|component| !matches!(component, Component::Normal(_)))
+        {
+            return Err(format!("{} is not a valid storage key", key));
+        }
+        Ok(self.root.join(relative))
+    }
+
+    /// Keys of the files under `dir`, relative to the root
+    async fn walk(&self, dir: PathBuf) -> Result<Vec<String>, String> {
+        let mut keys = Vec::new();
+        let mut dirs = vec![dir];
+
+        while let Some(dir) = dirs.pop() {
+            let mut entries = match fs::read_dir(&dir).await {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => return Err(format!("Failed to read {}: {}", dir.display(), e)),
+            };
+
+            while let Some(entry) = entries
+                .next_entry()
+                .await
+                .map_err(|e| format!("Failed to read {}: {}", dir.display(), e))?
+            {
+                let path = entry.path();
+                let file_type = entry.file_type().await.map_err(|e| e.to_string())?;
+                if file_type.is_dir() {
+                    dirs.push(path);
+                } else if file_type.is_file()
+                    && !entry.file_name().to_string_lossy().starts_with(TEMP_PREFIX)
+                {
+                    if let Ok(relative) = path.strip_prefix(&self.root) {
+                        let parts: Vec<_> = relative
+                            .components()
+                            .map(|component| component.as_os_str().to_string_lossy())
+                            .collect();
+                        keys.push(parts.join("/"));
+                    }
+                }
+            }
+        }
+
+        Ok(keys)
+    }
+}
+
+#[async_trait]
+impl Storage for LocalStorage {
+    async fn put(&self, key: &str, mut body: ByteStream) -> Result<u64, String> {
+        let path = self.path(key)?;
+        let dir = path.parent().unwrap_or(&self.root);
+        fs::create_dir_all(dir)
+            .await
+            .map_err(|e| format!("Failed to create directory: {}", e))?;
+
+        // Written next to the target and renamed when complete, so a failed write leaves no file
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+        let temp_path = dir.join(format!("{}{}-{}", TEMP_PREFIX, next_job_id(), file_name));
+
+        let write = async {
+            let mut file = File::create(&temp_path)
+                .await
+                .map_err(|e| format!("Failed to create file: {}", e))?;
+            let mut size = 0;
+            while let Some(chunk) = body.next().await {
+                let chunk = chunk.map_err(|e| e.to_string())?;
+                file.write_all(&chunk)
+                    .await
+                    .map_err(|e| format!("Failed to write to file: {}", e))?;
+                size += chunk.len() as u64;
+            }
+            file.flush()
+                .await
+                .map_err(|e| format!("Failed to flush file: {}", e))?;
+            fs::rename(&temp_path, &path)
+                .await
+                .map_err(|e| format!("Failed to move file: {}", e))?;
+            Ok(size)
+        };
+
+        let result = write.await;
+        if result.is_err() {
+            let _ = fs::remove_file(&temp_path).await;
+        }
+        result
+    }
+
+    async fn get(&self, key: &str, range: Option<ByteRange>) -> Result<StoredObject, StorageError> {
+        let path = self.path(key).map_err(StorageError::Failed)?;
+        let mut file = match File::open(&path).await {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Err(StorageError::NotFound),
+            Err(e) => return Err(StorageError::Failed(e.to_string())),
+        };
+        let size = file
+            .metadata()
+            .await
+            .map_err(|e| StorageError::Failed(e.to_string()))?
+            .len();
+
+        let range = match range {
+            Some(range) => Some(range.resolve(size).ok_or(StorageError::InvalidRange)?),
+            None => None,
+        };
+        let body: ByteStream = match range {
+            Some((start, end)) => {
+                file.seek(SeekFrom::Start(start))
+                    .await
+                    .map_err(|e| StorageError::Failed(e.to_string()))?;
+                Box::pin(ReaderStream::new(file.take(end - start + 1)))
+            }
+            None => Box::pin(ReaderStream::new(file)),
+        };
+
+        Ok(StoredObject { body, size, range })
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), String> {
+        match fs::remove_file(self.path(key)?).await {
+            Err(e) if e.kind() != ErrorKind::NotFound => {
+                Err(format!("Failed to remove {}: {}", key, e))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    async fn exists(&self, key: &str) -> Result<bool, String> {
+        Ok(fs::metadata(self.path(key)?)
+            .await
+            .is_ok_and(|metadata| metadata.is_file()))
+    }
+
+    async fn rename(&self, from: &str, to: &str) -> Result<(), String> {
+        let target = self.path(to)?;
+        fs::create_dir_all(target.parent().unwrap_or(&self.root))
+            .await
+            .map_err(|e| format!("Failed to create directory: {}", e))?;
+        fs::rename(self.path(from)?, &target)
+            .await
+            .map_err(|e| format!("Failed to move {} to {}: {}", from, to, e))
+    }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<String>, String> {
+        // Only the directory holding every key with the prefix is walked
+        let dir = match prefix.rsplit_once('/') {
+            Some((dir, _)) => self.path(dir)?,
+            None => self.root.clone(),
+        };
+
+        Ok(self
+            .walk(dir)
+            .await?
+            .into_iter()
+            .filter(|key| key.starts_with(prefix))
+            .collect())
+    }
+}
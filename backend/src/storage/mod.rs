@@ -0,0 +1,4 @@
+pub mod dto;
+pub mod local_storage;
+pub mod s3_storage;
+pub mod storage_service;
@@ -0,0 +1,629 @@
+use super::{
+    dto::{ByteRange, ByteStream, StorageError, StoredObject},
+    storage_service::Storage,
+};
+use crate::config::Config;
+use async_trait::async_trait;
+use aws_config::{BehaviorVersion, Region};
+use aws_sdk_s3::{
+    config::{Builder, Credentials},
+    error::{DisplayErrorContext, ProvideErrorMetadata},
+    primitives::ByteStream as S3ByteStream,
+    types::{CompletedMultipartUpload, CompletedPart},
+    Client,
+};
+use bytes::{Bytes, BytesMut};
+use futures::{stream, StreamExt};
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use std::io;
+
+/// Characters escaped in the `x-amz-copy-source` header, `/` separates the parts of a key
+const COPY_SOURCE: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'/')
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'.')
+    .remove(b'~');
+
+/// Bodies up to this size are stored by one request, larger ones are uploaded in parts of it
+const PART_SIZE: usize = 8 * 1024 * 1024;
+
+/// Objects of a bucket of S3 or of a compatible server like MinIO, a key is the object name
+/// after `S3_PREFIX`
+pub struct S3Storage {
+    client: Client,
+    bucket: String,
+    prefix: String,
+}
+
+impl S3Storage {
+    pub async fn new(config: &Config) -> Result<Self, String> {
+        let bucket = config
+            .s3_bucket
+            .clone()
+            .ok_or_else(|| "S3_BUCKET must be set for the s3 storage".to_string())?;
+
+        let shared = aws_config::defaults(BehaviorVersion::latest())
+            .region(Region::new(config.s3_region.clone()))
+            .load()
+            .await;
+        let mut builder = Builder::from(&shared).force_path_style(config.s3_path_style);
+        if let Some(endpoint) = &config.s3_endpoint {
+            builder = builder.endpoint_url(endpoint);
+        }
+        // Keys from the config win over the default AWS credential chain
+        if let (Some(access_key), Some(secret_key)) =
+            (&config.s3_access_key_id, &config.s3_secret_access_key)
+        {
+            builder = builder.credentials_provider(Credentials::new(
+                access_key, secret_key, None, None, "config",
+            ));
+        }
+
+        Ok(Self {
+            client: Client::from_conf(builder.build()),
+            bucket,
+            prefix: config
+                .s3_prefix
+                .as_deref()
+                .map(|prefix| format!("{}/", prefix.trim_matches('/')))
+                .filter(|prefix| prefix != "/")
+                .unwrap_or_default(),
+        })
+    }
+
+    fn object_name(&self, key: &str) -> String {
+        format!("{}{}", self.prefix, key)
+    }
+
+    /// Uploads a body larger than one part, the upload is aborted when any part fails
+    async fn put_parts(
+        &self,
+        name: &str,
+        first: Bytes,
+        body: &mut ByteStream,
+    ) -> Result<u64, String> {
+        let upload = self
+            .client
+            .create_multipart_upload()
+            .bucket(&self.bucket)
+            .key(name)
+            .send()
+            .await
+            .map_err(|e| failed("start upload of", name, e))?;
+        let upload_id = upload.upload_id().unwrap_or_default().to_string();
+
+        let upload_parts = async {
+            let mut parts = Vec::new();
+            let mut size = 0;
+            let mut pending = Some(first);
+
+            while let Some(part) = match pending.take() {
+                Some(part) => Some(part),
+                None => read_part(body).await?,
+            } {
+                size += part.len() as u64;
+                let number = parts.len() as i32 + 1;
+                let uploaded = self
+                    .client
+                    .upload_part()
+                    .bucket(&self.bucket)
+                    .key(name)
+                    .upload_id(&upload_id)
+                    .part_number(number)
+                    .body(S3ByteStream::from(part))
+                    .send()
+                    .await
+                    .map_err(|e| failed("upload part of", name, e))?;
+                parts.push(
+                    CompletedPart::builder()
+                        .part_number(number)
+                        .set_e_tag(uploaded.e_tag().map(str::to_string))
+                        .build(),
+                );
+            }
+
+            self.client
+                .complete_multipart_upload()
+                .bucket(&self.bucket)
+                .key(name)
+                .upload_id(&upload_id)
+                .multipart_upload(
+                    CompletedMultipartUpload::builder()
+                        .set_parts(Some(parts))
+                        .build(),
+                )
+                .send()
+                .await
+                .map_err(|e| failed("complete upload of", name, e))?;
+            Ok(size)
+        };
+
+        let result = upload_parts.await;
+        if result.is_err() {
+            let _ = self
+                .client
+                .abort_multipart_upload()
+                .bucket(&self.bucket)
+                .key(name)
+                .upload_id(&upload_id)
+                .send()
+                .await;
+        }
+        result
+    }
+}
+
+#[async_trait]
+impl Storage for S3Storage {
+    async fn put(&self, key: &str, body: ByteStream) -> Result<u64, String> {
+        let name = self.object_name(key);
+        // Parts are read until the body ends, a fused body can be read again after its end
+        let mut body: ByteStream = Box::pin(body.fuse());
+        let first = read_part(&mut body).await?.unwrap_or_default();
+
+        if first.len() == PART_SIZE {
+            return self.put_parts(&name, first, &mut body).await;
+        }
+
+        let size = first.len() as u64;
+        self.client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(&name)
+            .body(S3ByteStream::from(first))
+            .send()
+            .await
+            .map_err(|e| failed("store", &name, e))?;
+        Ok(size)
+    }
+
+    async fn get(&self, key: &str, range: Option<ByteRange>) -> Result<StoredObject, StorageError> {
+        let name = self.object_name(key);
+        let object = self
+            .client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(&name)
+            .set_range(range.map(|range| range.header()))
+            .send()
+            .await
+            .map_err(|e| match e.code() {
+                Some("NoSuchKey") | Some("NotFound") => StorageError::NotFound,
+                Some("InvalidRange") => StorageError::InvalidRange,
+                _ => StorageError::Failed(failed("read", &name, e)),
+            })?;
+
+        // `Content-Range` looks like `bytes 0-99/1000`
+        let content_range = object.content_range().and_then(|content_range| {
+            let (range, size) = content_range.strip_prefix("bytes ")?.split_once('/')?;
+            let (start, end) = range.split_once('-')?;
+            Some(((start.parse().ok()?, end.parse().ok()?), size.parse().ok()?))
+        });
+        let (range, size) = match content_range {
+            Some((range, size)) => (Some(range), size),
+            None => (None, object.content_length().unwrap_or_default() as u64),
+        };
+
+        let body = stream::unfold(object.body, |mut body| async move {
+            body.next()
+                .await
+                .map(|chunk| (chunk.map_err(io::Error::other), body))
+        });
+
+        Ok(StoredObject {
+            body: Box::pin(body),
+            size,
+            range,
+        })
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), String> {
+        let name = self.object_name(key);
+        self.client
+            .delete_object()
+            .bucket(&self.bucket)
+            .key(&name)
+            .send()
+            .await
+            .map_err(|e| failed("remove", &name, e))?;
+        Ok(())
+    }
+
+    async fn exists(&self, key: &str) -> Result<bool, String> {
+        let name = self.object_name(key);
+        match self
+            .client
+            .head_object()
+            .bucket(&self.bucket)
+            .key(&name)
+            .send()
+            .await
+        {
+            Ok(_) => Ok(true),
+            Err(e) if e.as_service_error().is_some_and(|e| e.is_not_found()) => Ok(false),
+            Err(e) => Err(failed("check", &name, e)),
+        }
+    }
+
+    async fn rename(&self, from: &str, to: &str) -> Result<(), String> {
+        // S3 has no rename, the object is copied and the original removed
+        let source = self.object_name(from);
+        let name = self.object_name(to);
+        self.client
+            .copy_object()
+            .bucket(&self.bucket)
+            .key(&name)
+            .copy_source(format!(
+                "{}/{}",
+                self.bucket,
+                utf8_percent_encode(&source, COPY_SOURCE)
+            ))
+            .send()
+            .await
+            .map_err(|e| failed("copy", &source, e))?;
+        self.delete(from).await
+    }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<String>, String> {
+        let mut pages = self
+            .client
+            .list_objects_v2()
+            .bucket(&self.bucket)
+            .prefix(self.object_name(prefix))
+            .into_paginator()
+            .send();
+
+        let mut keys = Vec::new();
+        while let Some(page) = pages.next().await {
+            let page = page.map_err(|e| failed("list", prefix, e))?;
+            keys.extend(
+                page.contents()
+                    .iter()
+                    .filter_map(|object| object.key())
+                    .filter_map(|name| name.strip_prefix(&self.prefix))
+                    .map(str::to_string),
+            );
+        }
+
+        Ok(keys)
+    }
+}
+
+/// Reads up to one part of the body, none when the body has ended
+async fn read_part(body: &mut ByteStream) -> Result<Option<Bytes>, String> {
+    let mut part = BytesMut::new();
+
+    while part.len() < PART_SIZE {
+        match body.next().await {
+            Some(chunk) => {
+                let mut chunk = chunk.map_err(|e| e.to_string())?;
+                let rest = chunk.split_off(chunk.len().min(PART_SIZE - part.len()));
+                part.extend_from_slice(&chunk);
+                if !rest.is_empty() {
+                    // The rest starts the next part
+                    let rest: ByteStream = Box::pin(stream::once(async { Ok(rest) }));
+                    let tail = std::mem::replace(body, Box::pin(stream::empty()));
+                    *body = Box::pin(rest.chain(tail));
+                }
+            }
+            None if part.is_empty() => return Ok(None),
+            None => break,
+        }
+    }
+
+    Ok(Some(part.freeze()))
+}
+
+fn failed<E: std::error::Error>(action: &str, name: &str, e: E) -> String {
+    format!("Failed to {} {}: {}", action, name, DisplayErrorContext(e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::tests::config;
+    use axum::{
+        extract::{DefaultBodyLimit, Query, State},
+        http::{header, HeaderMap, Method, StatusCode, Uri},
+        response::{IntoResponse, Response},
+        Router, Server,
+    };
+    use percent_encoding::percent_decode_str;
+    use std::{
+        collections::{BTreeMap, HashMap},
+        net::TcpListener,
+        sync::{Arc, Mutex},
+    };
+
+    const BUCKET: &str = "media";
+
+    /// Objects and uploads of the bucket of an in-process S3 server
+    #[derive(Default)]
+    struct Bucket {
+        objects: BTreeMap<String, Vec<u8>>,
+        /// Name and parts by number of each unfinished multipart upload
+        uploads: HashMap<String, (String, BTreeMap<i32, Vec<u8>>)>,
+        /// Sizes of the parts of every completed upload, in order
+        part_sizes: Vec<usize>,
+    }
+
+    type Shared = Arc<Mutex<Bucket>>;
+
+    /// Storage on an in-process server answering the S3 requests `S3Storage` makes,
+    /// with path style urls and under a prefix, so that the prefix is left out of the keys
+    async fn test_storage() -> (S3Storage, Shared) {
+        let bucket = Shared::default();
+        let app = Router::new()
+            .fallback(s3_request)
+            .layer(DefaultBodyLimit::disable())
+            .with_state(Arc::clone(&bucket));
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(
+            Server::from_tcp(listener)
+                .unwrap()
+                .serve(app.into_make_service()),
+        );
+
+        let config = config(&[
+            "storage_backend=s3",
+            "s3_path_style=true",
+            &format!("s3_endpoint=http://{}", address),
+            &format!("s3_bucket={}", BUCKET),
+            "s3_access_key_id=test",
+            "s3_secret_access_key=test",
+            "s3_prefix=library",
+        ]);
+        (S3Storage::new(&config).await.unwrap(), bucket)
+    }
+
+    async fn s3_request(
+        State(bucket): State<Shared>,
+        method: Method,
+        uri: Uri,
+        Query(query): Query<HashMap<String, String>>,
+        headers: HeaderMap,
+        body: Bytes,
+    ) -> Response {
+        let path = percent_decode_str(uri.path()).decode_utf8_lossy();
+        let name = match path.trim_start_matches('/').split_once('/') {
+            Some((BUCKET, name)) => name.to_string(),
+            _ if path.trim_matches('/') == BUCKET => String::new(),
+            _ => return s3_error(StatusCode::NOT_FOUND, "NoSuchBucket"),
+        };
+        let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+        let body = body.to_vec();
+        let mut bucket = bucket.lock().unwrap();
+
+        match (method, query.get("uploadId")) {
+            (Method::GET, _) if name.is_empty() => {
+                let prefix = query.get("prefix").cloned().unwrap_or_default();
+                let contents: String = bucket
+                    .objects
+                    .iter()
+                    .filter(|(name, _)| name.starts_with(&prefix))
+                    .map(|(name, object)| {
+                        format!(
+                            "<Contents><Key>{}</Key><Size>{}</Size></Contents>",
+                            name,
+                            object.len()
+                        )
+                    })
+                    .collect();
+                xml(format!(
+                    "<ListBucketResult><Name>{}</Name><Prefix>{}</Prefix>\
+                    <IsTruncated>false</IsTruncated>{}</ListBucketResult>",
+                    BUCKET, prefix, contents
+                ))
+            }
+            (Method::GET, _) => match bucket.objects.get(&name) {
+                Some(object) => match header("range") {
+                    Some(range) => match byte_range(range, object.len()) {
+                        Some((start, end)) => (
+                            StatusCode::PARTIAL_CONTENT,
+                            [(
+                                header::CONTENT_RANGE,
+                                format!("bytes {}-{}/{}", start, end, object.len()),
+                            )],
+                            object[start..=end].to_vec(),
+                        )
+                            .into_response(),
+                        None => s3_error(StatusCode::RANGE_NOT_SATISFIABLE, "InvalidRange"),
+                    },
+                    None => object.clone().into_response(),
+                },
+                None => s3_error(StatusCode::NOT_FOUND, "NoSuchKey"),
+            },
+            (Method::HEAD, _) => match bucket.objects.get(&name) {
+                Some(object) => {
+                    ([(header::CONTENT_LENGTH, object.len().to_string())]).into_response()
+                }
+                None => StatusCode::NOT_FOUND.into_response(),
+            },
+            (Method::PUT, Some(upload_id)) => {
+                let number = query["partNumber"].parse().unwrap();
+                match bucket.uploads.get_mut(upload_id) {
+                    Some((_, parts)) => {
+                        parts.insert(number, body);
+                        ([(header::ETAG, format!("\"{}\"", number))]).into_response()
+                    }
+                    None => s3_error(StatusCode::NOT_FOUND, "NoSuchUpload"),
+                }
+            }
+            (Method::PUT, None) => {
+                let object = match header("x-amz-copy-source") {
+                    Some(source) => {
+                        let source = percent_decode_str(source).decode_utf8_lossy();
+                        let source = source.trim_start_matches('/');
+                        let source = source.strip_prefix(&format!("{}/", BUCKET)).unwrap();
+                        match bucket.objects.get(source) {
+                            Some(object) => object.clone(),
+                            None => return s3_error(StatusCode::NOT_FOUND, "NoSuchKey"),
+                        }
+                    }
+                    None => body,
+                };
+                let copied = header("x-amz-copy-source").is_some();
+                bucket.objects.insert(name, object);
+                if copied {
+                    xml("<CopyObjectResult><ETag>\"copy\"</ETag></CopyObjectResult>".to_string())
+                } else {
+                    ([(header::ETAG, "\"object\"")]).into_response()
+                }
+            }
+            (Method::POST, None) if query.contains_key("uploads") => {
+                let upload_id = (bucket.uploads.len() + 1).to_string();
+                bucket
+                    .uploads
+                    .insert(upload_id.clone(), (name.clone(), BTreeMap::new()));
+                xml(format!(
+                    "<InitiateMultipartUploadResult><Bucket>{}</Bucket><Key>{}</Key>\
+                    <UploadId>{}</UploadId></InitiateMultipartUploadResult>",
+                    BUCKET, name, upload_id
+                ))
+            }
+            (Method::POST, Some(upload_id)) => match bucket.uploads.remove(upload_id) {
+                Some((name, parts)) => {
+                    bucket
+                        .part_sizes
+                        .extend(parts.values().map(|part| part.len()));
+                    let object = parts.into_values().flatten().collect();
+                    bucket.objects.insert(name.clone(), object);
+                    xml(format!(
+                        "<CompleteMultipartUploadResult><Bucket>{}</Bucket><Key>{}</Key>\
+                        <ETag>\"parts\"</ETag></CompleteMultipartUploadResult>",
+                        BUCKET, name
+                    ))
+                }
+                None => s3_error(StatusCode::NOT_FOUND, "NoSuchUpload"),
+            },
+            (Method::DELETE, Some(upload_id)) => {
+                bucket.uploads.remove(upload_id);
+                StatusCode::NO_CONTENT.into_response()
+            }
+            (Method::DELETE, None) => {
+                bucket.objects.remove(&name);
+                StatusCode::NO_CONTENT.into_response()
+            }
+            _ => s3_error(StatusCode::NOT_IMPLEMENTED, "NotImplemented"),
+        }
+    }
+
+    fn xml(body: String) -> Response {
+        (
+            [(header::CONTENT_TYPE, "application/xml")],
+            format!("<?xml version=\"1.0\" encoding=\"UTF-8\"?>{}", body),
+        )
+            .into_response()
+    }
+
+    fn s3_error(status: StatusCode, code: &str) -> Response {
+        let mut response = xml(format!(
+            "<Error><Code>{}</Code><Message>{}</Message></Error>",
+            code, code
+        ));
+        *response.status_mut() = status;
+        response
+    }
+
+    /// First and last byte of a `Range` header, like `bytes=2-4` or `bytes=-3`
+    fn byte_range(range: &str, size: usize) -> Option<(usize, usize)> {
+        let (start, end) = range.strip_prefix("bytes=")?.split_once('-')?;
+        let (start, end) = match (start.parse::<usize>(), end.parse::<usize>()) {
+            (Ok(start), Ok(end)) => (start, end.min(size.checked_sub(1)?)),
+            (Ok(start), Err(_)) => (start, size.checked_sub(1)?),
+            (Err(_), Ok(suffix)) => (size.saturating_sub(suffix), size.checked_sub(1)?),
+            _ => return None,
+        };
+        (start <= end).then_some((start, end))
+    }
+
+    fn body(bytes: &'static [u8]) -> ByteStream {
+        Box::pin(stream::iter([Ok(Bytes::from_static(bytes))]))
+    }
+
+    async fn read(storage: &S3Storage, key: &str, range: Option<ByteRange>) -> Vec<u8> {
+        let mut object = storage.get(key, range).await.unwrap();
+        let mut bytes = Vec::new();
+        while let Some(chunk) = object.body.next().await {
+            bytes.extend_from_slice(&chunk.unwrap());
+        }
+        bytes
+    }
+
+    #[tokio::test]
+    async fn put_get_list_rename_and_delete() {
+        let (storage, bucket) = test_storage().await;
+
+        assert_eq!(storage.put("a/1.jpg", body(b"0123456789")).await, Ok(10));
+        assert_eq!(storage.put("a/2.jpg", body(b"two")).await, Ok(3));
+        assert_eq!(storage.put("b/1.jpg", body(b"")).await, Ok(0));
+
+        assert_eq!(read(&storage, "a/1.jpg", None).await, b"0123456789");
+        assert_eq!(
+            read(&storage, "a/1.jpg", Some(ByteRange::Inclusive(2, 4))).await,
+            b"234"
+        );
+        let object = storage
+            .get("a/1.jpg", Some(ByteRange::Suffix(3)))
+            .await
+            .unwrap();
+        assert_eq!((object.range, object.size), (Some((7, 9)), 10));
+        assert!(matches!(
+            storage.get("a/missing.jpg", None).await,
+            Err(StorageError::NotFound)
+        ));
+
+        let mut keys = storage.list("a/").await.unwrap();
+        keys.sort();
+        assert_eq!(keys, ["a/1.jpg", "a/2.jpg"]);
+
+        storage.rename("a/2.jpg", "c/2.jpg").await.unwrap();
+        assert!(!storage.exists("a/2.jpg").await.unwrap());
+        assert_eq!(read(&storage, "c/2.jpg", None).await, b"two");
+
+        for key in ["a/1.jpg", "b/1.jpg", "c/2.jpg"] {
+            storage.delete(key).await.unwrap();
+            assert!(!storage.exists(key).await.unwrap());
+        }
+        // Removing a missing object is not an error
+        storage.delete("a/1.jpg").await.unwrap();
+        assert!(storage.list("").await.unwrap().is_empty());
+        // Bodies smaller than a part are stored by one request
+        assert!(bucket.lock().unwrap().part_sizes.is_empty());
+    }
+
+    #[tokio::test]
+    async fn large_bodies_are_uploaded_in_parts() {
+        let (storage, bucket) = test_storage().await;
+        let size = PART_SIZE * 2 + 100;
+        // Chunks not aligned with the parts
+        let chunks = (0..size).step_by(1_000_000).map(move |start| {
+            Ok(Bytes::from(vec![
+                (start % 251) as u8;
+                1_000_000.min(size - start)
+            ]))
+        });
+
+        assert_eq!(
+            storage.put("big.bin", Box::pin(stream::iter(chunks))).await,
+            Ok(size as u64)
+        );
+        let object = storage.get("big.bin", None).await.unwrap();
+        assert_eq!(object.size, size as u64);
+        let bytes = read(&storage, "big.bin", None).await;
+        assert_eq!(bytes.len(), size);
+        assert!(bytes
+            .iter()
+            .enumerate()
+            .all(|(i, byte)| *byte == ((i - i % 1_000_000) % 251) as u8));
+        assert_eq!(
+            bucket.lock().unwrap().part_sizes,
+            [PART_SIZE, PART_SIZE, 100]
+        );
+
+        storage.delete("big.bin").await.unwrap();
+        assert!(bucket.lock().unwrap().objects.is_empty());
+    }
+}
@@ -0,0 +1,196 @@
+use super::{
+    dto::{ByteRange, ByteStream, FileDisposal, StorageError, StoredObject},
+    local_storage::LocalStorage,
+    s3_storage::S3Storage,
+};
+use crate::config::Config;
+use async_trait::async_trait;
+use futures::StreamExt;
+use log::{error, info};
+use sha2::{Digest, Sha256};
+use std::{path::Path, sync::Arc};
+
+/// Folder of the content addressed layout, files are named by their hash
+pub const STORE_DIR: &str = "store";
+
+/// Folder downloads are written to until their hash is known, with the content addressed layout
+pub const INCOMING_DIR: &str = ".incoming";
+
+/// Folder removed files are moved to, under their own key
+pub const TRASH_DIR: &str = ".trash";
+
+/// Place downloaded files are kept in. Objects are named by keys like `host/gallery/1.jpg`,
+/// `/` separates the parts of a key in every implementation
+#[async_trait]
+pub trait Storage: Send + Sync {
+    /// Writes the body to `key`, replacing the object stored there, returns the number of
+    /// bytes written. Nothing is stored when the body fails
+    async fn put(&self, key: &str, body: ByteStream) -> Result<u64, String>;
+
+    /// Reads the object, or only the `range` of it
+    async fn get(&self, key: &str, range: Option<ByteRange>) -> Result<StoredObject, StorageError>;
+
+    /// Removes the object, removing a missing object is not an error
+    async fn delete(&self, key: &str) -> Result<(), String>;
+
+    async fn exists(&self, key: &str) -> Result<bool, String>;
+
+    /// Moves the object to `to`, replacing the object stored there
+    async fn rename(&self, from: &str, to: &str) -> Result<(), String>;
+
+    /// Keys of every object starting with `prefix`, in no particular order
+    async fn list(&self, prefix: &str) -> Result<Vec<String>, String>;
+}
+
+/// Builds the storage chosen by `STORAGE_BACKEND`, once in `main`, it is passed to the
+/// services that read and write files
+pub async fn new_storage(config: &Config) -> Result<Arc<dyn Storage>, String> {
+    Ok(match config.storage_backend.as_str() {
+        "s3" => {
+            info!(
+                "Storing files in S3 bucket {}",
+                config.s3_bucket.as_deref().unwrap_or_default()
+            );
+            Arc::new(S3Storage::new(config).await?)
+        }
+        _ => {
+            info!("Storing files in {}", config.storage_root.display());
+            Arc::new(LocalStorage::new(&config.storage_root))
+        }
+    })
+}
+
+/// Key of a file in the storage, parts are joined with `/`
+pub fn object_key(dir: &str, name: &str) -> String {
+    match dir.trim_matches('/') {
+        "" => name.to_string(),
+        dir => format!("{}/{}", dir, name),
+    }
+}
+
+/// Key a file is moved to in the trash
+pub fn trash_key(key: &str) -> String {
+    object_key(TRASH_DIR, key)
+}
+
+fn is_trash_key(key: &str) -> bool {
+    key.starts_with(&format!("{}/", TRASH_DIR))
+}
+
+/// Moves files of trashed records to the trash, or back when `restore` is set.
+/// A file that fails is logged and left where it is
+pub async fn move_to_trash(storage: &dyn Storage, keys: &[String], restore: bool) {
+    for key in keys {
+        let (from, to) = if restore {
+            (trash_key(key), key.clone())
+        } else {
+            (key.clone(), trash_key(key))
+        };
+        let result = match storage.exists(&from).await {
+            Ok(true) => storage.rename(&from, &to).await,
+            Ok(false) => continue,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            error!("Failed to move {} to {}: {}", from, to, e);
+        }
+    }
+}
+
+/// Last part of a key
+pub fn key_name(key: &str) -> &str {
+    key.rsplit('/').next().unwrap_or(key)
+}
+
+/// Hash and size of a stored object, none when it does not exist
+pub async fn get_hash_size(
+    storage: &dyn Storage,
+    key: &str,
+) -> Result<Option<(String, usize)>, String> {
+    let object = match storage.get(key, None).await {
+        Ok(object) => object,
+        Err(StorageError::NotFound) => return Ok(None),
+        Err(e) => return Err(e.to_string()),
+    };
+
+    let mut hasher = Sha256::new();
+    let mut size = 0;
+    let mut body = object.body;
+    while let Some(chunk) = body.next().await {
+        let chunk = chunk.map_err(|e| format!("Failed to read {}: {}", key, e))?;
+        hasher.update(&chunk);
+        size += chunk.len();
+    }
+
+    Ok(Some((format!("{:x}", hasher.finalize()), size)))
+}
+
+/// Keeps, deletes or trashes files whose records were removed, returns the files handled.
+/// Files already in the trash stay there when trashed. A file that fails is logged and left
+/// where it is
+pub async fn dispose(
+    storage: &dyn Storage,
+    keys: &[String],
+    disposal: FileDisposal,
+) -> Vec<String> {
+    let mut handled = Vec::new();
+    for key in keys {
+        let result = match disposal {
+            FileDisposal::Keep => continue,
+            FileDisposal::Delete => storage.delete(key).await,
+            FileDisposal::Trash if is_trash_key(key) => continue,
+            FileDisposal::Trash => match storage.exists(key).await {
+                Ok(true) => storage.rename(key, &trash_key(key)).await,
+                Ok(false) => continue,
+                Err(e) => Err(e),
+            },
+        };
+        match result {
+            Ok(()) => handled.push(key.clone()),
+            Err(e) => error!("Failed to remove {}: {}", key, e),
+        }
+    }
+    handled
+}
+
+/// Key of a file in the content addressed layout, `store/ab/cd/<sha256>.<ext>`
+/// with the lower case extension of `name`
+pub fn content_key(hash: &str, name: &str) -> String {
+    let extension = Path::new(name)
+        .extension()
+        .map(|extension| format!(".{}", extension.to_string_lossy().to_lowercase()))
+        .unwrap_or_default();
+    format!(
+        "{}/{}/{}/{}{}",
+        STORE_DIR,
+        hash.get(..2).unwrap_or_default(),
+        hash.get(2..4).unwrap_or_default(),
+        hash,
+        extension
+    )
+}
+
+pub fn is_content_key(key: &str) -> bool {
+    key.starts_with(&format!("{}/", STORE_DIR))
+}
+
+/// Moves an object to its key in the content addressed layout, returns that key.
+/// When the store already holds the content the moved object is removed instead
+pub async fn move_to_store(
+    storage: &dyn Storage,
+    key: &str,
+    hash: &str,
+    name: &str,
+) -> Result<String, String> {
+    let target = content_key(hash, name);
+    if target == key {
+        return Ok(target);
+    }
+
+    if storage.exists(&target).await? {
+        storage.delete(key).await?;
+    } else {
+        storage.rename(key, &target).await?;
+    }
+    Ok(target)
+}
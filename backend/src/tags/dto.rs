@@ -0,0 +1,62 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Tag {
+    pub id: usize,
+    pub name: String,
+    pub color: Option<String>,
+    pub links: usize,
+    pub mediafiles: usize,
+    #[serde(rename = "dateCreate")]
+    pub date_create: String,
+}
+
+#[derive(Deserialize)]
+pub struct TagDto {
+    pub name: String,
+    pub color: Option<String>,
+}
+
+/// Tags added to or removed from links
+#[derive(Deserialize)]
+pub struct LinkTagsDto {
+    #[serde(rename = "linkIds")]
+    pub link_ids: Vec<usize>,
+    pub tags: Vec<String>,
+}
+
+/// Tags added to or removed from mediafiles
+#[derive(Deserialize)]
+pub struct MediafileTagsDto {
+    #[serde(rename = "mediafileIds")]
+    pub mediafile_ids: Vec<usize>,
+    pub tags: Vec<String>,
+}
+
+/// Tag filter of a listing: every tag of `all`, at least one of `any` and none of `none`
+#[derive(Debug, Default)]
+pub struct TagFilter {
+    pub all: Vec<String>,
+    pub any: Vec<String>,
+    pub none: Vec<String>,
+}
+
+impl TagFilter {
+    /// Filter from comma separated `tags` (AND), `anyTags` (OR) and `notTags` (NOT) query values
+    pub fn from_query(all: Option<&str>, any: Option<&str>, none: Option<&str>) -> Self {
+        Self {
+            all: split_tags(all),
+            any: split_tags(any),
+            none: split_tags(none),
+        }
+    }
+}
+
+pub fn split_tags(tags: Option<&str>) -> Vec<String> {
+    tags.unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|tag| !tag.is_empty())
+        .map(str::to_string)
+        .collect()
+}
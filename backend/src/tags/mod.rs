@@ -0,0 +1,4 @@
+pub mod dto;
+pub mod tags_controller;
+pub mod tags_db_service;
+pub mod tags_service;
@@ -0,0 +1,113 @@
+use axum::{
+    extract::{Query, State},
+    middleware,
+    response::IntoResponse,
+    routing::{delete, get, post, put},
+    Extension, Json, Router,
+};
+
+use std::sync::Arc;
+
+use super::{
+    dto::{LinkTagsDto, MediafileTagsDto, TagDto},
+    tags_service::TagsService,
+};
+use crate::{
+    auth::{
+        auth_service::{require_read, require_write, AuthService},
+        dto::Access,
+    },
+    config::Config,
+    links::dto::IdDto,
+};
+
+pub struct TagsController {}
+
+impl TagsController {
+    pub async fn create(
+        State(service): State<Arc<TagsService>>,
+        Json(dto): Json<TagDto>,
+    ) -> impl IntoResponse {
+        service.create_one(dto).await
+    }
+
+    pub async fn get_all(
+        State(service): State<Arc<TagsService>>,
+        Extension(access): Extension<Access>,
+    ) -> impl IntoResponse {
+        service.get_all(access).await
+    }
+
+    pub async fn update(
+        State(service): State<Arc<TagsService>>,
+        Query(query): Query<IdDto>,
+        Json(dto): Json<TagDto>,
+    ) -> impl IntoResponse {
+        service.update_one(query.id, dto).await
+    }
+
+    pub async fn remove(
+        State(service): State<Arc<TagsService>>,
+        Query(query): Query<IdDto>,
+    ) -> impl IntoResponse {
+        service.remove(query.id).await
+    }
+
+    pub async fn add_to_links(
+        State(service): State<Arc<TagsService>>,
+        Extension(access): Extension<Access>,
+        Json(dto): Json<LinkTagsDto>,
+    ) -> impl IntoResponse {
+        service.tag_links(access, dto, true).await
+    }
+
+    pub async fn remove_from_links(
+        State(service): State<Arc<TagsService>>,
+        Extension(access): Extension<Access>,
+        Json(dto): Json<LinkTagsDto>,
+    ) -> impl IntoResponse {
+        service.tag_links(access, dto, false).await
+    }
+
+    pub async fn add_to_mediafiles(
+        State(service): State<Arc<TagsService>>,
+        Extension(access): Extension<Access>,
+        Json(dto): Json<MediafileTagsDto>,
+    ) -> impl IntoResponse {
+        service.tag_mediafiles(access, dto, true).await
+    }
+
+    pub async fn remove_from_mediafiles(
+        State(service): State<Arc<TagsService>>,
+        Extension(access): Extension<Access>,
+        Json(dto): Json<MediafileTagsDto>,
+    ) -> impl IntoResponse {
+        service.tag_mediafiles(access, dto, false).await
+    }
+}
+
+pub fn tags_routes(config: Arc<Config>, auth: Arc<AuthService>) -> Router {
+    let read = Router::new()
+        .route("/tags", get(TagsController::get_all))
+        .route_layer(middleware::from_fn_with_state(
+            Arc::clone(&auth),
+            require_read,
+        ));
+    let write = Router::new()
+        .route("/tags", post(TagsController::create))
+        .route("/tags", put(TagsController::update))
+        .route("/tags", delete(TagsController::remove))
+        .route("/tags/links", post(TagsController::add_to_links))
+        .route("/tags/links", delete(TagsController::remove_from_links))
+        .route("/tags/mediafiles", post(TagsController::add_to_mediafiles))
+        .route(
+            "/tags/mediafiles",
+            delete(TagsController::remove_from_mediafiles),
+        )
+        .route_layer(middleware::from_fn_with_state(auth, require_write));
+
+    Router::new()
+        .merge(read)
+        .merge(write)
+        .with_state(Arc::new(TagsService::new(&config)))
+}
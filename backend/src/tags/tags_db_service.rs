@@ -0,0 +1,247 @@
+use super::dto::{Tag, TagDto, TagFilter};
+use crate::{config::Config, metrics, utils::get_now_time};
+use log::error;
+use rusqlite::{params, types::Value, Connection, Result, Row};
+
+/// Tables attaching tags to links or mediafiles
+#[derive(Clone, Copy)]
+pub enum Tagged {
+    Links,
+    Mediafiles,
+}
+
+impl Tagged {
+    fn table(&self) -> &'static str {
+        match self {
+            Tagged::Links => "link_tags",
+            Tagged::Mediafiles => "mediafile_tags",
+        }
+    }
+
+    fn owner_column(&self) -> &'static str {
+        match self {
+            Tagged::Links => "link_id",
+            Tagged::Mediafiles => "mediafile_id",
+        }
+    }
+}
+
+pub struct TagsDbService {
+    db_name: String,
+}
+
+impl TagsDbService {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            db_name: config.db_name.clone(),
+        }
+    }
+
+    fn open_connection(&self) -> Result<Connection> {
+        let mut conn = Connection::open(&self.db_name)?;
+        conn.profile(Some(metrics::observe_query));
+        Ok(conn)
+    }
+
+    pub fn create_one(&self, dto: &TagDto) -> Result<&str> {
+        let conn = self.open_connection()?;
+
+        match conn.execute(
+            "INSERT INTO tags (name, color, date_create) VALUES (?, ?, ?)",
+            params![dto.name.trim(), dto.color, get_now_time()],
+        ) {
+            Ok(changes) => {
+                if changes == 1 {
+                    Ok("One tag created")
+                } else {
+                    Ok("No tag created")
+                }
+            }
+            Err(e) => {
+                error!("Error creating tag: {}", e);
+                Err(e)
+            }
+        }
+    }
+
+    pub fn update_one(&self, id: usize, dto: &TagDto) -> Result<&str> {
+        let conn = self.open_connection()?;
+
+        let changes = conn.execute(
+            "UPDATE tags SET name = ?, color = ? WHERE id = ?",
+            params![dto.name.trim(), dto.color, id],
+        )?;
+
+        Ok(if changes == 1 {
+            "One tag updated"
+        } else {
+            "No tag updated"
+        })
+    }
+
+    /// Tags with the number of links and mediafiles they are attached to, counting only
+    /// those of one owner when `user_id` is set
+    pub fn get_all(&self, user_id: Option<usize>) -> Result<Vec<Tag>> {
+        let conn = self.open_connection()?;
+        let mut stmt = conn.prepare(
+            "SELECT t.*,
+                (SELECT COUNT(*) FROM link_tags lt JOIN links l ON l.id = lt.link_id
+                    WHERE lt.tag_id = t.id AND (?1 IS NULL OR l.user_id = ?1)) AS links,
+                (SELECT COUNT(*) FROM mediafile_tags mt
+                    WHERE mt.tag_id = t.id AND (?1 IS NULL OR mt.mediafile_id IN (
+                        SELECT ml.mediafile_id FROM mediafiles_links ml
+                            JOIN links l ON l.id = ml.link_id
+                            WHERE l.user_id = ?1
+                    ))) AS mediafiles
+            FROM tags t
+            ORDER BY t.name",
+        )?;
+
+        let rows = stmt.query_map([user_id], map_tag)?;
+
+        let result: Result<Vec<_>, _> = rows.collect();
+        result
+    }
+
+    /// Removes the tag together with its links and mediafiles attachments
+    pub fn remove(&self, id: usize) -> Result<&str> {
+        let mut conn = self.open_connection()?;
+        let tx = conn.transaction()?;
+
+        tx.execute("DELETE FROM link_tags WHERE tag_id = ?", [id])?;
+        tx.execute("DELETE FROM mediafile_tags WHERE tag_id = ?", [id])?;
+        let changes = tx.execute("DELETE FROM tags WHERE id = ?", [id])?;
+        tx.commit()?;
+
+        Ok(if changes == 1 {
+            "One tag removed"
+        } else {
+            "No tag removed"
+        })
+    }
+
+    /// Attaches tags by name, creating missing ones, returns the number of new attachments
+    pub fn attach(&self, tagged: Tagged, ids: &[usize], names: &[String]) -> Result<usize> {
+        let mut conn = self.open_connection()?;
+        let tx = conn.transaction()?;
+        let now = get_now_time();
+        let mut attached = 0;
+
+        for name in names {
+            tx.execute(
+                "INSERT OR IGNORE INTO tags (name, date_create) VALUES (?, ?)",
+                params![name, now],
+            )?;
+
+            for id in ids {
+                attached += tx.execute(
+                    &format!(
+                        "INSERT OR IGNORE INTO {} ({}, tag_id) SELECT ?, id FROM tags WHERE name = ?",
+                        tagged.table(),
+                        tagged.owner_column()
+                    ),
+                    params![id, name],
+                )?;
+            }
+        }
+
+        tx.commit()?;
+        Ok(attached)
+    }
+
+    /// Detaches tags by name, returns the number of removed attachments
+    pub fn detach(&self, tagged: Tagged, ids: &[usize], names: &[String]) -> Result<usize> {
+        let mut conn = self.open_connection()?;
+        let tx = conn.transaction()?;
+        let mut detached = 0;
+
+        for name in names {
+            for id in ids {
+                detached += tx.execute(
+                    &format!(
+                        "DELETE FROM {} WHERE {} = ? AND tag_id = (SELECT id FROM tags WHERE name = ?)",
+                        tagged.table(),
+                        tagged.owner_column()
+                    ),
+                    params![id, name],
+                )?;
+            }
+        }
+
+        tx.commit()?;
+        Ok(detached)
+    }
+}
+
+fn map_tag(row: &Row) -> Result<Tag> {
+    Ok(Tag {
+        id: row.get("id")?,
+        name: row.get("name")?,
+        color: row.get("color")?,
+        links: row.get("links")?,
+        mediafiles: row.get("mediafiles")?,
+        date_create: row.get("date_create")?,
+    })
+}
+
+/// Comma separated tag names of the row with `id_column`, selected as `tags`
+pub fn tags_column(tagged: Tagged, id_column: &str) -> String {
+    format!(
+        "(SELECT group_concat(t.name, ',') FROM {table} tt JOIN tags t ON t.id = tt.tag_id
+            WHERE tt.{owner} = {id_column}) AS tags",
+        table = tagged.table(),
+        owner = tagged.owner_column(),
+    )
+}
+
+/// SQL conditions on `id_column` matching the filter, joined by AND, with their parameters
+pub fn tag_filter_conditions(
+    filter: &TagFilter,
+    tagged: Tagged,
+    id_column: &str,
+) -> (Vec<String>, Vec<Value>) {
+    let tagged_with = |count: usize| {
+        format!(
+            "SELECT tt.{owner} FROM {table} tt JOIN tags t ON t.id = tt.tag_id WHERE t.name IN ({})",
+            vec!["?"; count].join(", "),
+            table = tagged.table(),
+            owner = tagged.owner_column(),
+        )
+    };
+    let mut conditions = Vec::new();
+    let mut values = Vec::new();
+
+    for name in &filter.all {
+        conditions.push(format!("{} IN ({})", id_column, tagged_with(1)));
+        values.push(Value::from(name.clone()));
+    }
+
+    if !filter.any.is_empty() {
+        conditions.push(format!(
+            "{} IN ({})",
+            id_column,
+            tagged_with(filter.any.len())
+        ));
+        values.extend(filter.any.iter().cloned().map(Value::from));
+    }
+
+    if !filter.none.is_empty() {
+        conditions.push(format!(
+            "{} NOT IN ({})",
+            id_column,
+            tagged_with(filter.none.len())
+        ));
+        values.extend(filter.none.iter().cloned().map(Value::from));
+    }
+
+    (conditions, values)
+}
+
+/// Tag names of a `tags` column, empty when the row has none or the column was not selected
+pub fn parse_tags(row: &Row) -> Vec<String> {
+    row.get::<_, Option<String>>("tags")
+        .ok()
+        .flatten()
+        .map(|tags| tags.split(',').map(str::to_string).collect())
+        .unwrap_or_default()
+}
@@ -0,0 +1,186 @@
+use super::{
+    dto::{LinkTagsDto, MediafileTagsDto, TagDto},
+    tags_db_service::{Tagged, TagsDbService},
+};
+use crate::{
+    auth::dto::Access,
+    config::Config,
+    links::{dto::IResult, links_db_service::LinksDbService},
+    mediafiles::mediafiles_db_service::MediafilesDbService,
+    utils::{error_response, server_error_response, success_response},
+};
+use axum::{http::StatusCode, response::IntoResponse, Json};
+use log::{error, info, warn};
+use std::sync::Arc;
+
+pub struct TagsService {
+    tags_db_service: Arc<TagsDbService>,
+    links_db_service: Arc<LinksDbService>,
+    mediafiles_db_service: Arc<MediafilesDbService>,
+}
+
+impl TagsService {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            tags_db_service: Arc::new(TagsDbService::new(config)),
+            links_db_service: Arc::new(LinksDbService::new(config)),
+            mediafiles_db_service: Arc::new(MediafilesDbService::new(config)),
+        }
+    }
+
+    pub async fn create_one(&self, dto: TagDto) -> impl IntoResponse {
+        info!("Creating tag {}", &dto.name);
+
+        if let Err(e) = validate_name(&dto.name) {
+            return Err(error_response(e, StatusCode::BAD_REQUEST));
+        }
+
+        match self.tags_db_service.create_one(&dto) {
+            Ok(m) => Ok(success_response(m.to_string())),
+            Err(e) => Err(server_error_response(e.to_string())),
+        }
+    }
+
+    /// Every tag, counting only the links and mediafiles of the requesting user unless admin
+    pub async fn get_all(&self, access: Access) -> impl IntoResponse {
+        match self
+            .tags_db_service
+            .get_all((!access.is_admin).then_some(access.user_id))
+        {
+            Ok(tags) => Ok((StatusCode::OK, Json(tags))),
+            Err(e) => {
+                error!("Error getting tags: {}", e);
+                Err(server_error_response("Error getting tags".to_string()))
+            }
+        }
+    }
+
+    pub async fn update_one(&self, id: usize, dto: TagDto) -> impl IntoResponse {
+        info!("Updating tag with id: {}", &id);
+
+        if let Err(e) = validate_name(&dto.name) {
+            return Err(error_response(e, StatusCode::BAD_REQUEST));
+        }
+
+        match self.tags_db_service.update_one(id, &dto) {
+            Ok(m) => Ok(success_response(m.to_string())),
+            Err(e) => Err(server_error_response(e.to_string())),
+        }
+    }
+
+    pub async fn remove(&self, id: usize) -> impl IntoResponse {
+        info!("Removing tag with id: {}", &id);
+
+        match self.tags_db_service.remove(id) {
+            Ok(m) => Ok(success_response(m.to_string())),
+            Err(e) => Err(server_error_response(e.to_string())),
+        }
+    }
+
+    pub async fn tag_links(
+        &self,
+        access: Access,
+        dto: LinkTagsDto,
+        attach: bool,
+    ) -> impl IntoResponse {
+        self.tag(access, Tagged::Links, &dto.link_ids, &dto.tags, attach)
+    }
+
+    pub async fn tag_mediafiles(
+        &self,
+        access: Access,
+        dto: MediafileTagsDto,
+        attach: bool,
+    ) -> impl IntoResponse {
+        self.tag(
+            access,
+            Tagged::Mediafiles,
+            &dto.mediafile_ids,
+            &dto.tags,
+            attach,
+        )
+    }
+
+    fn tag(
+        &self,
+        access: Access,
+        tagged: Tagged,
+        ids: &[usize],
+        names: &[String],
+        attach: bool,
+    ) -> Result<impl IntoResponse, impl IntoResponse> {
+        let names: Vec<String> = names.iter().map(|name| name.trim().to_string()).collect();
+        if let Some(e) = names.iter().find_map(|name| validate_name(name).err()) {
+            return Err(error_response(e, StatusCode::BAD_REQUEST));
+        }
+
+        self.check_owned(access, tagged, ids)?;
+
+        let result = if attach {
+            self.tags_db_service
+                .attach(tagged, ids, &names)
+                .map(|count| format!("{} tags added", count))
+        } else {
+            self.tags_db_service
+                .detach(tagged, ids, &names)
+                .map(|count| format!("{} tags removed", count))
+        };
+
+        match result {
+            Ok(m) => Ok(success_response(m)),
+            Err(e) => Err(server_error_response(e.to_string())),
+        }
+    }
+
+    /// Fails with 404 on the first link or mediafile the requesting user does not own
+    fn check_owned(
+        &self,
+        access: Access,
+        tagged: Tagged,
+        ids: &[usize],
+    ) -> Result<(), (StatusCode, Json<IResult>)> {
+        if access.is_admin {
+            return Ok(());
+        }
+
+        for &id in ids {
+            let (owned, kind) = match tagged {
+                Tagged::Links => (
+                    self.links_db_service
+                        .get_one(id)
+                        .map(|link| link.is_some_and(|link| access.owns(link.user_id))),
+                    "Link",
+                ),
+                Tagged::Mediafiles => (
+                    self.mediafiles_db_service
+                        .get_owners(id)
+                        .map(|owners| owners.iter().any(|&owner| access.owns(owner))),
+                    "Mediafile",
+                ),
+            };
+
+            if !owned.map_err(|e| server_error_response(e.to_string()))? {
+                warn!("{} with id {} not found", kind, id);
+                return Err(error_response(
+                    format!("{} not found", kind),
+                    StatusCode::NOT_FOUND,
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Names are listed comma separated in filters and `tags` columns
+fn validate_name(name: &str) -> Result<(), String> {
+    if name.trim().is_empty() {
+        return Err("Tag name is required".to_string());
+    }
+
+    if name.contains(',') {
+        return Err(format!("Tag name {} must not contain commas", name));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,43 @@
+use crate::{links::dto::Link, mediafiles::dto::Mediafile};
+use serde::{Deserialize, Serialize};
+
+/// Links and mediafiles in the trash, recently trashed first
+#[derive(Debug, Serialize)]
+pub struct Trash {
+    pub links: Vec<Link>,
+    pub mediafiles: Vec<Mediafile>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum TrashKind {
+    Link,
+    Mediafile,
+}
+
+#[derive(Deserialize)]
+pub struct TrashQuery {
+    /// Trash of one user, admins only
+    #[serde(rename = "userId")]
+    pub user_id: Option<usize>,
+}
+
+#[derive(Deserialize)]
+pub struct TrashItemQuery {
+    pub kind: TrashKind,
+    pub id: usize,
+}
+
+/// Purges one item when `kind` and `id` are set, the whole trash otherwise
+#[derive(Deserialize)]
+pub struct PurgeQuery {
+    pub kind: Option<TrashKind>,
+    pub id: Option<usize>,
+}
+
+/// Items removed for good by a purge
+#[derive(Debug, Default, Serialize)]
+pub struct PurgeReport {
+    pub links: usize,
+    pub mediafiles: usize,
+}
@@ -0,0 +1,3 @@
+pub mod dto;
+pub mod trash_controller;
+pub mod trash_service;
@@ -0,0 +1,82 @@
+use axum::{
+    extract::{Query, State},
+    middleware,
+    response::IntoResponse,
+    routing::{delete, get},
+    Extension, Router,
+};
+
+use std::sync::Arc;
+
+use crate::{
+    auth::{
+        auth_service::{require_read, require_write, AuthService},
+        dto::Access,
+    },
+    config::Config,
+    http_client::HttpClient,
+    links::download_permits::DownloadPermits,
+    storage::storage_service::Storage,
+};
+
+use super::{
+    dto::{PurgeQuery, TrashItemQuery, TrashQuery},
+    trash_service::TrashService,
+};
+
+pub struct TrashController {}
+
+impl TrashController {
+    pub async fn get_trash(
+        State(service): State<Arc<TrashService>>,
+        Extension(access): Extension<Access>,
+        Query(query): Query<TrashQuery>,
+    ) -> impl IntoResponse {
+        service.get_trash(access, query.user_id).await
+    }
+
+    pub async fn restore(
+        State(service): State<Arc<TrashService>>,
+        Extension(access): Extension<Access>,
+        Query(query): Query<TrashItemQuery>,
+    ) -> impl IntoResponse {
+        service.restore(access, query).await
+    }
+
+    pub async fn purge(
+        State(service): State<Arc<TrashService>>,
+        Extension(access): Extension<Access>,
+        Query(query): Query<PurgeQuery>,
+    ) -> impl IntoResponse {
+        service.purge(access, query.kind, query.id).await
+    }
+}
+
+pub fn trash_routes(
+    config: Arc<Config>,
+    http: Arc<HttpClient>,
+    storage: Arc<dyn Storage>,
+    download_permits: Arc<DownloadPermits>,
+    auth: Arc<AuthService>,
+) -> Router {
+    let read = Router::new()
+        .route("/trash", get(TrashController::get_trash))
+        .route_layer(middleware::from_fn_with_state(
+            Arc::clone(&auth),
+            require_read,
+        ));
+    let write = Router::new()
+        .route("/trash", delete(TrashController::purge))
+        .route("/trash/restore", get(TrashController::restore))
+        .route_layer(middleware::from_fn_with_state(auth, require_write));
+
+    Router::new()
+        .merge(read)
+        .merge(write)
+        .with_state(Arc::new(TrashService::new(
+            config,
+            http,
+            storage,
+            download_permits,
+        )))
+}
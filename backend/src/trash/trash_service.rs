@@ -0,0 +1,200 @@
+use super::dto::{PurgeReport, Trash, TrashItemQuery, TrashKind};
+use crate::{
+    auth::dto::Access,
+    config::Config,
+    http_client::HttpClient,
+    links::{download_permits::DownloadPermits, dto::IResult, links_service::LinksService},
+    mediafiles::mediafiles_service::MediafilesService,
+    storage::{dto::FileDisposal, storage_service::Storage},
+    utils::{error_response, get_time_before, server_error_response, success_response},
+};
+use axum::{http::StatusCode, response::IntoResponse, Json};
+use chrono::Duration;
+use log::{error, info};
+use std::sync::Arc;
+
+/// Lists, restores and purges trashed links and mediafiles. Files of purged items are deleted
+pub struct TrashService {
+    links_service: Arc<LinksService>,
+    mediafiles_service: Arc<MediafilesService>,
+    /// How long items stay in the trash before `purge_expired` removes them
+    retention: Duration,
+}
+
+impl TrashService {
+    pub fn new(
+        config: Arc<Config>,
+        http: Arc<HttpClient>,
+        storage: Arc<dyn Storage>,
+        download_permits: Arc<DownloadPermits>,
+    ) -> Self {
+        Self {
+            mediafiles_service: Arc::new(MediafilesService::new(
+                &config,
+                Arc::clone(&http),
+                Arc::clone(&storage),
+            )),
+            retention: config.trash_retention,
+            links_service: Arc::new(LinksService::new(config, http, storage, download_permits)),
+        }
+    }
+
+    /// Trash of the requesting user, admins see every user's unless they filter by `userId`
+    pub async fn get_trash(&self, access: Access, user_id: Option<usize>) -> impl IntoResponse {
+        let user_id = if access.is_admin {
+            user_id
+        } else {
+            Some(access.user_id)
+        };
+
+        match self.get_items(user_id) {
+            Ok(trash) => Ok((StatusCode::OK, Json(trash))),
+            Err(e) => {
+                error!("Error getting trash: {}", e);
+                Err(server_error_response("Error getting trash".to_string()))
+            }
+        }
+    }
+
+    pub async fn restore(&self, access: Access, query: TrashItemQuery) -> impl IntoResponse {
+        info!("Restoring {:?} with id: {}", query.kind, query.id);
+        self.check_trashed(access, query.kind, query.id)?;
+
+        let result = match query.kind {
+            TrashKind::Link => self.links_service.restore_link(query.id).await,
+            TrashKind::Mediafile => self.mediafiles_service.restore_mediafile(query.id).await,
+        };
+        result.map(success_response).map_err(server_error_response)
+    }
+
+    /// Removes one trashed item, or everything in the trash of the requesting user, for good
+    pub async fn purge(
+        &self,
+        access: Access,
+        kind: Option<TrashKind>,
+        id: Option<usize>,
+    ) -> impl IntoResponse {
+        let (links, mediafiles) = match (kind, id) {
+            (Some(kind), Some(id)) => {
+                self.check_trashed(access, kind, id)?;
+                match kind {
+                    TrashKind::Link => (vec![id], vec![]),
+                    TrashKind::Mediafile => (vec![], vec![id]),
+                }
+            }
+            (None, None) => {
+                let trash = self
+                    .get_items((!access.is_admin).then_some(access.user_id))
+                    .map_err(server_error_response)?;
+                (
+                    trash.links.iter().map(|link| link.id).collect(),
+                    trash
+                        .mediafiles
+                        .iter()
+                        .map(|mediafile| mediafile.id)
+                        .collect(),
+                )
+            }
+            _ => {
+                return Err(error_response(
+                    "Give both kind and id, or neither to empty the trash".to_string(),
+                    StatusCode::BAD_REQUEST,
+                ))
+            }
+        };
+
+        self.purge_items(&links, &mediafiles)
+            .await
+            .map(|report| (StatusCode::OK, Json(report)))
+            .map_err(server_error_response)
+    }
+
+    /// Removes items trashed longer than the retention period for good
+    pub async fn purge_expired(&self) -> Result<PurgeReport, String> {
+        let before = get_time_before(self.retention)
+            .ok_or_else(|| "TRASH_RETENTION is out of range".to_string())?;
+
+        let links = self.links_service.get_trashed_before(&before)?;
+        let report = self.purge_items(&links, &[]).await?;
+        // Mediafiles trashed with a link are gone with it, only the ones trashed alone are left
+        let mediafiles = self.mediafiles_service.get_trashed_before(&before)?;
+        let mediafiles = self.purge_items(&[], &mediafiles).await?;
+
+        Ok(PurgeReport {
+            links: report.links,
+            mediafiles: mediafiles.mediafiles,
+        })
+    }
+
+    /// Removes every item in the trash for good
+    pub async fn purge_all(&self) -> Result<PurgeReport, String> {
+        let trash = self.get_items(None)?;
+        let links: Vec<usize> = trash.links.iter().map(|link| link.id).collect();
+        let mediafiles: Vec<usize> = trash.mediafiles.iter().map(|m| m.id).collect();
+        self.purge_items(&links, &mediafiles).await
+    }
+
+    async fn purge_items(
+        &self,
+        links: &[usize],
+        mediafiles: &[usize],
+    ) -> Result<PurgeReport, String> {
+        let mut report = PurgeReport::default();
+
+        for &id in links {
+            self.links_service
+                .purge_link(id, FileDisposal::Delete)
+                .await?;
+            report.links += 1;
+        }
+        for &id in mediafiles {
+            // A mediafile trashed with a purged link is already gone
+            if self.mediafiles_service.get_one(id).await?.is_some() {
+                self.mediafiles_service
+                    .purge_mediafile(id, FileDisposal::Delete)
+                    .await?;
+                report.mediafiles += 1;
+            }
+        }
+
+        if report.links > 0 || report.mediafiles > 0 {
+            info!(
+                "Purged {} links and {} mediafiles from the trash",
+                report.links, report.mediafiles
+            );
+        }
+        Ok(report)
+    }
+
+    fn get_items(&self, user_id: Option<usize>) -> Result<Trash, String> {
+        Ok(Trash {
+            links: self.links_service.get_trashed(user_id)?,
+            mediafiles: self.mediafiles_service.get_trashed(user_id)?,
+        })
+    }
+
+    /// An item not in the trash of the requesting user is not found like a missing one
+    fn check_trashed(
+        &self,
+        access: Access,
+        kind: TrashKind,
+        id: usize,
+    ) -> Result<(), (StatusCode, Json<IResult>)> {
+        let trash = self
+            .get_items((!access.is_admin).then_some(access.user_id))
+            .map_err(server_error_response)?;
+
+        let found = match kind {
+            TrashKind::Link => trash.links.iter().any(|link| link.id == id),
+            TrashKind::Mediafile => trash.mediafiles.iter().any(|m| m.id == id),
+        };
+        if found {
+            Ok(())
+        } else {
+            Err(error_response(
+                "Not found in the trash".to_string(),
+                StatusCode::NOT_FOUND,
+            ))
+        }
+    }
+}
@@ -1,12 +1,85 @@
 use crate::links::dto::IResult;
 use axum::Json;
-use chrono::Utc;
+use chrono::{Duration, TimeDelta, Utc};
 use reqwest::StatusCode;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+static JOB_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Longest interval accepted, about a hundred years
+const MAX_INTERVAL_DAYS: i64 = 36525;
 
 pub fn get_now_time() -> String {
     Utc::now().format("%Y-%m-%d %H:%M:%S").to_string()
 }
 
+/// Id of a background job like a media download or a scheduler run, unique within the process
+pub fn next_job_id() -> u64 {
+    JOB_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Time `interval` from now, none when it is out of the range of dates
+pub fn get_time_after(interval: Duration) -> Option<String> {
+    Utc::now()
+        .checked_add_signed(interval)
+        .map(|time| time.format("%Y-%m-%d %H:%M:%S").to_string())
+}
+
+/// Time `interval` ago, none when it is out of the range of dates
+pub fn get_time_before(interval: Duration) -> Option<String> {
+    Utc::now()
+        .checked_sub_signed(interval)
+        .map(|time| time.format("%Y-%m-%d %H:%M:%S").to_string())
+}
+
+/// Parses intervals like `90`, `45s`, `30m`, `6h`, `1d` or `2w`, bare numbers are seconds.
+/// Intervals longer than about a hundred years are rejected
+pub fn parse_interval(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    let (number, unit) = match value.find(|c: char| !c.is_ascii_digit()) {
+        Some(index) => value.split_at(index),
+        None => (value, "s"),
+    };
+    let number: i64 = number.parse().ok()?;
+
+    let interval = match unit.trim() {
+        "s" => TimeDelta::try_seconds(number),
+        "m" => TimeDelta::try_minutes(number),
+        "h" => TimeDelta::try_hours(number),
+        "d" => TimeDelta::try_days(number),
+        "w" => TimeDelta::try_weeks(number),
+        _ => return None,
+    }?;
+
+    if interval > Duration::zero() && interval <= TimeDelta::try_days(MAX_INTERVAL_DAYS)? {
+        Some(interval)
+    } else {
+        None
+    }
+}
+
+/// Parses sizes like `1048576`, `500K`, `200M`, `10G` or `1T`, units are powers of 1024
+pub fn parse_size(value: &str) -> Option<u64> {
+    let value = value.trim().to_uppercase();
+    let (number, unit) = match value.find(|c: char| !c.is_ascii_digit()) {
+        Some(index) => value.split_at(index),
+        None => (value.as_str(), ""),
+    };
+    let number: u64 = number.parse().ok()?;
+
+    let multiplier: u64 = match unit.trim().trim_end_matches(['B', 'I']) {
+        "" => 1,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        "T" => 1 << 40,
+        _ => return None,
+    };
+
+    number.checked_mul(multiplier)
+}
+
+#[allow(clippy::needless_return)]
 pub fn server_error_response(message: String) -> (StatusCode, Json<IResult>) {
     return (
         StatusCode::INTERNAL_SERVER_ERROR,
@@ -17,6 +90,7 @@ pub fn server_error_response(message: String) -> (StatusCode, Json<IResult>) {
     );
 }
 
+#[allow(clippy::needless_return)]
 pub fn error_response(message: String, status: StatusCode) -> (StatusCode, Json<IResult>) {
     return (
         status,
@@ -27,6 +101,7 @@ pub fn error_response(message: String, status: StatusCode) -> (StatusCode, Json<
     );
 }
 
+#[allow(clippy::needless_return)]
 pub fn success_response(message: String) -> (StatusCode, Json<IResult>) {
     return (
         StatusCode::OK,
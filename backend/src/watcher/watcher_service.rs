@@ -0,0 +1,201 @@
+use crate::{
+    config::Config,
+    links::links_service::{is_internal_key, LinksService},
+    storage::storage_service::Storage,
+    utils::next_job_id,
+};
+use log::{debug, error, info, warn};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::{
+    collections::HashMap,
+    path::{Component, Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::{select, spawn, sync::mpsc, time};
+use tracing::{info_span, Instrument};
+
+/// Starts watching the storage root. A changed path is handled once it has been quiet for
+/// `WATCH_DEBOUNCE`, so files still being copied wait until they are complete. When the
+/// system dropped events, and once at start, the whole storage is compared with the records
+pub fn start(
+    links_service: Arc<LinksService>,
+    storage: Arc<dyn Storage>,
+    config: &Config,
+) -> Result<(), String> {
+    let root = config
+        .storage_root
+        .canonicalize()
+        .map_err(|e| format!("Failed to read {}: {}", config.storage_root.display(), e))?;
+    let debounce = config
+        .watch_debounce
+        .to_std()
+        .expect("WATCH_DEBOUNCE must be positive");
+
+    let (sender, receiver) = mpsc::unbounded_channel();
+    let mut watcher = notify::recommended_watcher(move |event| {
+        // The receiver is only gone when the task stopped
+        let _ = sender.send(event);
+    })
+    .map_err(|e| format!("Failed to start the watcher: {}", e))?;
+    watcher
+        .watch(&root, RecursiveMode::Recursive)
+        .map_err(|e| format!("Failed to watch {}: {}", root.display(), e))?;
+
+    info!(
+        "Watching {} for changes, debounce {} ms",
+        root.display(),
+        debounce.as_millis()
+    );
+    spawn(watch(
+        watcher,
+        receiver,
+        links_service,
+        storage,
+        root,
+        debounce,
+    ));
+    Ok(())
+}
+
+async fn watch(
+    // Events stop when the watcher is dropped, the task keeps it
+    _watcher: RecommendedWatcher,
+    mut receiver: mpsc::UnboundedReceiver<notify::Result<Event>>,
+    links_service: Arc<LinksService>,
+    storage: Arc<dyn Storage>,
+    root: PathBuf,
+    debounce: Duration,
+) {
+    // Last event time of every changed path not handled yet
+    let mut pending: HashMap<PathBuf, Instant> = HashMap::new();
+    let mut last_event = Instant::now();
+    let mut rescan = true;
+    let mut tick = time::interval(debounce / 2);
+    tick.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
+
+    loop {
+        select! {
+            event = receiver.recv() => match event {
+                Some(Ok(event)) => {
+                    last_event = Instant::now();
+                    if event.need_rescan() && !rescan {
+                        warn!("Watcher dropped events, the storage will be synced");
+                        rescan = true;
+                    }
+                    if !matches!(event.kind, EventKind::Access(_)) {
+                        for path in event.paths {
+                            pending.insert(path, last_event);
+                        }
+                    }
+                }
+                Some(Err(e)) => error!("Watcher error: {}", e),
+                None => break,
+            },
+            _ = tick.tick() => {
+                if rescan {
+                    // A sync while files are still coming in would see them half copied
+                    if last_event.elapsed() < debounce {
+                        continue;
+                    }
+                    rescan = false;
+                    pending.clear();
+                    let span = info_span!("watcher", job_id = next_job_id());
+                    if let Err(e) = links_service.sync_storage().instrument(span).await {
+                        error!("Failed to sync the storage: {}", e);
+                    }
+                    continue;
+                }
+
+                let ready: Vec<PathBuf> = pending
+                    .iter()
+                    .filter(|(_, seen)| seen.elapsed() >= debounce)
+                    .map(|(path, _)| path.clone())
+                    .collect();
+                if ready.is_empty() {
+                    continue;
+                }
+                for path in &ready {
+                    pending.remove(path);
+                }
+                let span = info_span!("watcher", job_id = next_job_id());
+                handle_changes(&links_service, storage.as_ref(), &root, ready)
+                    .instrument(span)
+                    .await;
+            }
+        }
+    }
+}
+
+/// Handles removed paths before the ones that appeared, so a renamed file is missing
+/// by the time its new name is matched by hash
+async fn handle_changes(
+    links_service: &LinksService,
+    storage: &dyn Storage,
+    root: &Path,
+    paths: Vec<PathBuf>,
+) {
+    let links = match links_service.get_links(&[]) {
+        Ok(links) => links,
+        Err(e) => {
+            error!("Failed to get links: {}", e);
+            return;
+        }
+    };
+
+    let (present, removed): (Vec<PathBuf>, Vec<PathBuf>) =
+        paths.into_iter().partition(|path| path.exists());
+
+    for path in removed {
+        let Some(key) = storage_key(root, &path) else {
+            continue;
+        };
+        if let Err(e) = links_service.sync_removed(&key).await {
+            error!("Failed to sync {}: {}", key, e);
+        }
+    }
+
+    for path in present {
+        let Some(key) = storage_key(root, &path) else {
+            continue;
+        };
+        // A folder moved or copied in comes with a single event, its files are listed
+        let keys = if path.is_dir() {
+            match storage.list(&format!("{}/", key)).await {
+                Ok(keys) => keys,
+                Err(e) => {
+                    error!("Failed to list {}: {}", key, e);
+                    continue;
+                }
+            }
+        } else {
+            vec![key]
+        };
+
+        for key in keys {
+            match links_service.sync_file(&key, &links).await {
+                Ok(Some(m)) => info!("{}: {}", key, m),
+                Ok(None) => debug!("{} is up to date", key),
+                Err(e) => error!("Failed to sync {}: {}", key, e),
+            }
+        }
+    }
+}
+
+/// Storage key of a path below the root, none for the root itself and internal folders
+fn storage_key(root: &Path, path: &Path) -> Option<String> {
+    let relative = path.strip_prefix(root).ok()?;
+    let parts: Vec<&str> = relative
+        .components()
+        .map(|component| match component {
+            Component::Normal(part) => part.to_str(),
+            _ => None,
+        })
+        .collect::<Option<_>>()?;
+    if parts.is_empty() {
+        return None;
+    }
+
+    let key = parts.join("/");
+    (!is_internal_key(&format!("{}/", key))).then_some(key)
+}
@@ -21,6 +21,10 @@ const LinkModal = () => {
     isReachable,
     duplicateId,
     duplicatePath,
+    displayName,
+    description,
+    author,
+    publishedAt,
   } = links.editModal;
 
   const close = (e) => {
@@ -54,7 +58,7 @@ const LinkModal = () => {
       <div className="modal-dialog" role="document">
         <div className="modal-content">
           <div className="modal-header">
-            <h5 className="modal-title">{name}</h5>
+            <h5 className="modal-title">{displayName}</h5>
             <button
               type="button"
               className="btn"
@@ -82,6 +86,18 @@ const LinkModal = () => {
                   <td scope="col">name</td>
                   <td>{name}</td>
                 </tr>
+                <tr>
+                  <td scope="col">description</td>
+                  <td>{description}</td>
+                </tr>
+                <tr>
+                  <td scope="col">author</td>
+                  <td>{author}</td>
+                </tr>
+                <tr>
+                  <td scope="col">published</td>
+                  <td>{publishedAt}</td>
+                </tr>
                 <tr>
                   <td scope="col">is downloaded</td>
                   <td>
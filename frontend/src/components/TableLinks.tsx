@@ -8,7 +8,7 @@ const columnHelper = createColumnHelper<iLink>();
 
 const columns = [
   columnHelper.accessor('id', { cell: (info) => info.getValue() }),
-  columnHelper.accessor('name', {
+  columnHelper.accessor('displayName', {
     header: () => 'Name',
     cell: (info) => info.getValue(),
   }),
@@ -81,7 +81,15 @@ const TableLinks = () => {
     <div className="table_links">
       <h3>List of links</h3>
       <div className="inline">
-        <div>total links: {links.links?.length ?? 0}</div>
+        <div>
+          total links: {links.links?.length ?? 0} of {links.total}
+        </div>
+        <input
+          type="search"
+          className="form-control"
+          placeholder="search"
+          onKeyDown={(e) => e.key === 'Enter' && links.search((e.target as HTMLInputElement).value)}
+        />
         Get list
         <div>
           <input type="radio" value="all" name="getList" defaultChecked onChange={onGetList} /> all
@@ -136,6 +144,11 @@ const TableLinks = () => {
             ))}
           </tfoot>
         </table>
+        {links.nextCursor && (
+          <button type="button" className="btn btn-primary" onClick={() => links.loadMore()}>
+            load more
+          </button>
+        )}
       </div>
     </div>
   );
@@ -6,6 +6,15 @@ export interface iLink extends iLinkCreateRequest, iLinkUpdateRequest {
   name: string;
   duplicateId?: number;
   duplicatePath?: string;
+  displayName: string;
+  title?: string;
+  ogTitle?: string;
+  description?: string;
+  ogImage?: string;
+  author?: string;
+  publishedAt?: string;
+  coverPath?: string;
+  tags: string[];
 }
 
 export interface iLinkCreateRequest {
@@ -18,3 +27,20 @@ export interface iLinkUpdateRequest {
   mediafiles: number;
   downloadedMediafiles: number;
 }
+
+export interface iLinksQuery {
+  q?: string;
+  isReachable?: boolean;
+  isDuplicate?: boolean;
+  sort?: string;
+  limit?: number;
+  cursor?: string;
+}
+
+export interface iLinksPage {
+  items: iLink[];
+  total: number;
+  limit: number;
+  offset?: number;
+  nextCursor?: string;
+}
@@ -2,17 +2,14 @@ import axios from "axios";
 import urlJoin from "url-join";
 import { HandleRequest } from "../helpers/handlers";
 import { iResult } from "../models/common";
-import { iLink, iLinkCreateRequest } from "../models/links";
+import { iLinkCreateRequest, iLinksPage, iLinksQuery } from "../models/links";
 
 const path = "/links";
 
-export function getAllLinks(
-  isReachable: boolean,
-  showDuplicate: boolean
-): Promise<iLink[]> {
+export function getAllLinks(query: iLinksQuery): Promise<iLinksPage> {
   return HandleRequest(
-    axios.get<iLink[]>(urlJoin(path, ""), {
-      params: { isReachable, showDuplicate },
+    axios.get<iLinksPage>(urlJoin(path, ""), {
+      params: query,
     })
   );
 }
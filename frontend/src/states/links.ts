@@ -1,10 +1,13 @@
 import { makeAutoObservable } from 'mobx';
-import { iLink } from '../models/links';
+import { iLink, iLinksQuery } from '../models/links';
 import { checkDownloaded, getAllLinks, downLoad, scanFilesForLink, addDuplicate } from '../services/links';
 import { NotificationManager } from 'react-notifications';
 
 class Links {
   links: iLink[] = [];
+  total: number = 0;
+  nextCursor: string | null = null;
+  query: iLinksQuery = { isReachable: true, isDuplicate: false, limit: 100 };
   editModal: iLink | null = null;
 
   constructor() {
@@ -12,7 +15,30 @@ class Links {
   }
 
   async getAll(isReachable: boolean = true, showDuplicate: boolean = false) {
-    this.links = await getAllLinks(isReachable, showDuplicate);
+    this.query = { ...this.query, isReachable, isDuplicate: showDuplicate, cursor: undefined };
+    await this.load(false);
+  }
+
+  async search(q: string) {
+    this.query = { ...this.query, q: q.trim() || undefined, cursor: undefined };
+    await this.load(false);
+  }
+
+  async loadMore() {
+    if (this.nextCursor) {
+      this.query = { ...this.query, cursor: this.nextCursor };
+      await this.load(true);
+    }
+  }
+
+  private async load(append: boolean) {
+    const page = await getAllLinks(this.query);
+    if (!page) {
+      return;
+    }
+    this.links = append ? [...this.links, ...page.items] : page.items;
+    this.total = page.total;
+    this.nextCursor = page.nextCursor ?? null;
   }
 
   openEdit(id: number | null) {